@@ -0,0 +1,91 @@
+/*!
+ * Opt-in branding for indices, for when several modules are built at once.
+ *
+ * `ModuleScope::with` hands its closure a scope carrying a unique, invariant
+ * lifetime `'id`. Indices branded by that scope can only be turned back into
+ * plain indices by the same scope, so an index from one module used while
+ * building another is rejected by the compiler:
+ *
+ * ```compile_fail
+ * use wasmuter::{branded_index::ModuleScope, index::FunctionIndex};
+ *
+ * ModuleScope::with(|main| {
+ *     ModuleScope::with(|helper| {
+ *         let function_index = helper.brand(FunctionIndex(0));
+ *         main.index(function_index);
+ *     })
+ * });
+ * ```
+ */
+use std::marker::PhantomData;
+
+/**
+ * An invariant lifetime, so that two different `'id`s can never be unified
+ * by the compiler.
+ */
+#[derive(Clone, Copy)]
+struct Id<'id>(PhantomData<fn(&'id ()) -> &'id ()>);
+
+/**
+ * An index that can only be used with the `ModuleScope` that branded it.
+ */
+#[derive(Clone, Copy)]
+pub struct Branded<'id, I> {
+    index: I,
+    _id: Id<'id>,
+}
+
+pub struct ModuleScope<'id> {
+    _id: Id<'id>,
+}
+
+impl<'id> ModuleScope<'id> {
+    /**
+     * Runs `f` with a new scope. Since `f` has to work for any `'id`, nothing
+     * branded by the scope can escape it.
+     */
+    pub fn with<R>(f: impl for<'new_id> FnOnce(ModuleScope<'new_id>) -> R) -> R {
+        f(ModuleScope {
+            _id: Id(PhantomData),
+        })
+    }
+
+    /**
+     * Brands an index as belonging to this scope's module. This should be done
+     * once, wherever the indexed item is added to the module.
+     */
+    pub fn brand<I>(&self, index: I) -> Branded<'id, I> {
+        Branded {
+            index,
+            _id: Id(PhantomData),
+        }
+    }
+
+    /** Returns the plain index, as long as it was branded by this scope */
+    pub fn index<I>(&self, branded: Branded<'id, I>) -> I {
+        branded.index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        expression::Instruction,
+        index::{FunctionIndex, GlobalIndex},
+    };
+
+    #[test]
+    fn test_round_trip() {
+        ModuleScope::with(|module| {
+            let function_index = module.brand(FunctionIndex(3));
+            let global_index = module.brand(GlobalIndex(1));
+
+            assert_eq!(module.index(function_index), FunctionIndex(3));
+            assert!(matches!(
+                Instruction::GlobalGet(module.index(global_index)),
+                Instruction::GlobalGet(GlobalIndex(1))
+            ));
+        });
+    }
+}
@@ -0,0 +1,643 @@
+/*!
+ * A control flow graph of basic blocks, and its translation into Wasm's
+ * structured control instructions.
+ *
+ * Reducible graphs are translated following Ramsey's "Beyond Relooper": each
+ * block's code is placed where its immediate dominator ends, loop headers
+ * become `Loop`s, and blocks with several forward predecessors are reached by
+ * branching out of an enclosing `Block`. Irreducible graphs fall back to a
+ * dispatch loop, which stores the next block's number in a local and jumps
+ * through a `BranchTable`.
+ */
+use crate::{
+    expression::{BlockType, Expression, Instruction},
+    index::{LabelIndex, LocalIndex},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BlockId(pub usize);
+
+/**
+ * How control leaves a basic block. `Branch` and `Switch` take their operand
+ * (an `i32` condition or case number) from the top of the stack, and
+ * `Return` returns whatever the block left on it.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    /** Goes to the first block if the condition is non-zero */
+    Branch(BlockId, BlockId),
+    /** Goes to the case's block, or the default block if out of range */
+    Switch(Vec<BlockId>, BlockId),
+    Return,
+    Unreachable,
+}
+
+impl Terminator {
+    fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch(then_target, else_target) => vec![*then_target, *else_target],
+            Terminator::Switch(targets, default) => {
+                let mut successors = targets.clone();
+                successors.push(*default);
+                successors
+            }
+            Terminator::Return | Terminator::Unreachable => vec![],
+        }
+    }
+}
+
+struct BasicBlock {
+    instructions: Vec<Instruction>,
+    terminator: Terminator,
+}
+
+/**
+ * A function body as basic blocks. Blocks shouldn't leave values on the stack
+ * for their successors, other than the operand their terminator consumes.
+ */
+#[derive(Default)]
+pub struct ControlFlowGraph {
+    blocks: Vec<BasicBlock>,
+}
+
+impl ControlFlowGraph {
+    pub fn new() -> ControlFlowGraph {
+        ControlFlowGraph::default()
+    }
+
+    /** Adds a block, which is `Unreachable`-terminated until told otherwise */
+    pub fn add_block(&mut self, instructions: Vec<Instruction>) -> BlockId {
+        self.blocks.push(BasicBlock {
+            instructions,
+            terminator: Terminator::Unreachable,
+        });
+        BlockId(self.blocks.len() - 1)
+    }
+
+    pub fn instructions_mut(&mut self, block: BlockId) -> &mut Vec<Instruction> {
+        &mut self.blocks[block.0].instructions
+    }
+
+    pub fn set_terminator(&mut self, block: BlockId, terminator: Terminator) {
+        self.blocks[block.0].terminator = terminator;
+    }
+
+    /**
+     * Translates the blocks reachable from `entry` into an `Expression`.
+     * `dispatch_local` has to be an `i32` local, and is only used if the
+     * graph is irreducible.
+     */
+    pub fn build(&self, entry: BlockId, dispatch_local: LocalIndex) -> Expression {
+        let analysis = Analysis::new(self, entry);
+        let mut instructions = vec![];
+        if analysis.is_reducible() {
+            Structurer {
+                cfg: self,
+                analysis: &analysis,
+            }
+            .do_tree(entry, &mut vec![], &mut instructions);
+        } else {
+            self.dispatch(&analysis, dispatch_local, &mut instructions);
+        }
+        // Every path ends in a branch, but validation can't tell after an `If`
+        instructions.push(Instruction::Unreachable);
+        Expression(instructions)
+    }
+
+    fn dispatch(
+        &self,
+        analysis: &Analysis,
+        dispatch_local: LocalIndex,
+        instructions: &mut Vec<Instruction>,
+    ) {
+        use Instruction::*;
+        let order = &analysis.order;
+        let case = |block: BlockId| analysis.rpo[block.0] as i32;
+
+        // Blocks are nested so that block `i`'s code follows the end of the
+        // `i`th `Block`, with `depth` labels between it and the loop.
+        let mut body = vec![
+            LocalGet(dispatch_local),
+            BranchTable(
+                (0..order.len() as u32 - 1).map(LabelIndex).collect(),
+                LabelIndex(order.len() as u32 - 1),
+            ),
+        ];
+        for (i, block) in order.iter().enumerate() {
+            let depth = (order.len() - 1 - i) as u32;
+            let goto = |target: BlockId, depth: u32| {
+                vec![
+                    I32Const(case(target)),
+                    LocalSet(dispatch_local),
+                    Branch(LabelIndex(depth)),
+                ]
+            };
+            let instructions = self.blocks[block.0].instructions.iter().cloned();
+            let mut code = vec![Block(BlockType::Empty, body)];
+            if !matches!(self.blocks[block.0].terminator, Terminator::Switch(..)) {
+                code.extend(instructions.clone());
+            }
+            match &self.blocks[block.0].terminator {
+                Terminator::Jump(target) => code.extend(goto(*target, depth)),
+                Terminator::Branch(then_target, else_target) => code.push(IfElse(
+                    BlockType::Empty,
+                    goto(*then_target, depth + 1),
+                    goto(*else_target, depth + 1),
+                )),
+                Terminator::Switch(targets, default) => {
+                    let mut cases = targets.clone();
+                    cases.push(*default);
+                    let distinct = distinct(&cases);
+                    let labels: Vec<LabelIndex> = cases
+                        .iter()
+                        .map(|target| {
+                            LabelIndex(distinct.iter().position(|t| t == target).unwrap() as u32)
+                        })
+                        .collect();
+                    let mut switch: Vec<Instruction> = instructions.collect();
+                    switch.push(split_branch_table(labels));
+                    for (j, target) in distinct.iter().enumerate() {
+                        let mut case_code = vec![Block(BlockType::Empty, switch)];
+                        let case_depth = depth + (distinct.len() - 1 - j) as u32;
+                        case_code.extend(goto(*target, case_depth));
+                        switch = case_code;
+                    }
+                    code.extend(switch);
+                }
+                Terminator::Return => code.push(Return),
+                Terminator::Unreachable => code.push(Unreachable),
+            }
+            body = code;
+        }
+
+        instructions.push(I32Const(case(order[0])));
+        instructions.push(LocalSet(dispatch_local));
+        instructions.push(Loop(BlockType::Empty, body));
+    }
+}
+
+fn distinct(blocks: &[BlockId]) -> Vec<BlockId> {
+    let mut distinct: Vec<BlockId> = vec![];
+    for block in blocks {
+        if !distinct.contains(block) {
+            distinct.push(*block);
+        }
+    }
+    distinct
+}
+
+/** Uses the last label as the default */
+fn split_branch_table(mut labels: Vec<LabelIndex>) -> Instruction {
+    let default = labels.pop().unwrap();
+    Instruction::BranchTable(labels, default)
+}
+
+const UNREACHED: usize = usize::MAX;
+
+/**
+ * Dominance and loop information for the blocks reachable from the entry,
+ * which are numbered in reverse postorder.
+ */
+struct Analysis {
+    order: Vec<BlockId>,
+    rpo: Vec<usize>,
+    successors: Vec<Vec<BlockId>>,
+    idom: Vec<usize>,
+    children: Vec<Vec<BlockId>>,
+    is_loop_header: Vec<bool>,
+    is_merge_node: Vec<bool>,
+}
+
+impl Analysis {
+    fn new(cfg: &ControlFlowGraph, entry: BlockId) -> Analysis {
+        let block_count = cfg.blocks.len();
+        let successors: Vec<Vec<BlockId>> = cfg
+            .blocks
+            .iter()
+            .map(|block| block.terminator.successors())
+            .collect();
+
+        // Reverse postorder, through an iterative depth-first search
+        let mut postorder = vec![];
+        let mut visited = vec![false; block_count];
+        let mut stack = vec![(entry, 0)];
+        visited[entry.0] = true;
+        while let Some((block, next)) = stack.pop() {
+            match successors[block.0].get(next) {
+                Some(successor) => {
+                    stack.push((block, next + 1));
+                    if !visited[successor.0] {
+                        visited[successor.0] = true;
+                        stack.push((*successor, 0));
+                    }
+                }
+                None => postorder.push(block),
+            }
+        }
+        let order: Vec<BlockId> = postorder.into_iter().rev().collect();
+        let mut rpo = vec![UNREACHED; block_count];
+        for (i, block) in order.iter().enumerate() {
+            rpo[block.0] = i;
+        }
+
+        let mut predecessors = vec![vec![]; block_count];
+        for block in order.iter() {
+            for successor in successors[block.0].iter() {
+                if !predecessors[successor.0].contains(block) {
+                    predecessors[successor.0].push(*block);
+                }
+            }
+        }
+
+        // Immediate dominators (Cooper, Harvey and Kennedy)
+        let mut idom = vec![UNREACHED; block_count];
+        idom[entry.0] = entry.0;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in order.iter().skip(1) {
+                let mut new_idom = UNREACHED;
+                for predecessor in predecessors[block.0].iter() {
+                    if idom[predecessor.0] == UNREACHED {
+                        continue;
+                    }
+                    new_idom = if new_idom == UNREACHED {
+                        predecessor.0
+                    } else {
+                        intersect(&idom, &rpo, predecessor.0, new_idom)
+                    };
+                }
+                if idom[block.0] != new_idom {
+                    idom[block.0] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children = vec![vec![]; block_count];
+        for block in order.iter().skip(1) {
+            children[idom[block.0]].push(*block);
+        }
+
+        let mut is_loop_header = vec![false; block_count];
+        let mut forward_predecessors = vec![0; block_count];
+        for block in order.iter() {
+            for predecessor in predecessors[block.0].iter() {
+                if rpo[predecessor.0] < rpo[block.0] {
+                    forward_predecessors[block.0] += 1;
+                } else {
+                    is_loop_header[block.0] = true;
+                }
+            }
+        }
+        let is_merge_node = forward_predecessors
+            .iter()
+            .map(|count| *count > 1)
+            .collect();
+
+        Analysis {
+            order,
+            rpo,
+            successors,
+            idom,
+            children,
+            is_loop_header,
+            is_merge_node,
+        }
+    }
+
+    fn dominates(&self, dominator: BlockId, mut block: usize) -> bool {
+        loop {
+            if block == dominator.0 {
+                return true;
+            }
+            if self.idom[block] == block {
+                return false;
+            }
+            block = self.idom[block];
+        }
+    }
+
+    /** A graph is reducible if every retreating edge goes to a dominator */
+    fn is_reducible(&self) -> bool {
+        self.order.iter().all(|block| {
+            self.successors[block.0].iter().all(|successor| {
+                self.rpo[successor.0] > self.rpo[block.0] || self.dominates(*successor, block.0)
+            })
+        })
+    }
+
+    fn is_backward(&self, source: BlockId, target: BlockId) -> bool {
+        self.rpo[target.0] <= self.rpo[source.0]
+    }
+}
+
+fn intersect(idom: &[usize], rpo: &[usize], mut first: usize, mut second: usize) -> usize {
+    while first != second {
+        while rpo[first] > rpo[second] {
+            first = idom[first];
+        }
+        while rpo[second] > rpo[first] {
+            second = idom[second];
+        }
+    }
+    first
+}
+
+/** The structured constructs enclosing the code being generated */
+#[derive(Clone, Copy, PartialEq)]
+enum Frame {
+    IfElse,
+    LoopHeadedBy(BlockId),
+    BlockFollowedBy(BlockId),
+}
+
+struct Structurer<'a> {
+    cfg: &'a ControlFlowGraph,
+    analysis: &'a Analysis,
+}
+
+impl<'a> Structurer<'a> {
+    fn do_tree(&self, block: BlockId, context: &mut Vec<Frame>, out: &mut Vec<Instruction>) {
+        let mut merge_children: Vec<BlockId> = self.analysis.children[block.0]
+            .iter()
+            .copied()
+            .filter(|child| self.analysis.is_merge_node[child.0])
+            .collect();
+        // The block that is placed last gets the outermost `Block`
+        merge_children.sort_by_key(|child| std::cmp::Reverse(self.analysis.rpo[child.0]));
+
+        if self.analysis.is_loop_header[block.0] {
+            context.push(Frame::LoopHeadedBy(block));
+            let mut body = vec![];
+            self.node_within(block, &merge_children, context, &mut body);
+            context.pop();
+            out.push(Instruction::Loop(BlockType::Empty, body));
+        } else {
+            self.node_within(block, &merge_children, context, out);
+        }
+    }
+
+    fn node_within(
+        &self,
+        block: BlockId,
+        merge_children: &[BlockId],
+        context: &mut Vec<Frame>,
+        out: &mut Vec<Instruction>,
+    ) {
+        match merge_children.split_first() {
+            Some((child, rest)) => {
+                context.push(Frame::BlockFollowedBy(*child));
+                let mut body = vec![];
+                self.node_within(block, rest, context, &mut body);
+                context.pop();
+                out.push(Instruction::Block(BlockType::Empty, body));
+                self.do_tree(*child, context, out);
+            }
+            None => {
+                // A switch's operand is computed inside the blocks it exits
+                if !matches!(self.cfg.blocks[block.0].terminator, Terminator::Switch(..)) {
+                    out.extend(self.cfg.blocks[block.0].instructions.iter().cloned());
+                }
+                self.terminator(block, context, out);
+            }
+        }
+    }
+
+    fn terminator(&self, block: BlockId, context: &mut Vec<Frame>, out: &mut Vec<Instruction>) {
+        match &self.cfg.blocks[block.0].terminator {
+            Terminator::Jump(target) => self.do_branch(block, *target, context, out),
+            // Both arms going to the same block doesn't make it a merge node,
+            // so it would be placed in each of them
+            Terminator::Branch(then_target, else_target) if then_target == else_target => {
+                out.push(Instruction::Drop);
+                self.do_branch(block, *then_target, context, out);
+            }
+            Terminator::Branch(then_target, else_target) => {
+                context.push(Frame::IfElse);
+                let mut then_instructions = vec![];
+                self.do_branch(block, *then_target, context, &mut then_instructions);
+                let mut else_instructions = vec![];
+                self.do_branch(block, *else_target, context, &mut else_instructions);
+                context.pop();
+                out.push(Instruction::IfElse(
+                    BlockType::Empty,
+                    then_instructions,
+                    else_instructions,
+                ));
+            }
+            Terminator::Switch(targets, default) => {
+                let mut cases = targets.clone();
+                cases.push(*default);
+                // Cases that would otherwise be placed inline each get a
+                // `Block` to branch out of, like merge nodes do.
+                let inline_targets: Vec<BlockId> = distinct(&cases)
+                    .into_iter()
+                    .filter(|target| {
+                        !self.analysis.is_backward(block, *target)
+                            && !self.analysis.is_merge_node[target.0]
+                    })
+                    .collect();
+                self.switch(block, &cases, &inline_targets, context, out);
+            }
+            Terminator::Return => out.push(Instruction::Return),
+            Terminator::Unreachable => out.push(Instruction::Unreachable),
+        }
+    }
+
+    fn switch(
+        &self,
+        block: BlockId,
+        cases: &[BlockId],
+        inline_targets: &[BlockId],
+        context: &mut Vec<Frame>,
+        out: &mut Vec<Instruction>,
+    ) {
+        match inline_targets.split_last() {
+            Some((target, rest)) => {
+                context.push(Frame::BlockFollowedBy(*target));
+                let mut body = vec![];
+                self.switch(block, cases, rest, context, &mut body);
+                context.pop();
+                out.push(Instruction::Block(BlockType::Empty, body));
+                self.do_tree(*target, context, out);
+            }
+            None => {
+                let labels = cases
+                    .iter()
+                    .map(|target| self.label(block, *target, context))
+                    .collect();
+                out.extend(self.cfg.blocks[block.0].instructions.iter().cloned());
+                out.push(split_branch_table(labels));
+            }
+        }
+    }
+
+    fn do_branch(
+        &self,
+        source: BlockId,
+        target: BlockId,
+        context: &mut Vec<Frame>,
+        out: &mut Vec<Instruction>,
+    ) {
+        if self.analysis.is_backward(source, target) || self.analysis.is_merge_node[target.0] {
+            out.push(Instruction::Branch(self.label(source, target, context)));
+        } else {
+            self.do_tree(target, context, out);
+        }
+    }
+
+    fn label(&self, source: BlockId, target: BlockId, context: &[Frame]) -> LabelIndex {
+        let frame = if self.analysis.is_backward(source, target) {
+            Frame::LoopHeadedBy(target)
+        } else {
+            Frame::BlockFollowedBy(target)
+        };
+        let position = context
+            .iter()
+            .rposition(|f| *f == frame)
+            .expect("branch target is not in scope");
+        LabelIndex((context.len() - 1 - position) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    #[test]
+    fn test_diamond() {
+        let mut cfg = ControlFlowGraph::new();
+        let entry = cfg.add_block(vec![LocalGet(LocalIndex(0))]);
+        let then_block = cfg.add_block(vec![I32Const(1)]);
+        let else_block = cfg.add_block(vec![I32Const(2)]);
+        let merge = cfg.add_block(vec![]);
+        cfg.set_terminator(entry, Terminator::Branch(then_block, else_block));
+        cfg.set_terminator(then_block, Terminator::Jump(merge));
+        cfg.set_terminator(else_block, Terminator::Jump(merge));
+        cfg.set_terminator(merge, Terminator::Return);
+
+        assert_eq!(
+            cfg.build(entry, LocalIndex(1)),
+            Expression(vec![
+                Block(
+                    BlockType::Empty,
+                    vec![
+                        LocalGet(LocalIndex(0)),
+                        IfElse(
+                            BlockType::Empty,
+                            vec![I32Const(1), Branch(LabelIndex(1))],
+                            vec![I32Const(2), Branch(LabelIndex(1))],
+                        ),
+                    ],
+                ),
+                Return,
+                Unreachable,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut cfg = ControlFlowGraph::new();
+        let entry = cfg.add_block(vec![]);
+        let header = cfg.add_block(vec![LocalGet(LocalIndex(0))]);
+        let body = cfg.add_block(vec![Nop]);
+        let exit = cfg.add_block(vec![]);
+        cfg.set_terminator(entry, Terminator::Jump(header));
+        cfg.set_terminator(header, Terminator::Branch(body, exit));
+        cfg.set_terminator(body, Terminator::Jump(header));
+        cfg.set_terminator(exit, Terminator::Return);
+
+        assert_eq!(
+            cfg.build(entry, LocalIndex(1)),
+            Expression(vec![
+                Loop(
+                    BlockType::Empty,
+                    vec![
+                        LocalGet(LocalIndex(0)),
+                        IfElse(
+                            BlockType::Empty,
+                            vec![Nop, Branch(LabelIndex(1))],
+                            vec![Return],
+                        ),
+                    ],
+                ),
+                Unreachable,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_switch() {
+        let mut cfg = ControlFlowGraph::new();
+        let entry = cfg.add_block(vec![LocalGet(LocalIndex(0))]);
+        let first = cfg.add_block(vec![]);
+        let second = cfg.add_block(vec![]);
+        cfg.set_terminator(entry, Terminator::Switch(vec![first, second], first));
+        cfg.set_terminator(first, Terminator::Return);
+        cfg.set_terminator(second, Terminator::Unreachable);
+
+        assert_eq!(
+            cfg.build(entry, LocalIndex(1)),
+            Expression(vec![
+                Block(
+                    BlockType::Empty,
+                    vec![
+                        Block(
+                            BlockType::Empty,
+                            vec![
+                                LocalGet(LocalIndex(0)),
+                                BranchTable(vec![LabelIndex(0), LabelIndex(1)], LabelIndex(0)),
+                            ],
+                        ),
+                        Return,
+                    ],
+                ),
+                Unreachable,
+                Unreachable,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_branch_to_same_block() {
+        let mut cfg = ControlFlowGraph::new();
+        let entry = cfg.add_block(vec![LocalGet(LocalIndex(0))]);
+        let target = cfg.add_block(vec![I32Const(1)]);
+        let next = cfg.add_block(vec![]);
+        cfg.set_terminator(entry, Terminator::Branch(target, target));
+        cfg.set_terminator(target, Terminator::Jump(next));
+        cfg.set_terminator(next, Terminator::Return);
+
+        assert_eq!(
+            cfg.build(entry, LocalIndex(1)),
+            Expression(vec![
+                LocalGet(LocalIndex(0)),
+                Drop,
+                I32Const(1),
+                Return,
+                Unreachable,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_irreducible_dispatch() {
+        let mut cfg = ControlFlowGraph::new();
+        let entry = cfg.add_block(vec![LocalGet(LocalIndex(0))]);
+        let first = cfg.add_block(vec![]);
+        let second = cfg.add_block(vec![]);
+        cfg.set_terminator(entry, Terminator::Branch(first, second));
+        cfg.set_terminator(first, Terminator::Jump(second));
+        cfg.set_terminator(second, Terminator::Jump(first));
+
+        let dispatch = LocalIndex(1);
+        let Expression(instructions) = cfg.build(entry, dispatch);
+        assert_eq!(instructions[..2], [I32Const(0), LocalSet(dispatch)]);
+        assert!(matches!(instructions[2], Loop(..)));
+    }
+}
@@ -0,0 +1,281 @@
+use std::{error, fmt};
+
+pub trait WasmDecode: Sized {
+    fn decode(decoder: &mut WasmDecoder) -> Result<Self, DecodeError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    /** A LEB128 integer with more bits than its type holds */
+    IntegerTooLarge,
+    UnknownOpcode(u8),
+    UnknownPrefixedOpcode(u8, u32),
+    InvalidValueType(u8),
+    /** A byte the binary format reserves, which has to be zero */
+    NonZeroReserved(u8),
+    UnexpectedElse,
+    InvalidMagicNumber,
+    UnsupportedVersion(u32),
+    UnknownSection(u8),
+    /** A section after one that has to follow it, or a second of its kind */
+    SectionOutOfOrder(u8),
+    /** A function section and code section with different numbers of functions */
+    FunctionCountMismatch,
+    /** A section whose contents don't take up the size it declares */
+    SectionSizeMismatch(u8),
+    InvalidUtf8,
+    InvalidFunctionType(u8),
+    InvalidElementType(u8),
+    InvalidMutability(u8),
+    InvalidLimits(u8),
+    InvalidDescriptor(u8),
+    /** A custom section whose payload doesn't follow its format */
+    MalformedCustomSection(&'static str),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::IntegerTooLarge => write!(f, "integer too large"),
+            DecodeError::UnknownOpcode(opcode) => write!(f, "unknown opcode {:#04x}", opcode),
+            DecodeError::UnknownPrefixedOpcode(prefix, opcode) => {
+                write!(f, "unknown opcode {:#04x} {}", prefix, opcode)
+            }
+            DecodeError::InvalidValueType(byte) => write!(f, "invalid value type {:#04x}", byte),
+            DecodeError::NonZeroReserved(byte) => {
+                write!(f, "reserved byte is {:#04x} instead of zero", byte)
+            }
+            DecodeError::UnexpectedElse => write!(f, "else outside of an if"),
+            DecodeError::InvalidMagicNumber => write!(f, "invalid magic number"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported version {}", version)
+            }
+            DecodeError::UnknownSection(id) => write!(f, "unknown section id {}", id),
+            DecodeError::SectionOutOfOrder(id) => write!(f, "section {} is out of order", id),
+            DecodeError::FunctionCountMismatch => {
+                write!(f, "function and code section have inconsistent lengths")
+            }
+            DecodeError::SectionSizeMismatch(id) => {
+                write!(f, "section {} doesn't match its declared size", id)
+            }
+            DecodeError::InvalidUtf8 => write!(f, "malformed UTF-8 encoding"),
+            DecodeError::InvalidFunctionType(byte) => {
+                write!(f, "invalid function type {:#04x}", byte)
+            }
+            DecodeError::InvalidElementType(byte) => {
+                write!(f, "invalid element type {:#04x}", byte)
+            }
+            DecodeError::InvalidMutability(byte) => write!(f, "invalid mutability {:#04x}", byte),
+            DecodeError::InvalidLimits(byte) => write!(f, "invalid limits flag {:#04x}", byte),
+            DecodeError::InvalidDescriptor(byte) => {
+                write!(f, "invalid import or export kind {:#04x}", byte)
+            }
+            DecodeError::MalformedCustomSection(message) => {
+                write!(f, "malformed custom section: {}", message)
+            }
+        }
+    }
+}
+
+impl error::Error for DecodeError {}
+
+pub struct WasmDecoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> WasmDecoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        WasmDecoder { bytes, position: 0 }
+    }
+
+    /** The number of bytes read so far */
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    pub fn peek_u8(&self) -> Result<u8, DecodeError> {
+        self.bytes
+            .get(self.position)
+            .copied()
+            .ok_or(DecodeError::UnexpectedEnd)
+    }
+
+    pub fn peek_u32(&self) -> Result<u32, DecodeError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(
+            self.bytes
+                .get(self.position..self.position + 4)
+                .ok_or(DecodeError::UnexpectedEnd)?,
+        );
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        let byte = self.peek_u8()?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() - self.position < count {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let bytes = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, DecodeError> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64, DecodeError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(f64::from_bits(u64::from_le_bytes(bytes)))
+    }
+
+    /** Reads a count, then that many items */
+    pub fn read_vector<T: WasmDecode>(&mut self) -> Result<Vec<T>, DecodeError> {
+        let count = self.read_leb_u32()?;
+        (0..count).map(|_| T::decode(self)).collect()
+    }
+
+    /** Reads a length-prefixed vector of bytes */
+    pub fn read_byte_vector(&mut self) -> Result<&'a [u8], DecodeError> {
+        let length = self.read_leb_u32()?;
+        self.read_bytes(length as usize)
+    }
+
+    pub fn read_name(&mut self) -> Result<String, DecodeError> {
+        let bytes = self.read_byte_vector()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    pub fn read_leb_u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(self.read_leb_unsigned(32)? as u32)
+    }
+
+    pub fn read_leb_u64(&mut self) -> Result<u64, DecodeError> {
+        self.read_leb_unsigned(64)
+    }
+
+    pub fn read_leb_i32(&mut self) -> Result<i32, DecodeError> {
+        Ok(self.read_leb_signed(32)? as i32)
+    }
+
+    pub fn read_leb_i64(&mut self) -> Result<i64, DecodeError> {
+        self.read_leb_signed(64)
+    }
+
+    fn read_leb_unsigned(&mut self, bits: u32) -> Result<u64, DecodeError> {
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            let low_bits = (byte & 0x7f) as u64;
+            // The last byte can't have bits beyond the type's width
+            if shift >= bits || (bits - shift < 7 && low_bits >> (bits - shift) != 0) {
+                return Err(DecodeError::IntegerTooLarge);
+            }
+            value |= low_bits << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn read_leb_signed(&mut self, bits: u32) -> Result<i64, DecodeError> {
+        let mut value: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            if shift >= bits {
+                return Err(DecodeError::IntegerTooLarge);
+            }
+            // Only one bit of a 64-bit value's tenth byte is used, so the rest
+            // must extend its sign
+            if shift == 63 && byte != 0x00 && byte != 0x7f {
+                return Err(DecodeError::IntegerTooLarge);
+            }
+            value |= ((byte & 0x7f) as i64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                // Sign extend from the last bit read, then from the type's width
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                if bits < 64 {
+                    let unused = 64 - bits;
+                    if (value << unused) >> unused != value {
+                        return Err(DecodeError::IntegerTooLarge);
+                    }
+                }
+                return Ok(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::WasmEncoder;
+
+    #[test]
+    fn test_leb_round_trip() {
+        let mut encoder = WasmEncoder::new();
+        encoder.push_leb_u32(u32::MAX);
+        encoder.push_leb_i32(i32::MIN);
+        encoder.push_leb_i64(-1);
+        encoder.push_leb_i64(i64::MAX);
+        encoder.push_leb_u32(624485);
+
+        let mut decoder = WasmDecoder::new(encoder.as_slice());
+        assert_eq!(decoder.read_leb_u32(), Ok(u32::MAX));
+        assert_eq!(decoder.read_leb_i32(), Ok(i32::MIN));
+        assert_eq!(decoder.read_leb_i64(), Ok(-1));
+        assert_eq!(decoder.read_leb_i64(), Ok(i64::MAX));
+        assert_eq!(decoder.read_leb_u32(), Ok(624485));
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn test_leb_errors() {
+        let mut decoder = WasmDecoder::new(&[0xff, 0xff, 0xff, 0xff, 0x1f]);
+        assert_eq!(decoder.read_leb_u32(), Err(DecodeError::IntegerTooLarge));
+
+        let mut decoder = WasmDecoder::new(&[0x80, 0x80]);
+        assert_eq!(decoder.read_leb_u32(), Err(DecodeError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_leb_i64_last_byte() {
+        let continued = [0x80; 9];
+
+        for (last, expected) in [(0x00, Ok(0)), (0x7f, Ok(i64::MIN))].iter() {
+            let bytes = [&continued[..], &[*last]].concat();
+            let mut decoder = WasmDecoder::new(&bytes);
+            assert_eq!(decoder.read_leb_i64(), *expected);
+        }
+        for last in [0x01, 0x02, 0x40, 0x7e].iter() {
+            let bytes = [&continued[..], &[*last]].concat();
+            let mut decoder = WasmDecoder::new(&bytes);
+            assert_eq!(decoder.read_leb_i64(), Err(DecodeError::IntegerTooLarge));
+        }
+    }
+}
@@ -0,0 +1,503 @@
+/*!
+ * A typed DSL for writing instruction sequences with Rust operators.
+ *
+ * Each value wraps the instructions that push it, and combining values
+ * appends their instructions followed by the typed operator, so
+ * `local(a) + local(b) * i32c(3)` produces `local.get a`, `local.get b`,
+ * `i32.const 3`, `i32.mul`, `i32.add`. Only operands of the same type can be
+ * combined, so ill-typed sequences don't compile.
+ *
+ * Integer division, remainder and right shifts through `/`, `%` and `>>` are
+ * signed; the unsigned versions are available as methods.
+ */
+use std::{
+    marker::PhantomData,
+    ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Neg, Not, Rem, Shl, Shr, Sub},
+};
+
+use crate::{
+    expression::{Expression, Instruction, MemoryArguments},
+    function_type::ValueType,
+    index::{FunctionIndex, GlobalIndex, LocalIndex},
+};
+
+pub trait Value: Sized {
+    const VALUE_TYPE: ValueType;
+
+    fn from_instructions(instructions: Vec<Instruction>) -> Self;
+
+    fn into_instructions(self) -> Vec<Instruction>;
+
+    fn global(global_index: GlobalIndex) -> Self {
+        Self::from_instructions(vec![Instruction::GlobalGet(global_index)])
+    }
+
+    /** Loads a full-width value from `address` */
+    fn load(address: I32Val, mem_args: MemoryArguments) -> Self;
+}
+
+/**
+ * A local that is known to hold values of type `T`.
+ */
+pub struct TypedLocal<T: Value> {
+    index: LocalIndex,
+    value_type: PhantomData<T>,
+}
+
+impl<T: Value> Clone for TypedLocal<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Value> Copy for TypedLocal<T> {}
+
+impl<T: Value> TypedLocal<T> {
+    pub fn new(index: LocalIndex) -> TypedLocal<T> {
+        TypedLocal {
+            index,
+            value_type: PhantomData,
+        }
+    }
+
+    pub fn index(&self) -> LocalIndex {
+        self.index
+    }
+
+    pub fn get(&self) -> T {
+        T::from_instructions(vec![Instruction::LocalGet(self.index)])
+    }
+
+    pub fn set(&self, value: T) -> Vec<Instruction> {
+        let mut instructions = value.into_instructions();
+        instructions.push(Instruction::LocalSet(self.index));
+        instructions
+    }
+
+    pub fn tee(&self, value: T) -> T {
+        let mut instructions = value.into_instructions();
+        instructions.push(Instruction::LocalTee(self.index));
+        T::from_instructions(instructions)
+    }
+}
+
+pub fn local<T: Value>(local: TypedLocal<T>) -> T {
+    local.get()
+}
+
+pub fn i32c(value: i32) -> I32Val {
+    I32Val(vec![Instruction::I32Const(value)])
+}
+
+pub fn i64c(value: i64) -> I64Val {
+    I64Val(vec![Instruction::I64Const(value)])
+}
+
+pub fn f32c(value: f32) -> F32Val {
+    F32Val(vec![Instruction::F32Const(value)])
+}
+
+pub fn f64c(value: f64) -> F64Val {
+    F64Val(vec![Instruction::F64Const(value)])
+}
+
+/** Calls a function returning a `T`, with arguments already lowered */
+pub fn call<T: Value>(function_index: FunctionIndex, arguments: Vec<Vec<Instruction>>) -> T {
+    let mut instructions: Vec<Instruction> = arguments.into_iter().flatten().collect();
+    instructions.push(Instruction::Call(function_index));
+    T::from_instructions(instructions)
+}
+
+fn unary<T: Value, R: Value>(operand: T, instruction: Instruction) -> R {
+    let mut instructions = operand.into_instructions();
+    instructions.push(instruction);
+    R::from_instructions(instructions)
+}
+
+fn binary<T: Value, R: Value>(lhs: T, rhs: T, instruction: Instruction) -> R {
+    let mut instructions = lhs.into_instructions();
+    instructions.extend(rhs.into_instructions());
+    instructions.push(instruction);
+    R::from_instructions(instructions)
+}
+
+fn store<T: Value>(address: I32Val, value: T, instruction: Instruction) -> Vec<Instruction> {
+    let mut instructions = address.0;
+    instructions.extend(value.into_instructions());
+    instructions.push(instruction);
+    instructions
+}
+
+macro_rules! value {
+    ($name:ident, $value_type:ident, $load:ident, $store:ident) => {
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct $name(pub Vec<Instruction>);
+
+        impl Value for $name {
+            const VALUE_TYPE: ValueType = ValueType::$value_type;
+
+            fn from_instructions(instructions: Vec<Instruction>) -> Self {
+                $name(instructions)
+            }
+
+            fn into_instructions(self) -> Vec<Instruction> {
+                self.0
+            }
+
+            fn load(address: I32Val, mem_args: MemoryArguments) -> Self {
+                unary(address, Instruction::$load(mem_args))
+            }
+        }
+
+        impl $name {
+            pub fn store(self, address: I32Val, mem_args: MemoryArguments) -> Vec<Instruction> {
+                store(address, self, Instruction::$store(mem_args))
+            }
+
+            pub fn drop(self) -> Vec<Instruction> {
+                unary::<$name, $name>(self, Instruction::Drop).0
+            }
+
+            /** Picks `self` if `condition` is non-zero and `other` otherwise */
+            pub fn select(self, other: $name, condition: I32Val) -> $name {
+                let mut instructions = self.0;
+                instructions.extend(other.0);
+                instructions.extend(condition.0);
+                instructions.push(Instruction::Select);
+                $name(instructions)
+            }
+        }
+
+        impl From<$name> for Vec<Instruction> {
+            fn from(value: $name) -> Vec<Instruction> {
+                value.0
+            }
+        }
+
+        impl From<$name> for Expression {
+            fn from(value: $name) -> Expression {
+                Expression(value.0)
+            }
+        }
+    };
+}
+
+macro_rules! binary_op {
+    ($name:ident, $trait:ident, $method:ident, $instruction:ident) => {
+        impl $trait for $name {
+            type Output = $name;
+
+            fn $method(self, rhs: $name) -> $name {
+                binary(self, rhs, Instruction::$instruction)
+            }
+        }
+    };
+}
+
+macro_rules! methods {
+    ($name:ident, $result:ident, unary { $($method:ident => $instruction:ident),* $(,)? }) => {
+        impl $name {
+            $(
+                pub fn $method(self) -> $result {
+                    unary(self, Instruction::$instruction)
+                }
+            )*
+        }
+    };
+    ($name:ident, $result:ident, binary { $($method:ident => $instruction:ident),* $(,)? }) => {
+        impl $name {
+            $(
+                pub fn $method(self, rhs: $name) -> $result {
+                    binary(self, rhs, Instruction::$instruction)
+                }
+            )*
+        }
+    };
+}
+
+value!(I32Val, I32, I32Load, I32Store);
+value!(I64Val, I64, I64Load, I64Store);
+value!(F32Val, F32, F32Load, F32Store);
+value!(F64Val, F64, F64Load, F64Store);
+
+binary_op!(I32Val, Add, add, I32Add);
+binary_op!(I32Val, Sub, sub, I32Sub);
+binary_op!(I32Val, Mul, mul, I32Mul);
+binary_op!(I32Val, Div, div, I32DivS);
+binary_op!(I32Val, Rem, rem, I32RemS);
+binary_op!(I32Val, BitAnd, bitand, I32And);
+binary_op!(I32Val, BitOr, bitor, I32Or);
+binary_op!(I32Val, BitXor, bitxor, I32Xor);
+binary_op!(I32Val, Shl, shl, I32Shl);
+binary_op!(I32Val, Shr, shr, I32ShrS);
+
+binary_op!(I64Val, Add, add, I64Add);
+binary_op!(I64Val, Sub, sub, I64Sub);
+binary_op!(I64Val, Mul, mul, I64Mul);
+binary_op!(I64Val, Div, div, I64DivS);
+binary_op!(I64Val, Rem, rem, I64RemS);
+binary_op!(I64Val, BitAnd, bitand, I64And);
+binary_op!(I64Val, BitOr, bitor, I64Or);
+binary_op!(I64Val, BitXor, bitxor, I64Xor);
+binary_op!(I64Val, Shl, shl, I64Shl);
+binary_op!(I64Val, Shr, shr, I64ShrS);
+
+binary_op!(F32Val, Add, add, F32Add);
+binary_op!(F32Val, Sub, sub, F32Sub);
+binary_op!(F32Val, Mul, mul, F32Mul);
+binary_op!(F32Val, Div, div, F32Div);
+
+binary_op!(F64Val, Add, add, F64Add);
+binary_op!(F64Val, Sub, sub, F64Sub);
+binary_op!(F64Val, Mul, mul, F64Mul);
+binary_op!(F64Val, Div, div, F64Div);
+
+methods!(I32Val, I32Val, binary {
+    div_u => I32DivU,
+    rem_u => I32RemU,
+    shr_u => I32ShrU,
+    rotl => I32Rotl,
+    rotr => I32Rotr,
+    equals => I32Eq,
+    not_equals => I32Ne,
+    lt_s => I32LtS,
+    lt_u => I32LtU,
+    gt_s => I32GtS,
+    gt_u => I32GtU,
+    le_s => I32LeS,
+    le_u => I32LeU,
+    ge_s => I32GeS,
+    ge_u => I32GeU,
+});
+methods!(I32Val, I32Val, unary {
+    eqz => I32Eqz,
+    clz => I32Clz,
+    ctz => I32Ctz,
+    popcnt => I32PopCnt,
+});
+methods!(I32Val, I64Val, unary {
+    extend_s => I64ExtendI32S,
+    extend_u => I64ExtendI32U,
+});
+methods!(I32Val, F32Val, unary {
+    convert_s_f32 => F32ConvertI32S,
+    convert_u_f32 => F32ConvertI32U,
+    reinterpret => F32ReinterpretI32,
+});
+methods!(I32Val, F64Val, unary {
+    convert_s_f64 => F64ConvertI32S,
+    convert_u_f64 => F64ConvertI32U,
+});
+
+methods!(I64Val, I64Val, binary {
+    div_u => I64DivU,
+    rem_u => I64RemU,
+    shr_u => I64ShrU,
+    rotl => I64Rotl,
+    rotr => I64Rotr,
+});
+methods!(I64Val, I32Val, binary {
+    equals => I64Eq,
+    not_equals => I64Ne,
+    lt_s => I64LtS,
+    lt_u => I64LtU,
+    gt_s => I64GtS,
+    gt_u => I64GtU,
+    le_s => I64LeS,
+    le_u => I64LeU,
+    ge_s => I64GeS,
+    ge_u => I64GeU,
+});
+methods!(I64Val, I64Val, unary {
+    clz => I64Clz,
+    ctz => I64Ctz,
+    popcnt => I64PopCnt,
+});
+methods!(I64Val, I32Val, unary {
+    eqz => I64Eqz,
+    wrap => I32WrapI64,
+});
+methods!(I64Val, F32Val, unary {
+    convert_s_f32 => F32ConvertI64S,
+    convert_u_f32 => F32ConvertI64U,
+});
+methods!(I64Val, F64Val, unary {
+    convert_s_f64 => F64ConvertI64S,
+    convert_u_f64 => F64ConvertI64U,
+    reinterpret => F64ReinterpretI64,
+});
+
+methods!(F32Val, F32Val, binary {
+    min => F32Min,
+    max => F32Max,
+    copysign => F32CopySign,
+});
+methods!(F32Val, I32Val, binary {
+    equals => F32Eq,
+    not_equals => F32Ne,
+    lt => F32Lt,
+    gt => F32Gt,
+    le => F32Le,
+    ge => F32Ge,
+});
+methods!(F32Val, F32Val, unary {
+    abs => F32Abs,
+    ceil => F32Ceil,
+    floor => F32Floor,
+    trunc => F32Trunc,
+    nearest => F32Nearest,
+    sqrt => F32Sqrt,
+});
+methods!(F32Val, I32Val, unary {
+    trunc_s_i32 => I32TruncF32S,
+    trunc_u_i32 => I32TruncF32U,
+    reinterpret => I32ReinterpretF32,
+});
+methods!(F32Val, I64Val, unary {
+    trunc_s_i64 => I64TruncF32S,
+    trunc_u_i64 => I64TruncF32U,
+});
+methods!(F32Val, F64Val, unary {
+    promote => F64PromoteF32,
+});
+
+methods!(F64Val, F64Val, binary {
+    min => F64Min,
+    max => F64Max,
+    copysign => F64CopySign,
+});
+methods!(F64Val, I32Val, binary {
+    equals => F64Eq,
+    not_equals => F64Ne,
+    lt => F64Lt,
+    gt => F64Gt,
+    le => F64Le,
+    ge => F64Ge,
+});
+methods!(F64Val, F64Val, unary {
+    abs => F64Abs,
+    ceil => F64Ceil,
+    floor => F64Floor,
+    trunc => F64Trunc,
+    nearest => F64Nearest,
+    sqrt => F64Sqrt,
+});
+methods!(F64Val, I32Val, unary {
+    trunc_s_i32 => I32TruncF64S,
+    trunc_u_i32 => I32TruncF64U,
+});
+methods!(F64Val, I64Val, unary {
+    trunc_s_i64 => I64TruncF64S,
+    trunc_u_i64 => I64TruncF64U,
+    reinterpret => I64ReinterpretF64,
+});
+methods!(F64Val, F32Val, unary {
+    demote => F32DemoteF64,
+});
+
+impl Neg for F32Val {
+    type Output = F32Val;
+
+    fn neg(self) -> F32Val {
+        unary(self, Instruction::F32Neg)
+    }
+}
+
+impl Neg for F64Val {
+    type Output = F64Val;
+
+    fn neg(self) -> F64Val {
+        unary(self, Instruction::F64Neg)
+    }
+}
+
+// Integers have no negation or complement instructions, so these are built
+// from subtraction and exclusive or.
+
+impl Neg for I32Val {
+    type Output = I32Val;
+
+    fn neg(self) -> I32Val {
+        i32c(0) - self
+    }
+}
+
+impl Neg for I64Val {
+    type Output = I64Val;
+
+    fn neg(self) -> I64Val {
+        i64c(0) - self
+    }
+}
+
+impl Not for I32Val {
+    type Output = I32Val;
+
+    fn not(self) -> I32Val {
+        self ^ i32c(-1)
+    }
+}
+
+impl Not for I64Val {
+    type Output = I64Val;
+
+    fn not(self) -> I64Val {
+        self ^ i64c(-1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    #[test]
+    fn test_operator_precedence() {
+        let a = TypedLocal::<I32Val>::new(LocalIndex(0));
+        let b = TypedLocal::<I32Val>::new(LocalIndex(1));
+
+        assert_eq!(
+            (local(a) + local(b) * i32c(3)).0,
+            vec![
+                LocalGet(LocalIndex(0)),
+                LocalGet(LocalIndex(1)),
+                I32Const(3),
+                I32Mul,
+                I32Add,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conversions_and_comparisons() {
+        let x = TypedLocal::<F64Val>::new(LocalIndex(0));
+
+        assert_eq!(
+            (-local(x)).sqrt().lt(f64c(2.0)).0,
+            vec![
+                LocalGet(LocalIndex(0)),
+                F64Neg,
+                F64Sqrt,
+                F64Const(2.0),
+                F64Lt
+            ]
+        );
+        assert_eq!(i32c(1).extend_u().0, vec![I32Const(1), I64ExtendI32U]);
+    }
+
+    #[test]
+    fn test_memory_access() {
+        let mem_args = MemoryArguments::new(0, 2);
+        assert_eq!(
+            (I32Val::load(i32c(8), mem_args) + i32c(1)).store(i32c(8), mem_args),
+            vec![
+                I32Const(8),
+                I32Const(8),
+                I32Load(mem_args),
+                I32Const(1),
+                I32Add,
+                I32Store(mem_args),
+            ]
+        );
+    }
+}
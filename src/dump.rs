@@ -0,0 +1,618 @@
+/*!
+ * An annotated hex dump of encoded modules, which says what every byte range
+ * means, with a disassembly of each function.
+ */
+use std::{error, fmt, ops::Range};
+
+use crate::{
+    constants::*,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
+    encoder::{WasmEncode, WasmEncoder},
+    expression::{BlockType, Instruction},
+    function_type::ValueType,
+    module::Module,
+    wat::escape_string,
+};
+
+/** How many bytes go on one line of a dump */
+const BYTES_PER_LINE: usize = 8;
+
+/** A range of bytes and what it means, or a header when the range is empty */
+#[derive(Debug, PartialEq)]
+pub struct Annotation {
+    pub range: Range<usize>,
+    /** How deeply the annotation is nested in sections, functions and blocks */
+    pub depth: usize,
+    pub note: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Dump {
+    bytes: Vec<u8>,
+    annotations: Vec<Annotation>,
+}
+
+/**
+ * A binary that couldn't be decoded. The dump annotates the bytes before
+ * `offset`, and the bytes from there on are annotated with the error.
+ */
+#[derive(Debug, PartialEq)]
+pub struct DumpError {
+    pub dump: Dump,
+    pub offset: usize,
+    pub error: DecodeError,
+}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {:#x}", self.error, self.offset)
+    }
+}
+
+impl error::Error for DumpError {}
+
+impl Dump {
+    /**
+     * Annotates a whole binary, or sections without the preamble, like the
+     * encoding of a single section.
+     */
+    pub fn new(bytes: &[u8]) -> Result<Dump, DumpError> {
+        let mut dumper = Dumper {
+            decoder: WasmDecoder::new(bytes),
+            annotations: vec![],
+            depth: 0,
+        };
+        let result = dumper.module();
+        let mut dump = Dump {
+            bytes: bytes.to_vec(),
+            annotations: dumper.annotations,
+        };
+        match result {
+            Ok(()) => Ok(dump),
+            Err(error) => {
+                // What failed to decode starts after the last annotated byte
+                let offset = dump
+                    .annotations
+                    .iter()
+                    .map(|annotation| annotation.range.end)
+                    .max()
+                    .unwrap_or(0);
+                dump.annotations.push(Annotation {
+                    range: offset..bytes.len(),
+                    depth: dumper.depth,
+                    note: format!("error: {}", error),
+                });
+                Err(DumpError {
+                    dump,
+                    offset,
+                    error,
+                })
+            }
+        }
+    }
+
+    pub fn from_module(module: &Module) -> Result<Dump, DumpError> {
+        let mut encoder = WasmEncoder::new();
+        module.encode(&mut encoder);
+        Dump::new(encoder.as_slice())
+    }
+
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+
+    /** The annotated bytes as the contents of an `assert_encoding_eq` array */
+    pub fn to_test_bytes(&self) -> String {
+        let mut text = String::new();
+        for annotation in self.annotations.iter() {
+            if annotation.range.is_empty() {
+                text.push_str(&format!("// {}\n", annotation.note));
+                continue;
+            }
+            let bytes: Vec<String> = self.bytes[annotation.range.clone()]
+                .iter()
+                .map(|byte| format!("0x{:02x},", byte))
+                .collect();
+            text.push_str(&format!("{} // {}\n", bytes.join(" "), annotation.note));
+        }
+        text
+    }
+}
+
+impl fmt::Display for Dump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for annotation in self.annotations.iter() {
+            let indent = "  ".repeat(annotation.depth);
+            if annotation.range.is_empty() {
+                writeln!(f, "{}; {}", indent, annotation.note)?;
+                continue;
+            }
+            let start = annotation.range.start;
+            let lines = self.bytes[annotation.range.clone()].chunks(BYTES_PER_LINE);
+            for (i, line) in lines.enumerate() {
+                let hex: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+                write!(
+                    f,
+                    "{:#06x}: {:width$}",
+                    start + i * BYTES_PER_LINE,
+                    hex.join(" "),
+                    width = BYTES_PER_LINE * 3 - 1
+                )?;
+                match i {
+                    0 => writeln!(f, " ; {}{}", indent, annotation.note)?,
+                    _ => writeln!(f)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn section_name(id: u8) -> &'static str {
+    match id {
+        CUSTOM_SECTION => "custom",
+        TYPE_SECTION => "type",
+        IMPORT_SECTION => "import",
+        FUNCTION_SECTION => "function",
+        TABLE_SECTION => "table",
+        MEMORY_SECTION => "memory",
+        GLOBAL_SECTION => "global",
+        EXPORT_SECTION => "export",
+        START_SECTION => "start",
+        ELEMENT_SECTION => "element",
+        CODE_SECTION => "code",
+        DATA_SECTION => "data",
+        _ => "unknown",
+    }
+}
+
+fn descriptor_name(kind: u8) -> &'static str {
+    match kind {
+        TYPE_INDEX => "func",
+        TABLE_TYPE => "table",
+        MEMORY_TYPE => "memory",
+        GLOBAL_TYPE => "global",
+        _ => "unknown",
+    }
+}
+
+struct Dumper<'a> {
+    decoder: WasmDecoder<'a>,
+    annotations: Vec<Annotation>,
+    depth: usize,
+}
+
+impl<'a> Dumper<'a> {
+    /** Annotates the bytes read since `start` */
+    fn note(&mut self, start: usize, note: String) {
+        self.annotations.push(Annotation {
+            range: start..self.decoder.position(),
+            depth: self.depth,
+            note,
+        });
+    }
+
+    fn header(&mut self, note: String) {
+        let position = self.decoder.position();
+        self.annotations.push(Annotation {
+            range: position..position,
+            depth: self.depth,
+            note,
+        });
+    }
+
+    fn byte(&mut self, describe: impl FnOnce(u8) -> String) -> Result<u8, DecodeError> {
+        let start = self.decoder.position();
+        let byte = self.decoder.read_u8()?;
+        self.note(start, describe(byte));
+        Ok(byte)
+    }
+
+    fn leb_u32(&mut self, label: &str) -> Result<u32, DecodeError> {
+        let start = self.decoder.position();
+        let value = self.decoder.read_leb_u32()?;
+        self.note(start, format!("{} {}", label, value));
+        Ok(value)
+    }
+
+    fn name(&mut self, label: &str) -> Result<(), DecodeError> {
+        let length = self.leb_u32(&format!("{} length", label))?;
+        let start = self.decoder.position();
+        let name = self.decoder.read_bytes(length as usize)?;
+        self.note(start, format!("{} {}", label, escape_string(name)));
+        Ok(())
+    }
+
+    fn value_type(&mut self, label: &str) -> Result<ValueType, DecodeError> {
+        let start = self.decoder.position();
+        let value_type = ValueType::decode(&mut self.decoder)?;
+        self.note(start, format!("{} {}", label, value_type));
+        Ok(value_type)
+    }
+
+    fn limits(&mut self) -> Result<(), DecodeError> {
+        let flag = self.byte(|flag| match flag {
+            MAX_PRESENT => "max flag (on)".to_string(),
+            _ => "max flag (off)".to_string(),
+        })?;
+        self.leb_u32("min")?;
+        match flag {
+            MAX_ABSENT => Ok(()),
+            MAX_PRESENT => self.leb_u32("max").map(|_| ()),
+            _ => Err(DecodeError::InvalidLimits(flag)),
+        }
+    }
+
+    fn table_type(&mut self) -> Result<(), DecodeError> {
+        match self.byte(|_| "element type - funcref".to_string())? {
+            FUNCTION_REFERENCE => self.limits(),
+            byte => Err(DecodeError::InvalidElementType(byte)),
+        }
+    }
+
+    fn global_type(&mut self) -> Result<(), DecodeError> {
+        self.value_type("value type")?;
+        match self.byte(|byte| match byte {
+            VAR => "mutability - var".to_string(),
+            _ => "mutability - const".to_string(),
+        })? {
+            CONST | VAR => Ok(()),
+            byte => Err(DecodeError::InvalidMutability(byte)),
+        }
+    }
+
+    /** Disassembles an expression, a line per instruction */
+    fn expression(&mut self) -> Result<(), DecodeError> {
+        let base = self.depth;
+        loop {
+            let start = self.decoder.position();
+            match self.decoder.peek_u8()? {
+                opcode @ (BLOCK | LOOP | IF) => {
+                    self.decoder.read_u8()?;
+                    let block_type = match BlockType::decode(&mut self.decoder)? {
+                        BlockType::Empty => String::new(),
+                        BlockType::Value(value_type) => format!(" (result {})", value_type),
+                    };
+                    let mnemonic = match opcode {
+                        BLOCK => "block",
+                        LOOP => "loop",
+                        _ => "if",
+                    };
+                    self.note(start, format!("{}{}", mnemonic, block_type));
+                    self.depth += 1;
+                }
+                ELSE if self.depth > base => {
+                    self.decoder.read_u8()?;
+                    self.depth -= 1;
+                    self.note(start, "else".to_string());
+                    self.depth += 1;
+                }
+                ELSE => return Err(DecodeError::UnexpectedElse),
+                END => {
+                    self.decoder.read_u8()?;
+                    let is_last = self.depth == base;
+                    if !is_last {
+                        self.depth -= 1;
+                    }
+                    self.note(start, "end".to_string());
+                    if is_last {
+                        return Ok(());
+                    }
+                }
+                _ => {
+                    let instruction = Instruction::decode(&mut self.decoder)?;
+                    self.note(start, instruction.to_string());
+                }
+            }
+        }
+    }
+
+    fn module(&mut self) -> Result<(), DecodeError> {
+        // Custom sections start with a zero byte too, so the preamble is told
+        // apart by the whole magic number
+        if self.decoder.peek_u32() == Ok(MAGIC_NUMBER) {
+            let start = self.decoder.position();
+            self.decoder.read_u32()?;
+            self.note(start, "magic number \"\\0asm\"".to_string());
+            let start = self.decoder.position();
+            let version = self.decoder.read_u32()?;
+            if version != VERSION {
+                return Err(DecodeError::UnsupportedVersion(version));
+            }
+            self.note(start, format!("version {}", version));
+        }
+        while !self.decoder.is_empty() {
+            self.section()?;
+        }
+        Ok(())
+    }
+
+    fn section(&mut self) -> Result<(), DecodeError> {
+        let id = self.decoder.peek_u8()?;
+        self.header(format!("{} section", section_name(id)));
+        self.byte(|id| format!("section id {}", id))?;
+        let size = self.leb_u32("section size")? as usize;
+        let start = self.decoder.position();
+        self.depth += 1;
+        match id {
+            CUSTOM_SECTION => {
+                self.name("name")?;
+                while self.decoder.position() - start < size {
+                    let chunk_start = self.decoder.position();
+                    let remaining = size - (chunk_start - start);
+                    self.decoder.read_bytes(remaining.min(BYTES_PER_LINE))?;
+                    self.note(chunk_start, "payload".to_string());
+                }
+            }
+            TYPE_SECTION => {
+                let count = self.leb_u32("type count")?;
+                for i in 0..count {
+                    self.byte(|_| format!("function type id (type {})", i))?;
+                    let parameter_count = self.leb_u32("param count")?;
+                    for _ in 0..parameter_count {
+                        self.value_type("param")?;
+                    }
+                    let result_count = self.leb_u32("result count")?;
+                    for _ in 0..result_count {
+                        self.value_type("result")?;
+                    }
+                }
+            }
+            IMPORT_SECTION => {
+                let count = self.leb_u32("import count")?;
+                for _ in 0..count {
+                    self.name("module name")?;
+                    self.name("name")?;
+                    match self.byte(|kind| format!("import type id - {}", descriptor_name(kind)))? {
+                        TYPE_INDEX => self.leb_u32("type index").map(|_| ())?,
+                        TABLE_TYPE => self.table_type()?,
+                        MEMORY_TYPE => self.limits()?,
+                        GLOBAL_TYPE => self.global_type()?,
+                        kind => return Err(DecodeError::InvalidDescriptor(kind)),
+                    }
+                }
+            }
+            FUNCTION_SECTION => {
+                let count = self.leb_u32("function count")?;
+                for _ in 0..count {
+                    self.leb_u32("type index")?;
+                }
+            }
+            TABLE_SECTION => {
+                let count = self.leb_u32("table count")?;
+                for _ in 0..count {
+                    self.table_type()?;
+                }
+            }
+            MEMORY_SECTION => {
+                let count = self.leb_u32("memory count")?;
+                for _ in 0..count {
+                    self.limits()?;
+                }
+            }
+            GLOBAL_SECTION => {
+                let count = self.leb_u32("global count")?;
+                for i in 0..count {
+                    self.header(format!("global {}", i));
+                    self.global_type()?;
+                    self.expression()?;
+                }
+            }
+            EXPORT_SECTION => {
+                let count = self.leb_u32("export count")?;
+                for _ in 0..count {
+                    self.name("name")?;
+                    let kind =
+                        self.byte(|kind| format!("export type id - {}", descriptor_name(kind)))?;
+                    if kind > GLOBAL_INDEX {
+                        return Err(DecodeError::InvalidDescriptor(kind));
+                    }
+                    self.leb_u32("export index")?;
+                }
+            }
+            START_SECTION => {
+                self.leb_u32("function index")?;
+            }
+            ELEMENT_SECTION => {
+                let count = self.leb_u32("element count")?;
+                for i in 0..count {
+                    self.header(format!("element {}", i));
+                    self.leb_u32("table index")?;
+                    self.expression()?;
+                    let function_count = self.leb_u32("function index count")?;
+                    for _ in 0..function_count {
+                        self.leb_u32("function index")?;
+                    }
+                }
+            }
+            CODE_SECTION => {
+                let count = self.leb_u32("function count")?;
+                for i in 0..count {
+                    self.header(format!("function body {}", i));
+                    self.leb_u32("function byte count")?;
+                    let local_count = self.leb_u32("local count")?;
+                    for _ in 0..local_count {
+                        self.leb_u32("count")?;
+                        self.value_type("local")?;
+                    }
+                    self.depth += 1;
+                    self.expression()?;
+                    self.depth -= 1;
+                }
+            }
+            DATA_SECTION => {
+                let count = self.leb_u32("data count")?;
+                for i in 0..count {
+                    self.header(format!("data {}", i));
+                    self.leb_u32("memory index")?;
+                    self.expression()?;
+                    let length = self.leb_u32("byte vec length")? as usize;
+                    let data_start = self.decoder.position();
+                    while self.decoder.position() - data_start < length {
+                        let chunk_start = self.decoder.position();
+                        let remaining = length - (chunk_start - data_start);
+                        let chunk = self.decoder.read_bytes(remaining.min(BYTES_PER_LINE))?;
+                        self.note(chunk_start, escape_string(chunk));
+                    }
+                }
+            }
+            _ => return Err(DecodeError::UnknownSection(id)),
+        }
+        self.depth -= 1;
+        if self.decoder.position() - start != size {
+            return Err(DecodeError::SectionSizeMismatch(id));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        index::FunctionIndex,
+        section::{
+            export_section::{Export, ExportDescriptor, ExportSection},
+            Section,
+        },
+    };
+
+    #[test]
+    fn test_dump() {
+        let module: Module = r#"(module
+            (memory 1)
+            (func (param i32) (result i32)
+              (if (result i32) (local.get 0)
+                (then (i32.load offset=4 (i32.const 0)))
+                (else (i32.const -1))))
+            (data (i32.const 0) "hello, world"))"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            Dump::from_module(&module).unwrap().to_string(),
+            r#"0x0000: 00 61 73 6d             ; magic number "\0asm"
+0x0004: 01 00 00 00             ; version 1
+; type section
+0x0008: 01                      ; section id 1
+0x0009: 06                      ; section size 6
+0x000a: 01                      ;   type count 1
+0x000b: 60                      ;   function type id (type 0)
+0x000c: 01                      ;   param count 1
+0x000d: 7f                      ;   param i32
+0x000e: 01                      ;   result count 1
+0x000f: 7f                      ;   result i32
+; function section
+0x0010: 03                      ; section id 3
+0x0011: 02                      ; section size 2
+0x0012: 01                      ;   function count 1
+0x0013: 00                      ;   type index 0
+; memory section
+0x0014: 05                      ; section id 5
+0x0015: 03                      ; section size 3
+0x0016: 01                      ;   memory count 1
+0x0017: 00                      ;   max flag (off)
+0x0018: 01                      ;   min 1
+; code section
+0x0019: 0a                      ; section id 10
+0x001a: 11                      ; section size 17
+0x001b: 01                      ;   function count 1
+  ; function body 0
+0x001c: 0f                      ;   function byte count 15
+0x001d: 00                      ;   local count 0
+0x001e: 20 00                   ;     local.get 0
+0x0020: 04 7f                   ;     if (result i32)
+0x0022: 41 00                   ;       i32.const 0
+0x0024: 28 02 04                ;       i32.load offset=4
+0x0027: 05                      ;     else
+0x0028: 41 7f                   ;       i32.const -1
+0x002a: 0b                      ;     end
+0x002b: 0b                      ;     end
+; data section
+0x002c: 0b                      ; section id 11
+0x002d: 12                      ; section size 18
+0x002e: 01                      ;   data count 1
+  ; data 0
+0x002f: 00                      ;   memory index 0
+0x0030: 41 00                   ;   i32.const 0
+0x0032: 0b                      ;   end
+0x0033: 0c                      ;   byte vec length 12
+0x0034: 68 65 6c 6c 6f 2c 20 77 ;   "hello, w"
+0x003c: 6f 72 6c 64             ;   "orld"
+"#
+        );
+    }
+
+    #[test]
+    fn test_section_to_test_bytes() {
+        let mut encoder = WasmEncoder::new();
+        ExportSection(vec![Export::new(
+            "add",
+            ExportDescriptor::FunctionIndex(FunctionIndex(300)),
+        )])
+        .encode(&mut encoder);
+        assert_eq!(
+            Dump::new(encoder.as_slice()).unwrap().to_test_bytes(),
+            r#"// export section
+0x07, // section id 7
+0x08, // section size 8
+0x01, // export count 1
+0x03, // name length 3
+0x61, 0x64, 0x64, // name "add"
+0x00, // export type id - func
+0xac, 0x02, // export index 300
+"#
+        );
+    }
+
+    #[test]
+    fn test_custom_section() {
+        let mut encoder = WasmEncoder::new();
+        Section::Custom {
+            name: "a".to_string(),
+            payload: vec![0x01, 0x02],
+        }
+        .encode(&mut encoder);
+        assert_eq!(
+            Dump::new(encoder.as_slice()).unwrap().to_test_bytes(),
+            r#"// custom section
+0x00, // section id 0
+0x04, // section size 4
+0x01, // name length 1
+0x61, // name "a"
+0x01, 0x02, // payload
+"#
+        );
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let error = Dump::new(&[0x05, 0x04, 0x01, 0x00, 0x01, 0x00]).unwrap_err();
+        assert_eq!(error.error, DecodeError::SectionSizeMismatch(5));
+        assert_eq!(error.offset, 5);
+        assert_eq!(
+            error.dump.to_test_bytes(),
+            r#"// memory section
+0x05, // section id 5
+0x04, // section size 4
+0x01, // memory count 1
+0x00, // max flag (off)
+0x01, // min 1
+0x00, // error: section 5 doesn't match its declared size
+"#
+        );
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let bytes = [
+            0x0a, 0x07, 0x01, // code section with one function
+            0x05, 0x00, 0x41, 0x00, // local count 0, i32.const 0
+            0xff, 0x0b,
+        ];
+        let error = Dump::new(&bytes).unwrap_err();
+        assert_eq!(error.error, DecodeError::UnknownOpcode(0xff));
+        assert_eq!(error.offset, 7);
+        assert_eq!(error.to_string(), "unknown opcode 0xff at offset 0x7");
+        assert_eq!(error.dump.annotations().last().unwrap().range, 7..9);
+    }
+}
@@ -0,0 +1,403 @@
+/*!
+ * Minimal DWARF debug info, in the custom sections native debuggers read.
+ *
+ * `.debug_line` maps code addresses to the source spans of a `SourceMap`, and
+ * `.debug_info` has a compile unit with a subprogram for each function, which
+ * `.debug_abbrev` describes. Addresses are offsets from the start of the code
+ * section's contents, as DWARF for WebAssembly requires.
+ */
+use crate::{
+    decoder::{WasmDecode, WasmDecoder},
+    encoder::{WasmEncode, WasmEncoder},
+    module::Module,
+    section::{custom_section::CustomSection, name_section::NameSection},
+    source_map::{imported_function_count, SourceMap, SourceMapError},
+};
+
+const DWARF_VERSION: u16 = 4;
+
+// Tags, attributes and forms
+const DW_TAG_COMPILE_UNIT: u32 = 0x11;
+const DW_TAG_SUBPROGRAM: u32 = 0x2e;
+const DW_CHILDREN_NO: u8 = 0x00;
+const DW_CHILDREN_YES: u8 = 0x01;
+const DW_AT_NAME: u32 = 0x03;
+const DW_AT_STMT_LIST: u32 = 0x10;
+const DW_AT_LOW_PC: u32 = 0x11;
+const DW_AT_HIGH_PC: u32 = 0x12;
+const DW_AT_PRODUCER: u32 = 0x25;
+const DW_FORM_ADDR: u32 = 0x01;
+const DW_FORM_DATA4: u32 = 0x06;
+const DW_FORM_STRING: u32 = 0x08;
+const DW_FORM_SEC_OFFSET: u32 = 0x17;
+
+// Line number program opcodes
+const DW_LNS_COPY: u8 = 0x01;
+const DW_LNS_ADVANCE_PC: u8 = 0x02;
+const DW_LNS_ADVANCE_LINE: u8 = 0x03;
+const DW_LNS_SET_FILE: u8 = 0x04;
+const DW_LNS_SET_COLUMN: u8 = 0x05;
+const DW_LNE_END_SEQUENCE: u8 = 0x01;
+const DW_LNE_SET_ADDRESS: u8 = 0x02;
+
+const LINE_BASE: i8 = -5;
+const LINE_RANGE: u8 = 14;
+const OPCODE_BASE: u8 = 13;
+/** How many operands each standard opcode, from `DW_LNS_copy` on, takes */
+const STANDARD_OPCODE_LENGTHS: [u8; 12] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+const COMPILE_UNIT_ABBREVIATION: u32 = 1;
+const SUBPROGRAM_ABBREVIATION: u32 = 2;
+
+/** A DWARF section, like `.debug_info` */
+struct DwarfSection {
+    name: &'static str,
+    contents: Vec<u8>,
+}
+
+impl CustomSection for DwarfSection {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn encode_payload(&self, encoder: &mut WasmEncoder) -> u32 {
+        encoder.push_bytes(&self.contents)
+    }
+}
+
+/**
+ * Adds `.debug_abbrev`, `.debug_info` and `.debug_line` sections to `module`,
+ * replacing any it has. Subprograms are named from the module's `name`
+ * section if it has one.
+ */
+pub fn add_debug_sections(
+    module: &mut Module,
+    source_map: &SourceMap,
+) -> Result<(), SourceMapError> {
+    let mut encoder = WasmEncoder::new();
+    module.encode(&mut encoder);
+    let layout = encoder.code_layout();
+    let mappings = source_map.mappings(module, layout)?;
+
+    let mut files: Vec<&str> = vec![];
+    let mut rows = vec![];
+    for (offset, span) in mappings.iter() {
+        let file = match files.iter().position(|file| *file == span.file) {
+            Some(file) => file,
+            None => {
+                files.push(&span.file);
+                files.len() - 1
+            }
+        };
+        rows.push(LineRow {
+            address: offset - layout.start,
+            file: file as u32 + 1,
+            line: span.line,
+            column: span.column,
+        });
+    }
+
+    let names = module
+        .custom_section("name")
+        .and_then(|payload| NameSection::decode(&mut WasmDecoder::new(payload)).ok())
+        .unwrap_or_default();
+    let imported_functions = imported_function_count(module);
+    let subprograms: Vec<Subprogram> = layout
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(i, function)| {
+            let index = imported_functions + i as u32;
+            Subprogram {
+                name: names
+                    .functions
+                    .get(&index)
+                    .cloned()
+                    .unwrap_or_else(|| format!("wasm-function[{}]", index)),
+                low_pc: function.start - layout.start,
+                size: function.end - function.start,
+            }
+        })
+        .collect();
+
+    let compile_unit = CompileUnit {
+        name: files.first().copied().unwrap_or("<unknown>"),
+        code_size: layout.end - layout.start,
+        subprograms,
+    };
+    module.set_custom_section(&DwarfSection {
+        name: ".debug_abbrev",
+        contents: debug_abbrev(),
+    });
+    module.set_custom_section(&DwarfSection {
+        name: ".debug_info",
+        contents: compile_unit.debug_info(),
+    });
+    module.set_custom_section(&DwarfSection {
+        name: ".debug_line",
+        contents: debug_line(&files, &rows, compile_unit.code_size),
+    });
+    Ok(())
+}
+
+/** Prefixes a unit with its 32-bit DWARF length */
+fn unit(contents: WasmEncoder) -> Vec<u8> {
+    let mut encoder = WasmEncoder::new();
+    encoder.push_u32(contents.as_slice().len() as u32);
+    encoder.push_bytes(contents.as_slice());
+    encoder.as_slice().to_vec()
+}
+
+fn push_c_string(encoder: &mut WasmEncoder, string: &str) -> u32 {
+    encoder.push_bytes(string.as_bytes()) + encoder.push_u8(0)
+}
+
+fn debug_abbrev() -> Vec<u8> {
+    let mut encoder = WasmEncoder::new();
+    let abbreviations = [
+        (
+            COMPILE_UNIT_ABBREVIATION,
+            DW_TAG_COMPILE_UNIT,
+            DW_CHILDREN_YES,
+            &[
+                (DW_AT_PRODUCER, DW_FORM_STRING),
+                (DW_AT_NAME, DW_FORM_STRING),
+                (DW_AT_STMT_LIST, DW_FORM_SEC_OFFSET),
+                (DW_AT_LOW_PC, DW_FORM_ADDR),
+                (DW_AT_HIGH_PC, DW_FORM_DATA4),
+            ][..],
+        ),
+        (
+            SUBPROGRAM_ABBREVIATION,
+            DW_TAG_SUBPROGRAM,
+            DW_CHILDREN_NO,
+            &[
+                (DW_AT_NAME, DW_FORM_STRING),
+                (DW_AT_LOW_PC, DW_FORM_ADDR),
+                (DW_AT_HIGH_PC, DW_FORM_DATA4),
+            ][..],
+        ),
+    ];
+    for (code, tag, children, attributes) in abbreviations.iter() {
+        encoder.push_leb_u32(*code);
+        encoder.push_leb_u32(*tag);
+        encoder.push_u8(*children);
+        for (attribute, form) in attributes.iter() {
+            encoder.push_leb_u32(*attribute);
+            encoder.push_leb_u32(*form);
+        }
+        encoder.push_u16(0);
+    }
+    encoder.push_u8(0);
+    encoder.as_slice().to_vec()
+}
+
+struct Subprogram {
+    name: String,
+    low_pc: u32,
+    size: u32,
+}
+
+struct CompileUnit<'a> {
+    name: &'a str,
+    code_size: u32,
+    subprograms: Vec<Subprogram>,
+}
+
+impl<'a> CompileUnit<'a> {
+    fn debug_info(&self) -> Vec<u8> {
+        let mut encoder = WasmEncoder::new();
+        encoder.push_u16(DWARF_VERSION);
+        encoder.push_u32(0); // .debug_abbrev offset
+        encoder.push_u8(4); // address size
+
+        encoder.push_leb_u32(COMPILE_UNIT_ABBREVIATION);
+        push_c_string(
+            &mut encoder,
+            concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")),
+        );
+        push_c_string(&mut encoder, self.name);
+        encoder.push_u32(0); // .debug_line offset
+        encoder.push_u32(0); // low_pc
+        encoder.push_u32(self.code_size);
+        for subprogram in self.subprograms.iter() {
+            encoder.push_leb_u32(SUBPROGRAM_ABBREVIATION);
+            push_c_string(&mut encoder, &subprogram.name);
+            encoder.push_u32(subprogram.low_pc);
+            encoder.push_u32(subprogram.size);
+        }
+        encoder.push_u8(0); // end of the compile unit's children
+        unit(encoder)
+    }
+}
+
+/** A row of the line table, with 1-based files, lines and columns */
+struct LineRow {
+    address: u32,
+    file: u32,
+    line: u32,
+    column: u32,
+}
+
+/**
+ * A line program with a single sequence over the code section, which only
+ * uses standard opcodes for simplicity.
+ */
+fn debug_line(files: &[&str], rows: &[LineRow], code_size: u32) -> Vec<u8> {
+    let mut header = WasmEncoder::new();
+    header.push_u8(1); // minimum instruction length
+    header.push_u8(1); // maximum operations per instruction
+    header.push_u8(1); // default is_stmt
+    header.push_u8(LINE_BASE as u8);
+    header.push_u8(LINE_RANGE);
+    header.push_u8(OPCODE_BASE);
+    header.push_bytes(&STANDARD_OPCODE_LENGTHS);
+    header.push_u8(0); // no include directories
+    for file in files.iter() {
+        push_c_string(&mut header, file);
+        header.push_leb_u32(0); // directory
+        header.push_leb_u32(0); // modification time
+        header.push_leb_u32(0); // length
+    }
+    header.push_u8(0);
+
+    let mut program = WasmEncoder::new();
+    program.push_u8(0); // extended opcode
+    program.push_leb_u32(5);
+    program.push_u8(DW_LNE_SET_ADDRESS);
+    program.push_u32(0);
+    let (mut address, mut file, mut line, mut column) = (0, 1, 1, 0);
+    for row in rows.iter() {
+        if row.file != file {
+            program.push_u8(DW_LNS_SET_FILE);
+            program.push_leb_u32(row.file);
+            file = row.file;
+        }
+        if row.line != line {
+            program.push_u8(DW_LNS_ADVANCE_LINE);
+            program.push_leb_i64(row.line as i64 - line as i64);
+            line = row.line;
+        }
+        if row.column != column {
+            program.push_u8(DW_LNS_SET_COLUMN);
+            program.push_leb_u32(row.column);
+            column = row.column;
+        }
+        if row.address != address {
+            program.push_u8(DW_LNS_ADVANCE_PC);
+            program.push_leb_u32(row.address - address);
+            address = row.address;
+        }
+        program.push_u8(DW_LNS_COPY);
+    }
+    if code_size > address {
+        program.push_u8(DW_LNS_ADVANCE_PC);
+        program.push_leb_u32(code_size - address);
+    }
+    program.push_u8(0); // extended opcode
+    program.push_leb_u32(1);
+    program.push_u8(DW_LNE_END_SEQUENCE);
+
+    let mut contents = WasmEncoder::new();
+    contents.push_u16(DWARF_VERSION);
+    contents.push_u32(header.as_slice().len() as u32);
+    contents.push_bytes(header.as_slice());
+    contents.push_bytes(program.as_slice());
+    unit(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        index::FunctionIndex,
+        section::{name_section::NameSection, Section},
+        source_map::SourceSpan,
+    };
+
+    fn custom_section<'a>(module: &'a Module, name: &str) -> &'a [u8] {
+        module.custom_section(name).unwrap()
+    }
+
+    #[test]
+    fn test_debug_sections() {
+        let mut module: Module = r#"(module
+            (import "env" "f" (func))
+            (func (result i32) (i32.const 1))
+            (func (drop (i32.const 2))))"#
+            .parse()
+            .unwrap();
+        let mut names = NameSection::new();
+        names.name_function(FunctionIndex(1), "one");
+        module.set_custom_section(&names);
+
+        let mut source_map = SourceMap::new();
+        source_map.add_span(FunctionIndex(1), 0, SourceSpan::new("a.src", 3, 5));
+        source_map.add_span(FunctionIndex(2), 0, SourceSpan::new("a.src", 2, 1));
+        source_map.add_span(FunctionIndex(2), 1, SourceSpan::new("b.src", 2, 1));
+        add_debug_sections(&mut module, &source_map).unwrap();
+        add_debug_sections(&mut module, &source_map).unwrap();
+        let custom_count = module
+            .0
+            .iter()
+            .filter(|section| matches!(section, Section::Custom { .. }))
+            .count();
+        assert_eq!(custom_count, 4);
+
+        // The code section's contents are a function count, then bodies of
+        // 5 and 6 bytes that each start with their size and local count
+        let producer = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+        let mut unit = vec![
+            0x04, 0x00, // version
+            0x00, 0x00, 0x00, 0x00, // .debug_abbrev offset
+            0x04, // address size
+            0x01, // compile unit
+        ];
+        unit.extend(producer.bytes());
+        unit.extend(&[
+            0x00, // producer
+            b'a', b'.', b's', b'r', b'c', 0x00, // name
+            0x00, 0x00, 0x00, 0x00, // .debug_line offset
+            0x00, 0x00, 0x00, 0x00, // low_pc
+            0x0c, 0x00, 0x00, 0x00, // code size
+            0x02, // subprogram
+            b'o', b'n', b'e', 0x00, // name
+            0x01, 0x00, 0x00, 0x00, // low_pc
+            0x05, 0x00, 0x00, 0x00, // size
+            0x02, // subprogram
+            b'w', b'a', b's', b'm', b'-', b'f', b'u', b'n', b'c', b't', b'i', b'o', b'n', b'[',
+            b'2', b']', 0x00, // name
+            0x06, 0x00, 0x00, 0x00, // low_pc
+            0x06, 0x00, 0x00, 0x00, // size
+            0x00, // end of children
+        ]);
+        let debug_info = custom_section(&module, ".debug_info");
+        assert_eq!(debug_info[..4], (unit.len() as u32).to_le_bytes()); // unit length
+        assert_eq!(&debug_info[4..], unit.as_slice());
+        let line = custom_section(&module, ".debug_line");
+        let header_length = u32::from_le_bytes([line[6], line[7], line[8], line[9]]) as usize;
+        assert_eq!(
+            &line[10 + header_length..],
+            &[
+                0x00, 0x05, 0x02, 0x00, 0x00, 0x00, 0x00, // set address 0
+                0x03, 0x02, // advance line to 3
+                0x05, 0x05, // set column 5
+                0x02, 0x03, // advance pc to 3, i32.const 1
+                0x01, // copy
+                0x03, 0x7f, // advance line to 2
+                0x05, 0x01, // set column 1
+                0x02, 0x05, // advance pc to 8, i32.const 2
+                0x01, // copy
+                0x04, 0x02, // set file b.src
+                0x02, 0x02, // advance pc to 10, drop
+                0x01, // copy
+                0x02, 0x02, // advance pc to the end of the code
+                0x00, 0x01, 0x01, // end sequence
+            ][..]
+        );
+        assert_eq!(
+            custom_section(&module, ".debug_abbrev")[..6],
+            [0x01, 0x11, 0x01, 0x25, 0x08, 0x03]
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::{f32, f64, i32, u32};
+use crate::source_map::{CodeLayout, FunctionLayout};
 
 pub trait WasmEncode {
     /** Returns number of bytes encoded */
@@ -15,19 +15,70 @@ impl<T: WasmEncode> WasmEncode for Vec<T> {
     }
 }
 
+#[derive(Default)]
 pub struct WasmEncoder {
     bytes: Vec<u8>,
+    /** Where the code section, its function bodies and their instructions went */
+    code: CodeLayout,
+    /** Whether a function body is being encoded, so its instructions are recorded */
+    in_function: bool,
 }
 
 impl WasmEncoder {
     pub fn new() -> Self {
-        WasmEncoder { bytes: vec![] }
+        WasmEncoder::default()
     }
 
     pub fn as_slice(&self) -> &[u8] {
         self.bytes.as_slice()
     }
 
+    /**
+     * Where the last code section encoded, and each function body and
+     * instruction in it, are in the bytes. Instructions are numbered the way
+     * source map spans number them.
+     */
+    pub(crate) fn code_layout(&self) -> &CodeLayout {
+        &self.code
+    }
+
+    /** Starts recording a code section, just after its size */
+    pub(crate) fn begin_code(&mut self) {
+        self.code = CodeLayout {
+            start: self.bytes.len() as u32,
+            ..CodeLayout::default()
+        };
+    }
+
+    pub(crate) fn end_code(&mut self) {
+        self.code.end = self.bytes.len() as u32;
+    }
+
+    /** Starts recording a function body, before its byte count */
+    pub(crate) fn begin_function(&mut self) {
+        self.code.functions.push(FunctionLayout {
+            start: self.bytes.len() as u32,
+            ..FunctionLayout::default()
+        });
+        self.in_function = true;
+    }
+
+    pub(crate) fn end_function(&mut self) {
+        if let Some(function) = self.code.functions.last_mut() {
+            function.end = self.bytes.len() as u32;
+        }
+        self.in_function = false;
+    }
+
+    /** Records that an instruction in the function body starts here */
+    pub(crate) fn mark_instruction(&mut self) {
+        let offset = self.bytes.len() as u32;
+        match self.code.functions.last_mut() {
+            Some(function) if self.in_function => function.instructions.push(offset),
+            _ => {}
+        }
+    }
+
     /**
      * Sections in Wasm require the length (in bytes) of the section to come
      * before the section data. This function allows for setting the length as
@@ -42,6 +93,9 @@ impl WasmEncoder {
             splice_index..splice_index + 1,
             encoder.bytes.iter().cloned(),
         );
+        // What was recorded after the placeholder moves along with it
+        let inserted = encoder.bytes.len() as u32 - 1;
+        self.code.shift(splice_index as u32, inserted);
         encoder.bytes.len() as u32
     }
 
@@ -64,6 +118,17 @@ impl WasmEncoder {
         4
     }
 
+    pub fn push_f32(&mut self, value: f32) -> u32 {
+        self.push_u32(value.to_bits())
+    }
+
+    pub fn push_f64(&mut self, value: f64) -> u32 {
+        for byte in value.to_bits().to_le_bytes().iter() {
+            self.bytes.push(*byte);
+        }
+        8
+    }
+
     pub fn push_leb_u32(&mut self, mut value: u32) -> u32 {
         let mut byte_count = 0;
         loop {
@@ -154,13 +219,36 @@ impl WasmEncoder {
         byte_count
     }
 
+    /**
+     * Pushes a `u32` as a LEB128 padded to its full five bytes, so that a
+     * linker can overwrite it with any other value in place.
+     */
+    pub fn push_padded_leb_u32(&mut self, value: u32) -> u32 {
+        for shift in (0..28).step_by(7) {
+            self.bytes.push((value >> shift) as u8 & 0x7f | 0x80);
+        }
+        self.bytes.push((value >> 28) as u8 & 0x0f);
+        5
+    }
+
+    /** Like `push_padded_leb_u32`, for a signed LEB128 */
+    pub fn push_padded_leb_i32(&mut self, value: i32) -> u32 {
+        for shift in (0..28).step_by(7) {
+            self.bytes.push((value >> shift) as u8 & 0x7f | 0x80);
+        }
+        self.bytes.push((value >> 28) as u8 & 0x7f);
+        5
+    }
+
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> u32 {
+        self.bytes.extend_from_slice(bytes);
+        bytes.len() as u32
+    }
+
+    /** Pushes a name, which is its UTF-8 bytes after their LEB128 length */
     pub fn push_str(&mut self, string: &str) -> u32 {
         let bytestring = string.as_bytes();
-        self.bytes.push(bytestring.len() as u8);
-        for byte in bytestring.iter() {
-            self.bytes.push(*byte);
-        }
-        bytestring.len() as u32 + 1
+        self.push_leb_u32(bytestring.len() as u32) + self.push_bytes(bytestring)
     }
 }
 
@@ -174,11 +262,12 @@ pub fn assert_encoding_eq<T: WasmEncode>(item: T, expected_bytes: &[u8]) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::decoder::WasmDecoder;
 
     #[test]
     fn test_leb_u32_min_encoding() {
         let mut encoder = WasmEncoder::new();
-        let byte_count = encoder.push_leb_u32(u32::min_value());
+        let byte_count = encoder.push_leb_u32(u32::MIN);
         let expected_bytes = [0x00];
 
         assert_eq!(encoder.as_slice(), expected_bytes);
@@ -188,7 +277,7 @@ mod tests {
     #[test]
     fn test_leb_u32_max_encoding() {
         let mut encoder = WasmEncoder::new();
-        let byte_count = encoder.push_leb_u32(u32::max_value());
+        let byte_count = encoder.push_leb_u32(u32::MAX);
         let expected_bytes = [0xff, 0xff, 0xff, 0xff, 0x0f];
 
         assert_eq!(encoder.as_slice(), expected_bytes);
@@ -208,8 +297,7 @@ mod tests {
     #[test]
     fn test_leb_i32_min_encoding() {
         let mut encoder = WasmEncoder::new();
-        let byte_count = encoder.push_leb_i32(i32::min_value());
-        println!("{}", i32::min_value());
+        let byte_count = encoder.push_leb_i32(i32::MIN);
         let expected_bytes = [0x80, 0x80, 0x80, 0x80, 0x78];
 
         assert_eq!(encoder.as_slice(), expected_bytes);
@@ -219,7 +307,7 @@ mod tests {
     #[test]
     fn test_leb_i32_max_encoding() {
         let mut encoder = WasmEncoder::new();
-        let byte_count = encoder.push_leb_i32(i32::max_value()); //2147483647
+        let byte_count = encoder.push_leb_i32(i32::MAX); //2147483647
         let expected_bytes = [0xff, 0xff, 0xff, 0xff, 0x07];
 
         assert_eq!(encoder.as_slice(), expected_bytes);
@@ -245,4 +333,51 @@ mod tests {
         assert_eq!(encoder.as_slice(), expected_bytes);
         assert_eq!(byte_count, expected_bytes.len() as u32);
     }
+
+    #[test]
+    fn test_float_encoding() {
+        let mut encoder = WasmEncoder::new();
+        let byte_count = encoder.push_f32(1.5) + encoder.push_f64(-2.0);
+        let expected_bytes = [
+            0x00, 0x00, 0xc0, 0x3f, // 1.5
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, // -2.0
+        ];
+
+        assert_eq!(encoder.as_slice(), expected_bytes);
+        assert_eq!(byte_count, expected_bytes.len() as u32);
+    }
+
+    #[test]
+    fn test_long_str_encoding() {
+        let mut encoder = WasmEncoder::new();
+        let string = "a".repeat(200);
+        let byte_count = encoder.push_str(&string);
+
+        assert_eq!(&encoder.as_slice()[..2], [0xc8, 0x01]);
+        assert_eq!(byte_count, 202);
+    }
+
+    #[test]
+    fn test_padded_leb_encoding() {
+        let mut encoder = WasmEncoder::new();
+        assert_eq!(encoder.push_padded_leb_u32(3), 5);
+        assert_eq!(encoder.push_padded_leb_u32(u32::MAX), 5);
+        assert_eq!(encoder.push_padded_leb_i32(-2), 5);
+        assert_eq!(encoder.push_padded_leb_i32(i32::MIN), 5);
+        assert_eq!(
+            encoder.as_slice(),
+            &[
+                0x83, 0x80, 0x80, 0x80, 0x00, // 3
+                0xff, 0xff, 0xff, 0xff, 0x0f, // u32::MAX
+                0xfe, 0xff, 0xff, 0xff, 0x7f, // -2
+                0x80, 0x80, 0x80, 0x80, 0x78, // i32::MIN
+            ]
+        );
+
+        let mut decoder = WasmDecoder::new(encoder.as_slice());
+        assert_eq!(decoder.read_leb_u32(), Ok(3));
+        assert_eq!(decoder.read_leb_u32(), Ok(u32::MAX));
+        assert_eq!(decoder.read_leb_i32(), Ok(-2));
+        assert_eq!(decoder.read_leb_i32(), Ok(i32::MIN));
+    }
 }
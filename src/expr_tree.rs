@@ -0,0 +1,1196 @@
+/*!
+ * A structured expression tree that lowers to a flat `Expression`.
+ *
+ * Operators are untyped (`BinaryOp::Add` rather than `I32Add`); the typed
+ * instruction is chosen from the operand types during lowering, which is also
+ * where type errors are reported.
+ */
+use std::{error, fmt, ops::Range};
+
+use crate::{
+    expression::{BlockType, Expression, Instruction, MemoryArguments},
+    function_type::{FunctionType, ValueType},
+    index::{FunctionIndex, GlobalIndex, LabelIndex, LocalIndex, TypeIndex},
+    section::global_section::{GlobalType, Mutability},
+    source_map::{instruction_count, SourceSpan},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Literal {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Literal {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Literal::I32(_) => ValueType::I32,
+            Literal::I64(_) => ValueType::I64,
+            Literal::F32(_) => ValueType::F32,
+            Literal::F64(_) => ValueType::F64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+    // Integer
+    Eqz,
+    Clz,
+    Ctz,
+    PopCnt,
+    // Float
+    Abs,
+    Neg,
+    Ceil,
+    Floor,
+    Trunc,
+    Nearest,
+    Sqrt,
+}
+
+/**
+ * Binary operators follow the spec's naming: integer operators that depend on
+ * signedness have `S` and `U` variants, while the float operators don't.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    // Integer and Float
+    Add,
+    Sub,
+    Mul,
+    Eq,
+    Ne,
+    // Integer
+    DivS,
+    DivU,
+    RemS,
+    RemU,
+    And,
+    Or,
+    Xor,
+    Shl,
+    ShrS,
+    ShrU,
+    Rotl,
+    Rotr,
+    LtS,
+    LtU,
+    GtS,
+    GtU,
+    LeS,
+    LeU,
+    GeS,
+    GeU,
+    // Float
+    Div,
+    Min,
+    Max,
+    CopySign,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signedness {
+    Signed,
+    Unsigned,
+}
+
+/** The width of a narrow memory access */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Width {
+    Bits8,
+    Bits16,
+    Bits32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Const(Literal),
+    Local(LocalIndex),
+    SetLocal(LocalIndex, Box<Expr>),
+    /** Binds a value to a local for the duration of the body */
+    Let(LocalIndex, Box<Expr>, Box<Expr>),
+    Global(GlobalIndex),
+    SetGlobal(GlobalIndex, Box<Expr>),
+
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /**
+     * Converts between numeric types. The signedness is ignored by the
+     * conversions that only have one form (wrap, demote and promote).
+     */
+    Convert(ValueType, Signedness, Box<Expr>),
+    Reinterpret(ValueType, Box<Expr>),
+
+    Load(ValueType, MemoryArguments, Box<Expr>),
+    /** Loads fewer bits than the value type holds and extends them */
+    LoadExtend(ValueType, Width, Signedness, MemoryArguments, Box<Expr>),
+    /** Stores the value (second) at the address (first) */
+    Store(MemoryArguments, Box<Expr>, Box<Expr>),
+    /** Stores only the low bits of the value */
+    StoreWrap(Width, MemoryArguments, Box<Expr>, Box<Expr>),
+    MemorySize,
+    MemoryGrow(Box<Expr>),
+
+    Call(FunctionIndex, Vec<Expr>),
+    /** Calls the table slot given by the last operand */
+    CallIndirect(TypeIndex, Vec<Expr>, Box<Expr>),
+
+    /** Evaluates each expression in turn, dropping all but the last value */
+    Seq(Vec<Expr>),
+    Block(Box<Expr>),
+    Loop(Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+    /** Branches out of the given label, carrying an optional value */
+    Break(LabelIndex, Option<Box<Expr>>),
+    BreakIf(LabelIndex, Box<Expr>),
+    Return(Option<Box<Expr>>),
+    /** Picks the first or second operand based on the third */
+    Select(Box<Expr>, Box<Expr>, Box<Expr>),
+    Unreachable,
+    /**
+     * Marks where an expression came from. Its instructions get the span,
+     * unless a `Spanned` expression inside gives them their own.
+     */
+    Spanned(SourceSpan, Box<Expr>),
+}
+
+/**
+ * The module and function environment that an `Expr` is lowered in. Functions
+ * are listed by their type index, covering imported functions as well.
+ */
+#[derive(Default)]
+pub struct Context<'a> {
+    pub types: &'a [FunctionType],
+    pub functions: &'a [TypeIndex],
+    pub globals: &'a [GlobalType],
+    pub locals: &'a [ValueType],
+    pub results: &'a [ValueType],
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TypeError {
+    Mismatch {
+        expected: Option<ValueType>,
+        found: Option<ValueType>,
+    },
+    MissingValue,
+    UnsupportedOperand {
+        operator: String,
+        operand: ValueType,
+    },
+    ArgumentCount {
+        expected: usize,
+        found: usize,
+    },
+    MultipleResults,
+    ImmutableGlobal(GlobalIndex),
+    UnknownLocal(LocalIndex),
+    UnknownGlobal(GlobalIndex),
+    UnknownFunction(FunctionIndex),
+    UnknownType(TypeIndex),
+    UnknownLabel(LabelIndex),
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeError::Mismatch { expected, found } => {
+                write!(f, "expected {:?}, found {:?}", expected, found)
+            }
+            TypeError::MissingValue => write!(f, "expected a value, found none"),
+            TypeError::UnsupportedOperand { operator, operand } => {
+                write!(f, "{} is not defined for {:?}", operator, operand)
+            }
+            TypeError::ArgumentCount { expected, found } => {
+                write!(f, "expected {} arguments, found {}", expected, found)
+            }
+            TypeError::MultipleResults => write!(f, "multiple results are not supported"),
+            TypeError::ImmutableGlobal(index) => write!(f, "global {} is immutable", index.0),
+            TypeError::UnknownLocal(index) => write!(f, "unknown local {}", index.0),
+            TypeError::UnknownGlobal(index) => write!(f, "unknown global {}", index.0),
+            TypeError::UnknownFunction(index) => write!(f, "unknown function {}", index.0),
+            TypeError::UnknownType(index) => write!(f, "unknown type {}", index.0),
+            TypeError::UnknownLabel(index) => write!(f, "unknown label {}", index.0),
+        }
+    }
+}
+
+impl error::Error for TypeError {}
+
+/**
+ * The type an expression leaves on the stack. `Unreachable` is the type of
+ * expressions that never complete, and is compatible with every other type.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Type {
+    Empty,
+    Value(ValueType),
+    Unreachable,
+}
+
+impl Type {
+    fn from_option(value_type: Option<ValueType>) -> Type {
+        match value_type {
+            Some(value_type) => Type::Value(value_type),
+            None => Type::Empty,
+        }
+    }
+
+    fn as_option(self) -> Option<ValueType> {
+        match self {
+            Type::Value(value_type) => Some(value_type),
+            _ => None,
+        }
+    }
+}
+
+impl Expr {
+    /**
+     * Lowers the expression as the body of a function, checking that it
+     * produces the context's results.
+     */
+    pub fn lower(&self, context: &Context) -> Result<Expression, TypeError> {
+        self.lower_with_spans(context)
+            .map(|(expression, _)| expression)
+    }
+
+    /**
+     * Lowers the expression like `lower`, along with the source span of each
+     * instruction that has one, by its number in the order instructions are
+     * encoded. These go in a `SourceMap`.
+     */
+    pub fn lower_with_spans(
+        &self,
+        context: &Context,
+    ) -> Result<(Expression, Vec<(u32, SourceSpan)>), TypeError> {
+        if context.results.len() > 1 {
+            return Err(TypeError::MultipleResults);
+        }
+        let mut lowering = Lowering::new(context);
+        let result_type = lowering.lower(self)?;
+        expect(result_type, context.results.first().copied())?;
+
+        // Spans are listed innermost first, so the first span wins
+        let mut spans: Vec<Option<&SourceSpan>> =
+            vec![None; instruction_count(&lowering.instructions) as usize];
+        for (range, span) in lowering.spans.iter() {
+            for instruction_span in spans[range.start as usize..range.end as usize].iter_mut() {
+                instruction_span.get_or_insert(span);
+            }
+        }
+        let spans = spans
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, span)| span.map(|span| (i as u32, span.clone())))
+            .collect();
+        Ok((Expression(lowering.instructions), spans))
+    }
+
+    /**
+     * Lowers the expression onto the end of `instructions` without checking
+     * its result, which is returned instead.
+     */
+    pub fn lower_into(
+        &self,
+        context: &Context,
+        instructions: &mut Vec<Instruction>,
+    ) -> Result<Option<ValueType>, TypeError> {
+        let mut lowering = Lowering::new(context);
+        let result_type = lowering.lower(self)?;
+        instructions.append(&mut lowering.instructions);
+        Ok(result_type.as_option())
+    }
+}
+
+fn expect(found: Type, expected: Option<ValueType>) -> Result<(), TypeError> {
+    match found {
+        Type::Unreachable => Ok(()),
+        _ if found == Type::from_option(expected) => Ok(()),
+        _ => Err(TypeError::Mismatch {
+            expected,
+            found: found.as_option(),
+        }),
+    }
+}
+
+fn unify(first: Type, second: Type) -> Result<Type, TypeError> {
+    match (first, second) {
+        (Type::Unreachable, other) | (other, Type::Unreachable) => Ok(other),
+        _ if first == second => Ok(first),
+        _ => Err(TypeError::Mismatch {
+            expected: first.as_option(),
+            found: second.as_option(),
+        }),
+    }
+}
+
+fn unsupported(operator: impl fmt::Debug, operand: ValueType) -> TypeError {
+    TypeError::UnsupportedOperand {
+        operator: format!("{:?}", operator),
+        operand,
+    }
+}
+
+struct Lowering<'a, 'b> {
+    context: &'a Context<'b>,
+    /**
+     * The types carried by branches to each enclosing label, innermost last.
+     * A label's type is unknown until its first branch is seen.
+     */
+    labels: Vec<Option<Type>>,
+    instructions: Vec<Instruction>,
+    /**
+     * The spans of `Spanned` expressions, innermost first, over the numbers
+     * of the instructions they lowered to within `instructions`.
+     */
+    spans: Vec<(Range<u32>, SourceSpan)>,
+    /** The spans of bodies lowered by `lower_labelled`, until `push_block` */
+    body_spans: Vec<Vec<(Range<u32>, SourceSpan)>>,
+}
+
+impl<'a, 'b> Lowering<'a, 'b> {
+    fn new(context: &'a Context<'b>) -> Lowering<'a, 'b> {
+        Lowering {
+            context,
+            labels: vec![],
+            instructions: vec![],
+            spans: vec![],
+            body_spans: vec![],
+        }
+    }
+
+    fn lower(&mut self, expr: &Expr) -> Result<Type, TypeError> {
+        use Instruction::*;
+        match expr {
+            Expr::Const(literal) => {
+                self.instructions.push(match *literal {
+                    Literal::I32(value) => I32Const(value),
+                    Literal::I64(value) => I64Const(value),
+                    Literal::F32(value) => F32Const(value),
+                    Literal::F64(value) => F64Const(value),
+                });
+                Ok(Type::Value(literal.value_type()))
+            }
+            Expr::Local(local_index) => {
+                let value_type = self.local_type(*local_index)?;
+                self.instructions.push(LocalGet(*local_index));
+                Ok(Type::Value(value_type))
+            }
+            Expr::SetLocal(local_index, value) => {
+                self.lower_set_local(*local_index, value)?;
+                Ok(Type::Empty)
+            }
+            Expr::Let(local_index, value, body) => {
+                self.lower_set_local(*local_index, value)?;
+                self.lower(body)
+            }
+            Expr::Global(global_index) => {
+                let global_type = self.global_type(*global_index)?;
+                self.instructions.push(GlobalGet(*global_index));
+                Ok(Type::Value(global_type.value_type))
+            }
+            Expr::SetGlobal(global_index, value) => {
+                let global_type = self.global_type(*global_index)?;
+                if global_type.mutability == Mutability::Const {
+                    return Err(TypeError::ImmutableGlobal(*global_index));
+                }
+                let value_type = self.lower(value)?;
+                expect(value_type, Some(global_type.value_type))?;
+                self.instructions.push(GlobalSet(*global_index));
+                Ok(Type::Empty)
+            }
+
+            Expr::Unary(op, operand) => {
+                let operand_type = self.lower_operand(operand)?;
+                let (instruction, result_type) = unary_instruction(*op, operand_type)?;
+                self.instructions.push(instruction);
+                Ok(Type::Value(result_type))
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs_type = self.lower_value(lhs)?;
+                let rhs_type = self.lower_value(rhs)?;
+                let operand_type = match unify(lhs_type, rhs_type)? {
+                    Type::Value(value_type) => value_type,
+                    _ => ValueType::I32,
+                };
+                let (instruction, result_type) = binary_instruction(*op, operand_type)?;
+                self.instructions.push(instruction);
+                Ok(Type::Value(result_type))
+            }
+            Expr::Convert(to, signedness, operand) => {
+                let from = self.lower_operand(operand)?;
+                if let Some(instruction) = convert_instruction(from, *to, *signedness) {
+                    self.instructions.push(instruction);
+                }
+                Ok(Type::Value(*to))
+            }
+            Expr::Reinterpret(to, operand) => {
+                let from = self.lower_operand(operand)?;
+                self.instructions.push(match (from, to) {
+                    (ValueType::F32, ValueType::I32) => I32ReinterpretF32,
+                    (ValueType::F64, ValueType::I64) => I64ReinterpretF64,
+                    (ValueType::I32, ValueType::F32) => F32ReinterpretI32,
+                    (ValueType::I64, ValueType::F64) => F64ReinterpretI64,
+                    _ => {
+                        return Err(TypeError::UnsupportedOperand {
+                            operator: "Reinterpret".to_owned(),
+                            operand: from,
+                        })
+                    }
+                });
+                Ok(Type::Value(*to))
+            }
+
+            Expr::Load(value_type, mem_args, address) => {
+                self.lower_address(address)?;
+                self.instructions.push(match value_type {
+                    ValueType::I32 => I32Load(*mem_args),
+                    ValueType::I64 => I64Load(*mem_args),
+                    ValueType::F32 => F32Load(*mem_args),
+                    ValueType::F64 => F64Load(*mem_args),
+                });
+                Ok(Type::Value(*value_type))
+            }
+            Expr::LoadExtend(value_type, width, signedness, mem_args, address) => {
+                self.lower_address(address)?;
+                let mem_args = *mem_args;
+                self.instructions
+                    .push(match (value_type, width, signedness) {
+                        (ValueType::I32, Width::Bits8, Signedness::Signed) => I32Load8S(mem_args),
+                        (ValueType::I32, Width::Bits8, Signedness::Unsigned) => I32Load8U(mem_args),
+                        (ValueType::I32, Width::Bits16, Signedness::Signed) => I32Load16S(mem_args),
+                        (ValueType::I32, Width::Bits16, Signedness::Unsigned) => {
+                            I32Load16U(mem_args)
+                        }
+                        (ValueType::I64, Width::Bits8, Signedness::Signed) => I64Load8S(mem_args),
+                        (ValueType::I64, Width::Bits8, Signedness::Unsigned) => I64Load8U(mem_args),
+                        (ValueType::I64, Width::Bits16, Signedness::Signed) => I64Load16S(mem_args),
+                        (ValueType::I64, Width::Bits16, Signedness::Unsigned) => {
+                            I64Load16U(mem_args)
+                        }
+                        (ValueType::I64, Width::Bits32, Signedness::Signed) => I64Load32S(mem_args),
+                        (ValueType::I64, Width::Bits32, Signedness::Unsigned) => {
+                            I64Load32U(mem_args)
+                        }
+                        _ => return Err(unsupported(width, *value_type)),
+                    });
+                Ok(Type::Value(*value_type))
+            }
+            Expr::Store(mem_args, address, value) => {
+                self.lower_address(address)?;
+                let value_type = self.lower_operand(value)?;
+                self.instructions.push(match value_type {
+                    ValueType::I32 => I32Store(*mem_args),
+                    ValueType::I64 => I64Store(*mem_args),
+                    ValueType::F32 => F32Store(*mem_args),
+                    ValueType::F64 => F64Store(*mem_args),
+                });
+                Ok(Type::Empty)
+            }
+            Expr::StoreWrap(width, mem_args, address, value) => {
+                self.lower_address(address)?;
+                let value_type = self.lower_operand(value)?;
+                let mem_args = *mem_args;
+                self.instructions.push(match (value_type, width) {
+                    (ValueType::I32, Width::Bits8) => I32Store8(mem_args),
+                    (ValueType::I32, Width::Bits16) => I32Store16(mem_args),
+                    (ValueType::I64, Width::Bits8) => I64Store8(mem_args),
+                    (ValueType::I64, Width::Bits16) => I64Store16(mem_args),
+                    (ValueType::I64, Width::Bits32) => I64Store32(mem_args),
+                    _ => return Err(unsupported(width, value_type)),
+                });
+                Ok(Type::Empty)
+            }
+            Expr::MemorySize => {
+                self.instructions.push(MemorySize);
+                Ok(Type::Value(ValueType::I32))
+            }
+            Expr::MemoryGrow(pages) => {
+                let pages_type = self.lower(pages)?;
+                expect(pages_type, Some(ValueType::I32))?;
+                self.instructions.push(MemoryGrow);
+                Ok(Type::Value(ValueType::I32))
+            }
+
+            Expr::Call(function_index, arguments) => {
+                let type_index = *self
+                    .context
+                    .functions
+                    .get(function_index.0 as usize)
+                    .ok_or(TypeError::UnknownFunction(*function_index))?;
+                let result_type = self.lower_arguments(type_index, arguments)?;
+                self.instructions.push(Call(*function_index));
+                Ok(result_type)
+            }
+            Expr::CallIndirect(type_index, arguments, callee) => {
+                let result_type = self.lower_arguments(*type_index, arguments)?;
+                let callee_type = self.lower(callee)?;
+                expect(callee_type, Some(ValueType::I32))?;
+                self.instructions.push(CallIndirect(*type_index));
+                Ok(result_type)
+            }
+
+            Expr::Seq(exprs) => self.lower_seq(exprs),
+            Expr::Block(body) => {
+                let (body_type, branch_type, instructions) = self.lower_labelled(body, None)?;
+                let result_type = unify_branch(body_type, branch_type)?;
+                self.push_block(Block(block_type(result_type), instructions));
+                self.diverge_after_block(result_type);
+                Ok(result_type)
+            }
+            Expr::Loop(body) => {
+                // Branching to a loop restarts it, which doesn't take values
+                let (body_type, _, instructions) = self.lower_labelled(body, Some(Type::Empty))?;
+                self.push_block(Loop(block_type(body_type), instructions));
+                self.diverge_after_block(body_type);
+                Ok(body_type)
+            }
+            Expr::If(condition, then_expr, else_expr) => {
+                let condition_type = self.lower(condition)?;
+                expect(condition_type, Some(ValueType::I32))?;
+                let (then_type, branch_type, then_instructions) =
+                    self.lower_labelled(then_expr, None)?;
+                match else_expr {
+                    Some(else_expr) => {
+                        let (else_type, branch_type, else_instructions) =
+                            self.lower_labelled(else_expr, branch_type)?;
+                        let result_type = unify_branch(unify(then_type, else_type)?, branch_type)?;
+                        self.push_block(IfElse(
+                            block_type(result_type),
+                            then_instructions,
+                            else_instructions,
+                        ));
+                        self.diverge_after_block(result_type);
+                        Ok(result_type)
+                    }
+                    None => {
+                        // Without an else, falling through can't produce a value
+                        let result_type = unify_branch(then_type, branch_type)?;
+                        expect(result_type, None)?;
+                        self.push_block(If(BlockType::Empty, then_instructions));
+                        Ok(Type::Empty)
+                    }
+                }
+            }
+            Expr::Break(label_index, value) => {
+                let value_type = match value {
+                    Some(value) => self.lower_value(value)?,
+                    None => Type::Empty,
+                };
+                self.branch(*label_index, value_type)?;
+                self.instructions.push(Branch(*label_index));
+                Ok(Type::Unreachable)
+            }
+            Expr::BreakIf(label_index, condition) => {
+                let condition_type = self.lower(condition)?;
+                expect(condition_type, Some(ValueType::I32))?;
+                self.branch(*label_index, Type::Empty)?;
+                self.instructions.push(BranchIf(*label_index));
+                Ok(Type::Empty)
+            }
+            Expr::Return(value) => {
+                let value_type = match value {
+                    Some(value) => self.lower(value)?,
+                    None => Type::Empty,
+                };
+                expect(value_type, self.context.results.first().copied())?;
+                self.instructions.push(Return);
+                Ok(Type::Unreachable)
+            }
+            Expr::Select(first, second, condition) => {
+                let first_type = self.lower_value(first)?;
+                let second_type = self.lower_value(second)?;
+                let result_type = unify(first_type, second_type)?;
+                let condition_type = self.lower(condition)?;
+                expect(condition_type, Some(ValueType::I32))?;
+                self.instructions.push(Select);
+                Ok(result_type)
+            }
+            Expr::Spanned(span, expr) => {
+                let start = instruction_count(&self.instructions);
+                let result_type = self.lower(expr)?;
+                let end = instruction_count(&self.instructions);
+                self.spans.push((start..end, span.clone()));
+                Ok(result_type)
+            }
+            Expr::Unreachable => {
+                self.instructions.push(Unreachable);
+                Ok(Type::Unreachable)
+            }
+        }
+    }
+
+    /** Lowers an expression that has to produce a value */
+    fn lower_value(&mut self, expr: &Expr) -> Result<Type, TypeError> {
+        match self.lower(expr)? {
+            Type::Empty => Err(TypeError::MissingValue),
+            value_type => Ok(value_type),
+        }
+    }
+
+    /**
+     * Lowers an operand whose type picks the instruction. Operands that never
+     * complete are treated as `i32`, since the instruction is dead code.
+     */
+    fn lower_operand(&mut self, expr: &Expr) -> Result<ValueType, TypeError> {
+        Ok(self
+            .lower_value(expr)?
+            .as_option()
+            .unwrap_or(ValueType::I32))
+    }
+
+    fn lower_address(&mut self, address: &Expr) -> Result<(), TypeError> {
+        let address_type = self.lower(address)?;
+        expect(address_type, Some(ValueType::I32))
+    }
+
+    fn lower_set_local(&mut self, local_index: LocalIndex, value: &Expr) -> Result<(), TypeError> {
+        let local_type = self.local_type(local_index)?;
+        let value_type = self.lower(value)?;
+        expect(value_type, Some(local_type))?;
+        self.instructions.push(Instruction::LocalSet(local_index));
+        Ok(())
+    }
+
+    fn lower_arguments(
+        &mut self,
+        type_index: TypeIndex,
+        arguments: &[Expr],
+    ) -> Result<Type, TypeError> {
+        let function_type = self
+            .context
+            .types
+            .get(type_index.0 as usize)
+            .ok_or(TypeError::UnknownType(type_index))?;
+        if function_type.parameters.len() != arguments.len() {
+            return Err(TypeError::ArgumentCount {
+                expected: function_type.parameters.len(),
+                found: arguments.len(),
+            });
+        }
+        if function_type.results.len() > 1 {
+            return Err(TypeError::MultipleResults);
+        }
+        for (argument, parameter_type) in arguments.iter().zip(&function_type.parameters) {
+            let argument_type = self.lower(argument)?;
+            expect(argument_type, Some(*parameter_type))?;
+        }
+        Ok(Type::from_option(function_type.results.first().copied()))
+    }
+
+    fn lower_seq(&mut self, exprs: &[Expr]) -> Result<Type, TypeError> {
+        let mut seq_type = Type::Empty;
+        for (i, expr) in exprs.iter().enumerate() {
+            let expr_type = self.lower(expr)?;
+            // Only the last value is kept, and none after an expression that
+            // never completes, since the sequence has no value then
+            if let Type::Value(_) = expr_type {
+                if i + 1 < exprs.len() || seq_type == Type::Unreachable {
+                    self.instructions.push(Instruction::Drop);
+                    continue;
+                }
+            }
+            if seq_type != Type::Unreachable {
+                seq_type = expr_type;
+            }
+        }
+        Ok(seq_type)
+    }
+
+    /**
+     * Lowers `body` into its own instruction list, inside a new label. Returns
+     * the body's type and the type carried by branches to the label, which is
+     * `None` if there weren't any and `branch_type` wasn't given.
+     */
+    fn lower_labelled(
+        &mut self,
+        body: &Expr,
+        branch_type: Option<Type>,
+    ) -> Result<(Type, Option<Type>, Vec<Instruction>), TypeError> {
+        let outer_instructions = std::mem::take(&mut self.instructions);
+        let outer_spans = std::mem::take(&mut self.spans);
+        self.labels.push(branch_type);
+        let body_type = self.lower(body);
+        let branch_type = self.labels.pop().unwrap();
+        let instructions = std::mem::replace(&mut self.instructions, outer_instructions);
+        let spans = std::mem::replace(&mut self.spans, outer_spans);
+        self.body_spans.push(spans);
+        Ok((body_type?, branch_type, instructions))
+    }
+
+    /**
+     * Pushes a block instruction whose bodies came from `lower_labelled`,
+     * moving the bodies' spans after the block's own instruction.
+     */
+    fn push_block(&mut self, instruction: Instruction) {
+        let bodies = instruction.bodies();
+        assert!(
+            !bodies.is_empty(),
+            "{} isn't a block instruction",
+            instruction.mnemonic()
+        );
+        let pending_spans = self
+            .body_spans
+            .split_off(self.body_spans.len() - bodies.len());
+        let mut offset = instruction_count(&self.instructions) + 1;
+        for (body, spans) in bodies.into_iter().zip(pending_spans) {
+            self.spans.extend(
+                spans
+                    .into_iter()
+                    .map(|(range, span)| (range.start + offset..range.end + offset, span)),
+            );
+            offset += instruction_count(body);
+        }
+        self.instructions.push(instruction);
+    }
+
+    /**
+     * Follows a block that never completes with `unreachable`. Its block type
+     * can't say that it doesn't complete, so otherwise the stack after it
+     * would be empty where a value might be expected.
+     */
+    fn diverge_after_block(&mut self, result_type: Type) {
+        if result_type == Type::Unreachable {
+            self.instructions.push(Instruction::Unreachable);
+        }
+    }
+
+    /** Checks a branch to `label_index` carrying a value of `value_type` */
+    fn branch(&mut self, label_index: LabelIndex, value_type: Type) -> Result<(), TypeError> {
+        let depth = label_index.0 as usize;
+        if depth >= self.labels.len() {
+            return Err(TypeError::UnknownLabel(label_index));
+        }
+        let index = self.labels.len() - 1 - depth;
+        self.labels[index] = Some(match self.labels[index] {
+            Some(branch_type) => unify(branch_type, value_type)?,
+            None => value_type,
+        });
+        Ok(())
+    }
+
+    fn local_type(&self, local_index: LocalIndex) -> Result<ValueType, TypeError> {
+        self.context
+            .locals
+            .get(local_index.0 as usize)
+            .copied()
+            .ok_or(TypeError::UnknownLocal(local_index))
+    }
+
+    fn global_type(&self, global_index: GlobalIndex) -> Result<GlobalType, TypeError> {
+        self.context
+            .globals
+            .get(global_index.0 as usize)
+            .copied()
+            .ok_or(TypeError::UnknownGlobal(global_index))
+    }
+}
+
+/** Combines the type a labelled body falls through with, and branches to it */
+fn unify_branch(body_type: Type, branch_type: Option<Type>) -> Result<Type, TypeError> {
+    match branch_type {
+        Some(branch_type) => unify(body_type, branch_type),
+        None => Ok(body_type),
+    }
+}
+
+fn block_type(body_type: Type) -> BlockType {
+    match body_type {
+        Type::Value(value_type) => BlockType::Value(value_type),
+        _ => BlockType::Empty,
+    }
+}
+
+fn unary_instruction(
+    op: UnaryOp,
+    operand: ValueType,
+) -> Result<(Instruction, ValueType), TypeError> {
+    use Instruction::*;
+    use UnaryOp::*;
+    use ValueType::{F32, F64, I32, I64};
+    let instruction = match (op, operand) {
+        (Eqz, I32) => I32Eqz,
+        (Clz, I32) => I32Clz,
+        (Ctz, I32) => I32Ctz,
+        (PopCnt, I32) => I32PopCnt,
+        (Eqz, I64) => I64Eqz,
+        (Clz, I64) => I64Clz,
+        (Ctz, I64) => I64Ctz,
+        (PopCnt, I64) => I64PopCnt,
+        (Abs, F32) => F32Abs,
+        (Neg, F32) => F32Neg,
+        (Ceil, F32) => F32Ceil,
+        (Floor, F32) => F32Floor,
+        (UnaryOp::Trunc, F32) => F32Trunc,
+        (Nearest, F32) => F32Nearest,
+        (Sqrt, F32) => F32Sqrt,
+        (Abs, F64) => F64Abs,
+        (Neg, F64) => F64Neg,
+        (Ceil, F64) => F64Ceil,
+        (Floor, F64) => F64Floor,
+        (UnaryOp::Trunc, F64) => F64Trunc,
+        (Nearest, F64) => F64Nearest,
+        (Sqrt, F64) => F64Sqrt,
+        _ => return Err(unsupported(op, operand)),
+    };
+    let result_type = if op == Eqz { I32 } else { operand };
+    Ok((instruction, result_type))
+}
+
+fn binary_instruction(
+    op: BinaryOp,
+    operand: ValueType,
+) -> Result<(Instruction, ValueType), TypeError> {
+    use BinaryOp::*;
+    use Instruction::*;
+    use ValueType::{F32, F64, I32, I64};
+    let instruction = match (op, operand) {
+        (Add, I32) => I32Add,
+        (Sub, I32) => I32Sub,
+        (Mul, I32) => I32Mul,
+        (DivS, I32) => I32DivS,
+        (DivU, I32) => I32DivU,
+        (RemS, I32) => I32RemS,
+        (RemU, I32) => I32RemU,
+        (And, I32) => I32And,
+        (Or, I32) => I32Or,
+        (Xor, I32) => I32Xor,
+        (Shl, I32) => I32Shl,
+        (ShrS, I32) => I32ShrS,
+        (ShrU, I32) => I32ShrU,
+        (Rotl, I32) => I32Rotl,
+        (Rotr, I32) => I32Rotr,
+        (BinaryOp::Eq, I32) => I32Eq,
+        (Ne, I32) => I32Ne,
+        (LtS, I32) => I32LtS,
+        (LtU, I32) => I32LtU,
+        (GtS, I32) => I32GtS,
+        (GtU, I32) => I32GtU,
+        (LeS, I32) => I32LeS,
+        (LeU, I32) => I32LeU,
+        (GeS, I32) => I32GeS,
+        (GeU, I32) => I32GeU,
+
+        (Add, I64) => I64Add,
+        (Sub, I64) => I64Sub,
+        (Mul, I64) => I64Mul,
+        (DivS, I64) => I64DivS,
+        (DivU, I64) => I64DivU,
+        (RemS, I64) => I64RemS,
+        (RemU, I64) => I64RemU,
+        (And, I64) => I64And,
+        (Or, I64) => I64Or,
+        (Xor, I64) => I64Xor,
+        (Shl, I64) => I64Shl,
+        (ShrS, I64) => I64ShrS,
+        (ShrU, I64) => I64ShrU,
+        (Rotl, I64) => I64Rotl,
+        (Rotr, I64) => I64Rotr,
+        (BinaryOp::Eq, I64) => I64Eq,
+        (Ne, I64) => I64Ne,
+        (LtS, I64) => I64LtS,
+        (LtU, I64) => I64LtU,
+        (GtS, I64) => I64GtS,
+        (GtU, I64) => I64GtU,
+        (LeS, I64) => I64LeS,
+        (LeU, I64) => I64LeU,
+        (GeS, I64) => I64GeS,
+        (GeU, I64) => I64GeU,
+
+        (Add, F32) => F32Add,
+        (Sub, F32) => F32Sub,
+        (Mul, F32) => F32Mul,
+        (Div, F32) => F32Div,
+        (Min, F32) => F32Min,
+        (Max, F32) => F32Max,
+        (CopySign, F32) => F32CopySign,
+        (BinaryOp::Eq, F32) => F32Eq,
+        (Ne, F32) => F32Ne,
+        (Lt, F32) => F32Lt,
+        (Gt, F32) => F32Gt,
+        (Le, F32) => F32Le,
+        (Ge, F32) => F32Ge,
+
+        (Add, F64) => F64Add,
+        (Sub, F64) => F64Sub,
+        (Mul, F64) => F64Mul,
+        (Div, F64) => F64Div,
+        (Min, F64) => F64Min,
+        (Max, F64) => F64Max,
+        (CopySign, F64) => F64CopySign,
+        (BinaryOp::Eq, F64) => F64Eq,
+        (Ne, F64) => F64Ne,
+        (Lt, F64) => F64Lt,
+        (Gt, F64) => F64Gt,
+        (Le, F64) => F64Le,
+        (Ge, F64) => F64Ge,
+
+        _ => return Err(unsupported(op, operand)),
+    };
+    let result_type = match op {
+        BinaryOp::Eq | Ne | LtS | LtU | GtS | GtU | LeS | LeU | GeS | GeU | Lt | Gt | Le | Ge => {
+            I32
+        }
+        _ => operand,
+    };
+    Ok((instruction, result_type))
+}
+
+/** Returns `None` when no instruction is needed, i.e. `from == to` */
+fn convert_instruction(
+    from: ValueType,
+    to: ValueType,
+    signedness: Signedness,
+) -> Option<Instruction> {
+    use Instruction::*;
+    use Signedness::{Signed, Unsigned};
+    use ValueType::{F32, F64, I32, I64};
+    Some(match (from, to, signedness) {
+        (I64, I32, _) => I32WrapI64,
+        (F32, I32, Signed) => I32TruncF32S,
+        (F32, I32, Unsigned) => I32TruncF32U,
+        (F64, I32, Signed) => I32TruncF64S,
+        (F64, I32, Unsigned) => I32TruncF64U,
+        (I32, I64, Signed) => I64ExtendI32S,
+        (I32, I64, Unsigned) => I64ExtendI32U,
+        (F32, I64, Signed) => I64TruncF32S,
+        (F32, I64, Unsigned) => I64TruncF32U,
+        (F64, I64, Signed) => I64TruncF64S,
+        (F64, I64, Unsigned) => I64TruncF64U,
+        (I32, F32, Signed) => F32ConvertI32S,
+        (I32, F32, Unsigned) => F32ConvertI32U,
+        (I64, F32, Signed) => F32ConvertI64S,
+        (I64, F32, Unsigned) => F32ConvertI64U,
+        (F64, F32, _) => F32DemoteF64,
+        (I32, F64, Signed) => F64ConvertI32S,
+        (I32, F64, Unsigned) => F64ConvertI32U,
+        (I64, F64, Signed) => F64ConvertI64S,
+        (I64, F64, Unsigned) => F64ConvertI64U,
+        (F32, F64, _) => F64PromoteF32,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    fn local(index: u32) -> Box<Expr> {
+        Box::new(Expr::Local(LocalIndex(index)))
+    }
+
+    fn i32c(value: i32) -> Box<Expr> {
+        Box::new(Expr::Const(Literal::I32(value)))
+    }
+
+    #[test]
+    fn test_operator_selection() {
+        let locals = [ValueType::F64, ValueType::F64];
+        let results = [ValueType::F64];
+        let context = Context {
+            locals: &locals,
+            results: &results,
+            ..Context::default()
+        };
+        let expr = Expr::Binary(
+            BinaryOp::Add,
+            local(0),
+            Box::new(Expr::Binary(BinaryOp::Mul, local(1), local(1))),
+        );
+
+        assert_eq!(
+            expr.lower(&context),
+            Ok(Expression(vec![
+                LocalGet(LocalIndex(0)),
+                LocalGet(LocalIndex(1)),
+                LocalGet(LocalIndex(1)),
+                F64Mul,
+                F64Add,
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_operand_mismatch() {
+        let locals = [ValueType::I64];
+        let context = Context {
+            locals: &locals,
+            ..Context::default()
+        };
+        let expr = Expr::Binary(BinaryOp::Add, local(0), i32c(1));
+
+        assert_eq!(
+            expr.lower(&context),
+            Err(TypeError::Mismatch {
+                expected: Some(ValueType::I64),
+                found: Some(ValueType::I32),
+            })
+        );
+    }
+
+    #[test]
+    fn test_unsupported_operand() {
+        let context = Context::default();
+        let expr = Expr::Binary(BinaryOp::Lt, i32c(1), i32c(2));
+
+        assert!(matches!(
+            expr.lower(&context),
+            Err(TypeError::UnsupportedOperand {
+                operand: ValueType::I32,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_call_and_if() {
+        let types = [FunctionType::new(
+            vec![ValueType::I32, ValueType::I32],
+            vec![ValueType::I32],
+        )];
+        let functions = [TypeIndex(0)];
+        let locals = [ValueType::I32];
+        let results = [ValueType::I32];
+        let context = Context {
+            types: &types,
+            functions: &functions,
+            locals: &locals,
+            results: &results,
+            ..Context::default()
+        };
+        let expr = Expr::Let(
+            LocalIndex(0),
+            Box::new(Expr::Call(FunctionIndex(0), vec![*i32c(1), *i32c(2)])),
+            Box::new(Expr::If(
+                Box::new(Expr::Unary(UnaryOp::Eqz, local(0))),
+                Box::new(Expr::Return(Some(i32c(-1)))),
+                Some(local(0)),
+            )),
+        );
+
+        assert_eq!(
+            expr.lower(&context),
+            Ok(Expression(vec![
+                I32Const(1),
+                I32Const(2),
+                Call(FunctionIndex(0)),
+                LocalSet(LocalIndex(0)),
+                LocalGet(LocalIndex(0)),
+                I32Eqz,
+                IfElse(
+                    BlockType::Value(ValueType::I32),
+                    vec![I32Const(-1), Return],
+                    vec![LocalGet(LocalIndex(0))],
+                ),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_loop_with_break() {
+        let locals = [ValueType::I32];
+        let context = Context {
+            locals: &locals,
+            ..Context::default()
+        };
+        // Counts local 0 down to zero
+        let expr = Expr::Block(Box::new(Expr::Loop(Box::new(Expr::Seq(vec![
+            Expr::BreakIf(LabelIndex(1), Box::new(Expr::Unary(UnaryOp::Eqz, local(0)))),
+            Expr::SetLocal(
+                LocalIndex(0),
+                Box::new(Expr::Binary(BinaryOp::Sub, local(0), i32c(1))),
+            ),
+            Expr::Break(LabelIndex(0), None),
+        ])))));
+
+        assert_eq!(
+            expr.lower(&context),
+            Ok(Expression(vec![Block(
+                BlockType::Empty,
+                vec![
+                    Loop(
+                        BlockType::Empty,
+                        vec![
+                            LocalGet(LocalIndex(0)),
+                            I32Eqz,
+                            BranchIf(LabelIndex(1)),
+                            LocalGet(LocalIndex(0)),
+                            I32Const(1),
+                            I32Sub,
+                            LocalSet(LocalIndex(0)),
+                            Branch(LabelIndex(0)),
+                        ],
+                    ),
+                    Unreachable,
+                ],
+            )]))
+        );
+    }
+
+    #[test]
+    fn test_blocks_that_never_complete() {
+        let results = [ValueType::I32];
+        let context = Context {
+            results: &results,
+            ..Context::default()
+        };
+        let returns = |value| Box::new(Expr::Return(Some(i32c(value))));
+
+        assert_eq!(
+            Expr::Block(returns(1)).lower(&context),
+            Ok(Expression(vec![
+                Block(BlockType::Empty, vec![I32Const(1), Return]),
+                Unreachable,
+            ]))
+        );
+        assert_eq!(
+            Expr::Loop(Box::new(Expr::Break(LabelIndex(0), None))).lower(&context),
+            Ok(Expression(vec![
+                Loop(BlockType::Empty, vec![Branch(LabelIndex(0))]),
+                Unreachable,
+            ]))
+        );
+        assert_eq!(
+            Expr::If(i32c(0), returns(1), Some(returns(2))).lower(&context),
+            Ok(Expression(vec![
+                I32Const(0),
+                IfElse(
+                    BlockType::Empty,
+                    vec![I32Const(1), Return],
+                    vec![I32Const(2), Return],
+                ),
+                Unreachable,
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_seq_after_return() {
+        let results = [ValueType::I32];
+        let context = Context {
+            results: &results,
+            ..Context::default()
+        };
+        let expr = Expr::Seq(vec![Expr::Return(Some(i32c(1))), *i32c(2), *i32c(3)]);
+
+        assert_eq!(
+            expr.lower(&context),
+            Ok(Expression(vec![
+                I32Const(1),
+                Return,
+                I32Const(2),
+                Drop,
+                I32Const(3),
+                Drop,
+            ]))
+        );
+    }
+}
@@ -1,11 +1,12 @@
 use crate::{
-    constants::END,
     constants::*,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
     function_type::ValueType,
     index::{FunctionIndex, GlobalIndex, LabelIndex, LocalIndex, TypeIndex},
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Expression(pub Vec<Instruction>);
 
 impl WasmEncode for Expression {
@@ -19,437 +20,406 @@ impl WasmEncode for Expression {
     }
 }
 
-pub enum Instruction {
-    // Control Instructions
-    Unreachable,
-    Nop,
-    Block(BlockType, Vec<Instruction>),
-    Loop(BlockType, Vec<Instruction>),
-    If(BlockType, Vec<Instruction>),
-    IfElse(BlockType, Vec<Instruction>, Vec<Instruction>),
-    Branch(LabelIndex),
-    BranchIf(LabelIndex),
-    BranchTable(Vec<LabelIndex>, LabelIndex),
-    Return,
-    Call(FunctionIndex),
-    CallIndirect(TypeIndex),
-
-    // Parametric Instructions
-    Drop,
-    Select,
-
-    // Variable Instructions
-    LocalGet(LocalIndex),
-    LocalSet(LocalIndex),
-    LocalTee(LocalIndex),
-    GlobalGet(GlobalIndex),
-    GlobalSet(GlobalIndex),
-
-    // Memory Instructions
-    I32Load(MemoryArguments),
-    I64Load(MemoryArguments),
-    F32Load(MemoryArguments),
-    F64Load(MemoryArguments),
-    I32Load8S(MemoryArguments),
-    I32Load8U(MemoryArguments),
-    I32Load16S(MemoryArguments),
-    I32Load16U(MemoryArguments),
-    I64Load8S(MemoryArguments),
-    I64Load8U(MemoryArguments),
-    I64Load16S(MemoryArguments),
-    I64Load16U(MemoryArguments),
-    I64Load32S(MemoryArguments),
-    I64Load32U(MemoryArguments),
-    I32Store(MemoryArguments),
-    I64Store(MemoryArguments),
-    F32Store(MemoryArguments),
-    F64Store(MemoryArguments),
-    I32Store8(MemoryArguments),
-    I32Store16(MemoryArguments),
-    I64Store8(MemoryArguments),
-    I64Store16(MemoryArguments),
-    I64Store32(MemoryArguments),
-    MemorySize,
-    MemoryGrow,
-
-    // Numeric Instructions
-    I32Const(i32),
-    I64Const(i64),
-    F32Const(f32),
-    F64Const(f64),
-
-    I32Eqz,
-    I32Eq,
-    I32Ne,
-    I32LtS,
-    I32LtU,
-    I32GtS,
-    I32GtU,
-    I32LeS,
-    I32LeU,
-    I32GeS,
-    I32GeU,
-
-    I64Eqz,
-    I64Eq,
-    I64Ne,
-    I64LtS,
-    I64LtU,
-    I64GtS,
-    I64GtU,
-    I64LeS,
-    I64LeU,
-    I64GeS,
-    I64GeU,
-
-    F32Eq,
-    F32Ne,
-    F32Lt,
-    F32Gt,
-    F32Le,
-    F32Ge,
-
-    F64Eq,
-    F64Ne,
-    F64Lt,
-    F64Gt,
-    F64Le,
-    F64Ge,
-
-    I32Clz,
-    I32Ctz,
-    I32PopCnt,
-    I32Add,
-    I32Sub,
-    I32Mul,
-    I32DivS,
-    I32DivU,
-    I32RemS,
-    I32RemU,
-    I32And,
-    I32Or,
-    I32Xor,
-    I32Shl,
-    I32ShrS,
-    I32ShrU,
-    I32Rotl,
-    I32Rotr,
-
-    I64Clz,
-    I64Ctz,
-    I64PopCnt,
-    I64Add,
-    I64Sub,
-    I64Mul,
-    I64DivS,
-    I64DivU,
-    I64RemS,
-    I64RemU,
-    I64And,
-    I64Or,
-    I64Xor,
-    I64Shl,
-    I64ShrS,
-    I64ShrU,
-    I64Rotl,
-    I64Rotr,
-
-    F32Abs,
-    F32Neg,
-    F32Ceil,
-    F32Floor,
-    F32Trunc,
-    F32Nearest,
-    F32Sqrt,
-    F32Add,
-    F32Sub,
-    F32Mul,
-    F32Div,
-    F32Min,
-    F32Max,
-    F32CopySign,
-
-    F64Abs,
-    F64Neg,
-    F64Ceil,
-    F64Floor,
-    F64Trunc,
-    F64Nearest,
-    F64Sqrt,
-    F64Add,
-    F64Sub,
-    F64Mul,
-    F64Div,
-    F64Min,
-    F64Max,
-    F64CopySign,
-
-    I32WrapI64,
-    I32TruncF32S,
-    I32TruncF32U,
-    I32TruncF64S,
-    I32TruncF64U,
-    I64ExtendI32S,
-    I32ExtendI32U,
-    I64TruncF32S,
-    I64TruncF32U,
-    I64TruncF64S,
-    I64TruncF64U,
-    F32ConvertI32S,
-    F32ConvertI32U,
-    F32ConvertI64S,
-    F32ConvertI64U,
-    F32DemoteF64,
-    F64ConvertI32S,
-    F64ConvertI32U,
-    F64ConvertI64S,
-    F64ConvertI64U,
-    F64PromoteF32,
-    I32ReinterpretF32,
-    I64ReinterpretF64,
-    F32ReinterpretI32,
-    F64ReinterpretI64,
+impl WasmDecode for Expression {
+    fn decode(decoder: &mut WasmDecoder) -> Result<Expression, DecodeError> {
+        match decode_body(decoder)? {
+            (instructions, false) => Ok(Expression(instructions)),
+            (_, true) => Err(DecodeError::UnexpectedElse),
+        }
+    }
 }
 
-impl WasmEncode for Instruction {
-    fn encode(&self, encoder: &mut WasmEncoder) -> u32 {
-        use Instruction::*;
-        match self {
-            // Control Instructions
-            Unreachable => encoder.push_u8(UNREACHABLE),
-            Nop => encoder.push_u8(NOP),
-            Block(block_type, instructions) => {
-                encoder.push_u8(BLOCK)
-                    + block_type.encode(encoder)
-                    + instructions.encode(encoder)
-                    + encoder.push_u8(END)
-            }
-            Loop(block_type, instructions) => {
-                encoder.push_u8(LOOP)
-                    + block_type.encode(encoder)
-                    + instructions.encode(encoder)
-                    + encoder.push_u8(END)
-            }
-            If(block_type, instructions) => {
-                encoder.push_u8(IF)
-                    + block_type.encode(encoder)
-                    + instructions.encode(encoder)
-                    + encoder.push_u8(END)
+/**
+ * Decodes instructions up to and including an `end` or `else`, returning
+ * whether it was an `else`.
+ */
+fn decode_body(decoder: &mut WasmDecoder) -> Result<(Vec<Instruction>, bool), DecodeError> {
+    let mut instructions = vec![];
+    loop {
+        match decoder.peek_u8()? {
+            END | ELSE => return Ok((instructions, decoder.read_u8()? == ELSE)),
+            _ => instructions.push(Instruction::decode(decoder)?),
+        }
+    }
+}
+
+fn decode_reserved(decoder: &mut WasmDecoder) -> Result<(), DecodeError> {
+    match decoder.read_u8()? {
+        0 => Ok(()),
+        byte => Err(DecodeError::NonZeroReserved(byte)),
+    }
+}
+
+/** How an instruction's immediates are encoded, after its opcode */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImmediateKind {
+    None,
+    /** A block type, then the body and an `end` */
+    Block,
+    If,
+    /** Like `If`, with an `else` and the else body before the `end` */
+    IfElse,
+    Label,
+    /** A vector of labels, then the default label */
+    LabelTable,
+    Function,
+    /** A type index, then a reserved table index */
+    CallIndirect,
+    Local,
+    Global,
+    MemoryArguments,
+    /** A reserved memory index */
+    Memory,
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+macro_rules! define_instructions {
+    ($(
+        $name:ident $(($($field:ident: $type:ty),*))?
+            = $constant:ident $opcode:tt $prefix:ident $(. $suffix:tt)? $kind:ident
+                $align:tt $signature:tt $($traps:ident)?;
+    )*) => {
+        #[derive(Clone, Debug, PartialEq)]
+        pub enum Instruction {
+            $($name $(($($type),*))?,)*
+        }
+
+        impl Instruction {
+            /** The instruction's name in the text format */
+            pub fn mnemonic(&self) -> &'static str {
+                match self {
+                    $(Instruction::$name { .. } => {
+                        concat!(stringify!($prefix) $(, ".", stringify!($suffix))?)
+                    })*
+                }
             }
-            IfElse(block_type, if_instr, else_instr) => {
-                encoder.push_u8(IF)
-                    + block_type.encode(encoder)
-                    + if_instr.encode(encoder)
-                    + encoder.push_u8(ELSE)
-                    + else_instr.encode(encoder)
-                    + encoder.push_u8(END)
+
+            pub fn immediate_kind(&self) -> ImmediateKind {
+                match self {
+                    $(Instruction::$name { .. } => ImmediateKind::$kind,)*
+                }
             }
-            Branch(label_index) => encoder.push_u8(BR) + encoder.push_leb_u32(label_index.0),
-            BranchIf(label_index) => encoder.push_u8(BR_IF) + encoder.push_leb_u32(label_index.0),
-            BranchTable(label_indices, label_index) => {
-                let mut byte_count = 0;
-                byte_count += encoder.push_u8(BR_TABLE);
-                for index in label_indices {
-                    byte_count += encoder.push_leb_u32(index.0);
+
+            /** The byte before the opcode of a prefixed instruction */
+            pub fn opcode_prefix(&self) -> Option<u8> {
+                match self {
+                    $(Instruction::$name { .. } => opcode_prefix!($opcode),)*
                 }
-                byte_count += encoder.push_leb_u32(label_index.0);
-                byte_count
             }
-            Return => encoder.push_u8(RETURN),
-            Call(function_index) => encoder.push_u8(CALL) + encoder.push_leb_u32(function_index.0),
-            CallIndirect(type_index) => {
-                encoder.push_u8(CALL_INDIRECT) + encoder.push_leb_u32(type_index.0)
+
+            /**
+             * The base-2 logarithm of the number of bytes a memory access
+             * reads or writes, which is its default alignment.
+             */
+            pub fn natural_alignment(&self) -> Option<u32> {
+                match self {
+                    $(Instruction::$name { .. } => natural_alignment!($align),)*
+                }
             }
 
-            // Parametric Instructions
-            Drop => encoder.push_u8(DROP),
-            Select => encoder.push_u8(SELECT),
+            /** The memory arguments of a load or store, e.g. to change its offset */
+            #[allow(unused_variables)]
+            pub fn memory_arguments_mut(&mut self) -> Option<&mut MemoryArguments> {
+                match self {
+                    $(Instruction::$name $(($($field),*))? => {
+                        memory_arguments!($kind $($($field)*)?)
+                    })*
+                }
+            }
+        }
 
-            // Variable Instructions
-            LocalGet(local_index) => {
-                encoder.push_u8(LOCAL_GET) + encoder.push_leb_u32(local_index.0)
+        impl WasmEncode for Instruction {
+            fn encode(&self, encoder: &mut WasmEncoder) -> u32 {
+                encoder.mark_instruction();
+                match self {
+                    $(Instruction::$name $(($($field),*))? => {
+                        encode_opcode!(encoder $opcode)
+                            + encode_immediates!($kind encoder $($($field)*)?)
+                    })*
+                }
             }
-            LocalSet(local_index) => {
-                encoder.push_u8(LOCAL_SET) + encoder.push_leb_u32(local_index.0)
+        }
+
+        impl WasmDecode for Instruction {
+            fn decode(decoder: &mut WasmDecoder) -> Result<Instruction, DecodeError> {
+                let opcode = decoder.read_u8()?;
+                let prefixed_opcode = match opcode {
+                    MISC_PREFIX => decoder.read_leb_u32()?,
+                    _ => 0,
+                };
+                Ok(match (opcode, prefixed_opcode) {
+                    $(opcode_pattern!($opcode) if is_decoded!($kind) => {
+                        decode_immediates!($kind decoder $name)
+                    })*
+                    (MISC_PREFIX, _) => {
+                        return Err(DecodeError::UnknownPrefixedOpcode(opcode, prefixed_opcode))
+                    }
+                    _ => return Err(DecodeError::UnknownOpcode(opcode)),
+                })
             }
-            LocalTee(local_index) => {
-                encoder.push_u8(LOCAL_TEE) + encoder.push_leb_u32(local_index.0)
+        }
+    };
+}
+
+macro_rules! encode_opcode {
+    ($encoder:ident [$prefix:literal, $opcode:literal]) => {
+        $encoder.push_u8($prefix) + $encoder.push_leb_u32($opcode)
+    };
+    ($encoder:ident $opcode:literal) => {
+        $encoder.push_u8($opcode)
+    };
+}
+
+macro_rules! opcode_prefix {
+    ([$prefix:literal, $opcode:literal]) => {
+        Some($prefix)
+    };
+    ($opcode:literal) => {
+        None
+    };
+}
+
+macro_rules! natural_alignment {
+    (_) => {
+        None
+    };
+    ($align:literal) => {
+        Some($align)
+    };
+}
+
+macro_rules! memory_arguments {
+    (MemoryArguments $memarg:ident) => {
+        Some($memarg)
+    };
+    ($kind:ident $($field:ident)*) => {
+        None
+    };
+}
+
+/** Matches the opcode and, for prefixed instructions, the prefixed opcode */
+macro_rules! opcode_pattern {
+    ([$prefix:literal, $opcode:literal]) => {
+        ($prefix, $opcode)
+    };
+    ($opcode:literal) => {
+        ($opcode, 0)
+    };
+}
+
+macro_rules! encode_immediates {
+    (None $encoder:ident) => {
+        0
+    };
+    (Block $encoder:ident $block_type:ident $body:ident) => {
+        $block_type.encode($encoder) + $body.encode($encoder) + $encoder.push_u8(END)
+    };
+    (If $encoder:ident $block_type:ident $body:ident) => {
+        encode_immediates!(Block $encoder $block_type $body)
+    };
+    (IfElse $encoder:ident $block_type:ident $then_body:ident $else_body:ident) => {
+        $block_type.encode($encoder)
+            + $then_body.encode($encoder)
+            + $encoder.push_u8(ELSE)
+            + $else_body.encode($encoder)
+            + $encoder.push_u8(END)
+    };
+    (LabelTable $encoder:ident $labels:ident $default:ident) => {{
+        let mut byte_count = $encoder.push_leb_u32($labels.len() as u32);
+        for label in $labels {
+            byte_count += $encoder.push_leb_u32(label.0);
+        }
+        byte_count + $encoder.push_leb_u32($default.0)
+    }};
+    (CallIndirect $encoder:ident $type_index:ident) => {
+        $encoder.push_leb_u32($type_index.0) + $encoder.push_u8(0)
+    };
+    (MemoryArguments $encoder:ident $memarg:ident) => {
+        $memarg.encode($encoder)
+    };
+    (Memory $encoder:ident) => {
+        $encoder.push_u8(0)
+    };
+    (I32 $encoder:ident $value:ident) => {
+        $encoder.push_leb_i32(*$value)
+    };
+    (I64 $encoder:ident $value:ident) => {
+        $encoder.push_leb_i64(*$value)
+    };
+    (F32 $encoder:ident $value:ident) => {
+        $encoder.push_f32(*$value)
+    };
+    (F64 $encoder:ident $value:ident) => {
+        $encoder.push_f64(*$value)
+    };
+    // Label, Function, Local and Global
+    ($kind:ident $encoder:ident $index:ident) => {
+        $encoder.push_leb_u32($index.0)
+    };
+}
+
+/** `If` decodes into `IfElse` too, since they share an opcode */
+macro_rules! is_decoded {
+    (IfElse) => {
+        false
+    };
+    ($kind:ident) => {
+        true
+    };
+}
+
+macro_rules! decode_immediates {
+    (None $decoder:ident $name:ident) => {
+        Instruction::$name
+    };
+    (Block $decoder:ident $name:ident) => {{
+        let block_type = BlockType::decode($decoder)?;
+        match decode_body($decoder)? {
+            (body, false) => Instruction::$name(block_type, body),
+            (_, true) => return Err(DecodeError::UnexpectedElse),
+        }
+    }};
+    (If $decoder:ident $name:ident) => {{
+        let block_type = BlockType::decode($decoder)?;
+        match decode_body($decoder)? {
+            (body, false) => Instruction::If(block_type, body),
+            (then_body, true) => match decode_body($decoder)? {
+                (else_body, false) => Instruction::IfElse(block_type, then_body, else_body),
+                (_, true) => return Err(DecodeError::UnexpectedElse),
+            },
+        }
+    }};
+    (IfElse $decoder:ident $name:ident) => {
+        decode_immediates!(If $decoder $name)
+    };
+    (Label $decoder:ident $name:ident) => {
+        Instruction::$name(LabelIndex($decoder.read_leb_u32()?))
+    };
+    (LabelTable $decoder:ident $name:ident) => {{
+        let count = $decoder.read_leb_u32()?;
+        let labels = (0..count)
+            .map(|_| $decoder.read_leb_u32().map(LabelIndex))
+            .collect::<Result<_, _>>()?;
+        Instruction::$name(labels, LabelIndex($decoder.read_leb_u32()?))
+    }};
+    (Function $decoder:ident $name:ident) => {
+        Instruction::$name(FunctionIndex($decoder.read_leb_u32()?))
+    };
+    (CallIndirect $decoder:ident $name:ident) => {{
+        let type_index = TypeIndex($decoder.read_leb_u32()?);
+        decode_reserved($decoder)?;
+        Instruction::$name(type_index)
+    }};
+    (Local $decoder:ident $name:ident) => {
+        Instruction::$name(LocalIndex($decoder.read_leb_u32()?))
+    };
+    (Global $decoder:ident $name:ident) => {
+        Instruction::$name(GlobalIndex($decoder.read_leb_u32()?))
+    };
+    (MemoryArguments $decoder:ident $name:ident) => {
+        Instruction::$name(MemoryArguments::decode($decoder)?)
+    };
+    (Memory $decoder:ident $name:ident) => {{
+        decode_reserved($decoder)?;
+        Instruction::$name
+    }};
+    (I32 $decoder:ident $name:ident) => {
+        Instruction::$name($decoder.read_leb_i32()?)
+    };
+    (I64 $decoder:ident $name:ident) => {
+        Instruction::$name($decoder.read_leb_i64()?)
+    };
+    (F32 $decoder:ident $name:ident) => {
+        Instruction::$name($decoder.read_f32()?)
+    };
+    (F64 $decoder:ident $name:ident) => {
+        Instruction::$name($decoder.read_f64()?)
+    };
+}
+
+crate::for_each_instruction!(define_instructions);
+
+impl Instruction {
+    /** The bodies of a block, loop or if, in the order they're encoded */
+    pub fn bodies(&self) -> Vec<&[Instruction]> {
+        match self {
+            Instruction::Block(_, body) | Instruction::Loop(_, body) | Instruction::If(_, body) => {
+                vec![body]
             }
-            GlobalGet(global_index) => {
-                encoder.push_u8(GLOBAL_GET) + encoder.push_leb_u32(global_index.0)
+            Instruction::IfElse(_, then_body, else_body) => vec![then_body, else_body],
+            _ => vec![],
+        }
+    }
+
+    pub fn bodies_mut(&mut self) -> Vec<&mut Vec<Instruction>> {
+        match self {
+            Instruction::Block(_, body) | Instruction::Loop(_, body) | Instruction::If(_, body) => {
+                vec![body]
             }
-            GlobalSet(global_index) => {
-                encoder.push_u8(GLOBAL_SET) + encoder.push_leb_u32(global_index.0)
+            Instruction::IfElse(_, then_body, else_body) => vec![then_body, else_body],
+            _ => vec![],
+        }
+    }
+}
+
+/**
+ * Iterates over instructions and everything in their bodies, in the order
+ * they're encoded: a block before its bodies, and `then` before `else`.
+ * Source map spans number instructions in this order.
+ */
+pub fn walk(instructions: &[Instruction]) -> Walk<'_> {
+    Walk {
+        stack: vec![instructions.iter()],
+    }
+}
+
+/** The iterator of `walk` */
+pub struct Walk<'a> {
+    /** The bodies being walked, innermost last */
+    stack: Vec<std::slice::Iter<'a, Instruction>>,
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = &'a Instruction;
+
+    fn next(&mut self) -> Option<&'a Instruction> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(instruction) => {
+                    let bodies = instruction.bodies();
+                    self.stack.extend(bodies.into_iter().rev().map(<[_]>::iter));
+                    return Some(instruction);
+                }
+                None => {
+                    self.stack.pop();
+                }
             }
+        }
+    }
+}
 
-            // Memory Instructions
-            I32Load(mem_args) => encoder.push_u8(I32_LOAD) + mem_args.encode(encoder),
-            I64Load(mem_args) => encoder.push_u8(I64_LOAD) + mem_args.encode(encoder),
-            F32Load(mem_args) => encoder.push_u8(F32_LOAD) + mem_args.encode(encoder),
-            F64Load(mem_args) => encoder.push_u8(F64_LOAD) + mem_args.encode(encoder),
-            I32Load8S(mem_args) => encoder.push_u8(I32_LOAD8_S) + mem_args.encode(encoder),
-            I32Load8U(mem_args) => encoder.push_u8(I32_LOAD8_U) + mem_args.encode(encoder),
-            I32Load16S(mem_args) => encoder.push_u8(I32_LOAD16_S) + mem_args.encode(encoder),
-            I32Load16U(mem_args) => encoder.push_u8(I32_LOAD16_U) + mem_args.encode(encoder),
-            I64Load8S(mem_args) => encoder.push_u8(I64_LOAD8_S) + mem_args.encode(encoder),
-            I64Load8U(mem_args) => encoder.push_u8(I64_LOAD8_U) + mem_args.encode(encoder),
-            I64Load16S(mem_args) => encoder.push_u8(I64_LOAD16_S) + mem_args.encode(encoder),
-            I64Load16U(mem_args) => encoder.push_u8(I64_LOAD16_U) + mem_args.encode(encoder),
-            I64Load32S(mem_args) => encoder.push_u8(I64_LOAD32_S) + mem_args.encode(encoder),
-            I64Load32U(mem_args) => encoder.push_u8(I64_LOAD32_U) + mem_args.encode(encoder),
-            I32Store(mem_args) => encoder.push_u8(I32_STORE) + mem_args.encode(encoder),
-            I64Store(mem_args) => encoder.push_u8(I64_STORE) + mem_args.encode(encoder),
-            F32Store(mem_args) => encoder.push_u8(F32_STORE) + mem_args.encode(encoder),
-            F64Store(mem_args) => encoder.push_u8(F64_STORE) + mem_args.encode(encoder),
-            I32Store8(mem_args) => encoder.push_u8(I32_STORE8) + mem_args.encode(encoder),
-            I32Store16(mem_args) => encoder.push_u8(I32_STORE16) + mem_args.encode(encoder),
-            I64Store8(mem_args) => encoder.push_u8(I64_STORE8) + mem_args.encode(encoder),
-            I64Store16(mem_args) => encoder.push_u8(I64_STORE16) + mem_args.encode(encoder),
-            I64Store32(mem_args) => encoder.push_u8(I64_STORE32) + mem_args.encode(encoder),
-            MemorySize => encoder.push_u16(MEMORY_SIZE),
-            MemoryGrow => encoder.push_u16(MEMORY_GROW),
-
-            // Numeric Instructions
-            I32Const(value) => encoder.push_u8(I32_CONST) + encoder.push_leb_i32(*value),
-            I64Const(value) => encoder.push_u8(I64_CONST) + encoder.push_leb_i64(*value),
-            F32Const(value) => unimplemented!(),
-            F64Const(value) => unimplemented!(),
-
-            I32Eqz => encoder.push_u8(I32_EQZ),
-            I32Eq => encoder.push_u8(I32_EQ),
-            I32Ne => encoder.push_u8(I32_NE),
-            I32LtS => encoder.push_u8(I32_LT_S),
-            I32LtU => encoder.push_u8(I32_LT_U),
-            I32GtS => encoder.push_u8(I32_GT_S),
-            I32GtU => encoder.push_u8(I32_GT_U),
-            I32LeS => encoder.push_u8(I32_LE_S),
-            I32LeU => encoder.push_u8(I32_LE_U),
-            I32GeS => encoder.push_u8(I32_GE_S),
-            I32GeU => encoder.push_u8(I32_GE_U),
-
-            I64Eqz => encoder.push_u8(I64_EQZ),
-            I64Eq => encoder.push_u8(I64_EQ),
-            I64Ne => encoder.push_u8(I64_NE),
-            I64LtS => encoder.push_u8(I64_LT_S),
-            I64LtU => encoder.push_u8(I64_LT_U),
-            I64GtS => encoder.push_u8(I64_GT_S),
-            I64GtU => encoder.push_u8(I64_GT_U),
-            I64LeS => encoder.push_u8(I64_LE_S),
-            I64LeU => encoder.push_u8(I64_LE_U),
-            I64GeS => encoder.push_u8(I64_GE_S),
-            I64GeU => encoder.push_u8(I64_GE_U),
-
-            F32Eq => encoder.push_u8(F32_EQ),
-            F32Ne => encoder.push_u8(F32_NE),
-            F32Lt => encoder.push_u8(F32_LT),
-            F32Gt => encoder.push_u8(F32_GT),
-            F32Le => encoder.push_u8(F32_LE),
-            F32Ge => encoder.push_u8(F32_GE),
-
-            F64Eq => encoder.push_u8(F64_EQ),
-            F64Ne => encoder.push_u8(F64_NE),
-            F64Lt => encoder.push_u8(F64_LT),
-            F64Gt => encoder.push_u8(F64_GT),
-            F64Le => encoder.push_u8(F64_LE),
-            F64Ge => encoder.push_u8(F64_GE),
-
-            I32Clz => encoder.push_u8(I32_CLZ),
-            I32Ctz => encoder.push_u8(I32_CTZ),
-            I32PopCnt => encoder.push_u8(I32_POPCNT),
-            I32Add => encoder.push_u8(I32_ADD),
-            I32Sub => encoder.push_u8(I32_SUB),
-            I32Mul => encoder.push_u8(I32_MUL),
-            I32DivS => encoder.push_u8(I32_DIV_S),
-            I32DivU => encoder.push_u8(I32_DIV_U),
-            I32RemS => encoder.push_u8(I32_REM_S),
-            I32RemU => encoder.push_u8(I32_REM_U),
-            I32And => encoder.push_u8(I32_AND),
-            I32Or => encoder.push_u8(I32_OR),
-            I32Xor => encoder.push_u8(I32_XOR),
-            I32Shl => encoder.push_u8(I32_SHL),
-            I32ShrS => encoder.push_u8(I32_SHR_S),
-            I32ShrU => encoder.push_u8(I32_SHR_U),
-            I32Rotl => encoder.push_u8(I32_ROTL),
-            I32Rotr => encoder.push_u8(I32_ROTR),
-
-            I64Clz => encoder.push_u8(I64_CLZ),
-            I64Ctz => encoder.push_u8(I64_CTZ),
-            I64PopCnt => encoder.push_u8(I64_POPCNT),
-            I64Add => encoder.push_u8(I64_ADD),
-            I64Sub => encoder.push_u8(I64_SUB),
-            I64Mul => encoder.push_u8(I64_MUL),
-            I64DivS => encoder.push_u8(I64_DIV_S),
-            I64DivU => encoder.push_u8(I64_DIV_U),
-            I64RemS => encoder.push_u8(I64_REM_S),
-            I64RemU => encoder.push_u8(I64_REM_U),
-            I64And => encoder.push_u8(I64_AND),
-            I64Or => encoder.push_u8(I64_OR),
-            I64Xor => encoder.push_u8(I64_XOR),
-            I64Shl => encoder.push_u8(I64_SHL),
-            I64ShrS => encoder.push_u8(I64_SHR_S),
-            I64ShrU => encoder.push_u8(I64_SHR_U),
-            I64Rotl => encoder.push_u8(I64_ROTL),
-            I64Rotr => encoder.push_u8(I64_ROTR),
-
-            F32Abs => encoder.push_u8(F32_ABS),
-            F32Neg => encoder.push_u8(F32_NEG),
-            F32Ceil => encoder.push_u8(F32_CEIL),
-            F32Floor => encoder.push_u8(F32_FLOOR),
-            F32Trunc => encoder.push_u8(F32_TRUNC),
-            F32Nearest => encoder.push_u8(F32_NEAREST),
-            F32Sqrt => encoder.push_u8(F32_SQRT),
-            F32Add => encoder.push_u8(F32_ADD),
-            F32Sub => encoder.push_u8(F32_SUB),
-            F32Mul => encoder.push_u8(F32_MUL),
-            F32Div => encoder.push_u8(F32_DIV),
-            F32Min => encoder.push_u8(F32_MIN),
-            F32Max => encoder.push_u8(F32_MAX),
-            F32CopySign => encoder.push_u8(F32_COPYSIGN),
-
-            F64Abs => encoder.push_u8(F64_ABS),
-            F64Neg => encoder.push_u8(F64_NEG),
-            F64Ceil => encoder.push_u8(F64_CEIL),
-            F64Floor => encoder.push_u8(F64_FLOOR),
-            F64Trunc => encoder.push_u8(F64_TRUNC),
-            F64Nearest => encoder.push_u8(F64_NEAREST),
-            F64Sqrt => encoder.push_u8(F64_SQRT),
-            F64Add => encoder.push_u8(F64_ADD),
-            F64Sub => encoder.push_u8(F64_SUB),
-            F64Mul => encoder.push_u8(F64_MUL),
-            F64Div => encoder.push_u8(F64_DIV),
-            F64Min => encoder.push_u8(F64_MIN),
-            F64Max => encoder.push_u8(F64_MAX),
-            F64CopySign => encoder.push_u8(F64_COPYSIGN),
-
-            I32WrapI64 => encoder.push_u8(I32_WRAP_I64),
-            I32TruncF32S => encoder.push_u8(I32_TRUNC_F32_S),
-            I32TruncF32U => encoder.push_u8(I32_TRUNC_F32_U),
-            I32TruncF64S => encoder.push_u8(I32_TRUNC_F64_S),
-            I32TruncF64U => encoder.push_u8(I32_TRUNC_F64_U),
-            I64ExtendI32S => encoder.push_u8(I64_EXTEND_I32_S),
-            I64ExtendI32U => encoder.push_u8(I64_EXTEND_I32_U),
-            I64TruncF32S => encoder.push_u8(I64_TRUNC_F32_S),
-            I64TruncF32U => encoder.push_u8(I64_TRUNC_F32_U),
-            I64TruncF64S => encoder.push_u8(I64_TRUNC_F64_S),
-            I64TruncF64U => encoder.push_u8(I64_TRUNC_F64_U),
-            F32ConvertI32S => encoder.push_u8(F32_CONVERT_I32_S),
-            F32ConvertI32U => encoder.push_u8(F32_CONVERT_I32_U),
-            F32ConvertI64S => encoder.push_u8(F32_CONVERT_I64_S),
-            F32ConvertI64U => encoder.push_u8(F32_CONVERT_I64_U),
-            F32DemoteF64 => encoder.push_u8(F32_DEMOTE_F64),
-            F64ConvertI32S => encoder.push_u8(F64_CONVERT_I32_S),
-            F64ConvertI32U => encoder.push_u8(F64_CONVERT_I32_U),
-            F64ConvertI64S => encoder.push_u8(F64_CONVERT_I64_S),
-            F64ConvertI64U => encoder.push_u8(F64_CONVERT_I64_U),
-            F64PromoteF32 => encoder.push_u8(F64_PROMOTE_F32),
-            I32ReinterpretF32 => encoder.push_u8(I32_REINTERPRET_F32),
-            I64ReinterpretF64 => encoder.push_u8(I64_REINTERPRET_F64),
-            F32ReinterpretI32 => encoder.push_u8(F32_REINTERPRET_I32),
-            F64ReinterpretI64 => encoder.push_u8(F64_REINTERPRET_I64),
+/**
+ * Calls `visit` with instructions and everything in their bodies, in the
+ * order `walk` goes in, stopping at the first error. A block is visited
+ * before its bodies, so `visit` can change them.
+ */
+pub fn walk_mut<E>(
+    instructions: &mut [Instruction],
+    visit: &mut impl FnMut(&mut Instruction) -> Result<(), E>,
+) -> Result<(), E> {
+    for instruction in instructions.iter_mut() {
+        visit(instruction)?;
+        for body in instruction.bodies_mut() {
+            walk_mut(body, visit)?;
         }
     }
+    Ok(())
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BlockType {
     Empty,
     Value(ValueType),
@@ -464,8 +434,22 @@ impl WasmEncode for BlockType {
     }
 }
 
+impl WasmDecode for BlockType {
+    fn decode(decoder: &mut WasmDecoder) -> Result<BlockType, DecodeError> {
+        match decoder.peek_u8()? {
+            EMPTY => {
+                decoder.read_u8()?;
+                Ok(BlockType::Empty)
+            }
+            _ => Ok(BlockType::Value(ValueType::decode(decoder)?)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct MemoryArguments {
     pub offset: u32,
+    /** The base-2 logarithm of the alignment, as in the binary format */
     pub align: u32,
 }
 
@@ -477,6 +461,200 @@ impl MemoryArguments {
 
 impl WasmEncode for MemoryArguments {
     fn encode(&self, encoder: &mut WasmEncoder) -> u32 {
-        encoder.push_leb_u32(self.offset) + encoder.push_leb_u32(self.align)
+        encoder.push_leb_u32(self.align) + encoder.push_leb_u32(self.offset)
+    }
+}
+
+impl WasmDecode for MemoryArguments {
+    fn decode(decoder: &mut WasmDecoder) -> Result<MemoryArguments, DecodeError> {
+        let align = decoder.read_leb_u32()?;
+        let offset = decoder.read_leb_u32()?;
+        Ok(MemoryArguments { offset, align })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::assert_encoding_eq;
+
+    #[test]
+    fn test_memory_instruction_encoding() {
+        assert_encoding_eq(
+            Instruction::I64Store(MemoryArguments::new(16, 3)),
+            &[
+                0x37, // i64.store
+                0x03, // align
+                0x10, // offset
+            ],
+        );
+    }
+
+    #[test]
+    fn test_conversion_encoding() {
+        assert_encoding_eq(
+            Expression(vec![Instruction::I64ExtendI32U, Instruction::I64TruncF32S]),
+            &[
+                0xad, // i64.extend_i32_u
+                0xae, // i64.trunc_f32_s
+                0x0b, // end
+            ],
+        );
+    }
+
+    #[test]
+    fn test_branch_table_encoding() {
+        assert_encoding_eq(
+            Instruction::BranchTable(vec![LabelIndex(0), LabelIndex(1)], LabelIndex(2)),
+            &[
+                0x0E, // br_table
+                0x02, // label count
+                0x00, 0x01, // labels
+                0x02, // default label
+            ],
+        );
+    }
+
+    #[test]
+    fn test_reserved_and_prefixed_encoding() {
+        assert_encoding_eq(
+            Instruction::MemoryGrow,
+            &[
+                0x40, // memory.grow
+                0x00, // memory index
+            ],
+        );
+        assert_encoding_eq(
+            Instruction::CallIndirect(TypeIndex(2)),
+            &[
+                0x11, // call_indirect
+                0x02, // type index
+                0x00, // table index
+            ],
+        );
+        assert_encoding_eq(
+            Instruction::I64TruncSatF64U,
+            &[
+                0xfc, 0x07, // i64.trunc_sat_f64_u
+            ],
+        );
+        assert_eq!(Instruction::I64TruncSatF64U.opcode_prefix(), Some(0xfc));
+        assert_eq!(Instruction::MemoryGrow.opcode_prefix(), None);
+    }
+
+    #[test]
+    fn test_decoding_round_trip() {
+        let expression = Expression(vec![
+            Instruction::Block(
+                BlockType::Value(ValueType::I64),
+                vec![
+                    Instruction::I64Const(-300),
+                    Instruction::LocalGet(LocalIndex(1)),
+                    Instruction::BranchTable(vec![LabelIndex(0), LabelIndex(1)], LabelIndex(0)),
+                ],
+            ),
+            Instruction::IfElse(
+                BlockType::Empty,
+                vec![Instruction::Call(FunctionIndex(4))],
+                vec![Instruction::F64Const(1.5), Instruction::Drop],
+            ),
+            Instruction::If(BlockType::Empty, vec![Instruction::Nop]),
+            Instruction::I32Load16U(MemoryArguments::new(8, 1)),
+            Instruction::MemorySize,
+            Instruction::I32TruncSatF32S,
+            Instruction::CallIndirect(TypeIndex(1)),
+        ]);
+        let mut encoder = WasmEncoder::new();
+        expression.encode(&mut encoder);
+
+        let mut decoder = WasmDecoder::new(encoder.as_slice());
+        assert_eq!(Expression::decode(&mut decoder), Ok(expression));
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_opcodes() {
+        let mut decoder = WasmDecoder::new(&[0xfc, 0x7f]);
+        assert_eq!(
+            Instruction::decode(&mut decoder),
+            Err(DecodeError::UnknownPrefixedOpcode(0xfc, 0x7f))
+        );
+        let mut decoder = WasmDecoder::new(&[0xc5]);
+        assert_eq!(
+            Instruction::decode(&mut decoder),
+            Err(DecodeError::UnknownOpcode(0xc5))
+        );
+    }
+
+    #[test]
+    fn test_mnemonics() {
+        let load = Instruction::I64Load8S(MemoryArguments::new(0, 0));
+        assert_eq!(load.mnemonic(), "i64.load8_s");
+        assert_eq!(load.immediate_kind(), ImmediateKind::MemoryArguments);
+        assert_eq!(
+            Instruction::IfElse(BlockType::Empty, vec![], vec![]).mnemonic(),
+            "if"
+        );
+        assert_eq!(Instruction::F32CopySign.mnemonic(), "f32.copysign");
+    }
+
+    #[test]
+    fn test_memory_arguments_mut() {
+        let mut store = Instruction::I64Store32(MemoryArguments::new(0, 2));
+        store.memory_arguments_mut().unwrap().offset = 16;
+        assert_eq!(store, Instruction::I64Store32(MemoryArguments::new(16, 2)));
+        assert_eq!(Instruction::MemoryGrow.memory_arguments_mut(), None);
+    }
+
+    #[test]
+    fn test_walk() {
+        let mut instructions = vec![
+            Instruction::Block(
+                BlockType::Empty,
+                vec![Instruction::IfElse(
+                    BlockType::Empty,
+                    vec![Instruction::I32Const(1)],
+                    vec![Instruction::I32Const(2)],
+                )],
+            ),
+            Instruction::I32Const(3),
+        ];
+        let constants: Vec<&Instruction> = walk(&instructions)
+            .filter(|instruction| matches!(instruction, Instruction::I32Const(_)))
+            .collect();
+        assert_eq!(
+            constants,
+            vec![
+                &Instruction::I32Const(1),
+                &Instruction::I32Const(2),
+                &Instruction::I32Const(3)
+            ]
+        );
+        assert_eq!(walk(&instructions).count(), 5);
+
+        let mut visited = 0;
+        let walked = walk_mut(&mut instructions, &mut |instruction| {
+            visited += 1;
+            match instruction {
+                Instruction::I32Const(2) => Err(visited),
+                Instruction::I32Const(value) => {
+                    *value *= 10;
+                    Ok(())
+                }
+                _ => Ok(()),
+            }
+        });
+        assert_eq!(walked, Err(4));
+        assert_eq!(walk(&instructions).nth(2), Some(&Instruction::I32Const(10)));
+        assert_eq!(walk(&instructions).nth(4), Some(&Instruction::I32Const(3)));
+    }
+
+    #[test]
+    fn test_natural_alignment() {
+        let memarg = MemoryArguments::new(0, 0);
+        assert_eq!(Instruction::I64Load16U(memarg).natural_alignment(), Some(1));
+        assert_eq!(Instruction::F64Store(memarg).natural_alignment(), Some(3));
+        assert_eq!(Instruction::I32Store8(memarg).natural_alignment(), Some(0));
+        assert_eq!(Instruction::MemorySize.natural_alignment(), None);
     }
 }
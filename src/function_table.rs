@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use crate::{
+    expression::{Expression, Instruction},
+    index::{FunctionIndex, TableIndex, TypeIndex},
+    limits::Limits,
+    section::{
+        element_section::{Element, ElementSection},
+        table_section::{ElementType, Table, TableSection},
+    },
+};
+
+/**
+ * Assigns table slots to functions that are called indirectly, and produces
+ * the `TableSection` and `ElementSection` that put them there. Each function
+ * is only given one slot, no matter how many times it is asked for.
+ */
+pub struct FunctionTable {
+    table_index: TableIndex,
+    base: u32,
+    functions: Vec<FunctionIndex>,
+    slots: HashMap<FunctionIndex, u32>,
+}
+
+impl FunctionTable {
+    pub fn new(table_index: TableIndex) -> FunctionTable {
+        FunctionTable::with_base(table_index, 0)
+    }
+
+    /**
+     * Starts assigning slots at `base`. Leaving slot 0 empty makes calls
+     * through a null function pointer trap.
+     */
+    pub fn with_base(table_index: TableIndex, base: u32) -> FunctionTable {
+        FunctionTable {
+            table_index,
+            base,
+            functions: vec![],
+            slots: HashMap::new(),
+        }
+    }
+
+    pub fn slot_for(&mut self, function_index: FunctionIndex) -> u32 {
+        if let Some(slot) = self.slots.get(&function_index) {
+            return *slot;
+        }
+        let slot = self.base + self.functions.len() as u32;
+        self.functions.push(function_index);
+        self.slots.insert(function_index, slot);
+        slot
+    }
+
+    /**
+     * Calls the function in `slot` directly from a constant slot number. Slot
+     * numbers computed at runtime should be followed by a plain
+     * `CallIndirect` instead. Panics if no function has been given `slot`.
+     */
+    pub fn call(&self, slot: u32, type_index: TypeIndex) -> Vec<Instruction> {
+        assert!(
+            slot >= self.base && slot < self.size(),
+            "no function in slot {}",
+            slot
+        );
+        vec![
+            Instruction::I32Const(slot as i32),
+            Instruction::CallIndirect(type_index),
+        ]
+    }
+
+    /** Returns the number of slots in the table, including unused base slots */
+    pub fn size(&self) -> u32 {
+        self.base + self.functions.len() as u32
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits::min(self.size())
+    }
+
+    pub fn table(&self) -> Table {
+        Table::new(ElementType::FunctionReference, self.limits())
+    }
+
+    pub fn element(&self) -> Element {
+        Element::new(
+            self.table_index,
+            Expression(vec![Instruction::I32Const(self.base as i32)]),
+            self.functions.clone(),
+        )
+    }
+
+    pub fn table_section(&self) -> TableSection {
+        TableSection(vec![self.table()])
+    }
+
+    pub fn element_section(&self) -> ElementSection {
+        ElementSection(vec![self.element()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::assert_encoding_eq;
+
+    #[test]
+    fn test_slot_assignment() {
+        let mut table = FunctionTable::with_base(TableIndex(0), 1);
+        assert_eq!(table.slot_for(FunctionIndex(7)), 1);
+        assert_eq!(table.slot_for(FunctionIndex(3)), 2);
+        assert_eq!(table.slot_for(FunctionIndex(7)), 1);
+        assert_eq!(table.size(), 3);
+    }
+
+    #[test]
+    fn test_call() {
+        let mut table = FunctionTable::with_base(TableIndex(0), 1);
+        let slot = table.slot_for(FunctionIndex(7));
+        assert_eq!(
+            table.call(slot, TypeIndex(2)),
+            vec![
+                Instruction::I32Const(1),
+                Instruction::CallIndirect(TypeIndex(2)),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_call_empty_slot() {
+        let mut table = FunctionTable::with_base(TableIndex(0), 1);
+        table.slot_for(FunctionIndex(7));
+        table.call(0, TypeIndex(2));
+    }
+
+    #[test]
+    fn test_section_encoding() {
+        let mut table = FunctionTable::with_base(TableIndex(0), 1);
+        table.slot_for(FunctionIndex(7));
+        table.slot_for(FunctionIndex(3));
+
+        assert_encoding_eq(
+            table.table_section(),
+            &[
+                0x04, // section id
+                0x04, // byte count
+                0x01, // table count
+                0x70, // element type - funcref
+                0x00, 0x03, // limits
+            ],
+        );
+        assert_encoding_eq(
+            table.element_section(),
+            &[
+                0x09, // section id
+                0x08, // byte count
+                0x01, // element count
+                0x00, // table index
+                0x41, 0x01, 0x0b, // (i32.const 1)
+                0x02, // function index count
+                0x07, 0x03, // function indices
+            ],
+        );
+    }
+}
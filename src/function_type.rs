@@ -1,8 +1,10 @@
 use crate::{
     constants::{F32, F64, FUNCTION_TYPE, I32, I64},
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct FunctionType {
     pub parameters: Vec<ValueType>,
     pub results: Vec<ValueType>,
@@ -36,6 +38,19 @@ impl WasmEncode for FunctionType {
     }
 }
 
+impl WasmDecode for FunctionType {
+    fn decode(decoder: &mut WasmDecoder) -> Result<FunctionType, DecodeError> {
+        match decoder.read_u8()? {
+            FUNCTION_TYPE => Ok(FunctionType::new(
+                decoder.read_vector()?,
+                decoder.read_vector()?,
+            )),
+            byte => Err(DecodeError::InvalidFunctionType(byte)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum ValueType {
     I32,
     I64,
@@ -54,6 +69,18 @@ impl WasmEncode for ValueType {
     }
 }
 
+impl WasmDecode for ValueType {
+    fn decode(decoder: &mut WasmDecoder) -> Result<ValueType, DecodeError> {
+        match decoder.read_u8()? {
+            I32 => Ok(ValueType::I32),
+            I64 => Ok(ValueType::I64),
+            F32 => Ok(ValueType::F32),
+            F64 => Ok(ValueType::F64),
+            byte => Err(DecodeError::InvalidValueType(byte)),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
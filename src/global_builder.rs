@@ -0,0 +1,196 @@
+use std::{error, fmt};
+
+use crate::{
+    expression::{Expression, Instruction},
+    function_type::ValueType,
+    index::GlobalIndex,
+    section::{
+        global_section::{Global, GlobalSection, GlobalType, Mutability},
+        import_section::{Import, ImportDescriptor},
+        name_section::NameSection,
+    },
+};
+
+#[derive(Debug, PartialEq)]
+pub enum GlobalError {
+    Immutable(GlobalIndex),
+}
+
+impl fmt::Display for GlobalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GlobalError::Immutable(index) => write!(f, "global {} is immutable", index.0),
+        }
+    }
+}
+
+impl error::Error for GlobalError {}
+
+/**
+ * A global issued by a `GlobalBuilder`, which remembers the global's type so
+ * that it can only be accessed in ways that validate.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlobalHandle {
+    index: GlobalIndex,
+    global_type: GlobalType,
+}
+
+impl GlobalHandle {
+    pub fn index(&self) -> GlobalIndex {
+        self.index
+    }
+
+    pub fn global_type(&self) -> GlobalType {
+        self.global_type
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.global_type.value_type
+    }
+
+    pub fn is_mutable(&self) -> bool {
+        self.global_type.mutability == Mutability::Var
+    }
+
+    pub fn get(&self) -> Instruction {
+        Instruction::GlobalGet(self.index)
+    }
+
+    pub fn set(&self) -> Result<Instruction, GlobalError> {
+        if self.is_mutable() {
+            Ok(Instruction::GlobalSet(self.index))
+        } else {
+            Err(GlobalError::Immutable(self.index))
+        }
+    }
+}
+
+/**
+ * Allocates global indices for imported and defined globals. Imported globals
+ * come first in the global index space, so every import has to be declared
+ * before the first global is defined. Globals can be given names, which go in
+ * the module's `name` section with `Module::add_names`.
+ */
+#[derive(Default)]
+pub struct GlobalBuilder {
+    imports: Vec<Import>,
+    globals: Vec<Global>,
+    names: NameSection,
+}
+
+impl GlobalBuilder {
+    pub fn new() -> GlobalBuilder {
+        GlobalBuilder::default()
+    }
+
+    pub fn import(
+        &mut self,
+        module_name: &str,
+        name: &str,
+        global_type: GlobalType,
+    ) -> GlobalHandle {
+        assert!(
+            self.globals.is_empty(),
+            "globals must be imported before any are defined"
+        );
+        let index = GlobalIndex(self.imports.len() as u32);
+        self.imports.push(Import::new(
+            module_name,
+            name,
+            ImportDescriptor::GlobalType(global_type),
+        ));
+        GlobalHandle { index, global_type }
+    }
+
+    pub fn define(&mut self, global: Global) -> GlobalHandle {
+        let index = GlobalIndex((self.imports.len() + self.globals.len()) as u32);
+        let global_type = global.global_type();
+        self.globals.push(global);
+        GlobalHandle { index, global_type }
+    }
+
+    pub fn define_const(&mut self, value_type: ValueType, initializer: Expression) -> GlobalHandle {
+        self.define(Global::Const(value_type, initializer))
+    }
+
+    pub fn define_var(&mut self, value_type: ValueType, initializer: Expression) -> GlobalHandle {
+        self.define(Global::Var(value_type, initializer))
+    }
+
+    pub fn name(&mut self, global: GlobalHandle, name: &str) {
+        self.names.name_global(global.index, name);
+    }
+
+    /** The names given to globals so far */
+    pub fn names(&self) -> &NameSection {
+        &self.names
+    }
+
+    /**
+     * Returns the global imports, which belong in the `ImportSection`, and the
+     * `GlobalSection` holding every defined global.
+     */
+    pub fn build(self) -> (Vec<Import>, GlobalSection) {
+        (self.imports, GlobalSection(self.globals))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_allocation() {
+        let mut globals = GlobalBuilder::new();
+        let imported = globals.import(
+            "env",
+            "__stack_pointer",
+            GlobalType::new(ValueType::I32, Mutability::Var),
+        );
+        let defined =
+            globals.define_const(ValueType::I64, Expression(vec![Instruction::I64Const(1)]));
+
+        assert_eq!(imported.index(), GlobalIndex(0));
+        assert_eq!(defined.index(), GlobalIndex(1));
+        assert_eq!(defined.value_type(), ValueType::I64);
+
+        let (imports, global_section) = globals.build();
+        assert_eq!(imports.len(), 1);
+        assert_eq!(global_section.0.len(), 1);
+    }
+
+    #[test]
+    fn test_names() {
+        let mut globals = GlobalBuilder::new();
+        let stack_pointer = globals.import(
+            "env",
+            "__stack_pointer",
+            GlobalType::new(ValueType::I32, Mutability::Var),
+        );
+        let counter =
+            globals.define_var(ValueType::I64, Expression(vec![Instruction::I64Const(0)]));
+        globals.name(stack_pointer, "sp");
+        globals.name(counter, "counter");
+
+        let mut expected = NameSection::new();
+        expected.name_global(GlobalIndex(0), "sp");
+        expected.name_global(GlobalIndex(1), "counter");
+        assert_eq!(globals.names(), &expected);
+    }
+
+    #[test]
+    fn test_set_immutable_global() {
+        let mut globals = GlobalBuilder::new();
+        let mutable =
+            globals.define_var(ValueType::I32, Expression(vec![Instruction::I32Const(0)]));
+        let immutable =
+            globals.define_const(ValueType::I32, Expression(vec![Instruction::I32Const(0)]));
+
+        assert!(mutable.set().is_ok());
+        assert_eq!(
+            immutable.set().err(),
+            Some(GlobalError::Immutable(GlobalIndex(1)))
+        );
+    }
+}
@@ -1,20 +1,62 @@
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct TypeIndex(pub u32);
 
-#[derive(Clone, Copy)]
+impl From<u32> for TypeIndex {
+    fn from(index: u32) -> TypeIndex {
+        TypeIndex(index)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct FunctionIndex(pub u32);
 
-#[derive(Clone, Copy)]
+impl From<u32> for FunctionIndex {
+    fn from(index: u32) -> FunctionIndex {
+        FunctionIndex(index)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct TableIndex(pub u32);
 
-#[derive(Clone, Copy)]
+impl From<u32> for TableIndex {
+    fn from(index: u32) -> TableIndex {
+        TableIndex(index)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct MemoryIndex(pub u32);
 
-#[derive(Clone, Copy)]
+impl From<u32> for MemoryIndex {
+    fn from(index: u32) -> MemoryIndex {
+        MemoryIndex(index)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct GlobalIndex(pub u32);
 
-#[derive(Clone, Copy)]
+impl From<u32> for GlobalIndex {
+    fn from(index: u32) -> GlobalIndex {
+        GlobalIndex(index)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct LocalIndex(pub u32);
 
-#[derive(Clone, Copy)]
+impl From<u32> for LocalIndex {
+    fn from(index: u32) -> LocalIndex {
+        LocalIndex(index)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct LabelIndex(pub u32);
+
+impl From<u32> for LabelIndex {
+    fn from(index: u32) -> LabelIndex {
+        LabelIndex(index)
+    }
+}
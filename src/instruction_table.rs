@@ -0,0 +1,240 @@
+/*!
+ * The table every instruction-related definition is generated from.
+ */
+
+/**
+ * Calls the macro `$m` with a row for every instruction:
+ *
+ * ```text
+ * Variant(field: Type, ...) = CONSTANT opcode mnemonic ImmediateKind align (params -> results) traps;
+ * ```
+ *
+ * The opcode is either a single byte, or a `[prefix, opcode]` pair for
+ * prefixed instructions, whose opcode follows the prefix as a LEB128 `u32`.
+ * The mnemonic is the instruction's name in the text format, written as
+ * tokens. The field names are what the rows' immediates are bound to, and
+ * the `ImmediateKind` says how they are encoded. `If` and `IfElse` are the
+ * same instruction in the binary format, so they share a constant and an
+ * opcode.
+ *
+ * Loads and stores give their natural alignment, as a base-2 logarithm, and
+ * every other row has `_` there. The signature is the value types the
+ * instruction pops and pushes, or `_` when they depend on where it is, as
+ * for calls and local variables. Rows that can trap end with `traps`, which
+ * includes calls and blocks, since the code they run might.
+ *
+ * The `Instruction` enum, its encoder, decoder, mnemonics, alignments and
+ * stack effects, the opcode constants and the `wasm!` macro are all
+ * generated from this table, so supporting new instructions only takes new
+ * rows. `$m` can be a path, and anything after it and a comma is passed
+ * along before the rows.
+ */
+#[macro_export]
+macro_rules! for_each_instruction {
+    ($m:ident $(:: $path:ident)* $(, $($arguments:tt)*)?) => {
+        $m $(:: $path)*! {
+            $($($arguments)*)?
+            // Control Instructions
+            Unreachable = UNREACHABLE 0x00 unreachable None _ _ traps;
+            Nop = NOP 0x01 nop None _ (->);
+            Block(block_type: BlockType, body: Vec<Instruction>) = BLOCK 0x02 block Block _ _ traps;
+            Loop(block_type: BlockType, body: Vec<Instruction>) = LOOP 0x03 loop Block _ _ traps;
+            If(block_type: BlockType, body: Vec<Instruction>) = IF 0x04 if If _ _ traps;
+            IfElse(block_type: BlockType, then_body: Vec<Instruction>, else_body: Vec<Instruction>)
+                = IF 0x04 if IfElse _ _ traps;
+            Branch(label: LabelIndex) = BR 0x0c br Label _ _;
+            BranchIf(label: LabelIndex) = BR_IF 0x0d br_if Label _ _;
+            BranchTable(labels: Vec<LabelIndex>, default: LabelIndex)
+                = BR_TABLE 0x0e br_table LabelTable _ _;
+            Return = RETURN 0x0f return None _ _;
+            Call(function: FunctionIndex) = CALL 0x10 call Function _ _ traps;
+            CallIndirect(type_index: TypeIndex) = CALL_INDIRECT 0x11 call_indirect CallIndirect _ _ traps;
+
+            // Parametric Instructions
+            Drop = DROP 0x1a drop None _ _;
+            Select = SELECT 0x1b select None _ _;
+
+            // Variable Instructions
+            LocalGet(local: LocalIndex) = LOCAL_GET 0x20 local.get Local _ _;
+            LocalSet(local: LocalIndex) = LOCAL_SET 0x21 local.set Local _ _;
+            LocalTee(local: LocalIndex) = LOCAL_TEE 0x22 local.tee Local _ _;
+            GlobalGet(global: GlobalIndex) = GLOBAL_GET 0x23 global.get Global _ _;
+            GlobalSet(global: GlobalIndex) = GLOBAL_SET 0x24 global.set Global _ _;
+
+            // Memory Instructions
+            I32Load(memarg: MemoryArguments) = I32_LOAD 0x28 i32.load MemoryArguments 2 (I32 -> I32) traps;
+            I64Load(memarg: MemoryArguments) = I64_LOAD 0x29 i64.load MemoryArguments 3 (I32 -> I64) traps;
+            F32Load(memarg: MemoryArguments) = F32_LOAD 0x2a f32.load MemoryArguments 2 (I32 -> F32) traps;
+            F64Load(memarg: MemoryArguments) = F64_LOAD 0x2b f64.load MemoryArguments 3 (I32 -> F64) traps;
+            I32Load8S(memarg: MemoryArguments) = I32_LOAD8_S 0x2c i32.load8_s MemoryArguments 0 (I32 -> I32) traps;
+            I32Load8U(memarg: MemoryArguments) = I32_LOAD8_U 0x2d i32.load8_u MemoryArguments 0 (I32 -> I32) traps;
+            I32Load16S(memarg: MemoryArguments) = I32_LOAD16_S 0x2e i32.load16_s MemoryArguments 1 (I32 -> I32) traps;
+            I32Load16U(memarg: MemoryArguments) = I32_LOAD16_U 0x2f i32.load16_u MemoryArguments 1 (I32 -> I32) traps;
+            I64Load8S(memarg: MemoryArguments) = I64_LOAD8_S 0x30 i64.load8_s MemoryArguments 0 (I32 -> I64) traps;
+            I64Load8U(memarg: MemoryArguments) = I64_LOAD8_U 0x31 i64.load8_u MemoryArguments 0 (I32 -> I64) traps;
+            I64Load16S(memarg: MemoryArguments) = I64_LOAD16_S 0x32 i64.load16_s MemoryArguments 1 (I32 -> I64) traps;
+            I64Load16U(memarg: MemoryArguments) = I64_LOAD16_U 0x33 i64.load16_u MemoryArguments 1 (I32 -> I64) traps;
+            I64Load32S(memarg: MemoryArguments) = I64_LOAD32_S 0x34 i64.load32_s MemoryArguments 2 (I32 -> I64) traps;
+            I64Load32U(memarg: MemoryArguments) = I64_LOAD32_U 0x35 i64.load32_u MemoryArguments 2 (I32 -> I64) traps;
+            I32Store(memarg: MemoryArguments) = I32_STORE 0x36 i32.store MemoryArguments 2 (I32 I32 ->) traps;
+            I64Store(memarg: MemoryArguments) = I64_STORE 0x37 i64.store MemoryArguments 3 (I32 I64 ->) traps;
+            F32Store(memarg: MemoryArguments) = F32_STORE 0x38 f32.store MemoryArguments 2 (I32 F32 ->) traps;
+            F64Store(memarg: MemoryArguments) = F64_STORE 0x39 f64.store MemoryArguments 3 (I32 F64 ->) traps;
+            I32Store8(memarg: MemoryArguments) = I32_STORE8 0x3a i32.store8 MemoryArguments 0 (I32 I32 ->) traps;
+            I32Store16(memarg: MemoryArguments) = I32_STORE16 0x3b i32.store16 MemoryArguments 1 (I32 I32 ->) traps;
+            I64Store8(memarg: MemoryArguments) = I64_STORE8 0x3c i64.store8 MemoryArguments 0 (I32 I64 ->) traps;
+            I64Store16(memarg: MemoryArguments) = I64_STORE16 0x3d i64.store16 MemoryArguments 1 (I32 I64 ->) traps;
+            I64Store32(memarg: MemoryArguments) = I64_STORE32 0x3e i64.store32 MemoryArguments 2 (I32 I64 ->) traps;
+            MemorySize = MEMORY_SIZE 0x3f memory.size Memory _ (-> I32);
+            MemoryGrow = MEMORY_GROW 0x40 memory.grow Memory _ (I32 -> I32);
+
+            // Numeric Instructions
+            I32Const(value: i32) = I32_CONST 0x41 i32.const I32 _ (-> I32);
+            I64Const(value: i64) = I64_CONST 0x42 i64.const I64 _ (-> I64);
+            F32Const(value: f32) = F32_CONST 0x43 f32.const F32 _ (-> F32);
+            F64Const(value: f64) = F64_CONST 0x44 f64.const F64 _ (-> F64);
+
+            I32Eqz = I32_EQZ 0x45 i32.eqz None _ (I32 -> I32);
+            I32Eq = I32_EQ 0x46 i32.eq None _ (I32 I32 -> I32);
+            I32Ne = I32_NE 0x47 i32.ne None _ (I32 I32 -> I32);
+            I32LtS = I32_LT_S 0x48 i32.lt_s None _ (I32 I32 -> I32);
+            I32LtU = I32_LT_U 0x49 i32.lt_u None _ (I32 I32 -> I32);
+            I32GtS = I32_GT_S 0x4a i32.gt_s None _ (I32 I32 -> I32);
+            I32GtU = I32_GT_U 0x4b i32.gt_u None _ (I32 I32 -> I32);
+            I32LeS = I32_LE_S 0x4c i32.le_s None _ (I32 I32 -> I32);
+            I32LeU = I32_LE_U 0x4d i32.le_u None _ (I32 I32 -> I32);
+            I32GeS = I32_GE_S 0x4e i32.ge_s None _ (I32 I32 -> I32);
+            I32GeU = I32_GE_U 0x4f i32.ge_u None _ (I32 I32 -> I32);
+
+            I64Eqz = I64_EQZ 0x50 i64.eqz None _ (I64 -> I32);
+            I64Eq = I64_EQ 0x51 i64.eq None _ (I64 I64 -> I32);
+            I64Ne = I64_NE 0x52 i64.ne None _ (I64 I64 -> I32);
+            I64LtS = I64_LT_S 0x53 i64.lt_s None _ (I64 I64 -> I32);
+            I64LtU = I64_LT_U 0x54 i64.lt_u None _ (I64 I64 -> I32);
+            I64GtS = I64_GT_S 0x55 i64.gt_s None _ (I64 I64 -> I32);
+            I64GtU = I64_GT_U 0x56 i64.gt_u None _ (I64 I64 -> I32);
+            I64LeS = I64_LE_S 0x57 i64.le_s None _ (I64 I64 -> I32);
+            I64LeU = I64_LE_U 0x58 i64.le_u None _ (I64 I64 -> I32);
+            I64GeS = I64_GE_S 0x59 i64.ge_s None _ (I64 I64 -> I32);
+            I64GeU = I64_GE_U 0x5a i64.ge_u None _ (I64 I64 -> I32);
+
+            F32Eq = F32_EQ 0x5b f32.eq None _ (F32 F32 -> I32);
+            F32Ne = F32_NE 0x5c f32.ne None _ (F32 F32 -> I32);
+            F32Lt = F32_LT 0x5d f32.lt None _ (F32 F32 -> I32);
+            F32Gt = F32_GT 0x5e f32.gt None _ (F32 F32 -> I32);
+            F32Le = F32_LE 0x5f f32.le None _ (F32 F32 -> I32);
+            F32Ge = F32_GE 0x60 f32.ge None _ (F32 F32 -> I32);
+
+            F64Eq = F64_EQ 0x61 f64.eq None _ (F64 F64 -> I32);
+            F64Ne = F64_NE 0x62 f64.ne None _ (F64 F64 -> I32);
+            F64Lt = F64_LT 0x63 f64.lt None _ (F64 F64 -> I32);
+            F64Gt = F64_GT 0x64 f64.gt None _ (F64 F64 -> I32);
+            F64Le = F64_LE 0x65 f64.le None _ (F64 F64 -> I32);
+            F64Ge = F64_GE 0x66 f64.ge None _ (F64 F64 -> I32);
+
+            I32Clz = I32_CLZ 0x67 i32.clz None _ (I32 -> I32);
+            I32Ctz = I32_CTZ 0x68 i32.ctz None _ (I32 -> I32);
+            I32PopCnt = I32_POPCNT 0x69 i32.popcnt None _ (I32 -> I32);
+            I32Add = I32_ADD 0x6a i32.add None _ (I32 I32 -> I32);
+            I32Sub = I32_SUB 0x6b i32.sub None _ (I32 I32 -> I32);
+            I32Mul = I32_MUL 0x6c i32.mul None _ (I32 I32 -> I32);
+            I32DivS = I32_DIV_S 0x6d i32.div_s None _ (I32 I32 -> I32) traps;
+            I32DivU = I32_DIV_U 0x6e i32.div_u None _ (I32 I32 -> I32) traps;
+            I32RemS = I32_REM_S 0x6f i32.rem_s None _ (I32 I32 -> I32) traps;
+            I32RemU = I32_REM_U 0x70 i32.rem_u None _ (I32 I32 -> I32) traps;
+            I32And = I32_AND 0x71 i32.and None _ (I32 I32 -> I32);
+            I32Or = I32_OR 0x72 i32.or None _ (I32 I32 -> I32);
+            I32Xor = I32_XOR 0x73 i32.xor None _ (I32 I32 -> I32);
+            I32Shl = I32_SHL 0x74 i32.shl None _ (I32 I32 -> I32);
+            I32ShrS = I32_SHR_S 0x75 i32.shr_s None _ (I32 I32 -> I32);
+            I32ShrU = I32_SHR_U 0x76 i32.shr_u None _ (I32 I32 -> I32);
+            I32Rotl = I32_ROTL 0x77 i32.rotl None _ (I32 I32 -> I32);
+            I32Rotr = I32_ROTR 0x78 i32.rotr None _ (I32 I32 -> I32);
+
+            I64Clz = I64_CLZ 0x79 i64.clz None _ (I64 -> I64);
+            I64Ctz = I64_CTZ 0x7a i64.ctz None _ (I64 -> I64);
+            I64PopCnt = I64_POPCNT 0x7b i64.popcnt None _ (I64 -> I64);
+            I64Add = I64_ADD 0x7c i64.add None _ (I64 I64 -> I64);
+            I64Sub = I64_SUB 0x7d i64.sub None _ (I64 I64 -> I64);
+            I64Mul = I64_MUL 0x7e i64.mul None _ (I64 I64 -> I64);
+            I64DivS = I64_DIV_S 0x7f i64.div_s None _ (I64 I64 -> I64) traps;
+            I64DivU = I64_DIV_U 0x80 i64.div_u None _ (I64 I64 -> I64) traps;
+            I64RemS = I64_REM_S 0x81 i64.rem_s None _ (I64 I64 -> I64) traps;
+            I64RemU = I64_REM_U 0x82 i64.rem_u None _ (I64 I64 -> I64) traps;
+            I64And = I64_AND 0x83 i64.and None _ (I64 I64 -> I64);
+            I64Or = I64_OR 0x84 i64.or None _ (I64 I64 -> I64);
+            I64Xor = I64_XOR 0x85 i64.xor None _ (I64 I64 -> I64);
+            I64Shl = I64_SHL 0x86 i64.shl None _ (I64 I64 -> I64);
+            I64ShrS = I64_SHR_S 0x87 i64.shr_s None _ (I64 I64 -> I64);
+            I64ShrU = I64_SHR_U 0x88 i64.shr_u None _ (I64 I64 -> I64);
+            I64Rotl = I64_ROTL 0x89 i64.rotl None _ (I64 I64 -> I64);
+            I64Rotr = I64_ROTR 0x8a i64.rotr None _ (I64 I64 -> I64);
+
+            F32Abs = F32_ABS 0x8b f32.abs None _ (F32 -> F32);
+            F32Neg = F32_NEG 0x8c f32.neg None _ (F32 -> F32);
+            F32Ceil = F32_CEIL 0x8d f32.ceil None _ (F32 -> F32);
+            F32Floor = F32_FLOOR 0x8e f32.floor None _ (F32 -> F32);
+            F32Trunc = F32_TRUNC 0x8f f32.trunc None _ (F32 -> F32);
+            F32Nearest = F32_NEAREST 0x90 f32.nearest None _ (F32 -> F32);
+            F32Sqrt = F32_SQRT 0x91 f32.sqrt None _ (F32 -> F32);
+            F32Add = F32_ADD 0x92 f32.add None _ (F32 F32 -> F32);
+            F32Sub = F32_SUB 0x93 f32.sub None _ (F32 F32 -> F32);
+            F32Mul = F32_MUL 0x94 f32.mul None _ (F32 F32 -> F32);
+            F32Div = F32_DIV 0x95 f32.div None _ (F32 F32 -> F32);
+            F32Min = F32_MIN 0x96 f32.min None _ (F32 F32 -> F32);
+            F32Max = F32_MAX 0x97 f32.max None _ (F32 F32 -> F32);
+            F32CopySign = F32_COPYSIGN 0x98 f32.copysign None _ (F32 F32 -> F32);
+
+            F64Abs = F64_ABS 0x99 f64.abs None _ (F64 -> F64);
+            F64Neg = F64_NEG 0x9a f64.neg None _ (F64 -> F64);
+            F64Ceil = F64_CEIL 0x9b f64.ceil None _ (F64 -> F64);
+            F64Floor = F64_FLOOR 0x9c f64.floor None _ (F64 -> F64);
+            F64Trunc = F64_TRUNC 0x9d f64.trunc None _ (F64 -> F64);
+            F64Nearest = F64_NEAREST 0x9e f64.nearest None _ (F64 -> F64);
+            F64Sqrt = F64_SQRT 0x9f f64.sqrt None _ (F64 -> F64);
+            F64Add = F64_ADD 0xa0 f64.add None _ (F64 F64 -> F64);
+            F64Sub = F64_SUB 0xa1 f64.sub None _ (F64 F64 -> F64);
+            F64Mul = F64_MUL 0xa2 f64.mul None _ (F64 F64 -> F64);
+            F64Div = F64_DIV 0xa3 f64.div None _ (F64 F64 -> F64);
+            F64Min = F64_MIN 0xa4 f64.min None _ (F64 F64 -> F64);
+            F64Max = F64_MAX 0xa5 f64.max None _ (F64 F64 -> F64);
+            F64CopySign = F64_COPYSIGN 0xa6 f64.copysign None _ (F64 F64 -> F64);
+
+            I32WrapI64 = I32_WRAP_I64 0xa7 i32.wrap_i64 None _ (I64 -> I32);
+            I32TruncF32S = I32_TRUNC_F32_S 0xa8 i32.trunc_f32_s None _ (F32 -> I32) traps;
+            I32TruncF32U = I32_TRUNC_F32_U 0xa9 i32.trunc_f32_u None _ (F32 -> I32) traps;
+            I32TruncF64S = I32_TRUNC_F64_S 0xaa i32.trunc_f64_s None _ (F64 -> I32) traps;
+            I32TruncF64U = I32_TRUNC_F64_U 0xab i32.trunc_f64_u None _ (F64 -> I32) traps;
+            I64ExtendI32S = I64_EXTEND_I32_S 0xac i64.extend_i32_s None _ (I32 -> I64);
+            I64ExtendI32U = I64_EXTEND_I32_U 0xad i64.extend_i32_u None _ (I32 -> I64);
+            I64TruncF32S = I64_TRUNC_F32_S 0xae i64.trunc_f32_s None _ (F32 -> I64) traps;
+            I64TruncF32U = I64_TRUNC_F32_U 0xaf i64.trunc_f32_u None _ (F32 -> I64) traps;
+            I64TruncF64S = I64_TRUNC_F64_S 0xb0 i64.trunc_f64_s None _ (F64 -> I64) traps;
+            I64TruncF64U = I64_TRUNC_F64_U 0xb1 i64.trunc_f64_u None _ (F64 -> I64) traps;
+            F32ConvertI32S = F32_CONVERT_I32_S 0xb2 f32.convert_i32_s None _ (I32 -> F32);
+            F32ConvertI32U = F32_CONVERT_I32_U 0xb3 f32.convert_i32_u None _ (I32 -> F32);
+            F32ConvertI64S = F32_CONVERT_I64_S 0xb4 f32.convert_i64_s None _ (I64 -> F32);
+            F32ConvertI64U = F32_CONVERT_I64_U 0xb5 f32.convert_i64_u None _ (I64 -> F32);
+            F32DemoteF64 = F32_DEMOTE_F64 0xb6 f32.demote_f64 None _ (F64 -> F32);
+            F64ConvertI32S = F64_CONVERT_I32_S 0xb7 f64.convert_i32_s None _ (I32 -> F64);
+            F64ConvertI32U = F64_CONVERT_I32_U 0xb8 f64.convert_i32_u None _ (I32 -> F64);
+            F64ConvertI64S = F64_CONVERT_I64_S 0xb9 f64.convert_i64_s None _ (I64 -> F64);
+            F64ConvertI64U = F64_CONVERT_I64_U 0xba f64.convert_i64_u None _ (I64 -> F64);
+            F64PromoteF32 = F64_PROMOTE_F32 0xbb f64.promote_f32 None _ (F32 -> F64);
+            I32ReinterpretF32 = I32_REINTERPRET_F32 0xbc i32.reinterpret_f32 None _ (F32 -> I32);
+            I64ReinterpretF64 = I64_REINTERPRET_F64 0xbd i64.reinterpret_f64 None _ (F64 -> I64);
+            F32ReinterpretI32 = F32_REINTERPRET_I32 0xbe f32.reinterpret_i32 None _ (I32 -> F32);
+            F64ReinterpretI64 = F64_REINTERPRET_I64 0xbf f64.reinterpret_i64 None _ (I64 -> F64);
+
+            // Non-trapping Float-to-int Conversions
+            I32TruncSatF32S = I32_TRUNC_SAT_F32_S [0xfc, 0x00] i32.trunc_sat_f32_s None _ (F32 -> I32);
+            I32TruncSatF32U = I32_TRUNC_SAT_F32_U [0xfc, 0x01] i32.trunc_sat_f32_u None _ (F32 -> I32);
+            I32TruncSatF64S = I32_TRUNC_SAT_F64_S [0xfc, 0x02] i32.trunc_sat_f64_s None _ (F64 -> I32);
+            I32TruncSatF64U = I32_TRUNC_SAT_F64_U [0xfc, 0x03] i32.trunc_sat_f64_u None _ (F64 -> I32);
+            I64TruncSatF32S = I64_TRUNC_SAT_F32_S [0xfc, 0x04] i64.trunc_sat_f32_s None _ (F32 -> I64);
+            I64TruncSatF32U = I64_TRUNC_SAT_F32_U [0xfc, 0x05] i64.trunc_sat_f32_u None _ (F32 -> I64);
+            I64TruncSatF64S = I64_TRUNC_SAT_F64_S [0xfc, 0x06] i64.trunc_sat_f64_s None _ (F64 -> I64);
+            I64TruncSatF64U = I64_TRUNC_SAT_F64_U [0xfc, 0x07] i64.trunc_sat_f64_u None _ (F64 -> I64);
+        }
+    };
+}
@@ -1,8 +1,30 @@
+pub mod branded_index;
+pub mod cfg;
 pub mod constants;
+pub mod decoder;
+pub mod dsl;
+pub mod dump;
+pub mod dwarf;
 pub mod encoder;
+pub mod expr_tree;
 pub mod expression;
+pub mod function_table;
 pub mod function_type;
+pub mod global_builder;
 pub mod index;
+pub mod instruction_table;
 pub mod limits;
+pub mod linker;
+pub mod memory_layout;
 pub mod module;
+pub mod object;
 pub mod section;
+pub mod side_module;
+pub mod source_map;
+pub mod ssa;
+pub mod stack_effect;
+pub mod string_pool;
+pub mod text;
+pub mod wasm_macro;
+pub mod wast;
+pub mod wat;
@@ -1,8 +1,10 @@
 use crate::{
     constants::{MAX_ABSENT, MAX_PRESENT},
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Limits {
     pub min: u32,
     pub max: Option<u32>,
@@ -24,15 +26,25 @@ impl Limits {
 
 impl WasmEncode for Limits {
     fn encode(&self, encoder: &mut WasmEncoder) -> u32 {
-        if self.max.is_some() {
-            encoder.push_u8(MAX_PRESENT);
-            encoder.push_leb_u32(self.min);
-            encoder.push_leb_u32(self.max.unwrap());
-            3
+        if let Some(max) = self.max {
+            encoder.push_u8(MAX_PRESENT)
+                + encoder.push_leb_u32(self.min)
+                + encoder.push_leb_u32(max)
         } else {
-            encoder.push_u8(MAX_ABSENT);
-            encoder.push_leb_u32(self.min);
-            2
+            encoder.push_u8(MAX_ABSENT) + encoder.push_leb_u32(self.min)
+        }
+    }
+}
+
+impl WasmDecode for Limits {
+    fn decode(decoder: &mut WasmDecoder) -> Result<Limits, DecodeError> {
+        match decoder.read_u8()? {
+            MAX_ABSENT => Ok(Limits::min(decoder.read_leb_u32()?)),
+            MAX_PRESENT => Ok(Limits::min_max(
+                decoder.read_leb_u32()?,
+                decoder.read_leb_u32()?,
+            )),
+            byte => Err(DecodeError::InvalidLimits(byte)),
         }
     }
 }
@@ -64,4 +76,16 @@ mod tests {
             ],
         )
     }
+
+    #[test]
+    fn test_multi_byte_encoding() {
+        assert_encoding_eq(
+            Limits::min_max(128, 65536),
+            &[
+                0x01, // max flag (on)
+                0x80, 0x01, // min
+                0x80, 0x80, 0x04, // max
+            ],
+        )
+    }
 }
@@ -0,0 +1,1248 @@
+/*!
+ * A static linker for relocatable objects, like the ones
+ * `Module::encode_object` writes, which puts them together into one module
+ * that can be instantiated.
+ *
+ * Symbols are resolved by name, and a strong definition beats weak ones.
+ * Types are merged, every index in the code is renumbered, data segments are
+ * laid out from address 1024, and functions whose address is taken are put in
+ * the table from slot 1, so null pointers stay invalid. Init functions are
+ * called, in order of priority, from an exported `__wasm_call_ctors`.
+ */
+use std::{
+    collections::{BTreeMap, HashMap},
+    error, fmt, mem,
+};
+
+use crate::{
+    constants::{CODE_SECTION, DATA_SECTION, PAGE_SIZE},
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
+    expression::{walk_mut, Expression, Instruction},
+    function_table::FunctionTable,
+    function_type::{FunctionType, ValueType},
+    index::{FunctionIndex, GlobalIndex, MemoryIndex, TableIndex, TypeIndex},
+    limits::Limits,
+    memory_layout::MemoryLayout,
+    module::Module,
+    section::{
+        code_section::{CodeSection, Function},
+        data_section::Data,
+        export_section::{Export, ExportDescriptor, ExportSection},
+        function_section::FunctionSection,
+        global_section::{Global, GlobalSection},
+        import_section::{Import, ImportDescriptor, ImportSection},
+        linking_section::{LinkingSection, Symbol, SymbolKind, SYMBOL_EXPORTED},
+        memory_section::{Memory, MemorySection},
+        relocation_section::{Relocation, RelocationSection, RelocationType},
+        table_section::{ElementType, Table, TableSection},
+        type_section::TypeSection,
+        Section,
+    },
+    source_map::CodeLayout,
+};
+
+const GLOBAL_BASE: u32 = 1024;
+const TABLE_BASE: u32 = 1;
+const DEFAULT_STACK_SIZE: u32 = PAGE_SIZE;
+const STACK_ALIGNMENT: u32 = 16;
+const STACK_POINTER: &str = "__stack_pointer";
+const CALL_CTORS: &str = "__wasm_call_ctors";
+
+#[derive(Debug, PartialEq)]
+pub enum LinkError {
+    /** An object that isn't a module, by the order it was added in */
+    Decode(usize, DecodeError),
+    /** An object without a `linking` section */
+    NotRelocatable(usize),
+    /** An object whose relocations or indices don't match its contents */
+    MalformedObject(usize),
+    UnsupportedRelocation(usize, RelocationType),
+    DuplicateSymbol(String),
+    UndefinedSymbol(String),
+    /** A symbol that's defined or used as different kinds, like a function and data */
+    SymbolKindMismatch(String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::Decode(object, error) => write!(f, "object {}: {}", object, error),
+            LinkError::NotRelocatable(object) => {
+                write!(f, "object {} has no linking section", object)
+            }
+            LinkError::MalformedObject(object) => write!(
+                f,
+                "object {} has relocations or indices that don't match its contents",
+                object
+            ),
+            LinkError::UnsupportedRelocation(object, relocation_type) => write!(
+                f,
+                "object {} has an unsupported {:?} relocation",
+                object, relocation_type
+            ),
+            LinkError::DuplicateSymbol(name) => write!(f, "{} is defined more than once", name),
+            LinkError::UndefinedSymbol(name) => write!(f, "{} isn't defined", name),
+            LinkError::SymbolKindMismatch(name) => {
+                write!(
+                    f,
+                    "{} is defined or used as different kinds of symbol",
+                    name
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for LinkError {}
+
+/** A relocation in a data segment */
+struct DataRelocation {
+    segment: usize,
+    /** The offset of the patched bytes in the segment */
+    offset: u32,
+    relocation: Relocation,
+}
+
+/** The parts of an object the linker uses */
+struct Object {
+    types: Vec<FunctionType>,
+    function_imports: Vec<Import>,
+    global_imports: Vec<Import>,
+    /** The minimum size of the imported memory, in pages */
+    memory_min: u32,
+    /** The minimum size of the imported table, if there is one */
+    table_min: Option<u32>,
+    function_types: Vec<TypeIndex>,
+    functions: Vec<Function>,
+    globals: Vec<Global>,
+    data: Vec<Data>,
+    linking: LinkingSection,
+    /** The relocations in each function body, by instruction number */
+    code_relocations: Vec<BTreeMap<u32, Relocation>>,
+    data_relocations: Vec<DataRelocation>,
+}
+
+impl Object {
+    fn decode(index: usize, bytes: &[u8]) -> Result<Object, LinkError> {
+        let decode_error = |error: DecodeError| LinkError::Decode(index, error);
+        let module = Module::decode(&mut WasmDecoder::new(bytes)).map_err(decode_error)?;
+        let section_ids: Vec<u8> = module.0.iter().map(Section::id).collect();
+        let mut object = Object {
+            types: vec![],
+            function_imports: vec![],
+            global_imports: vec![],
+            memory_min: 0,
+            table_min: None,
+            function_types: vec![],
+            functions: vec![],
+            globals: vec![],
+            data: vec![],
+            linking: LinkingSection::new(),
+            code_relocations: vec![],
+            data_relocations: vec![],
+        };
+        let mut linking = None;
+        let mut relocation_sections = vec![];
+        for section in module.0 {
+            match section {
+                Section::TypeSection(section) => object.types = section.0,
+                Section::ImportSection(section) => {
+                    for import in section.0 {
+                        match &import.descriptor {
+                            ImportDescriptor::TypeIndex(_) => object.function_imports.push(import),
+                            ImportDescriptor::GlobalType(_) => object.global_imports.push(import),
+                            ImportDescriptor::MemoryType(memory) => {
+                                object.memory_min = memory.limits.min
+                            }
+                            ImportDescriptor::TableType(table) => {
+                                object.table_min = Some(table.limits.min)
+                            }
+                        }
+                    }
+                }
+                Section::FunctionSection(section) => object.function_types = section.0,
+                Section::GlobalSection(section) => object.globals = section.0,
+                Section::CodeSection(section) => object.functions = section.0,
+                Section::DataSection(section) => object.data = section.0,
+                Section::Custom { name, payload } => {
+                    let mut decoder = WasmDecoder::new(&payload);
+                    if name == "linking" {
+                        linking = Some(LinkingSection::decode(&mut decoder).map_err(decode_error)?);
+                    } else if name.starts_with("reloc.") {
+                        relocation_sections.push(
+                            RelocationSection::decode(&name, &mut decoder).map_err(decode_error)?,
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        object.linking = linking.ok_or(LinkError::NotRelocatable(index))?;
+
+        let code = CodeLayout::decode(bytes).map_err(decode_error)?;
+        let segments = segment_layout(bytes).map_err(decode_error)?;
+        object.code_relocations = vec![BTreeMap::new(); code.functions.len()];
+        for section in relocation_sections {
+            let target = section_ids.get(section.section_index as usize).copied();
+            for relocation in section.relocations {
+                match target {
+                    Some(CODE_SECTION) => {
+                        let (body, instruction) = code_location(&code, relocation.offset)
+                            .ok_or(LinkError::MalformedObject(index))?;
+                        object.code_relocations[body].insert(instruction, relocation);
+                    }
+                    Some(DATA_SECTION) => {
+                        let segment = segments
+                            .iter()
+                            .position(|&(start, size)| {
+                                start <= relocation.offset
+                                    && size
+                                        .checked_sub(4)
+                                        .is_some_and(|last| relocation.offset - start <= last)
+                            })
+                            .ok_or(LinkError::MalformedObject(index))?;
+                        object.data_relocations.push(DataRelocation {
+                            segment,
+                            offset: relocation.offset - segments[segment].0,
+                            relocation,
+                        });
+                    }
+                    // Custom sections, and so their relocations, aren't linked
+                    _ => {}
+                }
+            }
+        }
+        if !object.is_consistent() || object.code_relocations.len() != object.functions.len() {
+            return Err(LinkError::MalformedObject(index));
+        }
+        Ok(object)
+    }
+
+    /** Whether the symbols and segments are all in the module */
+    fn is_consistent(&self) -> bool {
+        let function_count = self.function_imports.len() + self.functions.len();
+        let global_count = self.global_imports.len() + self.globals.len();
+        let is_symbol_consistent = |symbol: &Symbol| match &symbol.kind {
+            SymbolKind::Function(index) => (index.0 as usize) < function_count,
+            SymbolKind::Global(index) => (index.0 as usize) < global_count,
+            SymbolKind::Data(Some(location)) => self
+                .data
+                .get(location.segment as usize)
+                .is_some_and(|data| {
+                    location.offset as u64 + location.size as u64 <= data.initializer.len() as u64
+                }),
+            _ => true,
+        };
+        self.linking.symbols.iter().all(is_symbol_consistent)
+            && self.linking.segments.len() <= self.data.len()
+            && self.linking.segments.iter().all(|info| info.alignment < 32)
+            && self.function_types.len() == self.functions.len()
+            && self
+                .function_types
+                .iter()
+                .all(|type_index| (type_index.0 as usize) < self.types.len())
+    }
+
+    /** The alignment of a data segment, in bytes */
+    fn segment_alignment(&self, segment: usize) -> u32 {
+        1 << self
+            .linking
+            .segments
+            .get(segment)
+            .map_or(0, |info| info.alignment)
+    }
+
+    /** The name a function or global is resolved by, unless it's local */
+    fn resolved_name(&self, kind: &SymbolKind) -> Option<&str> {
+        let import = match kind {
+            SymbolKind::Function(index) => self.function_imports.get(index.0 as usize),
+            SymbolKind::Global(index) => self.global_imports.get(index.0 as usize),
+            _ => None,
+        };
+        let symbol = self
+            .linking
+            .symbols
+            .iter()
+            .find(|symbol| symbol.kind == *kind);
+        match (symbol, import) {
+            (Some(symbol), _) if symbol.is_local() => None,
+            (Some(symbol), Some(import)) if symbol.name.is_empty() => Some(&import.name),
+            (Some(symbol), _) => Some(&symbol.name),
+            (None, Some(import)) => Some(&import.name),
+            (None, None) => None,
+        }
+    }
+}
+
+/** The offset and size of each data segment's bytes, in the data section's contents */
+fn segment_layout(bytes: &[u8]) -> Result<Vec<(u32, u32)>, DecodeError> {
+    let mut decoder = WasmDecoder::new(bytes);
+    decoder.read_bytes(8)?; // magic number and version
+    while !decoder.is_empty() {
+        let id = decoder.read_u8()?;
+        let size = decoder.read_leb_u32()? as usize;
+        let start = decoder.position();
+        if id != DATA_SECTION {
+            decoder.read_bytes(size)?;
+            continue;
+        }
+        let mut segments = vec![];
+        for _ in 0..decoder.read_leb_u32()? {
+            decoder.read_leb_u32()?; // memory index
+            Expression::decode(&mut decoder)?;
+            let size = decoder.read_leb_u32()?;
+            segments.push(((decoder.position() - start) as u32, size));
+            decoder.read_bytes(size as usize)?;
+        }
+        return Ok(segments);
+    }
+    Ok(vec![])
+}
+
+/** The function body and instruction number at an offset in the code section */
+fn code_location(code: &CodeLayout, offset: u32) -> Option<(usize, u32)> {
+    let offset = code.start.checked_add(offset)?;
+    let body = code
+        .functions
+        .iter()
+        .position(|function| function.start <= offset && offset < function.end)?;
+    let instructions = &code.functions[body].instructions;
+    let instruction = instructions
+        .partition_point(|&start| start <= offset)
+        .checked_sub(1)?;
+    Some((body, instruction as u32))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Definition {
+    /** A function of an object, by its index there */
+    Function(usize, FunctionIndex),
+    Global(usize, GlobalIndex),
+    /** A data symbol of an object, by its index in the symbol table */
+    Data(usize, usize),
+}
+
+/** Finds the definition each name resolves to */
+fn resolve(objects: &[Object]) -> Result<HashMap<&str, Definition>, LinkError> {
+    let mut definitions: HashMap<&str, (Definition, bool)> = HashMap::new();
+    for (object_index, object) in objects.iter().enumerate() {
+        for (symbol_index, symbol) in object.linking.symbols.iter().enumerate() {
+            if symbol.is_local() || symbol.is_undefined() {
+                continue;
+            }
+            let definition = match symbol.kind {
+                SymbolKind::Function(index) => Definition::Function(object_index, index),
+                SymbolKind::Global(index) => Definition::Global(object_index, index),
+                SymbolKind::Data(Some(_)) => Definition::Data(object_index, symbol_index),
+                _ => continue,
+            };
+            match definitions.get(symbol.name.as_str()) {
+                Some((existing, _))
+                    if mem::discriminant(existing) != mem::discriminant(&definition) =>
+                {
+                    return Err(LinkError::SymbolKindMismatch(symbol.name.clone()))
+                }
+                Some((_, false)) if !symbol.is_weak() => {
+                    return Err(LinkError::DuplicateSymbol(symbol.name.clone()))
+                }
+                Some(_) if symbol.is_weak() => continue,
+                _ => {}
+            }
+            definitions.insert(&symbol.name, (definition, symbol.is_weak()));
+        }
+    }
+    Ok(definitions
+        .into_iter()
+        .map(|(name, (definition, _))| (name, definition))
+        .collect())
+}
+
+fn intern_type(types: &mut Vec<FunctionType>, function_type: &FunctionType) -> TypeIndex {
+    match types.iter().position(|existing| existing == function_type) {
+        Some(index) => TypeIndex(index as u32),
+        None => {
+            types.push(function_type.clone());
+            TypeIndex(types.len() as u32 - 1)
+        }
+    }
+}
+
+/** The state of one link, as the objects are put together */
+struct Linking<'a> {
+    objects: &'a [Object],
+    definitions: HashMap<&'a str, Definition>,
+    types: Vec<FunctionType>,
+    /** The final index of each object's types, by their index in the object */
+    type_maps: Vec<Vec<TypeIndex>>,
+    function_imports: Vec<Import>,
+    global_imports: Vec<Import>,
+    /** The final index of each object's first function body */
+    function_bases: Vec<u32>,
+    global_bases: Vec<u32>,
+    /**
+     * Whether the linker defines `__stack_pointer`, as the first global after
+     * the imports, because an object uses it and none defines it
+     */
+    defines_stack_pointer: bool,
+    function_maps: Vec<Vec<FunctionIndex>>,
+    global_maps: Vec<Vec<GlobalIndex>>,
+    /** The address of each object's data segments */
+    segment_addresses: Vec<Vec<u32>>,
+    table: FunctionTable,
+}
+
+impl<'a> Linking<'a> {
+    fn new(objects: &'a [Object], allow_undefined: bool) -> Result<Linking<'a>, LinkError> {
+        let mut linking = Linking {
+            objects,
+            definitions: resolve(objects)?,
+            types: vec![],
+            type_maps: vec![],
+            function_imports: vec![],
+            global_imports: vec![],
+            function_bases: vec![],
+            global_bases: vec![],
+            defines_stack_pointer: false,
+            function_maps: vec![],
+            global_maps: vec![],
+            segment_addresses: vec![],
+            table: FunctionTable::with_base(TableIndex(0), TABLE_BASE),
+        };
+        for object in objects {
+            let type_map = object
+                .types
+                .iter()
+                .map(|function_type| intern_type(&mut linking.types, function_type))
+                .collect();
+            linking.type_maps.push(type_map);
+        }
+        linking.add_imports(allow_undefined)?;
+
+        let mut function_base = linking.function_imports.len() as u32;
+        let mut global_base = linking.global_imports.len() as u32;
+        if linking.defines_stack_pointer {
+            global_base += 1;
+        }
+        for object in objects {
+            linking.function_bases.push(function_base);
+            linking.global_bases.push(global_base);
+            function_base += object.functions.len() as u32;
+            global_base += object.globals.len() as u32;
+        }
+        for (object_index, object) in objects.iter().enumerate() {
+            let function_count = object.function_imports.len() + object.functions.len();
+            let function_map = (0..function_count as u32)
+                .map(|index| linking.function_index(object_index, FunctionIndex(index)))
+                .collect::<Result<_, _>>()?;
+            let global_count = object.global_imports.len() + object.globals.len();
+            let global_map = (0..global_count as u32)
+                .map(|index| linking.global_index(object_index, GlobalIndex(index)))
+                .collect::<Result<_, _>>()?;
+            linking.function_maps.push(function_map);
+            linking.global_maps.push(global_map);
+        }
+        Ok(linking)
+    }
+
+    /**
+     * Imports the functions and globals no object defines, and checks that
+     * every undefined symbol is defined as the same kind.
+     */
+    fn add_imports(&mut self, allow_undefined: bool) -> Result<(), LinkError> {
+        let objects = self.objects;
+        for (object_index, object) in objects.iter().enumerate() {
+            let functions = object
+                .function_imports
+                .iter()
+                .enumerate()
+                .map(|(index, import)| (SymbolKind::Function(FunctionIndex(index as u32)), import));
+            let globals = object
+                .global_imports
+                .iter()
+                .enumerate()
+                .map(|(index, import)| (SymbolKind::Global(GlobalIndex(index as u32)), import));
+            for (kind, import) in functions.chain(globals) {
+                let name = object.resolved_name(&kind).unwrap_or(&import.name);
+                match (&kind, self.definitions.get(name)) {
+                    (SymbolKind::Function(_), Some(Definition::Function(..)))
+                    | (SymbolKind::Global(_), Some(Definition::Global(..))) => {}
+                    (_, Some(_)) => return Err(LinkError::SymbolKindMismatch(name.to_owned())),
+                    (SymbolKind::Global(_), None) if name == STACK_POINTER => {
+                        self.defines_stack_pointer = true;
+                    }
+                    (_, None) if allow_undefined => self.add_import(object_index, import)?,
+                    (_, None) => return Err(LinkError::UndefinedSymbol(name.to_owned())),
+                }
+            }
+            for symbol in object.linking.symbols.iter() {
+                if let SymbolKind::Data(None) = symbol.kind {
+                    match self.definitions.get(symbol.name.as_str()) {
+                        Some(Definition::Data(..)) => {}
+                        Some(_) => return Err(LinkError::SymbolKindMismatch(symbol.name.clone())),
+                        None => return Err(LinkError::UndefinedSymbol(symbol.name.clone())),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_import(&mut self, object: usize, import: &Import) -> Result<(), LinkError> {
+        let (imports, descriptor) = match &import.descriptor {
+            ImportDescriptor::TypeIndex(type_index) => {
+                let type_index = self.type_maps[object]
+                    .get(type_index.0 as usize)
+                    .ok_or(LinkError::MalformedObject(object))?;
+                (
+                    &mut self.function_imports,
+                    ImportDescriptor::TypeIndex(*type_index),
+                )
+            }
+            descriptor => (&mut self.global_imports, descriptor.clone()),
+        };
+        if find_import(imports, import).is_none() {
+            imports.push(Import::new(&import.module_name, &import.name, descriptor));
+        }
+        Ok(())
+    }
+
+    fn function_index(
+        &self,
+        object: usize,
+        index: FunctionIndex,
+    ) -> Result<FunctionIndex, LinkError> {
+        let source = &self.objects[object];
+        let name = source.resolved_name(&SymbolKind::Function(index));
+        if let Some(Definition::Function(definer, definition)) =
+            name.and_then(|name| self.definitions.get(name))
+        {
+            let imports = self.objects[*definer].function_imports.len() as u32;
+            return Ok(FunctionIndex(
+                self.function_bases[*definer] + definition.0 - imports,
+            ));
+        }
+        match source.function_imports.get(index.0 as usize) {
+            // Every import that isn't resolved is imported by the linked module
+            Some(import) => find_import(&self.function_imports, import)
+                .map(FunctionIndex)
+                .ok_or(LinkError::MalformedObject(object)),
+            None => Ok(FunctionIndex(
+                self.function_bases[object] + index.0 - source.function_imports.len() as u32,
+            )),
+        }
+    }
+
+    fn global_index(&self, object: usize, index: GlobalIndex) -> Result<GlobalIndex, LinkError> {
+        let source = &self.objects[object];
+        let name = source.resolved_name(&SymbolKind::Global(index));
+        if let Some(Definition::Global(definer, definition)) =
+            name.and_then(|name| self.definitions.get(name))
+        {
+            let imports = self.objects[*definer].global_imports.len() as u32;
+            return Ok(GlobalIndex(
+                self.global_bases[*definer] + definition.0 - imports,
+            ));
+        }
+        match source.global_imports.get(index.0 as usize) {
+            Some(_) if name == Some(STACK_POINTER) && self.defines_stack_pointer => {
+                Ok(GlobalIndex(self.global_imports.len() as u32))
+            }
+            Some(import) => find_import(&self.global_imports, import)
+                .map(GlobalIndex)
+                .ok_or(LinkError::MalformedObject(object)),
+            None => Ok(GlobalIndex(
+                self.global_bases[object] + index.0 - source.global_imports.len() as u32,
+            )),
+        }
+    }
+
+    /** The address of a data symbol, following it to its definition */
+    fn data_address(&self, object: usize, symbol: &Symbol) -> Result<u32, LinkError> {
+        let (object, symbol) = match self.definitions.get(symbol.name.as_str()) {
+            _ if symbol.is_local() => (object, symbol),
+            Some(Definition::Data(definer, symbol_index)) => (
+                *definer,
+                &self.objects[*definer].linking.symbols[*symbol_index],
+            ),
+            _ => return Err(LinkError::UndefinedSymbol(symbol.name.clone())),
+        };
+        match symbol.kind {
+            SymbolKind::Data(Some(location)) => self.segment_addresses[object]
+                .get(location.segment as usize)
+                .map(|address| address + location.offset)
+                .ok_or(LinkError::MalformedObject(object)),
+            _ => Err(LinkError::MalformedObject(object)),
+        }
+    }
+
+    /** The value a relocation writes, giving functions a table slot if it's asked for */
+    fn relocation_value(
+        &mut self,
+        object: usize,
+        relocation: &Relocation,
+    ) -> Result<u32, LinkError> {
+        if relocation.relocation_type == RelocationType::TypeIndexLeb {
+            return self.type_maps[object]
+                .get(relocation.index as usize)
+                .map(|type_index| type_index.0)
+                .ok_or(LinkError::MalformedObject(object));
+        }
+        let objects = self.objects;
+        let symbol = objects[object]
+            .linking
+            .symbols
+            .get(relocation.index as usize)
+            .ok_or(LinkError::MalformedObject(object))?;
+        Ok(match (relocation.relocation_type, &symbol.kind) {
+            (RelocationType::FunctionIndexLeb, SymbolKind::Function(index)) => {
+                self.function_maps[object][index.0 as usize].0
+            }
+            (
+                RelocationType::TableIndexSleb | RelocationType::TableIndexI32,
+                SymbolKind::Function(index),
+            ) => {
+                let index = self.function_maps[object][index.0 as usize];
+                self.table.slot_for(index)
+            }
+            (
+                RelocationType::MemoryAddressLeb
+                | RelocationType::MemoryAddressSleb
+                | RelocationType::MemoryAddressI32,
+                SymbolKind::Data(_),
+            ) => self
+                .data_address(object, symbol)?
+                .wrapping_add(relocation.addend as u32),
+            (RelocationType::GlobalIndexLeb, SymbolKind::Global(index)) => {
+                self.global_maps[object][index.0 as usize].0
+            }
+            (
+                relocation_type @ (RelocationType::FunctionOffsetI32
+                | RelocationType::SectionOffsetI32),
+                _,
+            ) => return Err(LinkError::UnsupportedRelocation(object, relocation_type)),
+            _ => return Err(LinkError::MalformedObject(object)),
+        })
+    }
+
+    /**
+     * Renumbers the indices in instructions, and applies the relocations of
+     * those they're for, counting instructions from `number`.
+     */
+    fn relocate_instructions(
+        &mut self,
+        object: usize,
+        instructions: &mut [Instruction],
+        relocations: &BTreeMap<u32, Relocation>,
+        number: &mut u32,
+    ) -> Result<(), LinkError> {
+        walk_mut(instructions, &mut |instruction| {
+            match relocations.get(number) {
+                Some(relocation) => self.relocate(object, instruction, relocation)?,
+                None => self.renumber(object, instruction)?,
+            }
+            *number += 1;
+            Ok(())
+        })
+    }
+
+    fn relocate(
+        &mut self,
+        object: usize,
+        instruction: &mut Instruction,
+        relocation: &Relocation,
+    ) -> Result<(), LinkError> {
+        let value = self.relocation_value(object, relocation)?;
+        match (relocation.relocation_type, instruction) {
+            (RelocationType::FunctionIndexLeb, Instruction::Call(index)) => {
+                *index = FunctionIndex(value)
+            }
+            (RelocationType::TypeIndexLeb, Instruction::CallIndirect(index)) => {
+                *index = TypeIndex(value)
+            }
+            (
+                RelocationType::GlobalIndexLeb,
+                Instruction::GlobalGet(index) | Instruction::GlobalSet(index),
+            ) => *index = GlobalIndex(value),
+            (
+                RelocationType::TableIndexSleb | RelocationType::MemoryAddressSleb,
+                Instruction::I32Const(constant),
+            ) => *constant = value as i32,
+            (RelocationType::MemoryAddressLeb, instruction) => {
+                match instruction.memory_arguments_mut() {
+                    Some(arguments) => arguments.offset = value,
+                    None => return Err(LinkError::MalformedObject(object)),
+                }
+            }
+            _ => return Err(LinkError::MalformedObject(object)),
+        }
+        Ok(())
+    }
+
+    /** Renumbers the index of an instruction without a relocation */
+    fn renumber(&self, object: usize, instruction: &mut Instruction) -> Result<(), LinkError> {
+        let malformed = LinkError::MalformedObject(object);
+        match instruction {
+            Instruction::Call(index) => {
+                *index = *self.function_maps[object]
+                    .get(index.0 as usize)
+                    .ok_or(malformed)?
+            }
+            Instruction::CallIndirect(index) => {
+                *index = *self.type_maps[object]
+                    .get(index.0 as usize)
+                    .ok_or(malformed)?
+            }
+            Instruction::GlobalGet(index) | Instruction::GlobalSet(index) => {
+                *index = *self.global_maps[object]
+                    .get(index.0 as usize)
+                    .ok_or(malformed)?
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn relocate_segment(&mut self, object: usize, segment: usize) -> Result<Vec<u8>, LinkError> {
+        let objects = self.objects;
+        let mut bytes = objects[object].data[segment].initializer.clone();
+        for relocation in objects[object]
+            .data_relocations
+            .iter()
+            .filter(|relocation| relocation.segment == segment)
+        {
+            let value = self.relocation_value(object, &relocation.relocation)?;
+            match relocation.relocation.relocation_type {
+                RelocationType::MemoryAddressI32 | RelocationType::TableIndexI32 => {}
+                _ => return Err(LinkError::MalformedObject(object)),
+            }
+            let offset = relocation.offset as usize;
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        Ok(bytes)
+    }
+
+    /** The functions to call from `__wasm_call_ctors`, in order */
+    fn init_functions(&self) -> Result<Vec<FunctionIndex>, LinkError> {
+        let mut init_functions = vec![];
+        for (object_index, object) in self.objects.iter().enumerate() {
+            for init_function in object.linking.init_functions.iter() {
+                let function = match object.linking.symbols.get(init_function.symbol as usize) {
+                    Some(Symbol {
+                        kind: SymbolKind::Function(index),
+                        ..
+                    }) => self.function_maps[object_index].get(index.0 as usize),
+                    _ => None,
+                };
+                let function = function.ok_or(LinkError::MalformedObject(object_index))?;
+                init_functions.push((init_function.priority, *function));
+            }
+        }
+        init_functions.sort_by_key(|(priority, _)| *priority);
+        Ok(init_functions
+            .into_iter()
+            .map(|(_, function)| function)
+            .collect())
+    }
+
+    /** The memory, the symbols with `SYMBOL_EXPORTED`, and the symbols named in `names` */
+    fn exports(&self, names: &[String]) -> Result<Vec<Export>, LinkError> {
+        let mut exports = vec![Export::new(
+            "memory",
+            ExportDescriptor::MemoryIndex(MemoryIndex(0)),
+        )];
+        for name in names {
+            let descriptor = match self.definitions.get(name.as_str()) {
+                Some(Definition::Function(object, index)) => {
+                    ExportDescriptor::FunctionIndex(self.function_maps[*object][index.0 as usize])
+                }
+                Some(Definition::Global(object, index)) => {
+                    ExportDescriptor::GlobalIndex(self.global_maps[*object][index.0 as usize])
+                }
+                Some(Definition::Data(..)) => {
+                    return Err(LinkError::SymbolKindMismatch(name.clone()))
+                }
+                None => return Err(LinkError::UndefinedSymbol(name.clone())),
+            };
+            exports.push(Export::new(name, descriptor));
+        }
+        for (object_index, object) in self.objects.iter().enumerate() {
+            for symbol in object.linking.symbols.iter() {
+                if symbol.flags & SYMBOL_EXPORTED == 0 || symbol.is_undefined() {
+                    continue;
+                }
+                let descriptor = match &symbol.kind {
+                    SymbolKind::Function(index) => ExportDescriptor::FunctionIndex(
+                        self.function_maps[object_index][index.0 as usize],
+                    ),
+                    SymbolKind::Global(index) => ExportDescriptor::GlobalIndex(
+                        self.global_maps[object_index][index.0 as usize],
+                    ),
+                    _ => continue,
+                };
+                if exports.iter().all(|export| export.name != symbol.name) {
+                    exports.push(Export::new(&symbol.name, descriptor));
+                }
+            }
+        }
+        Ok(exports)
+    }
+}
+
+fn find_import(imports: &[Import], import: &Import) -> Option<u32> {
+    imports
+        .iter()
+        .position(|existing| {
+            existing.module_name == import.module_name && existing.name == import.name
+        })
+        .map(|index| index as u32)
+}
+
+/**
+ * Links relocatable objects into a module. Undefined symbols are an error,
+ * unless `allow_undefined` is called, when undefined functions and globals
+ * are imported as the objects import them. A `__stack_pointer` that no
+ * object defines is defined at the end of a stack after the data.
+ */
+pub struct Linker {
+    objects: Vec<Object>,
+    exports: Vec<String>,
+    allow_undefined: bool,
+    stack_size: u32,
+}
+
+impl Default for Linker {
+    fn default() -> Linker {
+        Linker {
+            objects: vec![],
+            exports: vec![],
+            allow_undefined: false,
+            stack_size: DEFAULT_STACK_SIZE,
+        }
+    }
+}
+
+impl Linker {
+    pub fn new() -> Linker {
+        Linker::default()
+    }
+
+    /** Adds an encoded object, which errors name by the order it was added in */
+    pub fn add_object(&mut self, bytes: &[u8]) -> Result<(), LinkError> {
+        let object = Object::decode(self.objects.len(), bytes)?;
+        self.objects.push(object);
+        Ok(())
+    }
+
+    /** Exports a function or global, as if it had `SYMBOL_EXPORTED` */
+    pub fn export(&mut self, name: &str) {
+        self.exports.push(name.to_owned());
+    }
+
+    pub fn allow_undefined(&mut self) {
+        self.allow_undefined = true;
+    }
+
+    pub fn set_stack_size(&mut self, size: u32) {
+        self.stack_size = size;
+    }
+
+    pub fn link(&self) -> Result<Module, LinkError> {
+        let mut linking = Linking::new(&self.objects, self.allow_undefined)?;
+
+        // The addresses of the segments are needed before their contents,
+        // which can have addresses in them
+        let mut layout = MemoryLayout::with_base(MemoryIndex(0), GLOBAL_BASE);
+        for object in self.objects.iter() {
+            let mut addresses = vec![];
+            for (segment, data) in object.data.iter().enumerate() {
+                let size = data.initializer.len() as u32;
+                addresses.push(
+                    layout
+                        .reserve(size, object.segment_alignment(segment))
+                        .start,
+                );
+            }
+            linking.segment_addresses.push(addresses);
+        }
+
+        let mut function_types = vec![];
+        let mut functions = vec![];
+        let mut globals = vec![];
+        let mut layout = MemoryLayout::with_base(MemoryIndex(0), GLOBAL_BASE);
+        for (object_index, object) in self.objects.iter().enumerate() {
+            for (body, function) in object.functions.iter().enumerate() {
+                let type_index = object.function_types[body];
+                function_types.push(linking.type_maps[object_index][type_index.0 as usize]);
+                let mut function = function.clone();
+                linking.relocate_instructions(
+                    object_index,
+                    &mut function.expression.0,
+                    &object.code_relocations[body],
+                    &mut 0,
+                )?;
+                functions.push(function);
+            }
+            for global in object.globals.iter() {
+                let mut global = global.clone();
+                let (Global::Const(_, expression) | Global::Var(_, expression)) = &mut global;
+                linking.relocate_instructions(
+                    object_index,
+                    &mut expression.0,
+                    &BTreeMap::new(),
+                    &mut 0,
+                )?;
+                globals.push(global);
+            }
+            for segment in 0..object.data.len() {
+                let bytes = linking.relocate_segment(object_index, segment)?;
+                let alignment = object.segment_alignment(segment);
+                // Zeroed segments, like .bss, don't need to be in the data section
+                if bytes.iter().all(|&byte| byte == 0) {
+                    layout.reserve(bytes.len() as u32, alignment);
+                } else {
+                    layout.alloc(&bytes, alignment);
+                }
+            }
+        }
+        if linking.defines_stack_pointer {
+            let stack = layout.reserve_stack(self.stack_size, STACK_ALIGNMENT);
+            globals.insert(
+                0,
+                Global::Var(
+                    ValueType::I32,
+                    Expression(vec![Instruction::I32Const(stack.end() as i32)]),
+                ),
+            );
+        }
+
+        let call_ctors =
+            FunctionIndex(linking.function_imports.len() as u32 + functions.len() as u32);
+        let ctors_type = intern_type(&mut linking.types, &FunctionType::new(vec![], vec![]));
+        function_types.push(ctors_type);
+        functions.push(Function::new(
+            vec![],
+            Expression(
+                linking
+                    .init_functions()?
+                    .into_iter()
+                    .map(Instruction::Call)
+                    .collect(),
+            ),
+        ));
+        let mut exports = linking.exports(&self.exports)?;
+        exports.push(Export::new(
+            CALL_CTORS,
+            ExportDescriptor::FunctionIndex(call_ctors),
+        ));
+
+        let memory_min = self
+            .objects
+            .iter()
+            .map(|object| object.memory_min)
+            .fold(layout.limits().min, u32::max);
+        let table_min = self
+            .objects
+            .iter()
+            .filter_map(|object| object.table_min)
+            .max();
+
+        let mut sections = vec![Section::TypeSection(TypeSection(linking.types))];
+        let imports: Vec<Import> = linking
+            .function_imports
+            .into_iter()
+            .chain(linking.global_imports)
+            .collect();
+        if !imports.is_empty() {
+            sections.push(Section::ImportSection(ImportSection(imports)));
+        }
+        sections.push(Section::FunctionSection(FunctionSection(function_types)));
+        if table_min.is_some() || linking.table.size() > TABLE_BASE {
+            let size = table_min.unwrap_or(0).max(linking.table.size());
+            sections.push(Section::TableSection(TableSection(vec![Table::new(
+                ElementType::FunctionReference,
+                Limits::min(size),
+            )])));
+        }
+        sections.push(Section::MemorySection(MemorySection(vec![Memory::new(
+            Limits::min(memory_min),
+        )])));
+        if !globals.is_empty() {
+            sections.push(Section::GlobalSection(GlobalSection(globals)));
+        }
+        sections.push(Section::ExportSection(ExportSection(exports)));
+        if linking.table.size() > TABLE_BASE {
+            sections.push(Section::ElementSection(linking.table.element_section()));
+        }
+        sections.push(Section::CodeSection(CodeSection(functions)));
+        let data = layout.data_section();
+        if !data.0.is_empty() {
+            sections.push(Section::DataSection(data));
+        }
+        Ok(Module(sections))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encoder::{WasmEncode, WasmEncoder},
+        object::{ObjectSymbols, Reference},
+        section::{custom_section::CustomSection, linking_section::SYMBOL_BINDING_WEAK},
+    };
+
+    /** Calls `helper`, and loads from the address of `value` */
+    fn caller() -> Vec<u8> {
+        let module: Module = r#"(module
+            (import "env" "print" (func $print (param i32)))
+            (import "env" "helper" (func $helper (param i32) (result i32)))
+            (func $main (export "main")
+              (call $print (call $helper (i32.load (i32.const 0))))))"#
+            .parse()
+            .unwrap();
+        let mut symbols = ObjectSymbols::new();
+        let value = symbols.import_data("value");
+        symbols.add_code_reference(FunctionIndex(2), 0, Reference::MemoryAddress(value, 4));
+        module.encode_object(&symbols).unwrap()
+    }
+
+    /** Defines `helper`, which is also in the table, and `value` */
+    fn callee() -> Vec<u8> {
+        let module: Module = r#"(module
+            (global $counter (mut i32) (i32.const 0))
+            (memory 1)
+            (func $helper (export "helper") (param i32) (result i32)
+              (i32.add (local.get 0) (global.get $counter)))
+            (func $init (global.set $counter (i32.const 100)))
+            (start $init)
+            (data (i32.const 0) "\00\00\00\00\2a\00\00\00"))"#
+            .parse()
+            .unwrap();
+        let mut symbols = ObjectSymbols::new();
+        symbols.define_data("value", 0, 0, 8);
+        symbols.add_data_reference(0, 0, Reference::TableIndex(FunctionIndex(0)));
+        module.encode_object(&symbols).unwrap()
+    }
+
+    fn link(objects: &[Vec<u8>]) -> Result<Module, LinkError> {
+        let mut linker = Linker::new();
+        for object in objects {
+            linker.add_object(object)?;
+        }
+        linker.allow_undefined();
+        linker.link()
+    }
+
+    /**
+     * Changes the linking section of an object, in place, since encoding a
+     * decoded object would lose the padding of its relocations.
+     */
+    fn with_linking(object: &[u8], change: impl FnOnce(&mut LinkingSection)) -> Vec<u8> {
+        let mut decoder = WasmDecoder::new(object);
+        decoder.read_bytes(8).unwrap();
+        loop {
+            let start = decoder.position();
+            let section = Section::decode(&mut decoder).unwrap();
+            if let Section::Custom { name, payload } = section {
+                if name == "linking" {
+                    let mut linking =
+                        LinkingSection::decode(&mut WasmDecoder::new(&payload)).unwrap();
+                    change(&mut linking);
+                    let mut encoder = WasmEncoder::new();
+                    linking.to_section().encode(&mut encoder);
+                    let end = decoder.position();
+                    return [&object[..start], encoder.as_slice(), &object[end..]].concat();
+                }
+            }
+        }
+    }
+
+    fn code(module: &Module) -> &[Function] {
+        module
+            .0
+            .iter()
+            .find_map(|section| match section {
+                Section::CodeSection(code) => Some(&code.0[..]),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_link() {
+        let module = link(&[caller(), callee()]).unwrap();
+        let section_ids: Vec<u8> = module.0.iter().map(Section::id).collect();
+        assert_eq!(section_ids, vec![1, 2, 3, 4, 5, 6, 7, 9, 10, 11]);
+        match &module.0[1] {
+            Section::ImportSection(imports) => {
+                assert_eq!(imports.0.len(), 1);
+                assert_eq!(imports.0[0].name, "print");
+            }
+            _ => unreachable!(),
+        }
+        match &module.0[6] {
+            Section::ExportSection(exports) => {
+                let names: Vec<&str> = exports.0.iter().map(|export| &export.name[..]).collect();
+                assert_eq!(names, vec!["memory", "main", "helper", CALL_CTORS]);
+            }
+            _ => unreachable!(),
+        }
+
+        // `helper` follows `main`, and the ctors function is last
+        let code = code(&module);
+        assert_eq!(
+            code[0].expression.0,
+            vec![
+                Instruction::I32Const(1028),
+                Instruction::I32Load(crate::expression::MemoryArguments::new(0, 2)),
+                Instruction::Call(FunctionIndex(2)),
+                Instruction::Call(FunctionIndex(0)),
+            ]
+        );
+        assert_eq!(
+            code[1].expression.0[1],
+            Instruction::GlobalGet(GlobalIndex(0))
+        );
+        assert_eq!(
+            code[3].expression.0,
+            vec![Instruction::Call(FunctionIndex(3))]
+        );
+        match &module.0[9] {
+            Section::DataSection(data) => {
+                assert_eq!(
+                    data.0[0].offset.0,
+                    vec![Instruction::I32Const(GLOBAL_BASE as i32)]
+                );
+                assert_eq!(data.0[0].initializer, vec![1, 0, 0, 0, 0x2a, 0, 0, 0]);
+            }
+            _ => unreachable!(),
+        }
+        match &module.0[7] {
+            Section::ElementSection(elements) => {
+                assert_eq!(elements.0[0].initializer, vec![FunctionIndex(2)])
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_exports() {
+        let mut linker = Linker::new();
+        linker.add_object(&caller()).unwrap();
+        linker.add_object(&callee()).unwrap();
+        linker.allow_undefined();
+        linker.export("helper");
+        let module = linker.link().unwrap();
+        match &module.0[6] {
+            Section::ExportSection(exports) => assert_eq!(
+                exports.0[1],
+                Export::new("helper", ExportDescriptor::FunctionIndex(FunctionIndex(2)))
+            ),
+            _ => unreachable!(),
+        }
+
+        linker.export("value");
+        assert_eq!(
+            linker.link(),
+            Err(LinkError::SymbolKindMismatch("value".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_stack_pointer() {
+        let module: Module = r#"(module
+            (import "env" "__stack_pointer" (global $sp (mut i32)))
+            (func $f (global.set $sp (i32.sub (global.get $sp) (i32.const 16)))))"#
+            .parse()
+            .unwrap();
+        let object = module.encode_object(&ObjectSymbols::new()).unwrap();
+        let mut linker = Linker::new();
+        linker.add_object(&object).unwrap();
+        linker.set_stack_size(1024);
+        let module = linker.link().unwrap();
+        let globals = module
+            .0
+            .iter()
+            .find_map(|section| match section {
+                Section::GlobalSection(globals) => Some(globals),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            globals.0,
+            vec![Global::Var(
+                ValueType::I32,
+                Expression(vec![Instruction::I32Const(2048)])
+            )]
+        );
+        assert_eq!(
+            code(&module)[0].expression.0[0],
+            Instruction::GlobalGet(GlobalIndex(0))
+        );
+    }
+
+    #[test]
+    fn test_weak_symbols() {
+        let make_weak = |linking: &mut LinkingSection| {
+            for symbol in linking.symbols.iter_mut() {
+                if symbol.name == "helper" {
+                    symbol.flags |= SYMBOL_BINDING_WEAK;
+                }
+            }
+        };
+        let weak = with_linking(&callee(), make_weak);
+        let strong: Module = r#"(module
+            (func $helper (export "helper") (param i32) (result i32) (local.get 0)))"#
+            .parse()
+            .unwrap();
+        let strong = strong.encode_object(&ObjectSymbols::new()).unwrap();
+        let module = link(&[caller(), weak, strong]).unwrap();
+        assert_eq!(
+            code(&module)[0].expression.0[2],
+            Instruction::Call(FunctionIndex(4))
+        );
+
+        assert_eq!(
+            link(&[caller(), callee(), callee()]).err(),
+            Some(LinkError::DuplicateSymbol("helper".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut linker = Linker::new();
+        linker.add_object(&caller()).unwrap();
+        linker.add_object(&callee()).unwrap();
+        assert_eq!(
+            linker.link(),
+            Err(LinkError::UndefinedSymbol("print".to_string()))
+        );
+        assert_eq!(
+            link(&[caller()]).err(),
+            Some(LinkError::UndefinedSymbol("value".to_string()))
+        );
+
+        let rename = |linking: &mut LinkingSection| {
+            for symbol in linking.symbols.iter_mut() {
+                if symbol.name == "helper" {
+                    symbol.name = "value".to_string();
+                }
+            }
+        };
+        assert_eq!(
+            link(&[callee(), with_linking(&callee(), rename)]).err(),
+            Some(LinkError::SymbolKindMismatch("value".to_string()))
+        );
+
+        let mut encoder = WasmEncoder::new();
+        Module(vec![]).encode(&mut encoder);
+        assert_eq!(
+            Linker::new().add_object(encoder.as_slice()),
+            Err(LinkError::NotRelocatable(0))
+        );
+        assert_eq!(
+            Linker::new().add_object(b"\0asm"),
+            Err(LinkError::Decode(0, DecodeError::UnexpectedEnd))
+        );
+    }
+}
@@ -1,15 +1,14 @@
 use std::{fs::File, io, io::prelude::*};
 
-use crate::{
+use wasmuter::{
     encoder::{WasmEncode, WasmEncoder},
     expression::{Expression, Instruction, MemoryArguments},
     function_type::{FunctionType, ValueType},
     index::{FunctionIndex, MemoryIndex, TypeIndex},
-    limits::Limits,
+    memory_layout::MemoryLayout,
     module::Module,
     section::{
         code_section::{CodeSection, Function},
-        data_section::{Data, DataSection},
         export_section::{Export, ExportDescriptor, ExportSection},
         function_section::FunctionSection,
         import_section::{Import, ImportDescriptor, ImportSection},
@@ -17,17 +16,9 @@ use crate::{
         type_section::TypeSection,
         Section,
     },
+    string_pool::StringPool,
 };
 
-mod constants;
-mod encoder;
-mod expression;
-mod function_type;
-mod index;
-mod limits;
-mod module;
-mod section;
-
 fn main() -> io::Result<()> {
     let wasm_module = hello_world_example();
     let mut encoder = WasmEncoder::new();
@@ -47,6 +38,14 @@ fn hello_world_example() -> Module {
     let write_type = TypeIndex(0);
     let hello_world_fn = FunctionIndex(1);
     let hello_world_type = TypeIndex(1);
+
+    let mut layout = MemoryLayout::new(memory);
+    let iovec = layout.reserve(8, 4);
+    let bytes_written = layout.reserve(4, 4);
+    let mut strings = StringPool::new(layout.size());
+    let hello_world = strings.intern("hello world!\n");
+    strings.place(&mut layout);
+
     Module(vec![
         Section::TypeSection(TypeSection(vec![
             FunctionType::new(
@@ -66,7 +65,7 @@ fn hello_world_example() -> Module {
             ImportDescriptor::TypeIndex(write_type),
         )])),
         Section::FunctionSection(FunctionSection(vec![hello_world_type])),
-        Section::MemorySection(MemorySection(vec![Memory::new(Limits::min(1))])),
+        Section::MemorySection(MemorySection(vec![Memory::new(layout.limits())])),
         Section::ExportSection(ExportSection(vec![
             Export::new("memory", ExportDescriptor::MemoryIndex(memory)),
             Export::new("_start", ExportDescriptor::FunctionIndex(hello_world_fn)),
@@ -74,24 +73,20 @@ fn hello_world_example() -> Module {
         Section::CodeSection(CodeSection(vec![Function::new(
             vec![],
             Expression(vec![
-                I32Const(0),
-                I32Const(8),
-                I32Store(MemoryArguments::new(2, 0)),
-                I32Const(4),
-                I32Const(12),
-                I32Store(MemoryArguments::new(2, 0)),
+                I32Const(iovec.start as i32),
+                I32Const(hello_world.ptr as i32),
+                I32Store(MemoryArguments::new(0, 2)),
+                I32Const(iovec.start as i32 + 4),
+                I32Const(hello_world.len as i32),
+                I32Store(MemoryArguments::new(0, 2)),
                 I32Const(1),
-                I32Const(0),
+                I32Const(iovec.start as i32),
                 I32Const(1),
-                I32Const(20),
+                I32Const(bytes_written.start as i32),
                 Call(write_fn),
                 Drop,
             ]),
         )])),
-        Section::DataSection(DataSection(vec![Data::new(
-            memory,
-            Expression(vec![I32Const(8)]),
-            "hello world!\n".as_bytes().to_owned(),
-        )])),
+        Section::DataSection(layout.data_section()),
     ])
 }
@@ -0,0 +1,231 @@
+use crate::{
+    constants::PAGE_SIZE,
+    expression::{Expression, Instruction},
+    index::MemoryIndex,
+    limits::Limits,
+    section::data_section::{Data, DataSection},
+};
+
+/**
+ * A contiguous range of linear memory, starting at `start` and spanning `size`
+ * bytes.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub start: u32,
+    pub size: u32,
+}
+
+impl Region {
+    pub fn new(start: u32, size: u32) -> Region {
+        Region { start, size }
+    }
+
+    pub fn end(&self) -> u32 {
+        self.start + self.size
+    }
+}
+
+struct Segment {
+    start: u32,
+    bytes: Vec<u8>,
+}
+
+/**
+ * Statically lays out a linear memory. Blobs are placed in allocation order at
+ * the next address satisfying their alignment, and everything placed so far
+ * can be emitted as a `DataSection` along with the minimum `Limits` for the
+ * memory.
+ *
+ * Consecutive allocations are merged into a single data segment (with the
+ * alignment padding zero-filled). Reserved regions, such as the stack, are
+ * never emitted, so they split segments instead.
+ */
+pub struct MemoryLayout {
+    memory_index: MemoryIndex,
+    cursor: u32,
+    segments: Vec<Segment>,
+    is_segment_open: bool,
+    stack: Option<Region>,
+    heap: Option<Region>,
+}
+
+impl MemoryLayout {
+    pub fn new(memory_index: MemoryIndex) -> MemoryLayout {
+        MemoryLayout::with_base(memory_index, 0)
+    }
+
+    /**
+     * Starts allocating at `base` rather than address 0, which is useful for
+     * keeping null pointers from aliasing real data.
+     */
+    pub fn with_base(memory_index: MemoryIndex, base: u32) -> MemoryLayout {
+        MemoryLayout {
+            memory_index,
+            cursor: base,
+            segments: vec![],
+            is_segment_open: false,
+            stack: None,
+            heap: None,
+        }
+    }
+
+    /** Places `bytes` in memory and returns the address they were placed at */
+    pub fn alloc(&mut self, bytes: &[u8], align: u32) -> u32 {
+        let address = self.advance(bytes.len() as u32, align);
+        if bytes.is_empty() {
+            return address;
+        }
+        match self.segments.last_mut() {
+            Some(segment) if self.is_segment_open => {
+                let padding = (address - segment.start) as usize - segment.bytes.len();
+                segment.bytes.extend(std::iter::repeat_n(0, padding));
+                segment.bytes.extend_from_slice(bytes);
+            }
+            _ => {
+                self.segments.push(Segment {
+                    start: address,
+                    bytes: bytes.to_owned(),
+                });
+                self.is_segment_open = true;
+            }
+        }
+        address
+    }
+
+    /** Reserves a zero-initialized region that is not emitted as data */
+    pub fn reserve(&mut self, size: u32, align: u32) -> Region {
+        let start = self.advance(size, align);
+        self.is_segment_open = false;
+        Region::new(start, size)
+    }
+
+    /**
+     * Reserves a region for the stack. Wasm stacks conventionally grow
+     * downwards, so the initial stack pointer is the end of the region.
+     */
+    pub fn reserve_stack(&mut self, size: u32, align: u32) -> Region {
+        assert!(self.stack.is_none(), "stack has already been reserved");
+        let region = self.reserve(size, align);
+        self.stack = Some(region);
+        region
+    }
+
+    /**
+     * Reserves at least `min_size` bytes for the heap. The heap occupies the
+     * rest of memory, so nothing else can be placed after it.
+     */
+    pub fn reserve_heap(&mut self, min_size: u32, align: u32) -> Region {
+        let region = self.reserve(min_size, align);
+        self.heap = Some(region);
+        region
+    }
+
+    pub fn stack(&self) -> Option<Region> {
+        self.stack
+    }
+
+    pub fn heap(&self) -> Option<Region> {
+        self.heap
+    }
+
+    /** Returns the number of bytes spanned by everything placed so far */
+    pub fn size(&self) -> u32 {
+        self.cursor
+    }
+
+    /** Returns the smallest memory limits able to hold the layout */
+    pub fn limits(&self) -> Limits {
+        Limits::min(self.cursor.div_ceil(PAGE_SIZE))
+    }
+
+    pub fn data_section(&self) -> DataSection {
+        DataSection(
+            self.segments
+                .iter()
+                .map(|segment| {
+                    Data::new(
+                        self.memory_index,
+                        Expression(vec![Instruction::I32Const(segment.start as i32)]),
+                        segment.bytes.clone(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    fn advance(&mut self, size: u32, align: u32) -> u32 {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        assert!(self.heap.is_none(), "cannot place data after the heap");
+        let address = self
+            .cursor
+            .checked_add(align - 1)
+            .map(|cursor| cursor & !(align - 1))
+            .expect("memory layout exceeds the 32-bit address space");
+        self.cursor = address
+            .checked_add(size)
+            .expect("memory layout exceeds the 32-bit address space");
+        address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::assert_encoding_eq;
+
+    #[test]
+    fn test_alignment() {
+        let mut layout = MemoryLayout::with_base(MemoryIndex(0), 1);
+        assert_eq!(layout.alloc(&[1, 2, 3], 4), 4);
+        assert_eq!(layout.alloc(&[4], 1), 7);
+        assert_eq!(layout.alloc(&[5, 6], 8), 8);
+        assert_eq!(layout.size(), 10);
+    }
+
+    #[test]
+    fn test_stack_and_heap() {
+        let mut layout = MemoryLayout::new(MemoryIndex(0));
+        layout.alloc(&[1; 12], 4);
+        let stack = layout.reserve_stack(0x10000, 16);
+        let heap = layout.reserve_heap(0, 16);
+
+        assert_eq!(stack, Region::new(16, 0x10000));
+        assert_eq!(heap.start, stack.end());
+        assert_eq!(layout.limits().min, 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_alloc_after_heap() {
+        let mut layout = MemoryLayout::new(MemoryIndex(0));
+        layout.reserve_heap(0, 16);
+        layout.alloc(&[1], 1);
+    }
+
+    #[test]
+    fn test_data_section_encoding() {
+        let mut layout = MemoryLayout::new(MemoryIndex(0));
+        layout.alloc(&[0xaa], 1);
+        layout.alloc(&[0xbb], 2);
+        layout.reserve(2, 1);
+        layout.alloc(&[0xcc], 1);
+
+        assert_encoding_eq(
+            layout.data_section(),
+            &[
+                0x0b, // section id
+                0x0f, // byte count
+                0x02, // data count
+                0x00, // memory index
+                0x41, 0x00, 0x0b, // (i32.const 0)
+                0x03, // byte vec length
+                0xaa, 0x00, 0xbb, // merged bytes with padding
+                0x00, // memory index
+                0x41, 0x05, 0x0b, // (i32.const 5)
+                0x01, // byte vec length
+                0xcc, // bytes
+            ],
+        );
+    }
+}
@@ -1,11 +1,82 @@
 use crate::{
-    constants::{MAGIC_NUMBER, VERSION},
+    constants::{CUSTOM_SECTION, MAGIC_NUMBER, VERSION},
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
-    section::Section,
+    object::{self, ObjectError, ObjectSymbols},
+    section::{
+        custom_section::CustomSection, dylink_section::DylinkSection, name_section::NameSection,
+        Section,
+    },
+    side_module::{self, SideModuleError},
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Module(pub Vec<Section>);
 
+impl Module {
+    /** The payload of the first custom section called `name`, if any */
+    pub fn custom_section(&self, name: &str) -> Option<&[u8]> {
+        self.0.iter().find_map(|section| match section {
+            Section::Custom {
+                name: section_name,
+                payload,
+            } if section_name == name => Some(payload.as_slice()),
+            _ => None,
+        })
+    }
+
+    /**
+     * Replaces the custom section with the same name, or adds the section at
+     * the end of the module, after the data section where tools expect
+     * sections like `name`.
+     */
+    pub fn set_custom_section(&mut self, custom_section: &impl CustomSection) {
+        let section = custom_section.to_section();
+        let existing = self.0.iter_mut().find(|existing| match existing {
+            Section::Custom { name, .. } => name == custom_section.name(),
+            _ => false,
+        });
+        match existing {
+            Some(existing) => *existing = section,
+            None => self.0.push(section),
+        }
+    }
+
+    /**
+     * Adds `names` to the module's `name` section, adding the section if
+     * there isn't one. They replace the names already there for the same
+     * things, and the error is for a `name` section that doesn't decode.
+     */
+    pub fn add_names(&mut self, names: &NameSection) -> Result<(), DecodeError> {
+        let mut section = match self.custom_section("name") {
+            Some(payload) => NameSection::decode(&mut WasmDecoder::new(payload))?,
+            None => NameSection::new(),
+        };
+        section.extend(names);
+        self.set_custom_section(&section);
+        Ok(())
+    }
+
+    /**
+     * Encodes the module as a relocatable object file for `wasm-ld`, with
+     * `linking` and `reloc.*` sections for the references in `symbols` and
+     * those the module has itself.
+     */
+    pub fn encode_object(&self, symbols: &ObjectSymbols) -> Result<Vec<u8>, ObjectError> {
+        object::encode_object(self, symbols)
+    }
+
+    /**
+     * Converts the module into a side module for a dynamic loader, with its
+     * data and elements placed at the imported `__memory_base` and
+     * `__table_base`, and `dylink` as its first section. The sizes in
+     * `dylink` are worked out from the module, and the rest is kept.
+     */
+    pub fn to_side_module(&self, dylink: &DylinkSection) -> Result<Module, SideModuleError> {
+        side_module::to_side_module(self, dylink)
+    }
+}
+
 impl WasmEncode for Module {
     fn encode(&self, encoder: &mut WasmEncoder) -> u32 {
         let mut byte_count = 0;
@@ -19,10 +90,56 @@ impl WasmEncode for Module {
     }
 }
 
+impl WasmDecode for Module {
+    /**
+     * Decodes a whole binary, keeping custom sections where they are. The
+     * other sections have to be in order, with at most one of each.
+     */
+    fn decode(decoder: &mut WasmDecoder) -> Result<Module, DecodeError> {
+        if decoder
+            .read_u32()
+            .map_err(|_| DecodeError::InvalidMagicNumber)?
+            != MAGIC_NUMBER
+        {
+            return Err(DecodeError::InvalidMagicNumber);
+        }
+        let version = decoder.read_u32()?;
+        if version != VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let mut sections = vec![];
+        let mut last_id = CUSTOM_SECTION;
+        let mut function_count = 0;
+        let mut code_count = 0;
+        while !decoder.is_empty() {
+            let section = Section::decode(decoder)?;
+            match &section {
+                Section::Custom { .. } => {}
+                section if section.id() <= last_id => {
+                    return Err(DecodeError::SectionOutOfOrder(section.id()))
+                }
+                section => last_id = section.id(),
+            }
+            match &section {
+                Section::FunctionSection(function_section) => {
+                    function_count = function_section.0.len()
+                }
+                Section::CodeSection(code_section) => code_count = code_section.0.len(),
+                _ => {}
+            }
+            sections.push(section);
+        }
+        if function_count != code_count {
+            return Err(DecodeError::FunctionCountMismatch);
+        }
+        Ok(Module(sections))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::encoder::assert_encoding_eq;
+    use crate::{constants::MEMORY_SECTION, encoder::assert_encoding_eq, index::FunctionIndex};
 
     #[test]
     fn test_encoding() {
@@ -34,4 +151,126 @@ mod tests {
             ],
         )
     }
+
+    #[test]
+    fn test_decoding_round_trip() {
+        let module: Module = r#"(module
+            (import "env" "print" (func $print (param i32 i64)))
+            (import "env" "table" (table 1 funcref))
+            (global $g (mut f64) (f64.const 2.5))
+            (memory (export "memory") 1 4)
+            (func $main (export "main") (local i32 i32 f32)
+              (call $print (i32.const 1) (i64.const -1)))
+            (elem (i32.const 0) $main)
+            (data (i32.const 16) "\00\ffdata")
+            (start $main))"#
+            .parse()
+            .unwrap();
+        let mut encoder = WasmEncoder::new();
+        module.encode(&mut encoder);
+
+        let mut decoder = WasmDecoder::new(encoder.as_slice());
+        assert_eq!(Module::decode(&mut decoder), Ok(module));
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn test_decoding_custom_sections() {
+        let bytes = b"\0asm\x01\0\0\0\x00\x04\x01a\x01\x02\x05\x03\x01\x00\x01\x00\x02\x01b";
+        let module = Module::decode(&mut WasmDecoder::new(bytes)).unwrap();
+        assert_eq!(module.0.len(), 3);
+        assert_eq!(
+            module.0[0],
+            Section::Custom {
+                name: "a".to_string(),
+                payload: vec![0x01, 0x02],
+            }
+        );
+        assert_eq!(module.0[1].id(), MEMORY_SECTION);
+
+        let mut encoder = WasmEncoder::new();
+        module.encode(&mut encoder);
+        assert_eq!(encoder.as_slice(), &bytes[..]);
+    }
+
+    #[test]
+    fn test_set_custom_section() {
+        let mut module: Module = "(func $f) (func $main)".parse().unwrap();
+        let mut names = NameSection::new();
+        names.name_function(FunctionIndex(1), "main");
+        module.set_custom_section(&names);
+        names.name_function(FunctionIndex(0), "f");
+        module.set_custom_section(&names);
+
+        assert_eq!(module.0.len(), 4);
+        assert_eq!(module.0[3], names.to_section());
+        let payload = module.custom_section("name").unwrap();
+        assert_eq!(
+            NameSection::decode(&mut WasmDecoder::new(payload)),
+            Ok(names)
+        );
+    }
+
+    #[test]
+    fn test_add_names() {
+        let mut module: Module = "(func) (func)".parse().unwrap();
+        let mut names = NameSection::new();
+        names.name_function(FunctionIndex(0), "f");
+        names.name_function(FunctionIndex(1), "g");
+        module.add_names(&names).unwrap();
+        let mut renamed = NameSection::new();
+        renamed.name_function(FunctionIndex(1), "main");
+        module.add_names(&renamed).unwrap();
+
+        names.name_function(FunctionIndex(1), "main");
+        let payload = module.custom_section("name").unwrap();
+        assert_eq!(
+            NameSection::decode(&mut WasmDecoder::new(payload)),
+            Ok(names)
+        );
+
+        module.0[3] = Section::Custom {
+            name: "name".to_string(),
+            payload: vec![0x01],
+        };
+        assert_eq!(module.add_names(&renamed), Err(DecodeError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_decoding_errors() {
+        let decode = |bytes: &[u8]| Module::decode(&mut WasmDecoder::new(bytes));
+        assert_eq!(decode(b"\0wasm"), Err(DecodeError::InvalidMagicNumber));
+        assert_eq!(
+            decode(b"\0asm\x02\0\0\0"),
+            Err(DecodeError::UnsupportedVersion(2))
+        );
+        assert_eq!(
+            decode(b"\0asm\x01\0\0\0\x0c\x00"),
+            Err(DecodeError::UnknownSection(12))
+        );
+        // A custom section whose name is longer than the section
+        assert_eq!(
+            decode(b"\0asm\x01\0\0\0\x00\x02\x05abcde"),
+            Err(DecodeError::SectionSizeMismatch(0))
+        );
+        // A memory section that claims to be a byte longer than it is
+        assert_eq!(
+            decode(b"\0asm\x01\0\0\0\x05\x04\x01\x00\x01\x00"),
+            Err(DecodeError::SectionSizeMismatch(5))
+        );
+        // A memory section before a table section, then a second memory section
+        assert_eq!(
+            decode(b"\0asm\x01\0\0\0\x05\x01\x00\x04\x01\x00"),
+            Err(DecodeError::SectionOutOfOrder(4))
+        );
+        assert_eq!(
+            decode(b"\0asm\x01\0\0\0\x05\x01\x00\x00\x01\x00\x05\x01\x00"),
+            Err(DecodeError::SectionOutOfOrder(5))
+        );
+        // A function section declaring a function without a body
+        assert_eq!(
+            decode(b"\0asm\x01\0\0\0\x01\x04\x01\x60\x00\x00\x03\x02\x01\x00"),
+            Err(DecodeError::FunctionCountMismatch)
+        );
+    }
 }
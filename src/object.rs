@@ -0,0 +1,897 @@
+/*!
+ * Relocatable object files, which `wasm-ld` links with the objects of other
+ * compilers.
+ *
+ * Linking moves functions, globals and data, and merges types, so every
+ * reference to them is written as a padded LEB128 that a `reloc.CODE` or
+ * `reloc.DATA` section tells the linker to patch. The references point at
+ * symbols in the `linking` section: one for each function, then one for each
+ * global, then the data symbols of `ObjectSymbols`.
+ *
+ * Calls, global accesses and `call_indirect` types are found in the code,
+ * but which `i32.const`s and data bytes are addresses or table slots has to
+ * be said with `ObjectSymbols`, numbering instructions as source maps do.
+ */
+use std::{collections::BTreeMap, error, fmt};
+
+use crate::{
+    constants::{
+        BLOCK, CALL, CALL_INDIRECT, CUSTOM_SECTION, ELSE, END, GLOBAL_GET, GLOBAL_SET, I32_CONST,
+        IF, IMPORT_SECTION, LOOP, MAGIC_NUMBER, VERSION,
+    },
+    decoder::{WasmDecode, WasmDecoder},
+    encoder::{WasmEncode, WasmEncoder},
+    expression::{walk, BlockType, Instruction},
+    index::{FunctionIndex, GlobalIndex},
+    module::Module,
+    section::{
+        code_section::Function,
+        custom_section::CustomSection,
+        data_section::Data,
+        export_section::ExportDescriptor,
+        import_section::{Import, ImportDescriptor, ImportSection},
+        linking_section::{
+            DataLocation, InitFunction, LinkingSection, SegmentInfo, Symbol, SymbolKind,
+            SYMBOL_BINDING_LOCAL, SYMBOL_EXPORTED, SYMBOL_UNDEFINED,
+        },
+        name_section::NameSection,
+        relocation_section::{Relocation, RelocationSection, RelocationType},
+        Section,
+    },
+};
+
+/** The priority a start function is called with, the lowest there is */
+const START_PRIORITY: u32 = 65535;
+
+#[derive(Debug, PartialEq)]
+pub enum ObjectError {
+    /** A reference in a function that's imported or isn't in the module */
+    UnknownFunction(FunctionIndex),
+    UnknownInstruction(FunctionIndex, u32),
+    /** A reference to an instruction other than `i32.const` */
+    NotAConstant(FunctionIndex, u32),
+    UnknownSegment(u32),
+    /** A reference or data symbol that doesn't fit in its segment */
+    OutOfBounds(u32, u32),
+    /** A data segment whose offset isn't an `i32.const` */
+    NonConstantOffset(u32),
+    /**
+     * An element segment, which an object can't have, since the linker fills
+     * the table from `TableIndex` references instead
+     */
+    ElementSegment,
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectError::UnknownFunction(index) => {
+                write!(f, "function {} isn't defined in the module", index.0)
+            }
+            ObjectError::UnknownInstruction(index, instruction) => {
+                write!(f, "function {} has no instruction {}", index.0, instruction)
+            }
+            ObjectError::NotAConstant(index, instruction) => write!(
+                f,
+                "instruction {} of function {} isn't an i32.const",
+                instruction, index.0
+            ),
+            ObjectError::UnknownSegment(segment) => {
+                write!(f, "there's no data segment {}", segment)
+            }
+            ObjectError::OutOfBounds(segment, offset) => {
+                write!(f, "offset {} is outside data segment {}", offset, segment)
+            }
+            ObjectError::NonConstantOffset(segment) => {
+                write!(f, "data segment {} doesn't have a constant offset", segment)
+            }
+            ObjectError::ElementSegment => write!(
+                f,
+                "element segments aren't supported, use table index references instead"
+            ),
+        }
+    }
+}
+
+impl error::Error for ObjectError {}
+
+/** A data symbol of an `ObjectSymbols` */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DataSymbol(u32);
+
+/** What an `i32.const`, or four bytes of a data segment, stand for */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reference {
+    /** The address of a data symbol, plus an addend */
+    MemoryAddress(DataSymbol, i32),
+    /** The table slot the linker gives a function */
+    TableIndex(FunctionIndex),
+}
+
+/**
+ * What `Module::encode_object` can't tell from the module itself: its data
+ * symbols, the references to them and to table slots, and how to place and
+ * initialize its data.
+ */
+#[derive(Debug, Default)]
+pub struct ObjectSymbols {
+    data: Vec<(String, Option<DataLocation>)>,
+    code_references: BTreeMap<(u32, u32), Reference>,
+    data_references: BTreeMap<(u32, u32), Reference>,
+    segments: BTreeMap<u32, SegmentInfo>,
+    init_functions: Vec<(u32, FunctionIndex)>,
+}
+
+impl ObjectSymbols {
+    pub fn new() -> ObjectSymbols {
+        ObjectSymbols::default()
+    }
+
+    /** Names `size` bytes at `offset` in the data segment `segment` */
+    pub fn define_data(&mut self, name: &str, segment: u32, offset: u32, size: u32) -> DataSymbol {
+        let location = DataLocation::new(segment, offset, size);
+        self.data.push((name.to_owned(), Some(location)));
+        DataSymbol(self.data.len() as u32 - 1)
+    }
+
+    /** A data symbol that another object defines */
+    pub fn import_data(&mut self, name: &str) -> DataSymbol {
+        self.data.push((name.to_owned(), None));
+        DataSymbol(self.data.len() as u32 - 1)
+    }
+
+    /** Says what the `i32.const` numbered `instruction` in `function` stands for */
+    pub fn add_code_reference(
+        &mut self,
+        function: FunctionIndex,
+        instruction: u32,
+        reference: Reference,
+    ) {
+        self.code_references
+            .insert((function.0, instruction), reference);
+    }
+
+    /** Says what the four bytes at `offset` in the data segment `segment` stand for */
+    pub fn add_data_reference(&mut self, segment: u32, offset: u32, reference: Reference) {
+        self.data_references.insert((segment, offset), reference);
+    }
+
+    /**
+     * Sets the name and alignment of a data segment, which are `.data.N` and
+     * a single byte unless set.
+     */
+    pub fn set_segment_info(&mut self, segment: u32, info: SegmentInfo) {
+        self.segments.insert(segment, info);
+    }
+
+    /** Has the linker call `function` on startup, lower priorities first */
+    pub fn add_init_function(&mut self, priority: u32, function: FunctionIndex) {
+        self.init_functions.push((priority, function));
+    }
+}
+
+/** The counts that symbol indices are offset by */
+struct SymbolSpace {
+    functions: u32,
+    globals: u32,
+}
+
+impl SymbolSpace {
+    fn global(&self, index: GlobalIndex) -> u32 {
+        self.functions + index.0
+    }
+
+    fn data(&self, symbol: DataSymbol) -> u32 {
+        self.functions + self.globals + symbol.0
+    }
+}
+
+/** The sections of the module, with everything an object can't have moved or removed */
+struct ObjectModule {
+    sections: Vec<Section>,
+    imports: Vec<Import>,
+    functions: Vec<Function>,
+    data: Vec<Data>,
+    function_exports: BTreeMap<u32, String>,
+    global_exports: BTreeMap<u32, String>,
+    start: Option<FunctionIndex>,
+}
+
+impl ObjectModule {
+    /**
+     * Memories and tables become the imports the linker provides, exports
+     * become exported symbols and the start function an init function.
+     * Element segments are an error, since the linker fills the table from
+     * `TableIndex` references.
+     */
+    fn new(module: &Module) -> Result<ObjectModule, ObjectError> {
+        let mut object = ObjectModule {
+            sections: vec![],
+            imports: vec![],
+            functions: vec![],
+            data: vec![],
+            function_exports: BTreeMap::new(),
+            global_exports: BTreeMap::new(),
+            start: None,
+        };
+        let mut linked_imports = vec![];
+        for section in module.0.iter() {
+            match section {
+                Section::ImportSection(section) => object.imports.extend(section.0.clone()),
+                Section::MemorySection(section) => {
+                    linked_imports.extend(section.0.iter().map(|memory| {
+                        let descriptor = ImportDescriptor::MemoryType(memory.clone());
+                        Import::new("env", "__linear_memory", descriptor)
+                    }))
+                }
+                Section::TableSection(section) => {
+                    linked_imports.extend(section.0.iter().map(|table| {
+                        let descriptor = ImportDescriptor::TableType(table.clone());
+                        Import::new("env", "__indirect_function_table", descriptor)
+                    }))
+                }
+                Section::ExportSection(section) => {
+                    for export in section.0.iter() {
+                        let (exports, index) = match export.descriptor {
+                            ExportDescriptor::FunctionIndex(index) => {
+                                (&mut object.function_exports, index.0)
+                            }
+                            ExportDescriptor::GlobalIndex(index) => {
+                                (&mut object.global_exports, index.0)
+                            }
+                            _ => continue,
+                        };
+                        exports.entry(index).or_insert_with(|| export.name.clone());
+                    }
+                }
+                Section::StartSection(section) => {
+                    object.start = Some(FunctionIndex(section.0 as u32))
+                }
+                Section::ElementSection(_) => return Err(ObjectError::ElementSegment),
+                Section::CodeSection(section) => {
+                    object.functions = section.0.clone();
+                    object.sections.push(Section::CodeSection(section.clone()));
+                }
+                Section::DataSection(section) => {
+                    object.data = section.0.clone();
+                    object.sections.push(Section::DataSection(section.clone()));
+                }
+                Section::Custom { name, .. } if name == "linking" || name.starts_with("reloc.") => {
+                }
+                _ => object.sections.push(section.clone()),
+            }
+        }
+        object.imports.extend(linked_imports);
+        if !object.imports.is_empty() {
+            let position = object
+                .sections
+                .iter()
+                .position(|section| section.id() > IMPORT_SECTION)
+                .unwrap_or(object.sections.len());
+            let imports = ImportSection(object.imports.clone());
+            object
+                .sections
+                .insert(position, Section::ImportSection(imports));
+        }
+        Ok(object)
+    }
+
+    fn count_imports(&self, is_kind: impl Fn(&ImportDescriptor) -> bool) -> u32 {
+        self.imports
+            .iter()
+            .filter(|import| is_kind(&import.descriptor))
+            .count() as u32
+    }
+
+    fn symbol_space(&self) -> SymbolSpace {
+        let defined_globals = self
+            .sections
+            .iter()
+            .map(|section| match section {
+                Section::GlobalSection(section) => section.0.len() as u32,
+                _ => 0,
+            })
+            .sum::<u32>();
+        SymbolSpace {
+            functions: self.imported_functions() + self.functions.len() as u32,
+            globals: self
+                .count_imports(|descriptor| matches!(descriptor, ImportDescriptor::GlobalType(_)))
+                + defined_globals,
+        }
+    }
+
+    fn imported_functions(&self) -> u32 {
+        self.count_imports(|descriptor| matches!(descriptor, ImportDescriptor::TypeIndex(_)))
+    }
+
+    /** The offset of each data segment, from its `i32.const` */
+    fn segment_offsets(&self) -> Result<Vec<i32>, ObjectError> {
+        self.data
+            .iter()
+            .enumerate()
+            .map(|(segment, data)| match data.offset.0.as_slice() {
+                [Instruction::I32Const(offset)] => Ok(*offset),
+                _ => Err(ObjectError::NonConstantOffset(segment as u32)),
+            })
+            .collect()
+    }
+}
+
+/** The symbol of a defined function or global, which is local unless exported */
+fn defined_symbol(
+    kind: SymbolKind,
+    export: Option<&String>,
+    name: Option<&String>,
+    default_name: String,
+) -> Symbol {
+    match export {
+        Some(export) => Symbol::new(kind, SYMBOL_EXPORTED, export),
+        None => Symbol::new(kind, SYMBOL_BINDING_LOCAL, name.unwrap_or(&default_name)),
+    }
+}
+
+fn linking_section(
+    module: &Module,
+    object: &ObjectModule,
+    symbols: &ObjectSymbols,
+) -> Result<LinkingSection, ObjectError> {
+    let names = module
+        .custom_section("name")
+        .and_then(|payload| NameSection::decode(&mut WasmDecoder::new(payload)).ok())
+        .unwrap_or_default();
+    let space = object.symbol_space();
+    let mut linking = LinkingSection::new();
+    for index in 0..space.functions {
+        let kind = SymbolKind::Function(FunctionIndex(index));
+        linking.add_symbol(match index < object.imported_functions() {
+            true => Symbol::new(kind, SYMBOL_UNDEFINED, ""),
+            false => defined_symbol(
+                kind,
+                object.function_exports.get(&index),
+                names.functions.get(&index),
+                format!("wasm-function[{}]", index),
+            ),
+        });
+    }
+    let imported_globals =
+        object.count_imports(|descriptor| matches!(descriptor, ImportDescriptor::GlobalType(_)));
+    for index in 0..space.globals {
+        let kind = SymbolKind::Global(GlobalIndex(index));
+        linking.add_symbol(match index < imported_globals {
+            true => Symbol::new(kind, SYMBOL_UNDEFINED, ""),
+            false => defined_symbol(
+                kind,
+                object.global_exports.get(&index),
+                names.globals.get(&index),
+                format!("wasm-global[{}]", index),
+            ),
+        });
+    }
+    for (name, location) in symbols.data.iter() {
+        let flags = match location {
+            Some(location) => {
+                let data = object
+                    .data
+                    .get(location.segment as usize)
+                    .ok_or(ObjectError::UnknownSegment(location.segment))?;
+                let length = data.initializer.len() as u32;
+                match location.offset.checked_add(location.size) {
+                    Some(end) if end <= length => {}
+                    _ => return Err(ObjectError::OutOfBounds(location.segment, location.offset)),
+                }
+                0
+            }
+            None => SYMBOL_UNDEFINED,
+        };
+        linking.add_symbol(Symbol::new(SymbolKind::Data(*location), flags, name));
+    }
+
+    for segment in 0..object.data.len() as u32 {
+        linking.segments.push(match symbols.segments.get(&segment) {
+            Some(info) => info.clone(),
+            None => SegmentInfo::new(&format!(".data.{}", segment), 0),
+        });
+    }
+    let init_functions = symbols.init_functions.iter().cloned();
+    let start = object.start.map(|start| (START_PRIORITY, start));
+    for (priority, function) in init_functions.chain(start) {
+        if function.0 >= space.functions {
+            return Err(ObjectError::UnknownFunction(function));
+        }
+        linking.init_functions.push(InitFunction {
+            priority,
+            symbol: function.0,
+        });
+    }
+    Ok(linking)
+}
+
+/** Writes a module's code with its references padded, and records where they are */
+struct CodeWriter<'a> {
+    encoder: WasmEncoder,
+    relocations: Vec<Relocation>,
+    space: &'a SymbolSpace,
+    symbols: &'a ObjectSymbols,
+    addresses: &'a [i32],
+    function: FunctionIndex,
+    instruction: u32,
+}
+
+impl<'a> CodeWriter<'a> {
+    fn relocate(&mut self, relocation_type: RelocationType, index: u32, addend: i32) {
+        let offset = self.encoder.as_slice().len() as u32;
+        self.relocations.push(Relocation {
+            relocation_type,
+            offset,
+            index,
+            addend,
+        });
+    }
+
+    fn write_reference(&mut self, reference: Reference) {
+        self.encoder.push_u8(I32_CONST);
+        match reference {
+            Reference::MemoryAddress(symbol, addend) => {
+                let index = self.space.data(symbol);
+                self.relocate(RelocationType::MemoryAddressSleb, index, addend);
+                let address = self.addresses[symbol.0 as usize].wrapping_add(addend);
+                self.encoder.push_padded_leb_i32(address);
+            }
+            Reference::TableIndex(function) => {
+                self.relocate(RelocationType::TableIndexSleb, function.0, 0);
+                self.encoder.push_padded_leb_i32(0);
+            }
+        }
+    }
+
+    fn write_block(&mut self, opcode: u8, block_type: &BlockType, bodies: &[&[Instruction]]) {
+        self.encoder.push_u8(opcode);
+        block_type.encode(&mut self.encoder);
+        for (i, body) in bodies.iter().enumerate() {
+            if i > 0 {
+                self.encoder.push_u8(ELSE);
+            }
+            self.write(body);
+        }
+        self.encoder.push_u8(END);
+    }
+
+    fn write(&mut self, instructions: &[Instruction]) {
+        for instruction in instructions.iter() {
+            let number = self.instruction;
+            self.instruction += 1;
+            if let Some(reference) = self.symbols.code_references.get(&(self.function.0, number)) {
+                self.write_reference(*reference);
+                continue;
+            }
+            match instruction {
+                Instruction::Block(block_type, body) => {
+                    self.write_block(BLOCK, block_type, &[body])
+                }
+                Instruction::Loop(block_type, body) => self.write_block(LOOP, block_type, &[body]),
+                Instruction::If(block_type, body) => self.write_block(IF, block_type, &[body]),
+                Instruction::IfElse(block_type, then_body, else_body) => {
+                    self.write_block(IF, block_type, &[then_body, else_body])
+                }
+                Instruction::Call(function) => {
+                    self.encoder.push_u8(CALL);
+                    self.relocate(RelocationType::FunctionIndexLeb, function.0, 0);
+                    self.encoder.push_padded_leb_u32(function.0);
+                }
+                Instruction::CallIndirect(type_index) => {
+                    self.encoder.push_u8(CALL_INDIRECT);
+                    self.relocate(RelocationType::TypeIndexLeb, type_index.0, 0);
+                    self.encoder.push_padded_leb_u32(type_index.0);
+                    self.encoder.push_u8(0);
+                }
+                Instruction::GlobalGet(global) | Instruction::GlobalSet(global) => {
+                    self.encoder.push_u8(match instruction {
+                        Instruction::GlobalGet(_) => GLOBAL_GET,
+                        _ => GLOBAL_SET,
+                    });
+                    let index = self.space.global(*global);
+                    self.relocate(RelocationType::GlobalIndexLeb, index, 0);
+                    self.encoder.push_padded_leb_u32(global.0);
+                }
+                _ => {
+                    instruction.encode(&mut self.encoder);
+                }
+            }
+        }
+    }
+}
+
+/** Checks that every code reference is to an `i32.const` of a defined function */
+fn check_code_references(
+    object: &ObjectModule,
+    symbols: &ObjectSymbols,
+) -> Result<(), ObjectError> {
+    let imported_functions = object.imported_functions();
+    for &(function, instruction) in symbols.code_references.keys() {
+        let index = FunctionIndex(function);
+        let body = function
+            .checked_sub(imported_functions)
+            .and_then(|body| object.functions.get(body as usize))
+            .ok_or(ObjectError::UnknownFunction(index))?;
+        match walk(&body.expression.0).nth(instruction as usize) {
+            Some(Instruction::I32Const(_)) => {}
+            Some(_) => return Err(ObjectError::NotAConstant(index, instruction)),
+            None => return Err(ObjectError::UnknownInstruction(index, instruction)),
+        }
+    }
+    Ok(())
+}
+
+/** Encodes the contents of the code section, and its relocations */
+fn code_section(
+    object: &ObjectModule,
+    space: &SymbolSpace,
+    symbols: &ObjectSymbols,
+    addresses: &[i32],
+) -> (Vec<u8>, Vec<Relocation>) {
+    let mut encoder = WasmEncoder::new();
+    let mut relocations = vec![];
+    encoder.push_leb_u32(object.functions.len() as u32);
+    for (i, function) in object.functions.iter().enumerate() {
+        let mut writer = CodeWriter {
+            encoder: WasmEncoder::new(),
+            relocations: vec![],
+            space,
+            symbols,
+            addresses,
+            function: FunctionIndex(object.imported_functions() + i as u32),
+            instruction: 0,
+        };
+        writer.encoder.push_leb_u32(function.locals.len() as u32);
+        function.locals.encode(&mut writer.encoder);
+        writer.write(&function.expression.0);
+        writer.encoder.push_u8(END);
+
+        encoder.push_leb_u32(writer.encoder.as_slice().len() as u32);
+        let start = encoder.as_slice().len() as u32;
+        encoder.push_bytes(writer.encoder.as_slice());
+        relocations.extend(writer.relocations.into_iter().map(|mut relocation| {
+            relocation.offset += start;
+            relocation
+        }));
+    }
+    (encoder.as_slice().to_vec(), relocations)
+}
+
+/** Encodes the contents of the data section, and its relocations */
+fn data_section(
+    object: &ObjectModule,
+    space: &SymbolSpace,
+    symbols: &ObjectSymbols,
+    addresses: &[i32],
+) -> Result<(Vec<u8>, Vec<Relocation>), ObjectError> {
+    if let Some(&(segment, _)) = symbols
+        .data_references
+        .keys()
+        .find(|(segment, _)| *segment as usize >= object.data.len())
+    {
+        return Err(ObjectError::UnknownSegment(segment));
+    }
+    let mut encoder = WasmEncoder::new();
+    let mut relocations = vec![];
+    encoder.push_leb_u32(object.data.len() as u32);
+    for (segment, data) in object.data.iter().enumerate() {
+        let segment = segment as u32;
+        encoder.push_leb_u32(data.memory_index.0);
+        data.offset.encode(&mut encoder);
+        encoder.push_leb_u32(data.initializer.len() as u32);
+        let start = encoder.as_slice().len() as u32;
+        let mut bytes = data.initializer.clone();
+        for (&(_, offset), reference) in symbols
+            .data_references
+            .range((segment, 0)..=(segment, u32::MAX))
+        {
+            let patched = bytes
+                .get_mut(offset as usize..offset as usize + 4)
+                .ok_or(ObjectError::OutOfBounds(segment, offset))?;
+            let (relocation, value) = match *reference {
+                Reference::MemoryAddress(symbol, addend) => (
+                    Relocation {
+                        relocation_type: RelocationType::MemoryAddressI32,
+                        offset: start + offset,
+                        index: space.data(symbol),
+                        addend,
+                    },
+                    addresses[symbol.0 as usize].wrapping_add(addend),
+                ),
+                Reference::TableIndex(function) => (
+                    Relocation::new(RelocationType::TableIndexI32, start + offset, function.0),
+                    0,
+                ),
+            };
+            patched.copy_from_slice(&value.to_le_bytes());
+            relocations.push(relocation);
+        }
+        encoder.push_bytes(&bytes);
+    }
+    Ok((encoder.as_slice().to_vec(), relocations))
+}
+
+/** Encodes `module` as a relocatable object file; see `Module::encode_object` */
+pub(crate) fn encode_object(
+    module: &Module,
+    symbols: &ObjectSymbols,
+) -> Result<Vec<u8>, ObjectError> {
+    let object = ObjectModule::new(module)?;
+    let space = object.symbol_space();
+    let linking = linking_section(module, &object, symbols)?;
+    check_code_references(&object, symbols)?;
+    for reference in symbols
+        .code_references
+        .values()
+        .chain(symbols.data_references.values())
+    {
+        if let Reference::TableIndex(function) = reference {
+            if function.0 >= space.functions {
+                return Err(ObjectError::UnknownFunction(*function));
+            }
+        }
+    }
+
+    // Data symbols are at their address in the module, which only the
+    // relocations' placeholders use
+    let offsets = object.segment_offsets()?;
+    let addresses: Vec<i32> = symbols
+        .data
+        .iter()
+        .map(|(_, location)| match location {
+            Some(location) => {
+                offsets[location.segment as usize].wrapping_add(location.offset as i32)
+            }
+            None => 0,
+        })
+        .collect();
+
+    let mut encoder = WasmEncoder::new();
+    encoder.push_u32(MAGIC_NUMBER);
+    encoder.push_u32(VERSION);
+    // The linking and relocation sections have to come before custom
+    // sections like `name`, which are after the last known section
+    let last_known = object
+        .sections
+        .iter()
+        .rposition(|section| section.id() != CUSTOM_SECTION);
+    if last_known.is_none() {
+        linking.to_section().encode(&mut encoder);
+    }
+    let mut relocation_sections = vec![];
+    for (index, section) in object.sections.iter().enumerate() {
+        let encoded = match section {
+            Section::CodeSection(_) => {
+                Some(("CODE", code_section(&object, &space, symbols, &addresses)))
+            }
+            Section::DataSection(_) => {
+                Some(("DATA", data_section(&object, &space, symbols, &addresses)?))
+            }
+            _ => None,
+        };
+        match encoded {
+            Some((target, (contents, relocations))) => {
+                encoder.push_u8(section.id());
+                encoder.push_leb_u32(contents.len() as u32);
+                encoder.push_bytes(&contents);
+                if !relocations.is_empty() {
+                    let mut relocation_section = RelocationSection::new(target, index as u32);
+                    relocation_section.relocations = relocations;
+                    relocation_sections.push(relocation_section);
+                }
+            }
+            None => {
+                section.encode(&mut encoder);
+            }
+        }
+        if Some(index) == last_known {
+            linking.to_section().encode(&mut encoder);
+            for relocation_section in relocation_sections.iter() {
+                relocation_section.to_section().encode(&mut encoder);
+            }
+        }
+    }
+    Ok(encoder.as_slice().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module() -> Module {
+        r#"(module
+            (import "env" "print" (func $print (param i32)))
+            (global $count (mut i32) (i32.const 0))
+            (memory (export "memory") 1)
+            (func $main (export "main")
+              (call $print (i32.const 20))
+              (global.set $count (i32.const 1)))
+            (data (i32.const 16) "\00\00\00\00hi\00"))"#
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_encode_object() {
+        let mut symbols = ObjectSymbols::new();
+        let greeting = symbols.define_data("greeting", 0, 4, 3);
+        let table = symbols.import_data("table");
+        symbols.add_code_reference(FunctionIndex(1), 0, Reference::MemoryAddress(greeting, 0));
+        symbols.add_data_reference(0, 0, Reference::MemoryAddress(table, 8));
+        symbols.set_segment_info(0, SegmentInfo::new(".rodata.greeting", 2));
+        let bytes = module().encode_object(&symbols).unwrap();
+        let object = Module::decode(&mut WasmDecoder::new(&bytes)).unwrap();
+
+        // The memory is imported, and the export is a symbol instead
+        let section_ids: Vec<u8> = object.0.iter().map(Section::id).collect();
+        assert_eq!(section_ids, vec![1, 2, 3, 6, 10, 11, 0, 0, 0, 0]);
+        match &object.0[1] {
+            Section::ImportSection(imports) => {
+                assert_eq!(imports.0[1].name, "__linear_memory");
+            }
+            _ => unreachable!(),
+        }
+
+        let linking = object.custom_section("linking").unwrap();
+        let linking = LinkingSection::decode(&mut WasmDecoder::new(linking)).unwrap();
+        assert_eq!(
+            linking.symbols,
+            vec![
+                Symbol::new(SymbolKind::Function(FunctionIndex(0)), SYMBOL_UNDEFINED, ""),
+                Symbol::new(
+                    SymbolKind::Function(FunctionIndex(1)),
+                    SYMBOL_EXPORTED,
+                    "main"
+                ),
+                Symbol::new(
+                    SymbolKind::Global(GlobalIndex(0)),
+                    SYMBOL_BINDING_LOCAL,
+                    "count"
+                ),
+                Symbol::new(
+                    SymbolKind::Data(Some(DataLocation::new(0, 4, 3))),
+                    0,
+                    "greeting"
+                ),
+                Symbol::new(SymbolKind::Data(None), SYMBOL_UNDEFINED, "table"),
+            ]
+        );
+        assert_eq!(
+            linking.segments,
+            vec![SegmentInfo::new(".rodata.greeting", 2)]
+        );
+
+        let code = object.custom_section("reloc.CODE").unwrap();
+        let code = RelocationSection::decode("reloc.CODE", &mut WasmDecoder::new(code)).unwrap();
+        assert_eq!(code.section_index, 4);
+        assert_eq!(
+            code.relocations,
+            vec![
+                Relocation {
+                    relocation_type: RelocationType::MemoryAddressSleb,
+                    offset: 4,
+                    index: 3,
+                    addend: 0,
+                },
+                Relocation::new(RelocationType::FunctionIndexLeb, 10, 0),
+                Relocation::new(RelocationType::GlobalIndexLeb, 18, 2),
+            ]
+        );
+        let data = object.custom_section("reloc.DATA").unwrap();
+        let data = RelocationSection::decode("reloc.DATA", &mut WasmDecoder::new(data)).unwrap();
+        assert_eq!(
+            data.relocations,
+            vec![Relocation {
+                relocation_type: RelocationType::MemoryAddressI32,
+                offset: 6,
+                index: 4,
+                addend: 8,
+            }]
+        );
+
+        // References are padded, with the module's addresses as placeholders
+        match &object.0[4] {
+            Section::CodeSection(code) => assert_eq!(
+                code.0[0].expression.0[..2],
+                [
+                    Instruction::I32Const(20),
+                    Instruction::Call(FunctionIndex(0))
+                ]
+            ),
+            _ => unreachable!(),
+        }
+        let padded = [
+            I32_CONST, 0x94, 0x80, 0x80, 0x80, 0x00, // i32.const 20
+            CALL, 0x80, 0x80, 0x80, 0x80, 0x00, // call 0
+        ];
+        assert!(bytes.windows(padded.len()).any(|window| window == padded));
+    }
+
+    #[test]
+    fn test_start_function() {
+        let mut module: Module = "(func $init) (start $init)".parse().unwrap();
+        module.0.push(Section::Custom {
+            name: "reloc.CODE".to_string(),
+            payload: vec![],
+        });
+        let bytes = module.encode_object(&ObjectSymbols::new()).unwrap();
+        let object = Module::decode(&mut WasmDecoder::new(&bytes)).unwrap();
+        assert_eq!(object.custom_section("reloc.CODE"), None);
+
+        let linking = object.custom_section("linking").unwrap();
+        let linking = LinkingSection::decode(&mut WasmDecoder::new(linking)).unwrap();
+        assert_eq!(
+            linking.init_functions,
+            vec![InitFunction {
+                priority: START_PRIORITY,
+                symbol: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let encode = |symbols: &ObjectSymbols| module().encode_object(symbols);
+        let code_reference = |reference| {
+            let mut symbols = ObjectSymbols::new();
+            symbols.add_code_reference(FunctionIndex(1), 0, reference);
+            symbols
+        };
+        let mut symbols = ObjectSymbols::new();
+        let symbol = symbols.define_data("x", 0, 5, 4);
+        assert_eq!(encode(&symbols), Err(ObjectError::OutOfBounds(0, 5)));
+
+        let mut symbols = ObjectSymbols::new();
+        symbols.define_data("x", 1, 0, 0);
+        assert_eq!(encode(&symbols), Err(ObjectError::UnknownSegment(1)));
+
+        let reference = Reference::MemoryAddress(symbol, 0);
+        let mut symbols = ObjectSymbols::new();
+        symbols.add_code_reference(FunctionIndex(0), 0, reference);
+        assert_eq!(
+            encode(&symbols),
+            Err(ObjectError::UnknownFunction(FunctionIndex(0)))
+        );
+
+        let mut symbols = ObjectSymbols::new();
+        symbols.add_code_reference(FunctionIndex(1), 1, reference);
+        assert_eq!(
+            encode(&symbols),
+            Err(ObjectError::NotAConstant(FunctionIndex(1), 1))
+        );
+
+        let mut symbols = ObjectSymbols::new();
+        symbols.add_code_reference(FunctionIndex(1), 4, reference);
+        assert_eq!(
+            encode(&symbols),
+            Err(ObjectError::UnknownInstruction(FunctionIndex(1), 4))
+        );
+
+        assert_eq!(
+            encode(&code_reference(Reference::TableIndex(FunctionIndex(2)))),
+            Err(ObjectError::UnknownFunction(FunctionIndex(2)))
+        );
+
+        let mut symbols = ObjectSymbols::new();
+        symbols.add_data_reference(0, 4, Reference::TableIndex(FunctionIndex(1)));
+        assert_eq!(encode(&symbols), Err(ObjectError::OutOfBounds(0, 4)));
+
+        let module: Module = r#"(global $base i32 (i32.const 0)) (memory 1)
+            (data (global.get $base) "")"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            module.encode_object(&ObjectSymbols::new()),
+            Err(ObjectError::NonConstantOffset(0))
+        );
+
+        let module: Module = "(table 1 funcref) (func $f) (elem (i32.const 0) $f)"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            module.encode_object(&ObjectSymbols::new()),
+            Err(ObjectError::ElementSegment)
+        );
+    }
+}
@@ -1,10 +1,12 @@
 use crate::{
     constants::CODE_SECTION,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
     expression::Expression,
     function_type::ValueType,
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct CodeSection(pub Vec<Function>);
 
 impl WasmEncode for CodeSection {
@@ -12,12 +14,21 @@ impl WasmEncode for CodeSection {
         let mut byte_count = 0;
         encoder.push_u8(CODE_SECTION);
         encoder.push_u8(0); // byte_count placeholder
+        encoder.begin_code();
         byte_count += encoder.push_leb_u32(self.0.len() as u32);
         byte_count += self.0.encode(encoder);
+        encoder.end_code();
         encoder.write_length(byte_count) + byte_count + 1
     }
 }
 
+impl WasmDecode for CodeSection {
+    fn decode(decoder: &mut WasmDecoder) -> Result<CodeSection, DecodeError> {
+        Ok(CodeSection(decoder.read_vector()?))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Function {
     pub locals: Vec<Local>,
     pub expression: Expression,
@@ -32,17 +43,33 @@ impl Function {
 impl WasmEncode for Function {
     fn encode(&self, encoder: &mut WasmEncoder) -> u32 {
         let mut byte_count = 0;
+        encoder.begin_function();
         encoder.push_u8(0); // byte_count placeholder
         byte_count += encoder.push_leb_u32(self.locals.len() as u32);
         byte_count += self.locals.encode(encoder);
         byte_count += self.expression.encode(encoder);
+        encoder.end_function();
         encoder.write_length(byte_count) + byte_count
     }
 }
 
+impl WasmDecode for Function {
+    fn decode(decoder: &mut WasmDecoder) -> Result<Function, DecodeError> {
+        let size = decoder.read_leb_u32()? as usize;
+        let start = decoder.position();
+        let locals = decoder.read_vector()?;
+        let expression = Expression::decode(decoder)?;
+        if decoder.position() - start != size {
+            return Err(DecodeError::SectionSizeMismatch(CODE_SECTION));
+        }
+        Ok(Function::new(locals, expression))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Local {
-    count: u32,
-    value_type: ValueType,
+    pub count: u32,
+    pub value_type: ValueType,
 }
 
 impl Local {
@@ -57,6 +84,15 @@ impl WasmEncode for Local {
     }
 }
 
+impl WasmDecode for Local {
+    fn decode(decoder: &mut WasmDecoder) -> Result<Local, DecodeError> {
+        Ok(Local::new(
+            decoder.read_leb_u32()?,
+            ValueType::decode(decoder)?,
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
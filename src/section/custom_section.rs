@@ -0,0 +1,89 @@
+use crate::{constants::CUSTOM_SECTION, encoder::WasmEncoder, section::Section};
+
+/**
+ * A typed custom section, such as `name` or `producers`, which only has to
+ * say how to encode its payload. `to_section` turns it into a
+ * `Section::Custom`, which can go anywhere in a module.
+ */
+pub trait CustomSection {
+    /** The name engines and tools recognize the section by */
+    fn name(&self) -> &str;
+
+    /** Returns number of bytes encoded, not counting the name */
+    fn encode_payload(&self, encoder: &mut WasmEncoder) -> u32;
+
+    /** The encoded payload, which the section's decoder reads */
+    fn payload(&self) -> Vec<u8> {
+        let mut encoder = WasmEncoder::new();
+        self.encode_payload(&mut encoder);
+        encoder.as_slice().to_vec()
+    }
+
+    fn to_section(&self) -> Section {
+        Section::Custom {
+            name: self.name().to_owned(),
+            payload: self.payload(),
+        }
+    }
+}
+
+/** Encodes a `Section::Custom` */
+pub(crate) fn encode_custom_section(name: &str, payload: &[u8], encoder: &mut WasmEncoder) -> u32 {
+    let mut byte_count = 0;
+    encoder.push_u8(CUSTOM_SECTION);
+    encoder.push_u8(0); // byte_count placeholder
+    byte_count += encoder.push_str(name);
+    byte_count += encoder.push_bytes(payload);
+    encoder.write_length(byte_count) + byte_count + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{assert_encoding_eq, WasmEncode};
+
+    struct Version(u32);
+
+    impl CustomSection for Version {
+        fn name(&self) -> &str {
+            "version"
+        }
+
+        fn encode_payload(&self, encoder: &mut WasmEncoder) -> u32 {
+            encoder.push_leb_u32(self.0)
+        }
+    }
+
+    #[test]
+    fn test_section_encoding() {
+        assert_encoding_eq(
+            Version(300).to_section(),
+            &[
+                0x00, // section id
+                0x0a, // byte count
+                0x07, // name length
+                0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, // name ("version")
+                0xac, 0x02, // payload
+            ],
+        );
+    }
+
+    #[test]
+    fn test_payload() {
+        assert_eq!(Version(300).payload(), vec![0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_long_payload_encoding() {
+        let payload = vec![0xab; 200];
+        let mut encoder = WasmEncoder::new();
+        let byte_count = Section::Custom {
+            name: "x".to_string(),
+            payload: payload.clone(),
+        }
+        .encode(&mut encoder);
+        assert_eq!(byte_count, 205);
+        assert_eq!(&encoder.as_slice()[..5], &[0x00, 0xca, 0x01, 0x01, b'x']);
+        assert_eq!(&encoder.as_slice()[5..], payload.as_slice());
+    }
+}
@@ -1,10 +1,12 @@
 use crate::{
     constants::DATA_SECTION,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
     expression::Expression,
     index::MemoryIndex,
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct DataSection(pub Vec<Data>);
 
 impl WasmEncode for DataSection {
@@ -18,6 +20,13 @@ impl WasmEncode for DataSection {
     }
 }
 
+impl WasmDecode for DataSection {
+    fn decode(decoder: &mut WasmDecoder) -> Result<DataSection, DecodeError> {
+        Ok(DataSection(decoder.read_vector()?))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Data {
     pub memory_index: MemoryIndex,
     pub offset: Expression,
@@ -47,6 +56,15 @@ impl WasmEncode for Data {
     }
 }
 
+impl WasmDecode for Data {
+    fn decode(decoder: &mut WasmDecoder) -> Result<Data, DecodeError> {
+        let memory_index = MemoryIndex(decoder.read_leb_u32()?);
+        let offset = Expression::decode(decoder)?;
+        let initializer = decoder.read_byte_vector()?.to_vec();
+        Ok(Data::new(memory_index, offset, initializer))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
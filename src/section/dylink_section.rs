@@ -0,0 +1,277 @@
+use crate::{
+    constants::CUSTOM_SECTION,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
+    encoder::WasmEncoder,
+    section::{custom_section::CustomSection, name_section::encode_subsection},
+};
+
+const MEMORY_INFO: u8 = 0x01;
+const NEEDED: u8 = 0x02;
+const EXPORT_INFO: u8 = 0x03;
+const IMPORT_INFO: u8 = 0x04;
+
+/** How much of the host's memory and table a side module needs */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryInfo {
+    /** The bytes reserved for the module's data, from `__memory_base` */
+    pub memory_size: u32,
+    /** The base-2 logarithm of the alignment of `__memory_base` */
+    pub memory_alignment: u32,
+    /** The table slots reserved for the module, from `__table_base` */
+    pub table_size: u32,
+    pub table_alignment: u32,
+}
+
+/** The symbol flags of an export, like `SYMBOL_TLS` */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportInfo {
+    pub name: String,
+    pub flags: u32,
+}
+
+/** The symbol flags of an import, like `SYMBOL_BINDING_WEAK` */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportInfo {
+    pub module_name: String,
+    pub name: String,
+    pub flags: u32,
+}
+
+/**
+ * The `dylink.0` custom section, which tells a dynamic loader where to put a
+ * side module and what to load before it. It has to be the module's first
+ * section, which `Module::to_side_module` puts it as.
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DylinkSection {
+    pub memory_info: MemoryInfo,
+    /** The names of the libraries to load first, like `libc.so` */
+    pub needed: Vec<String>,
+    pub export_info: Vec<ExportInfo>,
+    pub import_info: Vec<ImportInfo>,
+}
+
+impl DylinkSection {
+    pub fn new() -> DylinkSection {
+        DylinkSection::default()
+    }
+
+    pub fn add_needed(&mut self, library: &str) {
+        self.needed.push(library.to_owned());
+    }
+
+    /** Sets an export's flags, replacing any it already has */
+    pub fn set_export_flags(&mut self, name: &str, flags: u32) {
+        match self.export_info.iter_mut().find(|info| info.name == name) {
+            Some(info) => info.flags = flags,
+            None => self.export_info.push(ExportInfo {
+                name: name.to_owned(),
+                flags,
+            }),
+        }
+    }
+
+    /** Sets an import's flags, replacing any it already has */
+    pub fn set_import_flags(&mut self, module_name: &str, name: &str, flags: u32) {
+        let existing = self
+            .import_info
+            .iter_mut()
+            .find(|info| info.module_name == module_name && info.name == name);
+        match existing {
+            Some(info) => info.flags = flags,
+            None => self.import_info.push(ImportInfo {
+                module_name: module_name.to_owned(),
+                name: name.to_owned(),
+                flags,
+            }),
+        }
+    }
+}
+
+impl CustomSection for DylinkSection {
+    fn name(&self) -> &str {
+        "dylink.0"
+    }
+
+    /** Encodes the memory info, then the non-empty subsections */
+    fn encode_payload(&self, encoder: &mut WasmEncoder) -> u32 {
+        let info = &self.memory_info;
+        let mut byte_count = encode_subsection(MEMORY_INFO, encoder, |encoder| {
+            encoder.push_leb_u32(info.memory_size)
+                + encoder.push_leb_u32(info.memory_alignment)
+                + encoder.push_leb_u32(info.table_size)
+                + encoder.push_leb_u32(info.table_alignment)
+        });
+        if !self.needed.is_empty() {
+            byte_count += encode_subsection(NEEDED, encoder, |encoder| {
+                let mut byte_count = encoder.push_leb_u32(self.needed.len() as u32);
+                for library in self.needed.iter() {
+                    byte_count += encoder.push_str(library);
+                }
+                byte_count
+            });
+        }
+        if !self.export_info.is_empty() {
+            byte_count += encode_subsection(EXPORT_INFO, encoder, |encoder| {
+                let mut byte_count = encoder.push_leb_u32(self.export_info.len() as u32);
+                for export in self.export_info.iter() {
+                    byte_count += encoder.push_str(&export.name);
+                    byte_count += encoder.push_leb_u32(export.flags);
+                }
+                byte_count
+            });
+        }
+        if !self.import_info.is_empty() {
+            byte_count += encode_subsection(IMPORT_INFO, encoder, |encoder| {
+                let mut byte_count = encoder.push_leb_u32(self.import_info.len() as u32);
+                for import in self.import_info.iter() {
+                    byte_count += encoder.push_str(&import.module_name);
+                    byte_count += encoder.push_str(&import.name);
+                    byte_count += encoder.push_leb_u32(import.flags);
+                }
+                byte_count
+            });
+        }
+        byte_count
+    }
+}
+
+impl WasmDecode for DylinkSection {
+    /**
+     * Decodes the payload of a `dylink.0` section. Subsections added by later
+     * conventions, like the runtime path, are skipped.
+     */
+    fn decode(decoder: &mut WasmDecoder) -> Result<DylinkSection, DecodeError> {
+        let mut dylink = DylinkSection::new();
+        while !decoder.is_empty() {
+            let id = decoder.read_u8()?;
+            let size = decoder.read_leb_u32()? as usize;
+            let start = decoder.position();
+            match id {
+                MEMORY_INFO => {
+                    dylink.memory_info = MemoryInfo {
+                        memory_size: decoder.read_leb_u32()?,
+                        memory_alignment: decoder.read_leb_u32()?,
+                        table_size: decoder.read_leb_u32()?,
+                        table_alignment: decoder.read_leb_u32()?,
+                    }
+                }
+                NEEDED => {
+                    for _ in 0..decoder.read_leb_u32()? {
+                        dylink.needed.push(decoder.read_name()?);
+                    }
+                }
+                EXPORT_INFO => {
+                    for _ in 0..decoder.read_leb_u32()? {
+                        dylink.export_info.push(ExportInfo {
+                            name: decoder.read_name()?,
+                            flags: decoder.read_leb_u32()?,
+                        });
+                    }
+                }
+                IMPORT_INFO => {
+                    for _ in 0..decoder.read_leb_u32()? {
+                        dylink.import_info.push(ImportInfo {
+                            module_name: decoder.read_name()?,
+                            name: decoder.read_name()?,
+                            flags: decoder.read_leb_u32()?,
+                        });
+                    }
+                }
+                _ => {
+                    decoder.read_bytes(size)?;
+                }
+            }
+            if decoder.position() - start != size {
+                return Err(DecodeError::SectionSizeMismatch(CUSTOM_SECTION));
+            }
+        }
+        Ok(dylink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encoder::assert_encoding_eq,
+        section::linking_section::{SYMBOL_BINDING_WEAK, SYMBOL_TLS},
+    };
+
+    fn dylink() -> DylinkSection {
+        let mut dylink = DylinkSection::new();
+        dylink.memory_info = MemoryInfo {
+            memory_size: 200,
+            memory_alignment: 4,
+            table_size: 3,
+            table_alignment: 0,
+        };
+        dylink.add_needed("a.so");
+        dylink.set_import_flags("env", "f", SYMBOL_BINDING_WEAK);
+        dylink
+    }
+
+    #[test]
+    fn test_section_encoding() {
+        assert_encoding_eq(
+            dylink().to_section(),
+            &[
+                0x00, // section id
+                0x22, // byte count
+                0x08, // name length
+                0x64, 0x79, 0x6c, 0x69, 0x6e, 0x6b, 0x2e, 0x30, // name ("dylink.0")
+                0x01, // memory info
+                0x05, // subsection byte count
+                0xc8, 0x01, // memory size
+                0x04, // memory alignment
+                0x03, // table size
+                0x00, // table alignment
+                0x02, // needed
+                0x06, // subsection byte count
+                0x01, // library count
+                0x04, 0x61, 0x2e, 0x73, 0x6f, // name ("a.so")
+                0x04, // import info
+                0x08, // subsection byte count
+                0x01, // import count
+                0x03, 0x65, 0x6e, 0x76, // module name ("env")
+                0x01, 0x66, // name ("f")
+                0x01, // flags
+            ],
+        );
+    }
+
+    #[test]
+    fn test_set_flags() {
+        let mut dylink = dylink();
+        dylink.set_import_flags("env", "f", 0);
+        dylink.set_import_flags("other", "f", SYMBOL_BINDING_WEAK);
+        dylink.set_export_flags("x", SYMBOL_TLS);
+        dylink.set_export_flags("x", 0);
+
+        let flags: Vec<(&str, u32)> = dylink
+            .import_info
+            .iter()
+            .map(|info| (info.module_name.as_str(), info.flags))
+            .collect();
+        assert_eq!(flags, vec![("env", 0), ("other", SYMBOL_BINDING_WEAK)]);
+        assert_eq!(dylink.export_info.len(), 1);
+        assert_eq!(dylink.export_info[0].flags, 0);
+    }
+
+    #[test]
+    fn test_decoding_round_trip() {
+        let mut dylink = dylink();
+        dylink.set_export_flags("x", SYMBOL_TLS);
+        let payload = dylink.payload();
+        let mut decoder = WasmDecoder::new(&payload);
+        assert_eq!(DylinkSection::decode(&mut decoder), Ok(dylink));
+
+        // An unknown subsection, then memory info that's a byte short
+        let decode = |bytes: &[u8]| DylinkSection::decode(&mut WasmDecoder::new(bytes));
+        assert_eq!(decode(b"\x05\x01\x00"), Ok(DylinkSection::new()));
+        assert_eq!(
+            decode(b"\x01\x03\x00\x00\x00\x00"),
+            Err(DecodeError::SectionSizeMismatch(CUSTOM_SECTION))
+        );
+    }
+}
@@ -1,10 +1,12 @@
 use crate::{
     constants::ELEMENT_SECTION,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
     expression::Expression,
-    index::TableIndex,
+    index::{FunctionIndex, TableIndex},
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct ElementSection(pub Vec<Element>);
 
 impl WasmEncode for ElementSection {
@@ -18,14 +20,25 @@ impl WasmEncode for ElementSection {
     }
 }
 
+impl WasmDecode for ElementSection {
+    fn decode(decoder: &mut WasmDecoder) -> Result<ElementSection, DecodeError> {
+        Ok(ElementSection(decoder.read_vector()?))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Element {
     pub table_index: TableIndex,
     pub offset: Expression,
-    pub initializer: Vec<u32>,
+    pub initializer: Vec<FunctionIndex>,
 }
 
 impl Element {
-    pub fn new(table_index: TableIndex, offset: Expression, initializer: Vec<u32>) -> Element {
+    pub fn new(
+        table_index: TableIndex,
+        offset: Expression,
+        initializer: Vec<FunctionIndex>,
+    ) -> Element {
         Element {
             table_index,
             offset,
@@ -41,12 +54,24 @@ impl WasmEncode for Element {
         byte_count += self.offset.encode(encoder);
         byte_count += encoder.push_leb_u32(self.initializer.len() as u32);
         for function_index in self.initializer.iter() {
-            byte_count += encoder.push_leb_u32(*function_index);
+            byte_count += encoder.push_leb_u32(function_index.0);
         }
         byte_count
     }
 }
 
+impl WasmDecode for Element {
+    fn decode(decoder: &mut WasmDecoder) -> Result<Element, DecodeError> {
+        let table_index = TableIndex(decoder.read_leb_u32()?);
+        let offset = Expression::decode(decoder)?;
+        let count = decoder.read_leb_u32()?;
+        let initializer = (0..count)
+            .map(|_| decoder.read_leb_u32().map(FunctionIndex))
+            .collect::<Result<_, _>>()?;
+        Ok(Element::new(table_index, offset, initializer))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -58,7 +83,7 @@ mod tests {
             ElementSection(vec![Element::new(
                 TableIndex(0),
                 Expression(vec![Instruction::I32Const(0)]),
-                vec![0],
+                vec![FunctionIndex(0)],
             )]),
             &[
                 0x09, // section id
@@ -1,9 +1,11 @@
 use crate::{
     constants::{EXPORT_SECTION, FUNCTION_INDEX, GLOBAL_INDEX, MEMORY_INDEX, TABLE_INDEX},
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
     index::{FunctionIndex, GlobalIndex, MemoryIndex, TableIndex},
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct ExportSection(pub Vec<Export>);
 
 impl WasmEncode for ExportSection {
@@ -17,6 +19,13 @@ impl WasmEncode for ExportSection {
     }
 }
 
+impl WasmDecode for ExportSection {
+    fn decode(decoder: &mut WasmDecoder) -> Result<ExportSection, DecodeError> {
+        Ok(ExportSection(decoder.read_vector()?))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Export {
     pub name: String,
     pub descriptor: ExportDescriptor,
@@ -37,6 +46,23 @@ impl WasmEncode for Export {
     }
 }
 
+impl WasmDecode for Export {
+    fn decode(decoder: &mut WasmDecoder) -> Result<Export, DecodeError> {
+        let name = decoder.read_name()?;
+        let kind = decoder.read_u8()?;
+        let index = decoder.read_leb_u32()?;
+        let descriptor = match kind {
+            FUNCTION_INDEX => ExportDescriptor::FunctionIndex(FunctionIndex(index)),
+            TABLE_INDEX => ExportDescriptor::TableIndex(TableIndex(index)),
+            MEMORY_INDEX => ExportDescriptor::MemoryIndex(MemoryIndex(index)),
+            GLOBAL_INDEX => ExportDescriptor::GlobalIndex(GlobalIndex(index)),
+            byte => return Err(DecodeError::InvalidDescriptor(byte)),
+        };
+        Ok(Export::new(&name, descriptor))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum ExportDescriptor {
     FunctionIndex(FunctionIndex),
     TableIndex(TableIndex),
@@ -1,9 +1,11 @@
 use crate::{
     constants::FUNCTION_SECTION,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
     index::TypeIndex,
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct FunctionSection(pub Vec<TypeIndex>);
 
 impl WasmEncode for FunctionSection {
@@ -19,6 +21,16 @@ impl WasmEncode for FunctionSection {
     }
 }
 
+impl WasmDecode for FunctionSection {
+    fn decode(decoder: &mut WasmDecoder) -> Result<FunctionSection, DecodeError> {
+        let count = decoder.read_leb_u32()?;
+        let type_indices = (0..count)
+            .map(|_| decoder.read_leb_u32().map(TypeIndex))
+            .collect::<Result<_, _>>()?;
+        Ok(FunctionSection(type_indices))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,10 +1,12 @@
 use crate::{
     constants::{CONST, GLOBAL_SECTION, VAR},
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
-    expression::{Expression, Instruction},
+    expression::Expression,
     function_type::ValueType,
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct GlobalSection(pub Vec<Global>);
 
 impl WasmEncode for GlobalSection {
@@ -18,28 +20,103 @@ impl WasmEncode for GlobalSection {
     }
 }
 
+impl WasmDecode for GlobalSection {
+    fn decode(decoder: &mut WasmDecoder) -> Result<GlobalSection, DecodeError> {
+        Ok(GlobalSection(decoder.read_vector()?))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Global {
     Const(ValueType, Expression),
     Var(ValueType, Expression),
 }
 
+impl Global {
+    pub fn global_type(&self) -> GlobalType {
+        match self {
+            Global::Const(value_type, _) => GlobalType::new(*value_type, Mutability::Const),
+            Global::Var(value_type, _) => GlobalType::new(*value_type, Mutability::Var),
+        }
+    }
+}
+
 impl WasmEncode for Global {
     fn encode(&self, encoder: &mut WasmEncoder) -> u32 {
         match self {
-            Global::Const(value_type, expr) => {
-                value_type.encode(encoder) + encoder.push_u8(CONST) + expr.encode(encoder)
-            }
-            Global::Var(value_type, expr) => {
-                value_type.encode(encoder) + encoder.push_u8(VAR) + expr.encode(encoder)
+            Global::Const(_, expr) | Global::Var(_, expr) => {
+                self.global_type().encode(encoder) + expr.encode(encoder)
             }
         }
     }
 }
 
+impl WasmDecode for Global {
+    fn decode(decoder: &mut WasmDecoder) -> Result<Global, DecodeError> {
+        let global_type = GlobalType::decode(decoder)?;
+        let initializer = Expression::decode(decoder)?;
+        Ok(match global_type.mutability {
+            Mutability::Const => Global::Const(global_type.value_type, initializer),
+            Mutability::Var => Global::Var(global_type.value_type, initializer),
+        })
+    }
+}
+
+/**
+ * The type of a global without its initializer, as used by imports.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlobalType {
+    pub value_type: ValueType,
+    pub mutability: Mutability,
+}
+
+impl GlobalType {
+    pub fn new(value_type: ValueType, mutability: Mutability) -> GlobalType {
+        GlobalType {
+            value_type,
+            mutability,
+        }
+    }
+}
+
+impl WasmEncode for GlobalType {
+    fn encode(&self, encoder: &mut WasmEncoder) -> u32 {
+        self.value_type.encode(encoder) + self.mutability.encode(encoder)
+    }
+}
+
+impl WasmDecode for GlobalType {
+    fn decode(decoder: &mut WasmDecoder) -> Result<GlobalType, DecodeError> {
+        let value_type = ValueType::decode(decoder)?;
+        let mutability = match decoder.read_u8()? {
+            CONST => Mutability::Const,
+            VAR => Mutability::Var,
+            byte => return Err(DecodeError::InvalidMutability(byte)),
+        };
+        Ok(GlobalType::new(value_type, mutability))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mutability {
+    Const,
+    Var,
+}
+
+impl WasmEncode for Mutability {
+    fn encode(&self, encoder: &mut WasmEncoder) -> u32 {
+        match self {
+            Mutability::Const => encoder.push_u8(CONST),
+            Mutability::Var => encoder.push_u8(VAR),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::encoder::assert_encoding_eq;
+    use crate::{encoder::assert_encoding_eq, expression::Instruction};
 
     #[test]
     fn test_section_encoding() {
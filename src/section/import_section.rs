@@ -1,10 +1,12 @@
 use crate::{
     constants::{GLOBAL_TYPE, IMPORT_SECTION, MEMORY_TYPE, TABLE_TYPE, TYPE_INDEX},
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
     index::TypeIndex,
-    section::{global_section::Global, memory_section::Memory, table_section::Table},
+    section::{global_section::GlobalType, memory_section::Memory, table_section::Table},
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct ImportSection(pub Vec<Import>);
 
 impl WasmEncode for ImportSection {
@@ -18,6 +20,13 @@ impl WasmEncode for ImportSection {
     }
 }
 
+impl WasmDecode for ImportSection {
+    fn decode(decoder: &mut WasmDecoder) -> Result<ImportSection, DecodeError> {
+        Ok(ImportSection(decoder.read_vector()?))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Import {
     pub module_name: String,
     pub name: String,
@@ -42,11 +51,27 @@ impl WasmEncode for Import {
     }
 }
 
+impl WasmDecode for Import {
+    fn decode(decoder: &mut WasmDecoder) -> Result<Import, DecodeError> {
+        let module_name = decoder.read_name()?;
+        let name = decoder.read_name()?;
+        let descriptor = match decoder.read_u8()? {
+            TYPE_INDEX => ImportDescriptor::TypeIndex(TypeIndex(decoder.read_leb_u32()?)),
+            TABLE_TYPE => ImportDescriptor::TableType(Table::decode(decoder)?),
+            MEMORY_TYPE => ImportDescriptor::MemoryType(Memory::decode(decoder)?),
+            GLOBAL_TYPE => ImportDescriptor::GlobalType(GlobalType::decode(decoder)?),
+            byte => return Err(DecodeError::InvalidDescriptor(byte)),
+        };
+        Ok(Import::new(&module_name, &name, descriptor))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum ImportDescriptor {
     TypeIndex(TypeIndex),
     TableType(Table),
     MemoryType(Memory),
-    GlobalType(Global),
+    GlobalType(GlobalType),
 }
 
 impl WasmEncode for ImportDescriptor {
@@ -61,8 +86,8 @@ impl WasmEncode for ImportDescriptor {
             ImportDescriptor::MemoryType(memory) => {
                 encoder.push_u8(MEMORY_TYPE) + memory.encode(encoder)
             }
-            ImportDescriptor::GlobalType(global) => {
-                encoder.push_u8(GLOBAL_TYPE) + global.encode(encoder)
+            ImportDescriptor::GlobalType(global_type) => {
+                encoder.push_u8(GLOBAL_TYPE) + global_type.encode(encoder)
             }
         }
     }
@@ -71,7 +96,9 @@ impl WasmEncode for ImportDescriptor {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::encoder::assert_encoding_eq;
+    use crate::{
+        encoder::assert_encoding_eq, function_type::ValueType, section::global_section::Mutability,
+    };
 
     #[test]
     fn test_section_encoding() {
@@ -94,4 +121,24 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn test_global_import_encoding() {
+        assert_encoding_eq(
+            Import::new(
+                "env",
+                "sp",
+                ImportDescriptor::GlobalType(GlobalType::new(ValueType::I32, Mutability::Var)),
+            ),
+            &[
+                0x03, // module name length
+                0x65, 0x6e, 0x76, // module name ("env")
+                0x02, // name length
+                0x73, 0x70, // name ("sp")
+                0x03, // import type id
+                0x7f, // value type
+                0x01, // mutability
+            ],
+        );
+    }
 }
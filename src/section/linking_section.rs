@@ -0,0 +1,387 @@
+use crate::{
+    constants::CUSTOM_SECTION,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
+    encoder::WasmEncoder,
+    index::{FunctionIndex, GlobalIndex},
+    section::{custom_section::CustomSection, name_section::encode_subsection},
+};
+
+/** The version of the linking conventions the section follows */
+pub const LINKING_VERSION: u32 = 2;
+
+const SEGMENT_INFO: u8 = 0x05;
+const INIT_FUNCS: u8 = 0x06;
+const SYMBOL_TABLE: u8 = 0x08;
+
+const FUNCTION_SYMBOL: u8 = 0x00;
+const DATA_SYMBOL: u8 = 0x01;
+const GLOBAL_SYMBOL: u8 = 0x02;
+const SECTION_SYMBOL: u8 = 0x03;
+
+// Symbol flags
+pub const SYMBOL_BINDING_WEAK: u32 = 0x01;
+pub const SYMBOL_BINDING_LOCAL: u32 = 0x02;
+pub const SYMBOL_VISIBILITY_HIDDEN: u32 = 0x04;
+pub const SYMBOL_UNDEFINED: u32 = 0x10;
+pub const SYMBOL_EXPORTED: u32 = 0x20;
+pub const SYMBOL_EXPLICIT_NAME: u32 = 0x40;
+pub const SYMBOL_NO_STRIP: u32 = 0x80;
+pub const SYMBOL_TLS: u32 = 0x100;
+
+/** Where a defined data symbol is, within the module's data segments */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DataLocation {
+    /** The index of the segment in the data section */
+    pub segment: u32,
+    pub offset: u32,
+    pub size: u32,
+}
+
+impl DataLocation {
+    pub fn new(segment: u32, offset: u32, size: u32) -> DataLocation {
+        DataLocation {
+            segment,
+            offset,
+            size,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SymbolKind {
+    Function(FunctionIndex),
+    Global(GlobalIndex),
+    /** A data symbol, which is undefined if it has no location */
+    Data(Option<DataLocation>),
+    /** A section, by its index in the module, for relocations in debug info */
+    Section(u32),
+}
+
+/** An entry of the linking section's symbol table */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    /** Any of the `SYMBOL_*` flags */
+    pub flags: u32,
+    /**
+     * Empty for sections, and for undefined functions and globals without
+     * `SYMBOL_EXPLICIT_NAME`, which go by the name of their import.
+     */
+    pub name: String,
+}
+
+impl Symbol {
+    pub fn new(kind: SymbolKind, flags: u32, name: &str) -> Symbol {
+        Symbol {
+            kind,
+            flags,
+            name: name.to_owned(),
+        }
+    }
+
+    pub fn is_undefined(&self) -> bool {
+        self.flags & SYMBOL_UNDEFINED != 0
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.flags & SYMBOL_BINDING_LOCAL != 0
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.flags & SYMBOL_BINDING_WEAK != 0
+    }
+
+    /** Whether the symbol table has the name, rather than the import */
+    fn has_name(&self) -> bool {
+        !self.is_undefined() || self.flags & SYMBOL_EXPLICIT_NAME != 0
+    }
+}
+
+/** How the linker has to treat a data segment */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SegmentInfo {
+    /** Segments are merged by name prefix, like `.data` or `.rodata` */
+    pub name: String,
+    /** The base-2 logarithm of the segment's alignment */
+    pub alignment: u32,
+    pub flags: u32,
+}
+
+impl SegmentInfo {
+    pub fn new(name: &str, alignment: u32) -> SegmentInfo {
+        SegmentInfo {
+            name: name.to_owned(),
+            alignment,
+            flags: 0,
+        }
+    }
+}
+
+/** A function the linker calls before any other code runs */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InitFunction {
+    /** Lower priorities run first */
+    pub priority: u32,
+    /** The index of the function's symbol in the symbol table */
+    pub symbol: u32,
+}
+
+/**
+ * The `linking` custom section of a relocatable object file, which has the
+ * symbol table that relocations refer to, along with what the linker needs
+ * to know about data segments and initialization.
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LinkingSection {
+    pub symbols: Vec<Symbol>,
+    /** Has an entry for each data segment, or none at all */
+    pub segments: Vec<SegmentInfo>,
+    pub init_functions: Vec<InitFunction>,
+}
+
+impl LinkingSection {
+    pub fn new() -> LinkingSection {
+        LinkingSection::default()
+    }
+
+    /** Adds a symbol and returns its index in the symbol table */
+    pub fn add_symbol(&mut self, symbol: Symbol) -> u32 {
+        self.symbols.push(symbol);
+        self.symbols.len() as u32 - 1
+    }
+}
+
+fn encode_symbol(symbol: &Symbol, encoder: &mut WasmEncoder) -> u32 {
+    let (kind, index) = match &symbol.kind {
+        SymbolKind::Function(index) => (FUNCTION_SYMBOL, Some(index.0)),
+        SymbolKind::Global(index) => (GLOBAL_SYMBOL, Some(index.0)),
+        SymbolKind::Data(_) => (DATA_SYMBOL, None),
+        SymbolKind::Section(index) => (SECTION_SYMBOL, Some(*index)),
+    };
+    let mut byte_count = encoder.push_u8(kind) + encoder.push_leb_u32(symbol.flags);
+    if let Some(index) = index {
+        byte_count += encoder.push_leb_u32(index);
+    }
+    match &symbol.kind {
+        SymbolKind::Function(_) | SymbolKind::Global(_) if symbol.has_name() => {
+            byte_count += encoder.push_str(&symbol.name);
+        }
+        SymbolKind::Data(location) => {
+            byte_count += encoder.push_str(&symbol.name);
+            if let Some(location) = location {
+                byte_count += encoder.push_leb_u32(location.segment);
+                byte_count += encoder.push_leb_u32(location.offset);
+                byte_count += encoder.push_leb_u32(location.size);
+            }
+        }
+        _ => {}
+    }
+    byte_count
+}
+
+impl CustomSection for LinkingSection {
+    fn name(&self) -> &str {
+        "linking"
+    }
+
+    /** Encodes the version, then the non-empty subsections */
+    fn encode_payload(&self, encoder: &mut WasmEncoder) -> u32 {
+        let mut byte_count = encoder.push_leb_u32(LINKING_VERSION);
+        if !self.symbols.is_empty() {
+            byte_count += encode_subsection(SYMBOL_TABLE, encoder, |encoder| {
+                let mut byte_count = encoder.push_leb_u32(self.symbols.len() as u32);
+                for symbol in self.symbols.iter() {
+                    byte_count += encode_symbol(symbol, encoder);
+                }
+                byte_count
+            });
+        }
+        if !self.segments.is_empty() {
+            byte_count += encode_subsection(SEGMENT_INFO, encoder, |encoder| {
+                let mut byte_count = encoder.push_leb_u32(self.segments.len() as u32);
+                for segment in self.segments.iter() {
+                    byte_count += encoder.push_str(&segment.name);
+                    byte_count += encoder.push_leb_u32(segment.alignment);
+                    byte_count += encoder.push_leb_u32(segment.flags);
+                }
+                byte_count
+            });
+        }
+        if !self.init_functions.is_empty() {
+            byte_count += encode_subsection(INIT_FUNCS, encoder, |encoder| {
+                let mut byte_count = encoder.push_leb_u32(self.init_functions.len() as u32);
+                for init_function in self.init_functions.iter() {
+                    byte_count += encoder.push_leb_u32(init_function.priority);
+                    byte_count += encoder.push_leb_u32(init_function.symbol);
+                }
+                byte_count
+            });
+        }
+        byte_count
+    }
+}
+
+fn decode_symbol(decoder: &mut WasmDecoder) -> Result<Symbol, DecodeError> {
+    let kind = decoder.read_u8()?;
+    let flags = decoder.read_leb_u32()?;
+    let mut symbol = Symbol::new(SymbolKind::Section(0), flags, "");
+    symbol.kind = match kind {
+        FUNCTION_SYMBOL => SymbolKind::Function(FunctionIndex(decoder.read_leb_u32()?)),
+        GLOBAL_SYMBOL => SymbolKind::Global(GlobalIndex(decoder.read_leb_u32()?)),
+        DATA_SYMBOL => {
+            symbol.name = decoder.read_name()?;
+            match symbol.is_undefined() {
+                true => SymbolKind::Data(None),
+                false => SymbolKind::Data(Some(DataLocation::new(
+                    decoder.read_leb_u32()?,
+                    decoder.read_leb_u32()?,
+                    decoder.read_leb_u32()?,
+                ))),
+            }
+        }
+        SECTION_SYMBOL => SymbolKind::Section(decoder.read_leb_u32()?),
+        _ => {
+            return Err(DecodeError::MalformedCustomSection(
+                "unsupported symbol kind",
+            ))
+        }
+    };
+    if let SymbolKind::Function(_) | SymbolKind::Global(_) = symbol.kind {
+        if symbol.has_name() {
+            symbol.name = decoder.read_name()?;
+        }
+    }
+    Ok(symbol)
+}
+
+impl WasmDecode for LinkingSection {
+    /** Decodes the payload of a `linking` section */
+    fn decode(decoder: &mut WasmDecoder) -> Result<LinkingSection, DecodeError> {
+        if decoder.read_leb_u32()? != LINKING_VERSION {
+            return Err(DecodeError::MalformedCustomSection(
+                "unsupported linking version",
+            ));
+        }
+        let mut linking = LinkingSection::new();
+        while !decoder.is_empty() {
+            let id = decoder.read_u8()?;
+            let size = decoder.read_leb_u32()? as usize;
+            let start = decoder.position();
+            // Comdats can't be ignored, since they decide which duplicate
+            // definitions are kept
+            if ![SYMBOL_TABLE, SEGMENT_INFO, INIT_FUNCS].contains(&id) {
+                return Err(DecodeError::MalformedCustomSection(
+                    "unsupported linking subsection",
+                ));
+            }
+            for _ in 0..decoder.read_leb_u32()? {
+                match id {
+                    SYMBOL_TABLE => linking.symbols.push(decode_symbol(decoder)?),
+                    SEGMENT_INFO => linking.segments.push(SegmentInfo {
+                        name: decoder.read_name()?,
+                        alignment: decoder.read_leb_u32()?,
+                        flags: decoder.read_leb_u32()?,
+                    }),
+                    INIT_FUNCS => linking.init_functions.push(InitFunction {
+                        priority: decoder.read_leb_u32()?,
+                        symbol: decoder.read_leb_u32()?,
+                    }),
+                    _ => unreachable!(),
+                }
+            }
+            if decoder.position() - start != size {
+                return Err(DecodeError::SectionSizeMismatch(CUSTOM_SECTION));
+            }
+        }
+        Ok(linking)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::assert_encoding_eq;
+
+    fn linking() -> LinkingSection {
+        let mut linking = LinkingSection::new();
+        linking.add_symbol(Symbol::new(
+            SymbolKind::Function(FunctionIndex(0)),
+            SYMBOL_UNDEFINED,
+            "",
+        ));
+        let main = linking.add_symbol(Symbol::new(
+            SymbolKind::Function(FunctionIndex(1)),
+            SYMBOL_EXPORTED,
+            "main",
+        ));
+        linking.add_symbol(Symbol::new(
+            SymbolKind::Data(Some(DataLocation::new(0, 4, 8))),
+            SYMBOL_BINDING_LOCAL,
+            "s",
+        ));
+        linking.add_symbol(Symbol::new(SymbolKind::Data(None), SYMBOL_UNDEFINED, "t"));
+        linking.segments.push(SegmentInfo::new(".data", 2));
+        linking.init_functions.push(InitFunction {
+            priority: 1,
+            symbol: main,
+        });
+        linking
+    }
+
+    #[test]
+    fn test_section_encoding() {
+        assert_encoding_eq(
+            linking().to_section(),
+            &[
+                0x00, // section id
+                0x32, // byte count
+                0x07, // name length
+                0x6c, 0x69, 0x6e, 0x6b, 0x69, 0x6e, 0x67, // name ("linking")
+                0x02, // version
+                0x08, // symbol table
+                0x17, // subsection byte count
+                0x04, // symbol count
+                0x00, 0x10, 0x00, // undefined function 0
+                0x00, 0x20, 0x01, // exported function 1
+                0x04, 0x6d, 0x61, 0x69, 0x6e, // name ("main")
+                0x01, 0x02, // local data
+                0x01, 0x73, // name ("s")
+                0x00, 0x04, 0x08, // segment, offset and size
+                0x01, 0x10, // undefined data
+                0x01, 0x74, // name ("t")
+                0x05, // segment info
+                0x09, // subsection byte count
+                0x01, // segment count
+                0x05, 0x2e, 0x64, 0x61, 0x74, 0x61, // name (".data")
+                0x02, // alignment
+                0x00, // flags
+                0x06, // init functions
+                0x03, // subsection byte count
+                0x01, // init function count
+                0x01, // priority
+                0x01, // symbol
+            ],
+        );
+    }
+
+    #[test]
+    fn test_decoding_round_trip() {
+        let payload = linking().payload();
+        let mut decoder = WasmDecoder::new(&payload);
+        assert_eq!(LinkingSection::decode(&mut decoder), Ok(linking()));
+
+        let decode = |bytes: &[u8]| LinkingSection::decode(&mut WasmDecoder::new(bytes));
+        assert_eq!(
+            decode(b"\x01"),
+            Err(DecodeError::MalformedCustomSection(
+                "unsupported linking version"
+            ))
+        );
+        assert_eq!(
+            decode(b"\x02\x07\x01\x00"),
+            Err(DecodeError::MalformedCustomSection(
+                "unsupported linking subsection"
+            ))
+        );
+    }
+}
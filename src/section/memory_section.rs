@@ -1,9 +1,11 @@
 use crate::{
     constants::MEMORY_SECTION,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
     limits::Limits,
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct MemorySection(pub Vec<Memory>);
 
 impl WasmEncode for MemorySection {
@@ -17,6 +19,13 @@ impl WasmEncode for MemorySection {
     }
 }
 
+impl WasmDecode for MemorySection {
+    fn decode(decoder: &mut WasmDecoder) -> Result<MemorySection, DecodeError> {
+        Ok(MemorySection(decoder.read_vector()?))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Memory {
     pub limits: Limits,
 }
@@ -33,6 +42,12 @@ impl WasmEncode for Memory {
     }
 }
 
+impl WasmDecode for Memory {
+    fn decode(decoder: &mut WasmDecoder) -> Result<Memory, DecodeError> {
+        Ok(Memory::new(Limits::decode(decoder)?))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,27 +1,43 @@
 use crate::{
+    constants::*,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
     section::{
-        code_section::CodeSection, data_section::DataSection, element_section::ElementSection,
-        export_section::ExportSection, function_section::FunctionSection,
-        global_section::GlobalSection, import_section::ImportSection,
-        memory_section::MemorySection, start_section::StartSection, table_section::TableSection,
-        type_section::TypeSection,
+        code_section::CodeSection, custom_section::encode_custom_section,
+        data_section::DataSection, element_section::ElementSection, export_section::ExportSection,
+        function_section::FunctionSection, global_section::GlobalSection,
+        import_section::ImportSection, memory_section::MemorySection, start_section::StartSection,
+        table_section::TableSection, type_section::TypeSection,
     },
 };
 
 pub mod code_section;
+pub mod custom_section;
 pub mod data_section;
+pub mod dylink_section;
 pub mod element_section;
 pub mod export_section;
 pub mod function_section;
 pub mod global_section;
 pub mod import_section;
+pub mod linking_section;
 pub mod memory_section;
+pub mod name_section;
+pub mod producers_section;
+pub mod relocation_section;
+pub mod source_mapping_url_section;
 pub mod start_section;
 pub mod table_section;
+pub mod target_features_section;
 pub mod type_section;
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum Section {
+    /** A section engines ignore, unless they know it by name */
+    Custom {
+        name: String,
+        payload: Vec<u8>,
+    },
     TypeSection(TypeSection),
     ImportSection(ImportSection),
     FunctionSection(FunctionSection),
@@ -35,9 +51,29 @@ pub enum Section {
     DataSection(DataSection),
 }
 
+impl Section {
+    pub fn id(&self) -> u8 {
+        match self {
+            Section::Custom { .. } => CUSTOM_SECTION,
+            Section::TypeSection(_) => TYPE_SECTION,
+            Section::ImportSection(_) => IMPORT_SECTION,
+            Section::FunctionSection(_) => FUNCTION_SECTION,
+            Section::TableSection(_) => TABLE_SECTION,
+            Section::MemorySection(_) => MEMORY_SECTION,
+            Section::GlobalSection(_) => GLOBAL_SECTION,
+            Section::ExportSection(_) => EXPORT_SECTION,
+            Section::StartSection(_) => START_SECTION,
+            Section::ElementSection(_) => ELEMENT_SECTION,
+            Section::CodeSection(_) => CODE_SECTION,
+            Section::DataSection(_) => DATA_SECTION,
+        }
+    }
+}
+
 impl WasmEncode for Section {
     fn encode(&self, encoder: &mut WasmEncoder) -> u32 {
         match self {
+            Section::Custom { name, payload } => encode_custom_section(name, payload, encoder),
             Section::TypeSection(type_section) => type_section.encode(encoder),
             Section::ImportSection(import_section) => import_section.encode(encoder),
             Section::FunctionSection(function_section) => function_section.encode(encoder),
@@ -52,3 +88,39 @@ impl WasmEncode for Section {
         }
     }
 }
+
+impl WasmDecode for Section {
+    fn decode(decoder: &mut WasmDecoder) -> Result<Section, DecodeError> {
+        let id = decoder.read_u8()?;
+        let size = decoder.read_leb_u32()? as usize;
+        let start = decoder.position();
+        let section = match id {
+            CUSTOM_SECTION => {
+                let name = decoder.read_name()?;
+                let payload_size = (start + size)
+                    .checked_sub(decoder.position())
+                    .ok_or(DecodeError::SectionSizeMismatch(id))?;
+                Section::Custom {
+                    name,
+                    payload: decoder.read_bytes(payload_size)?.to_vec(),
+                }
+            }
+            TYPE_SECTION => Section::TypeSection(TypeSection::decode(decoder)?),
+            IMPORT_SECTION => Section::ImportSection(ImportSection::decode(decoder)?),
+            FUNCTION_SECTION => Section::FunctionSection(FunctionSection::decode(decoder)?),
+            TABLE_SECTION => Section::TableSection(TableSection::decode(decoder)?),
+            MEMORY_SECTION => Section::MemorySection(MemorySection::decode(decoder)?),
+            GLOBAL_SECTION => Section::GlobalSection(GlobalSection::decode(decoder)?),
+            EXPORT_SECTION => Section::ExportSection(ExportSection::decode(decoder)?),
+            START_SECTION => Section::StartSection(StartSection::decode(decoder)?),
+            ELEMENT_SECTION => Section::ElementSection(ElementSection::decode(decoder)?),
+            CODE_SECTION => Section::CodeSection(CodeSection::decode(decoder)?),
+            DATA_SECTION => Section::DataSection(DataSection::decode(decoder)?),
+            _ => return Err(DecodeError::UnknownSection(id)),
+        };
+        if decoder.position() - start != size {
+            return Err(DecodeError::SectionSizeMismatch(id));
+        }
+        Ok(section)
+    }
+}
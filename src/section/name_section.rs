@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    constants::CUSTOM_SECTION,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
+    encoder::WasmEncoder,
+    index::{FunctionIndex, GlobalIndex, LocalIndex, MemoryIndex, TableIndex, TypeIndex},
+    section::custom_section::CustomSection,
+};
+
+const MODULE_NAME: u8 = 0x00;
+const FUNCTION_NAMES: u8 = 0x01;
+const LOCAL_NAMES: u8 = 0x02;
+// The rest are from the extended name section proposal
+const LABEL_NAMES: u8 = 0x03;
+const TYPE_NAMES: u8 = 0x04;
+const TABLE_NAMES: u8 = 0x05;
+const MEMORY_NAMES: u8 = 0x06;
+const GLOBAL_NAMES: u8 = 0x07;
+const DATA_NAMES: u8 = 0x09;
+
+/** Names by index, kept sorted since the binary format requires it */
+pub type NameMap = BTreeMap<u32, String>;
+
+/** Names of things inside something else, like the locals of each function */
+pub type IndirectNameMap = BTreeMap<u32, NameMap>;
+
+/**
+ * The `name` custom section, which engines and debuggers show in place of
+ * indices, e.g. in stack traces. Name things with the `name_*` methods and
+ * add the section to a module with `Module::set_custom_section`.
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NameSection {
+    pub module: Option<String>,
+    pub functions: NameMap,
+    pub locals: IndirectNameMap,
+    /** Labels are numbered by the order their blocks start in a function */
+    pub labels: IndirectNameMap,
+    pub types: NameMap,
+    pub tables: NameMap,
+    pub memories: NameMap,
+    pub globals: NameMap,
+    pub data: NameMap,
+}
+
+impl NameSection {
+    pub fn new() -> NameSection {
+        NameSection::default()
+    }
+
+    pub fn name_module(&mut self, name: &str) {
+        self.module = Some(name.to_owned());
+    }
+
+    pub fn name_function(&mut self, index: FunctionIndex, name: &str) {
+        self.functions.insert(index.0, name.to_owned());
+    }
+
+    pub fn name_local(&mut self, function: FunctionIndex, index: LocalIndex, name: &str) {
+        let locals = self.locals.entry(function.0).or_default();
+        locals.insert(index.0, name.to_owned());
+    }
+
+    pub fn name_label(&mut self, function: FunctionIndex, label: u32, name: &str) {
+        let labels = self.labels.entry(function.0).or_default();
+        labels.insert(label, name.to_owned());
+    }
+
+    pub fn name_type(&mut self, index: TypeIndex, name: &str) {
+        self.types.insert(index.0, name.to_owned());
+    }
+
+    pub fn name_table(&mut self, index: TableIndex, name: &str) {
+        self.tables.insert(index.0, name.to_owned());
+    }
+
+    pub fn name_memory(&mut self, index: MemoryIndex, name: &str) {
+        self.memories.insert(index.0, name.to_owned());
+    }
+
+    pub fn name_global(&mut self, index: GlobalIndex, name: &str) {
+        self.globals.insert(index.0, name.to_owned());
+    }
+
+    /** Names the data segment at `index` in the data section */
+    pub fn name_data(&mut self, index: u32, name: &str) {
+        self.data.insert(index, name.to_owned());
+    }
+
+    /** Adds `other`'s names, which replace the ones here for the same things */
+    pub fn extend(&mut self, other: &NameSection) {
+        if other.module.is_some() {
+            self.module = other.module.clone();
+        }
+        self.functions.extend(other.functions.clone());
+        for (function, locals) in other.locals.iter() {
+            let names = self.locals.entry(*function).or_default();
+            names.extend(locals.clone());
+        }
+        for (function, labels) in other.labels.iter() {
+            let names = self.labels.entry(*function).or_default();
+            names.extend(labels.clone());
+        }
+        self.types.extend(other.types.clone());
+        self.tables.extend(other.tables.clone());
+        self.memories.extend(other.memories.clone());
+        self.globals.extend(other.globals.clone());
+        self.data.extend(other.data.clone());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == NameSection::default()
+    }
+}
+
+fn encode_name_map(names: &NameMap, encoder: &mut WasmEncoder) -> u32 {
+    let mut byte_count = encoder.push_leb_u32(names.len() as u32);
+    for (index, name) in names.iter() {
+        byte_count += encoder.push_leb_u32(*index);
+        byte_count += encoder.push_str(name);
+    }
+    byte_count
+}
+
+fn encode_indirect_name_map(names: &IndirectNameMap, encoder: &mut WasmEncoder) -> u32 {
+    let mut byte_count = encoder.push_leb_u32(names.len() as u32);
+    for (index, inner_names) in names.iter() {
+        byte_count += encoder.push_leb_u32(*index);
+        byte_count += encode_name_map(inner_names, encoder);
+    }
+    byte_count
+}
+
+/** Encodes a subsection's id, then its contents after their byte count */
+pub(crate) fn encode_subsection(
+    id: u8,
+    encoder: &mut WasmEncoder,
+    encode_contents: impl FnOnce(&mut WasmEncoder) -> u32,
+) -> u32 {
+    encoder.push_u8(id);
+    encoder.push_u8(0); // byte_count placeholder
+    let byte_count = encode_contents(encoder);
+    encoder.write_length(byte_count) + byte_count + 1
+}
+
+/** Encodes a subsection of a name map, unless the map is empty */
+fn encode_name_subsection(id: u8, names: &NameMap, encoder: &mut WasmEncoder) -> u32 {
+    match names.is_empty() {
+        true => 0,
+        false => encode_subsection(id, encoder, |encoder| encode_name_map(names, encoder)),
+    }
+}
+
+fn encode_indirect_name_subsection(
+    id: u8,
+    names: &IndirectNameMap,
+    encoder: &mut WasmEncoder,
+) -> u32 {
+    match names.is_empty() {
+        true => 0,
+        false => encode_subsection(id, encoder, |encoder| {
+            encode_indirect_name_map(names, encoder)
+        }),
+    }
+}
+
+impl CustomSection for NameSection {
+    fn name(&self) -> &str {
+        "name"
+    }
+
+    /** Encodes the non-empty subsections, which have to be in order of id */
+    fn encode_payload(&self, encoder: &mut WasmEncoder) -> u32 {
+        let mut byte_count = 0;
+        if let Some(module) = &self.module {
+            byte_count +=
+                encode_subsection(MODULE_NAME, encoder, |encoder| encoder.push_str(module));
+        }
+        byte_count += encode_name_subsection(FUNCTION_NAMES, &self.functions, encoder);
+        byte_count += encode_indirect_name_subsection(LOCAL_NAMES, &self.locals, encoder);
+        byte_count += encode_indirect_name_subsection(LABEL_NAMES, &self.labels, encoder);
+        byte_count += encode_name_subsection(TYPE_NAMES, &self.types, encoder);
+        byte_count += encode_name_subsection(TABLE_NAMES, &self.tables, encoder);
+        byte_count += encode_name_subsection(MEMORY_NAMES, &self.memories, encoder);
+        byte_count += encode_name_subsection(GLOBAL_NAMES, &self.globals, encoder);
+        byte_count += encode_name_subsection(DATA_NAMES, &self.data, encoder);
+        byte_count
+    }
+}
+
+fn decode_name_map(decoder: &mut WasmDecoder) -> Result<NameMap, DecodeError> {
+    let mut names = NameMap::new();
+    for _ in 0..decoder.read_leb_u32()? {
+        let index = decoder.read_leb_u32()?;
+        names.insert(index, decoder.read_name()?);
+    }
+    Ok(names)
+}
+
+fn decode_indirect_name_map(decoder: &mut WasmDecoder) -> Result<IndirectNameMap, DecodeError> {
+    let mut names = IndirectNameMap::new();
+    for _ in 0..decoder.read_leb_u32()? {
+        let index = decoder.read_leb_u32()?;
+        names.insert(index, decode_name_map(decoder)?);
+    }
+    Ok(names)
+}
+
+impl WasmDecode for NameSection {
+    /** Decodes the payload of a `name` section, skipping unknown subsections */
+    fn decode(decoder: &mut WasmDecoder) -> Result<NameSection, DecodeError> {
+        let mut names = NameSection::new();
+        while !decoder.is_empty() {
+            let id = decoder.read_u8()?;
+            let size = decoder.read_leb_u32()? as usize;
+            let start = decoder.position();
+            match id {
+                MODULE_NAME => names.module = Some(decoder.read_name()?),
+                FUNCTION_NAMES => names.functions = decode_name_map(decoder)?,
+                LOCAL_NAMES => names.locals = decode_indirect_name_map(decoder)?,
+                LABEL_NAMES => names.labels = decode_indirect_name_map(decoder)?,
+                TYPE_NAMES => names.types = decode_name_map(decoder)?,
+                TABLE_NAMES => names.tables = decode_name_map(decoder)?,
+                MEMORY_NAMES => names.memories = decode_name_map(decoder)?,
+                GLOBAL_NAMES => names.globals = decode_name_map(decoder)?,
+                DATA_NAMES => names.data = decode_name_map(decoder)?,
+                _ => {
+                    decoder.read_bytes(size)?;
+                }
+            }
+            if decoder.position() - start != size {
+                return Err(DecodeError::SectionSizeMismatch(CUSTOM_SECTION));
+            }
+        }
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::assert_encoding_eq;
+
+    fn names() -> NameSection {
+        let mut names = NameSection::new();
+        names.name_module("m");
+        names.name_local(FunctionIndex(1), LocalIndex(0), "x");
+        names.name_function(FunctionIndex(1), "f");
+        names.name_function(FunctionIndex(0), "g");
+        names.name_global(GlobalIndex(0), "sp");
+        names
+    }
+
+    #[test]
+    fn test_section_encoding() {
+        assert_encoding_eq(
+            names().to_section(),
+            &[
+                0x00, // section id
+                0x21, // byte count
+                0x04, // name length
+                0x6e, 0x61, 0x6d, 0x65, // name ("name")
+                0x00, // module name subsection id
+                0x02, // byte count
+                0x01, 0x6d, // module name ("m")
+                0x01, // function names subsection id
+                0x07, // byte count
+                0x02, // name count
+                0x00, 0x01, 0x67, // function 0 ("g")
+                0x01, 0x01, 0x66, // function 1 ("f")
+                0x02, // local names subsection id
+                0x06, // byte count
+                0x01, // function count
+                0x01, // function index
+                0x01, // name count
+                0x00, 0x01, 0x78, // local 0 ("x")
+                0x07, // global names subsection id
+                0x05, // byte count
+                0x01, // name count
+                0x00, 0x02, 0x73, 0x70, // global 0 ("sp")
+            ],
+        );
+    }
+
+    #[test]
+    fn test_decoding_round_trip() {
+        let mut names = names();
+        names.name_label(FunctionIndex(1), 0, "loop");
+        names.name_type(TypeIndex(2), "binary");
+        names.name_table(TableIndex(0), "table");
+        names.name_memory(MemoryIndex(0), "heap");
+        names.name_data(3, "strings");
+        let payload = names.payload();
+        let mut decoder = WasmDecoder::new(&payload);
+        assert_eq!(NameSection::decode(&mut decoder), Ok(names));
+    }
+
+    #[test]
+    fn test_empty_section() {
+        assert!(NameSection::new().is_empty());
+        assert_encoding_eq(
+            NameSection::new().to_section(),
+            &[0x00, 0x05, 0x04, 0x6e, 0x61, 0x6d, 0x65],
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
+    encoder::WasmEncoder,
+    section::custom_section::CustomSection,
+};
+
+/** A tool or language that took part in producing a module, and its version */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Producer {
+    pub name: String,
+    pub version: String,
+}
+
+impl Producer {
+    pub fn new(name: &str, version: &str) -> Producer {
+        Producer {
+            name: name.to_owned(),
+            version: version.to_owned(),
+        }
+    }
+}
+
+/**
+ * The `producers` custom section, which records the source languages, the
+ * tools that processed a module, and the SDKs it was built with.
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProducersSection {
+    pub language: Vec<Producer>,
+    pub processed_by: Vec<Producer>,
+    pub sdk: Vec<Producer>,
+}
+
+/** Adds a producer, replacing any with the same name, since names are unique */
+fn add(producers: &mut Vec<Producer>, name: &str, version: &str) {
+    match producers.iter_mut().find(|producer| producer.name == name) {
+        Some(producer) => producer.version = version.to_owned(),
+        None => producers.push(Producer::new(name, version)),
+    }
+}
+
+impl ProducersSection {
+    pub fn new() -> ProducersSection {
+        ProducersSection::default()
+    }
+
+    pub fn add_language(&mut self, name: &str, version: &str) {
+        add(&mut self.language, name, version);
+    }
+
+    pub fn add_processed_by(&mut self, name: &str, version: &str) {
+        add(&mut self.processed_by, name, version);
+    }
+
+    pub fn add_sdk(&mut self, name: &str, version: &str) {
+        add(&mut self.sdk, name, version);
+    }
+
+    /** Records this crate and its version under `processed-by` */
+    pub fn add_wasmuter(&mut self) {
+        self.add_processed_by(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    }
+
+    fn fields(&self) -> [(&'static str, &Vec<Producer>); 3] {
+        [
+            ("language", &self.language),
+            ("processed-by", &self.processed_by),
+            ("sdk", &self.sdk),
+        ]
+    }
+}
+
+impl CustomSection for ProducersSection {
+    fn name(&self) -> &str {
+        "producers"
+    }
+
+    /** Encodes the fields that have any producers */
+    fn encode_payload(&self, encoder: &mut WasmEncoder) -> u32 {
+        let fields = self.fields();
+        let fields = fields.iter().filter(|(_, producers)| !producers.is_empty());
+        let mut byte_count = encoder.push_leb_u32(fields.clone().count() as u32);
+        for (field_name, producers) in fields {
+            byte_count += encoder.push_str(field_name);
+            byte_count += encoder.push_leb_u32(producers.len() as u32);
+            for producer in producers.iter() {
+                byte_count += encoder.push_str(&producer.name);
+                byte_count += encoder.push_str(&producer.version);
+            }
+        }
+        byte_count
+    }
+}
+
+impl WasmDecode for ProducersSection {
+    /** Decodes the payload of a `producers` section */
+    fn decode(decoder: &mut WasmDecoder) -> Result<ProducersSection, DecodeError> {
+        let mut section = ProducersSection::new();
+        for _ in 0..decoder.read_leb_u32()? {
+            let field_name = decoder.read_name()?;
+            let producers = match field_name.as_str() {
+                "language" => &mut section.language,
+                "processed-by" => &mut section.processed_by,
+                "sdk" => &mut section.sdk,
+                _ => {
+                    return Err(DecodeError::MalformedCustomSection(
+                        "unknown producers field",
+                    ))
+                }
+            };
+            for _ in 0..decoder.read_leb_u32()? {
+                let name = decoder.read_name()?;
+                let version = decoder.read_name()?;
+                producers.push(Producer { name, version });
+            }
+        }
+        Ok(section)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::assert_encoding_eq;
+
+    #[test]
+    fn test_section_encoding() {
+        let mut producers = ProducersSection::new();
+        producers.add_language("C", "");
+        producers.add_sdk("x", "0.1");
+        producers.add_sdk("x", "2");
+        assert_encoding_eq(
+            producers.to_section(),
+            &[
+                0x00, // section id
+                0x21, // byte count
+                0x09, // name length
+                0x70, 0x72, 0x6f, 0x64, 0x75, 0x63, 0x65, 0x72, 0x73, // name ("producers")
+                0x02, // field count
+                0x08, // field name length
+                0x6c, 0x61, 0x6e, 0x67, 0x75, 0x61, 0x67, 0x65, // field name ("language")
+                0x01, // value count
+                0x01, 0x43, // name ("C")
+                0x00, // version ("")
+                0x03, // field name length
+                0x73, 0x64, 0x6b, // field name ("sdk")
+                0x01, // value count
+                0x01, 0x78, // name ("x")
+                0x01, 0x32, // version ("2")
+            ],
+        );
+    }
+
+    #[test]
+    fn test_decoding_round_trip() {
+        let mut producers = ProducersSection::new();
+        producers.add_language("Rust", "1.70");
+        producers.add_wasmuter();
+        assert_eq!(producers.processed_by[0].name, "wasmuter");
+
+        let payload = producers.payload();
+        let mut decoder = WasmDecoder::new(&payload);
+        assert_eq!(ProducersSection::decode(&mut decoder), Ok(producers));
+        assert_eq!(
+            ProducersSection::decode(&mut WasmDecoder::new(b"\x01\x03abc\x00")),
+            Err(DecodeError::MalformedCustomSection(
+                "unknown producers field"
+            ))
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{
+    decoder::{DecodeError, WasmDecoder},
+    encoder::WasmEncoder,
+    section::custom_section::CustomSection,
+};
+
+/** What a relocation patches, and how */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RelocationType {
+    /** A function index, as a padded LEB128, e.g. in a `call` */
+    FunctionIndexLeb,
+    /** A function's table slot, as a padded signed LEB128 in an `i32.const` */
+    TableIndexSleb,
+    /** A function's table slot, as four bytes in a data segment */
+    TableIndexI32,
+    MemoryAddressLeb,
+    /** A data symbol's address, as a padded signed LEB128 in an `i32.const` */
+    MemoryAddressSleb,
+    /** A data symbol's address, as four bytes in a data segment */
+    MemoryAddressI32,
+    /** A type index, as a padded LEB128, e.g. in a `call_indirect` */
+    TypeIndexLeb,
+    /** A global index, as a padded LEB128 */
+    GlobalIndexLeb,
+    FunctionOffsetI32,
+    SectionOffsetI32,
+}
+
+const RELOCATION_TYPES: [RelocationType; 10] = [
+    RelocationType::FunctionIndexLeb,
+    RelocationType::TableIndexSleb,
+    RelocationType::TableIndexI32,
+    RelocationType::MemoryAddressLeb,
+    RelocationType::MemoryAddressSleb,
+    RelocationType::MemoryAddressI32,
+    RelocationType::TypeIndexLeb,
+    RelocationType::GlobalIndexLeb,
+    RelocationType::FunctionOffsetI32,
+    RelocationType::SectionOffsetI32,
+];
+
+impl RelocationType {
+    /** The type's number in the binary format */
+    pub fn code(&self) -> u8 {
+        RELOCATION_TYPES
+            .iter()
+            .position(|relocation_type| relocation_type == self)
+            .unwrap() as u8
+    }
+
+    /** Whether the relocation has an addend after its index */
+    pub fn has_addend(&self) -> bool {
+        matches!(
+            self,
+            RelocationType::MemoryAddressLeb
+                | RelocationType::MemoryAddressSleb
+                | RelocationType::MemoryAddressI32
+                | RelocationType::FunctionOffsetI32
+                | RelocationType::SectionOffsetI32
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Relocation {
+    pub relocation_type: RelocationType,
+    /** The offset of the patched bytes from the start of the section's contents */
+    pub offset: u32,
+    /** A symbol index, or a type index for `TypeIndexLeb` */
+    pub index: u32,
+    /** Added to the address or offset, for the types that have one */
+    pub addend: i32,
+}
+
+impl Relocation {
+    pub fn new(relocation_type: RelocationType, offset: u32, index: u32) -> Relocation {
+        Relocation {
+            relocation_type,
+            offset,
+            index,
+            addend: 0,
+        }
+    }
+}
+
+/**
+ * A `reloc.*` custom section, e.g. `reloc.CODE`, which lists the places in
+ * another section that a linker has to patch, in order of offset.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct RelocationSection {
+    pub name: String,
+    /** The index of the patched section in the module, custom sections included */
+    pub section_index: u32,
+    pub relocations: Vec<Relocation>,
+}
+
+impl RelocationSection {
+    /** A section for the section called `target`, like `CODE` or `DATA` */
+    pub fn new(target: &str, section_index: u32) -> RelocationSection {
+        RelocationSection {
+            name: format!("reloc.{}", target),
+            section_index,
+            relocations: vec![],
+        }
+    }
+
+    /** Decodes the payload of a section called `name` */
+    pub fn decode(name: &str, decoder: &mut WasmDecoder) -> Result<RelocationSection, DecodeError> {
+        let mut section = RelocationSection {
+            name: name.to_owned(),
+            section_index: decoder.read_leb_u32()?,
+            relocations: vec![],
+        };
+        for _ in 0..decoder.read_leb_u32()? {
+            let relocation_type = match RELOCATION_TYPES.get(decoder.read_u8()? as usize) {
+                Some(relocation_type) => *relocation_type,
+                None => {
+                    return Err(DecodeError::MalformedCustomSection(
+                        "unsupported relocation type",
+                    ))
+                }
+            };
+            let mut relocation = Relocation::new(
+                relocation_type,
+                decoder.read_leb_u32()?,
+                decoder.read_leb_u32()?,
+            );
+            if relocation_type.has_addend() {
+                relocation.addend = decoder.read_leb_i32()?;
+            }
+            section.relocations.push(relocation);
+        }
+        Ok(section)
+    }
+}
+
+impl CustomSection for RelocationSection {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn encode_payload(&self, encoder: &mut WasmEncoder) -> u32 {
+        let mut byte_count = encoder.push_leb_u32(self.section_index);
+        byte_count += encoder.push_leb_u32(self.relocations.len() as u32);
+        for relocation in self.relocations.iter() {
+            byte_count += encoder.push_u8(relocation.relocation_type.code());
+            byte_count += encoder.push_leb_u32(relocation.offset);
+            byte_count += encoder.push_leb_u32(relocation.index);
+            if relocation.relocation_type.has_addend() {
+                byte_count += encoder.push_leb_i32(relocation.addend);
+            }
+        }
+        byte_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::assert_encoding_eq;
+
+    fn relocations() -> RelocationSection {
+        let mut relocations = RelocationSection::new("CODE", 3);
+        relocations
+            .relocations
+            .push(Relocation::new(RelocationType::FunctionIndexLeb, 4, 1));
+        relocations.relocations.push(Relocation {
+            relocation_type: RelocationType::MemoryAddressSleb,
+            offset: 10,
+            index: 2,
+            addend: -8,
+        });
+        relocations
+    }
+
+    #[test]
+    fn test_section_encoding() {
+        assert_encoding_eq(
+            relocations().to_section(),
+            &[
+                0x00, // section id
+                0x14, // byte count
+                0x0a, // name length
+                0x72, 0x65, 0x6c, 0x6f, 0x63, 0x2e, 0x43, 0x4f, 0x44,
+                0x45, // name ("reloc.CODE")
+                0x03, // section index
+                0x02, // relocation count
+                0x00, 0x04, 0x01, // function index at 4, of symbol 1
+                0x04, 0x0a, 0x02, 0x78, // memory address at 10, of symbol 2, minus 8
+            ],
+        );
+    }
+
+    #[test]
+    fn test_decoding_round_trip() {
+        let payload = relocations().payload();
+        let mut decoder = WasmDecoder::new(&payload);
+        assert_eq!(
+            RelocationSection::decode("reloc.CODE", &mut decoder),
+            Ok(relocations())
+        );
+        assert_eq!(
+            RelocationSection::decode("reloc.DATA", &mut WasmDecoder::new(b"\x00\x01\x0a")),
+            Err(DecodeError::MalformedCustomSection(
+                "unsupported relocation type"
+            ))
+        );
+    }
+}
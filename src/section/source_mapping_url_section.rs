@@ -0,0 +1,37 @@
+use crate::{encoder::WasmEncoder, section::custom_section::CustomSection};
+
+/** The `sourceMappingURL` custom section, with the URL of a module's source map */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceMappingUrlSection(pub String);
+
+impl CustomSection for SourceMappingUrlSection {
+    fn name(&self) -> &str {
+        "sourceMappingURL"
+    }
+
+    fn encode_payload(&self, encoder: &mut WasmEncoder) -> u32 {
+        encoder.push_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::assert_encoding_eq;
+
+    #[test]
+    fn test_section_encoding() {
+        assert_encoding_eq(
+            SourceMappingUrlSection("a.map".to_string()).to_section(),
+            &[
+                0x00, // section id
+                0x17, // byte count
+                0x10, // name length
+                0x73, 0x6f, 0x75, 0x72, 0x63, 0x65, 0x4d, 0x61, 0x70, 0x70, 0x69, 0x6e, 0x67, 0x55,
+                0x52, 0x4c, // name ("sourceMappingURL")
+                0x05, // url length
+                0x61, 0x2e, 0x6d, 0x61, 0x70, // url ("a.map")
+            ],
+        );
+    }
+}
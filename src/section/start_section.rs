@@ -1,8 +1,12 @@
+use std::convert::TryFrom;
+
 use crate::{
     constants::START_SECTION,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct StartSection(pub u8);
 
 impl WasmEncode for StartSection {
@@ -14,6 +18,15 @@ impl WasmEncode for StartSection {
     }
 }
 
+impl WasmDecode for StartSection {
+    fn decode(decoder: &mut WasmDecoder) -> Result<StartSection, DecodeError> {
+        let index = decoder.read_leb_u32()?;
+        u8::try_from(index)
+            .map(StartSection)
+            .map_err(|_| DecodeError::IntegerTooLarge)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,9 +1,11 @@
 use crate::{
     constants::{FUNCTION_REFERENCE, TABLE_SECTION},
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
     limits::Limits,
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct TableSection(pub Vec<Table>);
 
 impl WasmEncode for TableSection {
@@ -17,8 +19,15 @@ impl WasmEncode for TableSection {
     }
 }
 
+impl WasmDecode for TableSection {
+    fn decode(decoder: &mut WasmDecoder) -> Result<TableSection, DecodeError> {
+        Ok(TableSection(decoder.read_vector()?))
+    }
+}
+
 // The Wasm spec only supports one element_type currently, so we just push that
 // opcode without checking the field.
+#[derive(Clone, Debug, PartialEq)]
 #[allow(dead_code)]
 pub struct Table {
     pub element_type: ElementType,
@@ -40,6 +49,19 @@ impl WasmEncode for Table {
     }
 }
 
+impl WasmDecode for Table {
+    fn decode(decoder: &mut WasmDecoder) -> Result<Table, DecodeError> {
+        match decoder.read_u8()? {
+            FUNCTION_REFERENCE => Ok(Table::new(
+                ElementType::FunctionReference,
+                Limits::decode(decoder)?,
+            )),
+            byte => Err(DecodeError::InvalidElementType(byte)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum ElementType {
     FunctionReference,
 }
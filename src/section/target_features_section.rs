@@ -0,0 +1,222 @@
+use crate::{
+    constants::MISC_PREFIX,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
+    encoder::WasmEncoder,
+    expression::walk,
+    module::Module,
+    section::{
+        custom_section::CustomSection, export_section::ExportDescriptor,
+        global_section::Mutability, import_section::ImportDescriptor, Section,
+    },
+};
+
+/** What a `target_features` entry says about a feature */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeaturePolicy {
+    /** The module uses the feature */
+    Used,
+    /** The module can't be linked with modules that use the feature */
+    Disallowed,
+    /** Modules linked with this one have to use the feature too */
+    Required,
+}
+
+impl FeaturePolicy {
+    fn prefix(&self) -> u8 {
+        match self {
+            FeaturePolicy::Used => b'+',
+            FeaturePolicy::Disallowed => b'-',
+            FeaturePolicy::Required => b'=',
+        }
+    }
+}
+
+/**
+ * The `target_features` custom section, which lists the proposals beyond the
+ * MVP that a module was compiled for.
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TargetFeaturesSection(pub Vec<(FeaturePolicy, String)>);
+
+impl TargetFeaturesSection {
+    pub fn new() -> TargetFeaturesSection {
+        TargetFeaturesSection::default()
+    }
+
+    /** Sets the policy for a feature, replacing any it already has */
+    pub fn set(&mut self, policy: FeaturePolicy, feature: &str) {
+        match self.0.iter_mut().find(|(_, name)| name == feature) {
+            Some(entry) => entry.0 = policy,
+            None => self.0.push((policy, feature.to_owned())),
+        }
+    }
+
+    /** Marks every feature `module` uses as used, by its LLVM feature name */
+    pub fn from_module(module: &Module) -> TargetFeaturesSection {
+        let mut section = TargetFeaturesSection::new();
+        if uses_multivalue(module) {
+            section.set(FeaturePolicy::Used, "multivalue");
+        }
+        if uses_mutable_globals(module) {
+            section.set(FeaturePolicy::Used, "mutable-globals");
+        }
+        if uses_saturating_truncation(module) {
+            section.set(FeaturePolicy::Used, "nontrapping-fptoint");
+        }
+        section
+    }
+}
+
+fn uses_multivalue(module: &Module) -> bool {
+    module.0.iter().any(|section| match section {
+        Section::TypeSection(section) => section
+            .0
+            .iter()
+            .any(|function_type| function_type.results.len() > 1),
+        _ => false,
+    })
+}
+
+/** Whether a mutable global is imported or exported */
+fn uses_mutable_globals(module: &Module) -> bool {
+    let mut mutabilities = vec![];
+    for section in module.0.iter() {
+        match section {
+            Section::ImportSection(section) => {
+                for import in section.0.iter() {
+                    if let ImportDescriptor::GlobalType(global_type) = &import.descriptor {
+                        if global_type.mutability == Mutability::Var {
+                            return true;
+                        }
+                        mutabilities.push(global_type.mutability);
+                    }
+                }
+            }
+            Section::GlobalSection(section) => mutabilities.extend(
+                section
+                    .0
+                    .iter()
+                    .map(|global| global.global_type().mutability),
+            ),
+            _ => {}
+        }
+    }
+    module.0.iter().any(|section| match section {
+        Section::ExportSection(section) => section.0.iter().any(|export| {
+            matches!(
+                export.descriptor,
+                ExportDescriptor::GlobalIndex(index)
+                    if mutabilities.get(index.0 as usize) == Some(&Mutability::Var)
+            )
+        }),
+        _ => false,
+    })
+}
+
+/**
+ * Whether any code has a saturating truncation, which are the instructions
+ * with the misc prefix
+ */
+fn uses_saturating_truncation(module: &Module) -> bool {
+    module.0.iter().any(|section| match section {
+        Section::CodeSection(section) => section.0.iter().any(|function| {
+            walk(&function.expression.0)
+                .any(|instruction| instruction.opcode_prefix() == Some(MISC_PREFIX))
+        }),
+        _ => false,
+    })
+}
+
+impl CustomSection for TargetFeaturesSection {
+    fn name(&self) -> &str {
+        "target_features"
+    }
+
+    fn encode_payload(&self, encoder: &mut WasmEncoder) -> u32 {
+        let mut byte_count = encoder.push_leb_u32(self.0.len() as u32);
+        for (policy, feature) in self.0.iter() {
+            byte_count += encoder.push_u8(policy.prefix());
+            byte_count += encoder.push_str(feature);
+        }
+        byte_count
+    }
+}
+
+impl WasmDecode for TargetFeaturesSection {
+    /** Decodes the payload of a `target_features` section */
+    fn decode(decoder: &mut WasmDecoder) -> Result<TargetFeaturesSection, DecodeError> {
+        let mut section = TargetFeaturesSection::new();
+        for _ in 0..decoder.read_leb_u32()? {
+            let policy = match decoder.read_u8()? {
+                b'+' => FeaturePolicy::Used,
+                b'-' => FeaturePolicy::Disallowed,
+                b'=' => FeaturePolicy::Required,
+                _ => {
+                    return Err(DecodeError::MalformedCustomSection(
+                        "invalid target feature prefix",
+                    ))
+                }
+            };
+            section.0.push((policy, decoder.read_name()?));
+        }
+        Ok(section)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::assert_encoding_eq;
+
+    #[test]
+    fn test_section_encoding() {
+        let mut features = TargetFeaturesSection::new();
+        features.set(FeaturePolicy::Used, "simd128");
+        features.set(FeaturePolicy::Disallowed, "atomics");
+        assert_encoding_eq(
+            features.to_section(),
+            &[
+                0x00, // section id
+                0x23, // byte count
+                0x0f, // name length
+                0x74, 0x61, 0x72, 0x67, 0x65, 0x74, 0x5f, 0x66, 0x65, 0x61, 0x74, 0x75, 0x72, 0x65,
+                0x73, // name ("target_features")
+                0x02, // feature count
+                0x2b, // prefix ('+')
+                0x07, // feature name length
+                0x73, 0x69, 0x6d, 0x64, 0x31, 0x32, 0x38, // feature name ("simd128")
+                0x2d, // prefix ('-')
+                0x07, // feature name length
+                0x61, 0x74, 0x6f, 0x6d, 0x69, 0x63, 0x73, // feature name ("atomics")
+            ],
+        );
+    }
+
+    #[test]
+    fn test_from_module() {
+        let module: Module = "(func)".parse().unwrap();
+        assert_eq!(TargetFeaturesSection::from_module(&module).0, vec![]);
+
+        let module: Module = r#"(module
+            (global $g (mut i32) (i32.const 0))
+            (export "g" (global $g))
+            (func (param f32) (result i32 i32)
+              (block (result i32) (i32.trunc_sat_f32_s (local.get 0)))
+              (i32.const 0)))"#
+            .parse()
+            .unwrap();
+        let features = TargetFeaturesSection::from_module(&module);
+        assert_eq!(
+            features.0,
+            vec![
+                (FeaturePolicy::Used, "multivalue".to_string()),
+                (FeaturePolicy::Used, "mutable-globals".to_string()),
+                (FeaturePolicy::Used, "nontrapping-fptoint".to_string()),
+            ]
+        );
+
+        let payload = features.payload();
+        let mut decoder = WasmDecoder::new(&payload);
+        assert_eq!(TargetFeaturesSection::decode(&mut decoder), Ok(features));
+    }
+}
@@ -1,9 +1,11 @@
 use crate::{
     constants::TYPE_SECTION,
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
     encoder::{WasmEncode, WasmEncoder},
     function_type::FunctionType,
 };
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct TypeSection(pub Vec<FunctionType>);
 
 impl WasmEncode for TypeSection {
@@ -17,6 +19,12 @@ impl WasmEncode for TypeSection {
     }
 }
 
+impl WasmDecode for TypeSection {
+    fn decode(decoder: &mut WasmDecoder) -> Result<TypeSection, DecodeError> {
+        Ok(TypeSection(decoder.read_vector()?))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
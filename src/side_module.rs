@@ -0,0 +1,581 @@
+/*!
+ * Side modules, which a dynamic loader like Emscripten's places in the
+ * memory and table of a running host module.
+ *
+ * A side module imports the host's memory and table, and `__memory_base` and
+ * `__table_base`, the globals saying where the loader put its data and table
+ * slots. The data segments are merged into one, which keeps each segment's
+ * offset from `__memory_base`, and so do the element segments with
+ * `__table_base`. A merged segment that doesn't start at the base has an
+ * offset of the base plus a constant, which takes extended constant
+ * expressions. Code has to add the bases to the addresses and slots it uses
+ * itself.
+ */
+use std::{convert::Infallible, error, fmt, ops::Range};
+
+use crate::{
+    constants::{CUSTOM_SECTION, PAGE_SIZE},
+    decoder::{WasmDecode, WasmDecoder},
+    expression::{walk_mut, Expression, Instruction},
+    function_type::{FunctionType, ValueType},
+    index::{FunctionIndex, GlobalIndex, MemoryIndex, TableIndex, TypeIndex},
+    limits::Limits,
+    module::Module,
+    section::{
+        code_section::{CodeSection, Function},
+        custom_section::CustomSection,
+        data_section::{Data, DataSection},
+        dylink_section::DylinkSection,
+        element_section::{Element, ElementSection},
+        export_section::ExportDescriptor,
+        function_section::FunctionSection,
+        global_section::{Global, GlobalType, Mutability},
+        import_section::{Import, ImportDescriptor, ImportSection},
+        memory_section::Memory,
+        name_section::NameSection,
+        table_section::Table,
+        type_section::TypeSection,
+        Section,
+    },
+};
+
+const MEMORY_BASE: &str = "__memory_base";
+const TABLE_BASE: &str = "__table_base";
+
+#[derive(Debug, PartialEq)]
+pub enum SideModuleError {
+    /** A data segment whose offset isn't an `i32.const`, by its index */
+    NonConstantDataOffset(u32),
+    NonConstantElementOffset(u32),
+    /** A data segment that doesn't fit in the module's memory, by its index */
+    DataOutOfBounds(u32),
+    ElementOutOfBounds(u32),
+    /** An import of `__memory_base` or `__table_base` that isn't an immutable `i32` */
+    InvalidBaseImport(String),
+}
+
+impl fmt::Display for SideModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SideModuleError::NonConstantDataOffset(segment) => {
+                write!(f, "data segment {} doesn't have a constant offset", segment)
+            }
+            SideModuleError::NonConstantElementOffset(segment) => {
+                write!(
+                    f,
+                    "element segment {} doesn't have a constant offset",
+                    segment
+                )
+            }
+            SideModuleError::DataOutOfBounds(segment) => {
+                write!(f, "data segment {} doesn't fit in the memory", segment)
+            }
+            SideModuleError::ElementOutOfBounds(segment) => {
+                write!(f, "element segment {} doesn't fit in the table", segment)
+            }
+            SideModuleError::InvalidBaseImport(name) => {
+                write!(f, "{} is imported as something other than an i32", name)
+            }
+        }
+    }
+}
+
+impl error::Error for SideModuleError {}
+
+/** The contents of a section, which is added if the module doesn't have it */
+macro_rules! section_contents {
+    ($sections:expr, $variant:ident) => {{
+        if !$sections
+            .iter()
+            .any(|section| matches!(section, Section::$variant(_)))
+        {
+            insert_section($sections, Section::$variant($variant(vec![])));
+        }
+        $sections
+            .iter_mut()
+            .find_map(|section| match section {
+                Section::$variant(section) => Some(&mut section.0),
+                _ => None,
+            })
+            .unwrap()
+    }};
+}
+
+/** Inserts a section after the known sections that come before it */
+fn insert_section(sections: &mut Vec<Section>, section: Section) {
+    let position = sections
+        .iter()
+        .rposition(|existing| existing.id() != CUSTOM_SECTION && existing.id() < section.id())
+        .map_or(0, |index| index + 1);
+    sections.insert(position, section);
+}
+
+/**
+ * The range a segment of `length` bytes or slots covers, if its offset is an
+ * `i32.const`. Negative offsets are out of range, so they're left as large
+ * unsigned ones.
+ */
+fn segment_range(offset: &Expression, length: usize) -> Option<Range<u64>> {
+    match offset.0.as_slice() {
+        [Instruction::I32Const(offset)] => {
+            let offset = *offset as u32 as u64;
+            Some(offset..offset + length as u64)
+        }
+        _ => None,
+    }
+}
+
+/** `global.get base`, plus `offset` unless it's zero */
+fn base_offset(base: GlobalIndex, offset: u64) -> Expression {
+    let mut instructions = vec![Instruction::GlobalGet(base)];
+    if offset > 0 {
+        instructions.push(Instruction::I32Const(offset as i32));
+        instructions.push(Instruction::I32Add);
+    }
+    Expression(instructions)
+}
+
+fn renumber_globals(
+    instructions: &mut [Instruction],
+    renumber: &impl Fn(GlobalIndex) -> GlobalIndex,
+) {
+    walk_mut(instructions, &mut |instruction| {
+        if let Instruction::GlobalGet(index) | Instruction::GlobalSet(index) = instruction {
+            *index = renumber(*index);
+        }
+        Ok::<(), Infallible>(())
+    })
+    .unwrap()
+}
+
+/**
+ * The index of the base global called `name`, which is imported from `env`
+ * after the other globals unless the module already imports it.
+ */
+fn base_global(imports: &mut Vec<Import>, name: &str) -> Result<GlobalIndex, SideModuleError> {
+    let globals: Vec<&Import> = imports
+        .iter()
+        .filter(|import| matches!(import.descriptor, ImportDescriptor::GlobalType(_)))
+        .collect();
+    match globals
+        .iter()
+        .position(|import| import.module_name == "env" && import.name == name)
+    {
+        Some(index) => match globals[index].descriptor {
+            ImportDescriptor::GlobalType(GlobalType {
+                value_type: ValueType::I32,
+                mutability: Mutability::Const,
+            }) => Ok(GlobalIndex(index as u32)),
+            _ => Err(SideModuleError::InvalidBaseImport(name.to_owned())),
+        },
+        None => {
+            let index = GlobalIndex(globals.len() as u32);
+            let global_type = GlobalType::new(ValueType::I32, Mutability::Const);
+            imports.push(Import::new(
+                "env",
+                name,
+                ImportDescriptor::GlobalType(global_type),
+            ));
+            Ok(index)
+        }
+    }
+}
+
+pub(crate) fn to_side_module(
+    module: &Module,
+    dylink: &DylinkSection,
+) -> Result<Module, SideModuleError> {
+    let mut sections = vec![];
+    let mut imports = vec![];
+    let mut data = vec![];
+    let mut elements = vec![];
+    // The minimum sizes of the memory and table, which the segments have to fit in
+    let mut memory_pages = 0;
+    let mut table_size = 0;
+    for section in module.0.iter() {
+        match section {
+            Section::ImportSection(section) => {
+                for import in section.0.iter() {
+                    match &import.descriptor {
+                        ImportDescriptor::MemoryType(memory) => memory_pages = memory.limits.min,
+                        ImportDescriptor::TableType(table) => table_size = table.limits.min,
+                        _ => {}
+                    }
+                }
+                imports.extend(section.0.iter().cloned())
+            }
+            // The host's memory and table are imported in place of the module's
+            Section::MemorySection(section) => imports.extend(section.0.iter().map(|memory| {
+                memory_pages = memory.limits.min;
+                let memory = Memory::new(Limits::min(memory.limits.min));
+                Import::new("env", "memory", ImportDescriptor::MemoryType(memory))
+            })),
+            Section::TableSection(section) => imports.extend(section.0.iter().map(|table| {
+                table_size = table.limits.min;
+                let table = Table::new(table.element_type.clone(), Limits::min(table.limits.min));
+                let descriptor = ImportDescriptor::TableType(table);
+                Import::new("env", "__indirect_function_table", descriptor)
+            })),
+            Section::DataSection(section) => data.extend(section.0.iter()),
+            Section::ElementSection(section) => elements.extend(section.0.iter()),
+            _ => sections.push(section.clone()),
+        }
+    }
+
+    // Globals are imported before they're defined, so importing the bases
+    // moves the defined globals
+    let imported_globals = imports
+        .iter()
+        .filter(|import| matches!(import.descriptor, ImportDescriptor::GlobalType(_)))
+        .count() as u32;
+    let memory_base = match data.is_empty() {
+        true => None,
+        false => Some(base_global(&mut imports, MEMORY_BASE)?),
+    };
+    let table_base = match elements.is_empty() {
+        true => None,
+        false => Some(base_global(&mut imports, TABLE_BASE)?),
+    };
+    let added_globals = imports
+        .iter()
+        .filter(|import| matches!(import.descriptor, ImportDescriptor::GlobalType(_)))
+        .count() as u32
+        - imported_globals;
+    let renumber = |index: GlobalIndex| match index.0 < imported_globals {
+        true => index,
+        false => GlobalIndex(index.0 + added_globals),
+    };
+    if added_globals > 0 {
+        for section in sections.iter_mut() {
+            match section {
+                Section::GlobalSection(section) => {
+                    for global in section.0.iter_mut() {
+                        let (Global::Const(_, expression) | Global::Var(_, expression)) = global;
+                        renumber_globals(&mut expression.0, &renumber);
+                    }
+                }
+                Section::ExportSection(section) => {
+                    for export in section.0.iter_mut() {
+                        if let ExportDescriptor::GlobalIndex(index) = &mut export.descriptor {
+                            *index = renumber(*index);
+                        }
+                    }
+                }
+                Section::CodeSection(section) => {
+                    for function in section.0.iter_mut() {
+                        renumber_globals(&mut function.expression.0, &renumber);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    if !imports.is_empty() {
+        *section_contents!(&mut sections, ImportSection) = imports;
+    }
+
+    // The data is merged into a segment from the lowest offset, with zeros
+    // in the gaps, which the loader's memory has anyway
+    let mut dylink = dylink.clone();
+    if let Some(memory_base) = memory_base {
+        let memory_size = (memory_pages as u64 * PAGE_SIZE as u64).min(i32::MAX as u64);
+        let mut ranges = vec![];
+        for (segment, data) in data.iter().enumerate() {
+            let range = segment_range(&data.offset, data.initializer.len())
+                .ok_or(SideModuleError::NonConstantDataOffset(segment as u32))?;
+            if range.end > memory_size {
+                return Err(SideModuleError::DataOutOfBounds(segment as u32));
+            }
+            ranges.push(range);
+        }
+        let start = ranges.iter().map(|range| range.start).min().unwrap();
+        let end = ranges.iter().map(|range| range.end).max().unwrap();
+        let mut bytes = vec![0; (end - start) as usize];
+        for (range, data) in ranges.into_iter().zip(data) {
+            bytes[(range.start - start) as usize..(range.end - start) as usize]
+                .copy_from_slice(&data.initializer);
+        }
+        dylink.memory_info.memory_size = end as u32;
+        section_contents!(&mut sections, DataSection).push(Data::new(
+            MemoryIndex(0),
+            base_offset(memory_base, start),
+            bytes,
+        ));
+    }
+
+    // The slots in the gaps get a function that traps, like an empty slot
+    if let Some(table_base) = table_base {
+        let mut ranges = vec![];
+        for (segment, element) in elements.iter().enumerate() {
+            let range = segment_range(&element.offset, element.initializer.len())
+                .ok_or(SideModuleError::NonConstantElementOffset(segment as u32))?;
+            if range.end > table_size as u64 {
+                return Err(SideModuleError::ElementOutOfBounds(segment as u32));
+            }
+            ranges.push(range);
+        }
+        let start = ranges.iter().map(|range| range.start).min().unwrap();
+        let end = ranges.iter().map(|range| range.end).max().unwrap();
+        let mut slots = vec![None; (end - start) as usize];
+        for (range, element) in ranges.into_iter().zip(elements) {
+            for (slot, function) in element.initializer.iter().enumerate() {
+                slots[(range.start - start) as usize + slot] = Some(*function);
+            }
+        }
+        dylink.memory_info.table_size = end as u32;
+        let trap = match slots.contains(&None) {
+            true => Some(add_trap_function(&mut sections)),
+            false => None,
+        };
+        let initializer = slots
+            .into_iter()
+            .map(|function| function.or(trap).unwrap())
+            .collect();
+        let element = Element::new(TableIndex(0), base_offset(table_base, start), initializer);
+        section_contents!(&mut sections, ElementSection).push(element);
+    }
+
+    let mut side_module = Module(sections);
+    if added_globals > 0 {
+        let names = side_module
+            .custom_section("name")
+            .and_then(|names| NameSection::decode(&mut WasmDecoder::new(names)).ok());
+        if let Some(mut names) = names {
+            names.globals = names
+                .globals
+                .into_iter()
+                .map(|(index, name)| (renumber(GlobalIndex(index)).0, name))
+                .collect();
+            side_module.set_custom_section(&names);
+        }
+    }
+    side_module.0.insert(0, dylink.to_section());
+    Ok(side_module)
+}
+
+/** Adds a function that traps, for the table slots nothing is put in */
+fn add_trap_function(sections: &mut Vec<Section>) -> FunctionIndex {
+    let imported_functions = sections
+        .iter()
+        .map(|section| match section {
+            Section::ImportSection(section) => section
+                .0
+                .iter()
+                .filter(|import| matches!(import.descriptor, ImportDescriptor::TypeIndex(_)))
+                .count(),
+            _ => 0,
+        })
+        .sum::<usize>();
+    let types = section_contents!(sections, TypeSection);
+    let function_type = FunctionType::new(vec![], vec![]);
+    let type_index = match types.iter().position(|existing| *existing == function_type) {
+        Some(index) => TypeIndex(index as u32),
+        None => {
+            types.push(function_type);
+            TypeIndex(types.len() as u32 - 1)
+        }
+    };
+    let function_types = section_contents!(sections, FunctionSection);
+    function_types.push(type_index);
+    let index = FunctionIndex((imported_functions + function_types.len() - 1) as u32);
+    section_contents!(sections, CodeSection).push(Function::new(
+        vec![],
+        Expression(vec![Instruction::Unreachable]),
+    ));
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::{dylink_section::MemoryInfo, export_section::Export};
+
+    fn module() -> Module {
+        r#"(module
+            (import "env" "limit" (global $limit i32))
+            (global $count (export "count") (mut i32) (i32.const 0))
+            (memory 1 2)
+            (table 3 funcref)
+            (func $f (global.set $count (global.get $limit)))
+            (elem (i32.const 0) $f)
+            (elem (i32.const 2) $f)
+            (data (i32.const 2) "ab")
+            (data (i32.const 6) "c"))"#
+            .parse()
+            .unwrap()
+    }
+
+    fn find<'a, T>(module: &'a Module, find: impl Fn(&'a Section) -> Option<T>) -> T {
+        module.0.iter().find_map(find).unwrap()
+    }
+
+    #[test]
+    fn test_to_side_module() {
+        let mut dylink = DylinkSection::new();
+        dylink.add_needed("libhost.so");
+        dylink.memory_info.memory_alignment = 2;
+        let side_module = module().to_side_module(&dylink).unwrap();
+
+        let payload = match &side_module.0[0] {
+            Section::Custom { name, payload } if name == "dylink.0" => payload,
+            _ => unreachable!(),
+        };
+        let dylink = DylinkSection::decode(&mut WasmDecoder::new(payload)).unwrap();
+        assert_eq!(
+            dylink.memory_info,
+            MemoryInfo {
+                memory_size: 7,
+                memory_alignment: 2,
+                table_size: 3,
+                table_alignment: 0,
+            }
+        );
+        assert_eq!(dylink.needed, vec!["libhost.so"]);
+
+        let imports = find(&side_module, |section| match section {
+            Section::ImportSection(imports) => Some(&imports.0),
+            _ => None,
+        });
+        let names: Vec<&str> = imports.iter().map(|import| &import.name[..]).collect();
+        assert_eq!(
+            names,
+            vec![
+                "limit",
+                "__indirect_function_table",
+                "memory",
+                MEMORY_BASE,
+                TABLE_BASE
+            ]
+        );
+        assert_eq!(
+            imports[2].descriptor,
+            ImportDescriptor::MemoryType(Memory::new(Limits::min(1)))
+        );
+
+        // The defined global comes after the bases now
+        let code = find(&side_module, |section| match section {
+            Section::CodeSection(code) => Some(&code.0),
+            _ => None,
+        });
+        assert_eq!(
+            code[0].expression.0,
+            vec![
+                Instruction::GlobalGet(GlobalIndex(0)),
+                Instruction::GlobalSet(GlobalIndex(3)),
+            ]
+        );
+        assert_eq!(code[1].expression.0, vec![Instruction::Unreachable]);
+        let exports = find(&side_module, |section| match section {
+            Section::ExportSection(exports) => Some(&exports.0),
+            _ => None,
+        });
+        assert_eq!(
+            exports[0],
+            Export::new("count", ExportDescriptor::GlobalIndex(GlobalIndex(3)))
+        );
+
+        let data = find(&side_module, |section| match section {
+            Section::DataSection(data) => Some(&data.0),
+            _ => None,
+        });
+        assert_eq!(
+            *data,
+            vec![Data::new(
+                MemoryIndex(0),
+                Expression(vec![
+                    Instruction::GlobalGet(GlobalIndex(1)),
+                    Instruction::I32Const(2),
+                    Instruction::I32Add,
+                ]),
+                b"ab\0\0c".to_vec()
+            )]
+        );
+        let elements = find(&side_module, |section| match section {
+            Section::ElementSection(elements) => Some(&elements.0),
+            _ => None,
+        });
+        assert_eq!(
+            *elements,
+            vec![Element::new(
+                TableIndex(0),
+                Expression(vec![Instruction::GlobalGet(GlobalIndex(2))]),
+                vec![FunctionIndex(0), FunctionIndex(1), FunctionIndex(0)]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_existing_base() {
+        let module: Module = r#"(module
+            (import "env" "__memory_base" (global $base i32))
+            (global $pointer i32 (global.get $base))
+            (memory 1)
+            (data (i32.const 0) "a"))"#
+            .parse()
+            .unwrap();
+        let side_module = module.to_side_module(&DylinkSection::new()).unwrap();
+        let globals = find(&side_module, |section| match section {
+            Section::GlobalSection(globals) => Some(&globals.0),
+            _ => None,
+        });
+        assert_eq!(
+            globals[0],
+            Global::Const(
+                ValueType::I32,
+                Expression(vec![Instruction::GlobalGet(GlobalIndex(0))])
+            )
+        );
+
+        let module: Module = r#"(module
+            (import "env" "__memory_base" (global $base (mut i32)))
+            (memory 1)
+            (data (i32.const 0) "a"))"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            module.to_side_module(&DylinkSection::new()),
+            Err(SideModuleError::InvalidBaseImport(MEMORY_BASE.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_out_of_bounds_offset() {
+        let convert = |fields: &str| {
+            let module: Module = format!("(module (memory 1) (table 1 funcref) {})", fields)
+                .parse()
+                .unwrap();
+            module.to_side_module(&DylinkSection::new())
+        };
+        assert_eq!(
+            convert(r#"(data (i32.const 0) "a") (data (i32.const 0x40000000) "b")"#),
+            Err(SideModuleError::DataOutOfBounds(1))
+        );
+        assert_eq!(
+            convert(r#"(data (i32.const -1) "a")"#),
+            Err(SideModuleError::DataOutOfBounds(0))
+        );
+        assert_eq!(
+            convert(r#"(data (i32.const 0xffff) "ab")"#),
+            Err(SideModuleError::DataOutOfBounds(0))
+        );
+        assert_eq!(
+            convert("(func $f) (elem (i32.const 1) $f)"),
+            Err(SideModuleError::ElementOutOfBounds(0))
+        );
+    }
+
+    #[test]
+    fn test_non_constant_offset() {
+        let module: Module = r#"(module
+            (import "env" "offset" (global $offset i32))
+            (memory 1)
+            (data (i32.const 0) "a")
+            (data (global.get $offset) "b"))"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            module.to_side_module(&DylinkSection::new()),
+            Err(SideModuleError::NonConstantDataOffset(1))
+        );
+    }
+}
@@ -0,0 +1,441 @@
+/*!
+ * Source maps, which let debuggers like browser devtools step through the
+ * source an encoded module was compiled from.
+ *
+ * Spans are given to instructions by their number within their function,
+ * counting from zero in the order instructions are encoded: a block comes
+ * before its body, and `else` and `end` aren't counted.
+ * `Expr::lower_with_spans` numbers the spans of an expression tree this way.
+ */
+use std::{collections::BTreeMap, error, fmt, fmt::Write};
+
+use crate::{
+    constants::{BLOCK, CODE_SECTION, ELSE, END, IF, LOOP},
+    decoder::{DecodeError, WasmDecode, WasmDecoder},
+    encoder::{WasmEncode, WasmEncoder},
+    expression::{walk, BlockType, Instruction},
+    index::FunctionIndex,
+    module::Module,
+    section::{
+        code_section::Local, import_section::ImportDescriptor,
+        source_mapping_url_section::SourceMappingUrlSection, Section,
+    },
+};
+
+/** A position in a source file, with lines and columns counted from 1 */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceSpan {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl SourceSpan {
+    pub fn new(file: &str, line: u32, column: u32) -> SourceSpan {
+        SourceSpan {
+            file: file.to_owned(),
+            line,
+            column,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SourceMapError {
+    /** A span for a function that's imported or isn't in the module */
+    UnknownFunction(FunctionIndex),
+    UnknownInstruction(FunctionIndex, u32),
+}
+
+impl fmt::Display for SourceMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceMapError::UnknownFunction(index) => {
+                write!(f, "function {} isn't defined in the module", index.0)
+            }
+            SourceMapError::UnknownInstruction(index, instruction) => {
+                write!(f, "function {} has no instruction {}", index.0, instruction)
+            }
+        }
+    }
+}
+
+impl error::Error for SourceMapError {}
+
+/** Counts instructions the way source map spans number them */
+pub fn instruction_count(instructions: &[Instruction]) -> u32 {
+    walk(instructions).count() as u32
+}
+
+/** The source spans of a module's instructions */
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    spans: BTreeMap<(u32, u32), SourceSpan>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    pub fn add_span(&mut self, function: FunctionIndex, instruction: u32, span: SourceSpan) {
+        self.spans.insert((function.0, instruction), span);
+    }
+
+    /** Adds the spans of a function's body, like `Expr::lower_with_spans` returns */
+    pub fn add_spans(&mut self, function: FunctionIndex, spans: Vec<(u32, SourceSpan)>) {
+        for (instruction, span) in spans {
+            self.add_span(function, instruction, span);
+        }
+    }
+
+    /**
+     * Encodes `module` with a `sourceMappingURL` section that points to `url`,
+     * and returns the binary along with the source map to serve at `url`.
+     */
+    pub fn encode(&self, module: &Module, url: &str) -> Result<(Vec<u8>, String), SourceMapError> {
+        let mut module = module.clone();
+        module.set_custom_section(&SourceMappingUrlSection(url.to_owned()));
+        let mut encoder = WasmEncoder::new();
+        module.encode(&mut encoder);
+        let mappings = self.mappings(&module, encoder.code_layout())?;
+        Ok((encoder.as_slice().to_vec(), source_map_json(&mappings)))
+    }
+
+    /** The offset in the binary of each instruction with a span, in order */
+    pub(crate) fn mappings(
+        &self,
+        module: &Module,
+        layout: &CodeLayout,
+    ) -> Result<Vec<(u32, &SourceSpan)>, SourceMapError> {
+        let imported_functions = imported_function_count(module);
+        let mut mappings = vec![];
+        for (&(function, instruction), span) in self.spans.iter() {
+            let body = function
+                .checked_sub(imported_functions)
+                .and_then(|body| layout.functions.get(body as usize))
+                .ok_or(SourceMapError::UnknownFunction(FunctionIndex(function)))?;
+            let offset = body.instructions.get(instruction as usize).ok_or(
+                SourceMapError::UnknownInstruction(FunctionIndex(function), instruction),
+            )?;
+            mappings.push((*offset, span));
+        }
+        mappings.sort_by_key(|(offset, _)| *offset);
+        Ok(mappings)
+    }
+}
+
+pub(crate) fn imported_function_count(module: &Module) -> u32 {
+    let mut count = 0;
+    for section in module.0.iter() {
+        if let Section::ImportSection(section) = section {
+            count += section
+                .0
+                .iter()
+                .filter(|import| matches!(import.descriptor, ImportDescriptor::TypeIndex(_)))
+                .count() as u32;
+        }
+    }
+    count
+}
+
+/** Where a function body is in a binary */
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct FunctionLayout {
+    /** The offset of the body's byte count, which the body starts with */
+    pub start: u32,
+    pub end: u32,
+    /** The offset of each instruction, by its number */
+    pub instructions: Vec<u32>,
+}
+
+/** Where the contents of the code section, and each function body, are */
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct CodeLayout {
+    /** The offset of the code section's contents, just after its size */
+    pub start: u32,
+    pub end: u32,
+    pub functions: Vec<FunctionLayout>,
+}
+
+impl CodeLayout {
+    /**
+     * Finds the function bodies and their instructions in a binary that
+     * wasn't encoded here, like an object file being linked. The layout of a
+     * module being encoded is recorded by the encoder instead.
+     */
+    pub fn decode(bytes: &[u8]) -> Result<CodeLayout, DecodeError> {
+        let mut decoder = WasmDecoder::new(bytes);
+        decoder.read_bytes(8)?; // magic number and version
+        while !decoder.is_empty() {
+            let id = decoder.read_u8()?;
+            let size = decoder.read_leb_u32()? as usize;
+            let start = decoder.position();
+            if id != CODE_SECTION {
+                decoder.read_bytes(size)?;
+                continue;
+            }
+            let mut functions = vec![];
+            for _ in 0..decoder.read_leb_u32()? {
+                functions.push(function_layout(&mut decoder)?);
+            }
+            return Ok(CodeLayout {
+                start: start as u32,
+                end: (start + size) as u32,
+                functions,
+            });
+        }
+        Ok(CodeLayout::default())
+    }
+
+    /** Moves the offsets after `offset` along by `count` bytes */
+    pub(crate) fn shift(&mut self, offset: u32, count: u32) {
+        let shift = |position: &mut u32| {
+            if *position > offset {
+                *position += count;
+            }
+        };
+        shift(&mut self.start);
+        shift(&mut self.end);
+        for function in self.functions.iter_mut() {
+            shift(&mut function.start);
+            shift(&mut function.end);
+            function.instructions.iter_mut().for_each(shift);
+        }
+    }
+}
+
+fn function_layout(decoder: &mut WasmDecoder) -> Result<FunctionLayout, DecodeError> {
+    let start = decoder.position() as u32;
+    decoder.read_leb_u32()?; // function byte count
+    decoder.read_vector::<Local>()?;
+    let mut instructions = vec![];
+    let mut depth = 0;
+    loop {
+        let offset = decoder.position() as u32;
+        match decoder.peek_u8()? {
+            END if depth == 0 => {
+                decoder.read_u8()?;
+                break;
+            }
+            END => {
+                decoder.read_u8()?;
+                depth -= 1;
+            }
+            ELSE => {
+                decoder.read_u8()?;
+            }
+            BLOCK | LOOP | IF => {
+                decoder.read_u8()?;
+                BlockType::decode(decoder)?;
+                depth += 1;
+                instructions.push(offset);
+            }
+            _ => {
+                Instruction::decode(decoder)?;
+                instructions.push(offset);
+            }
+        }
+    }
+    Ok(FunctionLayout {
+        start,
+        end: decoder.position() as u32,
+        instructions,
+    })
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/** Appends a base64 VLQ, which stores the sign in its lowest bit */
+fn push_vlq(mappings: &mut String, value: i64) {
+    let mut value = if value < 0 {
+        ((-value) << 1) | 1
+    } else {
+        value << 1
+    };
+    loop {
+        let mut digit = value & 0x1f;
+        value >>= 5;
+        if value > 0 {
+            digit |= 0x20;
+        }
+        mappings.push(BASE64[digit as usize] as char);
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+fn json_string(string: &str) -> String {
+    let mut json = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/**
+ * A version 3 source map. A binary is a single line, whose columns are byte
+ * offsets, so every mapping goes on the first line.
+ */
+fn source_map_json(mappings: &[(u32, &SourceSpan)]) -> String {
+    let mut sources: Vec<&str> = vec![];
+    let mut segments = vec![];
+    // Each field is relative to the same field of the previous segment
+    let mut previous = [0i64; 4];
+    for (offset, span) in mappings.iter() {
+        let source = match sources.iter().position(|source| *source == span.file) {
+            Some(source) => source,
+            None => {
+                sources.push(&span.file);
+                sources.len() - 1
+            }
+        };
+        let fields = [
+            *offset as i64,
+            source as i64,
+            span.line.saturating_sub(1) as i64,
+            span.column.saturating_sub(1) as i64,
+        ];
+        let mut segment = String::new();
+        for (field, previous) in fields.iter().zip(previous.iter()) {
+            push_vlq(&mut segment, field - previous);
+        }
+        previous = fields;
+        segments.push(segment);
+    }
+    let sources: Vec<String> = sources.into_iter().map(json_string).collect();
+    format!(
+        "{{\"version\":3,\"sources\":[{}],\"names\":[],\"mappings\":\"{}\"}}",
+        sources.join(","),
+        segments.join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        expr_tree::{BinaryOp, Context, Expr, Literal},
+        function_type::ValueType,
+        index::LocalIndex,
+    };
+
+    #[test]
+    fn test_vlq() {
+        let mut mappings = String::new();
+        for value in [0, 1, -1, 15, 16, -16, 1000] {
+            push_vlq(&mut mappings, value);
+            mappings.push(' ');
+        }
+        assert_eq!(mappings, "A C D e gB hB w+B ");
+    }
+
+    #[test]
+    fn test_instruction_count() {
+        let instructions = [
+            Instruction::Block(BlockType::Empty, vec![Instruction::Nop]),
+            Instruction::IfElse(
+                BlockType::Empty,
+                vec![Instruction::Nop],
+                vec![Instruction::Nop, Instruction::Nop],
+            ),
+            Instruction::Drop,
+        ];
+        assert_eq!(instruction_count(&instructions), 7);
+    }
+
+    #[test]
+    fn test_recorded_code_layout() {
+        // The second body is long enough that its byte count, and the code
+        // section's, take two bytes, moving what was recorded after them
+        let long_body = "(drop (i64.const 0x7fffffffffffffff))".repeat(12);
+        let module: Module = format!(
+            "(global i32 (i32.const 1))
+             (func (block (if (i32.const 0) (then nop) (else (br 1)))))
+             (func {})",
+            long_body
+        )
+        .parse()
+        .unwrap();
+        let mut encoder = WasmEncoder::new();
+        module.encode(&mut encoder);
+
+        let layout = encoder.code_layout();
+        assert_eq!(layout.functions[0].instructions.len(), 5);
+        assert_eq!(layout.functions[1].instructions.len(), 24);
+        assert_eq!(Ok(layout), CodeLayout::decode(encoder.as_slice()).as_ref());
+    }
+
+    #[test]
+    fn test_encode() {
+        let span = |line, column| SourceSpan::new("main.src", line, column);
+        // if (x) { x + 1 } else { 0 }
+        let expr = Expr::Spanned(
+            span(1, 1),
+            Box::new(Expr::If(
+                Box::new(Expr::Local(LocalIndex(0))),
+                Box::new(Expr::Spanned(
+                    span(2, 3),
+                    Box::new(Expr::Binary(
+                        BinaryOp::Add,
+                        Box::new(Expr::Local(LocalIndex(0))),
+                        Box::new(Expr::Const(Literal::I32(1))),
+                    )),
+                )),
+                Some(Box::new(Expr::Const(Literal::I32(0)))),
+            )),
+        );
+        let context = Context {
+            locals: &[ValueType::I32],
+            results: &[ValueType::I32],
+            ..Context::default()
+        };
+        let (expression, spans) = expr.lower_with_spans(&context).unwrap();
+        assert_eq!(
+            spans,
+            vec![
+                (0, span(1, 1)),
+                (1, span(1, 1)),
+                (2, span(2, 3)),
+                (3, span(2, 3)),
+                (4, span(2, 3)),
+                (5, span(1, 1)),
+            ]
+        );
+
+        let mut module: Module = r#"(import "env" "f" (func)) (func (param i32) (result i32))"#
+            .parse()
+            .unwrap();
+        if let Section::CodeSection(code) = &mut module.0[3] {
+            code.0[0].expression = expression;
+        }
+        let mut source_map = SourceMap::new();
+        source_map.add_spans(FunctionIndex(1), spans);
+        let (bytes, json) = source_map.encode(&module, "main.wasm.map").unwrap();
+
+        let decoded = Module::decode(&mut WasmDecoder::new(&bytes)).unwrap();
+        assert_eq!(
+            decoded.custom_section("sourceMappingURL"),
+            Some(&b"\x0dmain.wasm.map"[..])
+        );
+        // The first instruction is at 0x27, after the body size and locals
+        assert_eq!(
+            json,
+            r#"{"version":3,"sources":["main.src"],"names":[],"mappings":"uCAAA,EAAA,EACE,EAAA,EAAA,EADF"}"#
+        );
+
+        source_map.add_span(FunctionIndex(0), 0, span(1, 1));
+        assert_eq!(
+            source_map.encode(&module, "").err(),
+            Some(SourceMapError::UnknownFunction(FunctionIndex(0)))
+        );
+    }
+}
@@ -0,0 +1,347 @@
+/*!
+ * A straight-line IR where each instruction defines a virtual value instead of
+ * pushing to the operand stack.
+ *
+ * When lowered, a value stays on the operand stack if it is used once, and it
+ * is still on top of the stack when its user runs. Any other value is stored
+ * in a local, and locals of the same type are reused once the value they hold
+ * is dead. Constants are never stored, and are rematerialized at each use.
+ */
+use std::collections::HashMap;
+
+use crate::{
+    expression::{Expression, Instruction},
+    function_type::ValueType,
+    index::LocalIndex,
+    section::code_section::{Function, Local},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Value(u32);
+
+#[derive(Clone, Copy)]
+enum Definition {
+    Parameter(LocalIndex),
+    Statement(usize),
+}
+
+struct Statement {
+    instruction: Instruction,
+    operands: Vec<Value>,
+    result: Option<Value>,
+}
+
+/**
+ * Builds a function body out of instructions and the values they consume.
+ * Instructions shouldn't be control instructions, or access locals other
+ * than the parameters, since the locals are allocated by `finish`.
+ */
+pub struct FunctionBuilder {
+    parameter_count: u32,
+    value_types: Vec<ValueType>,
+    definitions: Vec<Definition>,
+    statements: Vec<Statement>,
+}
+
+impl FunctionBuilder {
+    pub fn new(parameters: &[ValueType]) -> FunctionBuilder {
+        FunctionBuilder {
+            parameter_count: parameters.len() as u32,
+            value_types: parameters.to_vec(),
+            definitions: (0..parameters.len() as u32)
+                .map(|index| Definition::Parameter(LocalIndex(index)))
+                .collect(),
+            statements: vec![],
+        }
+    }
+
+    pub fn parameter(&self, index: u32) -> Value {
+        assert!(index < self.parameter_count, "no parameter {}", index);
+        Value(index)
+    }
+
+    pub fn value_type(&self, value: Value) -> ValueType {
+        self.value_types[value.0 as usize]
+    }
+
+    /** Adds an instruction that consumes `operands` and produces one value */
+    pub fn push(
+        &mut self,
+        instruction: Instruction,
+        operands: &[Value],
+        result_type: ValueType,
+    ) -> Value {
+        let result = Value(self.value_types.len() as u32);
+        self.value_types.push(result_type);
+        self.definitions
+            .push(Definition::Statement(self.statements.len()));
+        self.statements.push(Statement {
+            instruction,
+            operands: operands.to_vec(),
+            result: Some(result),
+        });
+        result
+    }
+
+    /** Adds an instruction that consumes `operands` and produces nothing */
+    pub fn push_effect(&mut self, instruction: Instruction, operands: &[Value]) {
+        self.statements.push(Statement {
+            instruction,
+            operands: operands.to_vec(),
+            result: None,
+        });
+    }
+
+    /** Lowers the body into a `Function` that returns `results` */
+    pub fn finish(self, results: &[Value]) -> Function {
+        let end = self.statements.len();
+        let operands = |j: usize| -> &[Value] {
+            if j == end {
+                results
+            } else {
+                &self.statements[j].operands
+            }
+        };
+
+        // A pure, operand-less definition is cheaper to repeat than to store
+        let is_constant: Vec<bool> = self
+            .definitions
+            .iter()
+            .map(|definition| match definition {
+                Definition::Statement(j) => matches!(
+                    self.statements[*j].instruction,
+                    Instruction::I32Const(_)
+                        | Instruction::I64Const(_)
+                        | Instruction::F32Const(_)
+                        | Instruction::F64Const(_)
+                ),
+                Definition::Parameter(_) => false,
+            })
+            .collect();
+
+        let mut use_counts = vec![0; self.value_types.len()];
+        let mut last_uses = vec![0; self.value_types.len()];
+        for j in 0..=end {
+            for operand in operands(j) {
+                use_counts[operand.0 as usize] += 1;
+                last_uses[operand.0 as usize] = j;
+            }
+        }
+
+        // Every statement-defined value used once starts out on the stack, and
+        // is moved to a local if it isn't on top of the stack when it is used.
+        // Moving a value never disturbs the values above it.
+        let mut on_stack: Vec<bool> = (0..self.value_types.len())
+            .map(|v| {
+                matches!(self.definitions[v], Definition::Statement(_))
+                    && !is_constant[v]
+                    && use_counts[v] == 1
+            })
+            .collect();
+        let mut stack: Vec<Value> = vec![];
+        for j in 0..=end {
+            let operands = operands(j);
+            let mut matched = operands.len().min(stack.len());
+            while !operands[..matched]
+                .iter()
+                .zip(&stack[stack.len() - matched..])
+                .all(|(operand, value)| operand == value && on_stack[operand.0 as usize])
+            {
+                matched -= 1;
+            }
+            stack.truncate(stack.len() - matched);
+            for operand in operands[matched..].iter() {
+                if on_stack[operand.0 as usize] {
+                    on_stack[operand.0 as usize] = false;
+                    stack.retain(|value| value != operand);
+                }
+            }
+            if let Some(result) = self.statements.get(j).and_then(|s| s.result) {
+                if on_stack[result.0 as usize] {
+                    stack.push(result);
+                }
+            }
+        }
+
+        // Locals are allocated in a linear scan, per value type
+        let needs_local = |v: usize| {
+            matches!(self.definitions[v], Definition::Statement(_))
+                && !is_constant[v]
+                && !on_stack[v]
+                && use_counts[v] > 0
+        };
+        let mut local_types: Vec<ValueType> = vec![];
+        let mut local_counts: HashMap<ValueType, u32> = HashMap::new();
+        let mut free_slots: HashMap<ValueType, Vec<u32>> = HashMap::new();
+        let mut slots: HashMap<Value, (ValueType, u32)> = HashMap::new();
+        for (j, statement) in self.statements.iter().enumerate() {
+            for operand in statement.operands.iter() {
+                if last_uses[operand.0 as usize] == j {
+                    if let Some((value_type, slot)) = slots.get(operand) {
+                        let free = free_slots.entry(*value_type).or_default();
+                        if !free.contains(slot) {
+                            free.push(*slot);
+                        }
+                    }
+                }
+            }
+            if let Some(result) = statement.result.filter(|r| needs_local(r.0 as usize)) {
+                let value_type = self.value_type(result);
+                let slot = match free_slots.entry(value_type).or_default().pop() {
+                    Some(slot) => slot,
+                    None => {
+                        let count = local_counts.entry(value_type).or_insert(0);
+                        if *count == 0 {
+                            local_types.push(value_type);
+                        }
+                        *count += 1;
+                        *count - 1
+                    }
+                };
+                slots.insert(result, (value_type, slot));
+            }
+        }
+
+        // Locals of the same type are declared together, after the parameters
+        let mut first_index = HashMap::new();
+        let mut next_index = self.parameter_count;
+        let mut locals = vec![];
+        for value_type in local_types {
+            first_index.insert(value_type, next_index);
+            next_index += local_counts[&value_type];
+            locals.push(Local::new(local_counts[&value_type], value_type));
+        }
+        let location = |value: &Value| match self.definitions[value.0 as usize] {
+            Definition::Parameter(index) => index,
+            Definition::Statement(_) => {
+                let (value_type, slot) = slots[value];
+                LocalIndex(first_index[&value_type] + slot)
+            }
+        };
+
+        let mut instructions = vec![];
+        for j in 0..=end {
+            for operand in operands(j) {
+                match self.definitions[operand.0 as usize] {
+                    Definition::Statement(k) if is_constant[operand.0 as usize] => {
+                        instructions.push(self.statements[k].instruction.clone())
+                    }
+                    _ if on_stack[operand.0 as usize] => {}
+                    _ => push_get(&mut instructions, location(operand)),
+                }
+            }
+            let Some(statement) = self.statements.get(j) else {
+                break;
+            };
+            match statement.result {
+                Some(result) if is_constant[result.0 as usize] => continue,
+                _ => instructions.push(statement.instruction.clone()),
+            }
+            match statement.result {
+                Some(result) if use_counts[result.0 as usize] == 0 => {
+                    instructions.push(Instruction::Drop)
+                }
+                Some(result) if !on_stack[result.0 as usize] => {
+                    instructions.push(Instruction::LocalSet(location(&result)))
+                }
+                _ => {}
+            }
+        }
+        Function::new(locals, Expression(instructions))
+    }
+}
+
+/** Turns a `local.set` straight followed by a `local.get` into a `local.tee` */
+fn push_get(instructions: &mut Vec<Instruction>, index: LocalIndex) {
+    match instructions.last() {
+        Some(Instruction::LocalSet(set_index)) if *set_index == index => {
+            *instructions.last_mut().unwrap() = Instruction::LocalTee(index)
+        }
+        _ => instructions.push(Instruction::LocalGet(index)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    #[test]
+    fn test_values_stay_on_stack() {
+        let mut builder = FunctionBuilder::new(&[ValueType::I32, ValueType::I32]);
+        let (a, b) = (builder.parameter(0), builder.parameter(1));
+        let sum = builder.push(I32Add, &[a, b], ValueType::I32);
+        let difference = builder.push(I32Sub, &[a, b], ValueType::I32);
+        let product = builder.push(I32Mul, &[sum, difference], ValueType::I32);
+        let function = builder.finish(&[product]);
+
+        assert!(function.locals.is_empty());
+        assert_eq!(
+            function.expression,
+            Expression(vec![
+                LocalGet(LocalIndex(0)),
+                LocalGet(LocalIndex(1)),
+                I32Add,
+                LocalGet(LocalIndex(0)),
+                LocalGet(LocalIndex(1)),
+                I32Sub,
+                I32Mul,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_locals_are_reused() {
+        let mut builder = FunctionBuilder::new(&[ValueType::I32]);
+        let a = builder.parameter(0);
+        let two = builder.push(I32Const(2), &[], ValueType::I32);
+        let doubled = builder.push(I32Mul, &[a, two], ValueType::I32);
+        let sum = builder.push(I32Add, &[doubled, doubled], ValueType::I32);
+        let square = builder.push(I32Mul, &[sum, sum], ValueType::I32);
+        let function = builder.finish(&[square]);
+
+        assert_eq!(function.locals, vec![Local::new(1, ValueType::I32)]);
+        assert_eq!(
+            function.expression,
+            Expression(vec![
+                LocalGet(LocalIndex(0)),
+                I32Const(2),
+                I32Mul,
+                LocalTee(LocalIndex(1)),
+                LocalGet(LocalIndex(1)),
+                I32Add,
+                LocalTee(LocalIndex(1)),
+                LocalGet(LocalIndex(1)),
+                I32Mul,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_buried_value_is_spilled() {
+        let mut builder = FunctionBuilder::new(&[ValueType::I64]);
+        let a = builder.parameter(0);
+        let first = builder.push(I64Clz, &[a], ValueType::I64);
+        let second = builder.push(I64Ctz, &[a], ValueType::I64);
+        builder.push(I64PopCnt, &[a], ValueType::I64);
+        let difference = builder.push(I64Sub, &[second, first], ValueType::I64);
+        let function = builder.finish(&[difference]);
+
+        assert_eq!(function.locals, vec![Local::new(1, ValueType::I64)]);
+        assert_eq!(
+            function.expression,
+            Expression(vec![
+                LocalGet(LocalIndex(0)),
+                I64Clz,
+                LocalSet(LocalIndex(1)),
+                LocalGet(LocalIndex(0)),
+                I64Ctz,
+                LocalGet(LocalIndex(0)),
+                I64PopCnt,
+                Drop,
+                LocalGet(LocalIndex(1)),
+                I64Sub,
+            ])
+        );
+    }
+}
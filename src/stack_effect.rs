@@ -0,0 +1,425 @@
+/*!
+ * What each `Instruction` pops and pushes, and which kinds of behaviour it
+ * has, for validation, stack-depth analysis, optimizers and printers.
+ */
+use std::{error, fmt};
+
+use crate::{
+    expression::{BlockType, Instruction},
+    function_type::{FunctionType, ValueType},
+    index::{FunctionIndex, GlobalIndex, LabelIndex, LocalIndex, TypeIndex},
+    section::global_section::GlobalType,
+};
+
+/**
+ * The module and function environment that an instruction is in. Functions
+ * are listed by their type index, covering imported functions as well.
+ */
+#[derive(Default)]
+pub struct Context<'a> {
+    pub types: &'a [FunctionType],
+    pub functions: &'a [TypeIndex],
+    pub globals: &'a [GlobalType],
+    pub locals: &'a [ValueType],
+    pub results: &'a [ValueType],
+    /** What branching to each enclosing label takes, innermost last */
+    pub labels: &'a [Vec<ValueType>],
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StackEffectError {
+    UnknownLocal(LocalIndex),
+    UnknownGlobal(GlobalIndex),
+    UnknownFunction(FunctionIndex),
+    UnknownType(TypeIndex),
+    UnknownLabel(LabelIndex),
+}
+
+impl fmt::Display for StackEffectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StackEffectError::UnknownLocal(index) => write!(f, "unknown local {}", index.0),
+            StackEffectError::UnknownGlobal(index) => write!(f, "unknown global {}", index.0),
+            StackEffectError::UnknownFunction(index) => write!(f, "unknown function {}", index.0),
+            StackEffectError::UnknownType(index) => write!(f, "unknown type {}", index.0),
+            StackEffectError::UnknownLabel(index) => write!(f, "unknown label {}", index.0),
+        }
+    }
+}
+
+impl error::Error for StackEffectError {}
+
+/** A stack slot, whose type is unknown for `Drop` and `Select` */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Value(ValueType),
+    /** Any type, the same for every `Any` in one effect */
+    Any,
+}
+
+/**
+ * The operands an instruction pops, bottom first, and the results it pushes.
+ * When `diverges` is set, control never reaches the next instruction, and the
+ * stack after the instruction can have any type.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct StackEffect {
+    pub parameters: Vec<Operand>,
+    pub results: Vec<Operand>,
+    pub diverges: bool,
+}
+
+impl StackEffect {
+    fn new(parameters: &[ValueType], results: &[ValueType]) -> StackEffect {
+        StackEffect {
+            parameters: parameters.iter().copied().map(Operand::Value).collect(),
+            results: results.iter().copied().map(Operand::Value).collect(),
+            diverges: false,
+        }
+    }
+
+    fn diverging(parameters: &[ValueType]) -> StackEffect {
+        StackEffect {
+            diverges: true,
+            ..StackEffect::new(parameters, &[])
+        }
+    }
+
+    /** The change in stack height when the instruction doesn't diverge */
+    pub fn height_change(&self) -> i32 {
+        self.results.len() as i32 - self.parameters.len() as i32
+    }
+}
+
+fn block_results(block_type: &BlockType) -> Vec<ValueType> {
+    match block_type {
+        BlockType::Empty => vec![],
+        BlockType::Value(value_type) => vec![*value_type],
+    }
+}
+
+fn function_type<'a>(
+    context: &Context<'a>,
+    type_index: TypeIndex,
+) -> Result<&'a FunctionType, StackEffectError> {
+    context
+        .types
+        .get(type_index.0 as usize)
+        .ok_or(StackEffectError::UnknownType(type_index))
+}
+
+fn label<'a>(
+    context: &Context<'a>,
+    label: LabelIndex,
+) -> Result<&'a [ValueType], StackEffectError> {
+    let depth = label.0 as usize;
+    if depth >= context.labels.len() {
+        return Err(StackEffectError::UnknownLabel(label));
+    }
+    Ok(&context.labels[context.labels.len() - 1 - depth])
+}
+
+macro_rules! define_stack_effects {
+    ($(
+        $name:ident $(($($field:ident: $type:ty),*))?
+            = $constant:ident $opcode:tt $prefix:ident $(. $suffix:tt)? $kind:ident
+                $align:tt $signature:tt $($traps:ident)?;
+    )*) => {
+        impl Instruction {
+            /**
+             * The types the instruction pops and pushes, when they don't
+             * depend on where it is.
+             */
+            fn signature(&self) -> Option<(&'static [ValueType], &'static [ValueType])> {
+                use ValueType::*;
+                match self {
+                    $(Instruction::$name { .. } => signature!($signature),)*
+                }
+            }
+
+            pub fn is_load(&self) -> bool {
+                match self {
+                    $(Instruction::$name { .. } => is_load!($kind $signature),)*
+                }
+            }
+
+            pub fn is_store(&self) -> bool {
+                match self {
+                    $(Instruction::$name { .. } => is_store!($kind $signature),)*
+                }
+            }
+
+            /**
+             * Whether executing the instruction can trap. Calls and
+             * instructions with a body count as trapping, since the code they
+             * run might.
+             */
+            pub fn can_trap(&self) -> bool {
+                match self {
+                    $(Instruction::$name { .. } => traps!($($traps)?),)*
+                }
+            }
+        }
+    };
+}
+
+macro_rules! signature {
+    (_) => {
+        None
+    };
+    (($($parameter:ident)* -> $($result:ident)*)) => {
+        Some((&[$($parameter),*], &[$($result),*]))
+    };
+}
+
+/** Loads push the value they read, and stores push nothing */
+macro_rules! is_load {
+    (MemoryArguments ($($parameter:ident)* -> $result:ident)) => {
+        true
+    };
+    ($kind:ident $signature:tt) => {
+        false
+    };
+}
+
+macro_rules! is_store {
+    (MemoryArguments ($($parameter:ident)* ->)) => {
+        true
+    };
+    ($kind:ident $signature:tt) => {
+        false
+    };
+}
+
+macro_rules! traps {
+    () => {
+        false
+    };
+    (traps) => {
+        true
+    };
+}
+
+crate::for_each_instruction!(define_stack_effects);
+
+impl Instruction {
+    /**
+     * Returns what the instruction pops and pushes in `context`. For
+     * instructions with a body, such as `Block`, that is the effect of the
+     * whole construct.
+     */
+    pub fn stack_effect(&self, context: &Context) -> Result<StackEffect, StackEffectError> {
+        use Instruction::*;
+        use ValueType::*;
+
+        if let Some((parameters, results)) = self.signature() {
+            return Ok(StackEffect::new(parameters, results));
+        }
+        let effect =
+            |parameters: &[ValueType], results: &[ValueType]| StackEffect::new(parameters, results);
+        Ok(match self {
+            Unreachable => StackEffect::diverging(&[]),
+            Block(block_type, _) | Loop(block_type, _) => effect(&[], &block_results(block_type)),
+            If(block_type, _) | IfElse(block_type, _, _) => {
+                effect(&[I32], &block_results(block_type))
+            }
+            Branch(label_index) => StackEffect::diverging(label(context, *label_index)?),
+            BranchIf(label_index) => {
+                let label_types = label(context, *label_index)?;
+                let mut parameters = label_types.to_vec();
+                parameters.push(I32);
+                effect(&parameters, label_types)
+            }
+            BranchTable(label_indices, default) => {
+                for label_index in label_indices {
+                    label(context, *label_index)?;
+                }
+                let mut parameters = label(context, *default)?.to_vec();
+                parameters.push(I32);
+                StackEffect::diverging(&parameters)
+            }
+            Return => StackEffect::diverging(context.results),
+            Call(function_index) => {
+                let type_index = context
+                    .functions
+                    .get(function_index.0 as usize)
+                    .ok_or(StackEffectError::UnknownFunction(*function_index))?;
+                let function_type = function_type(context, *type_index)?;
+                effect(&function_type.parameters, &function_type.results)
+            }
+            CallIndirect(type_index) => {
+                let function_type = function_type(context, *type_index)?;
+                let mut parameters = function_type.parameters.clone();
+                parameters.push(I32);
+                effect(&parameters, &function_type.results)
+            }
+
+            Drop => StackEffect {
+                parameters: vec![Operand::Any],
+                results: vec![],
+                diverges: false,
+            },
+            Select => StackEffect {
+                parameters: vec![Operand::Any, Operand::Any, Operand::Value(I32)],
+                results: vec![Operand::Any],
+                diverges: false,
+            },
+
+            LocalGet(index) | LocalSet(index) | LocalTee(index) => {
+                let value_type = *context
+                    .locals
+                    .get(index.0 as usize)
+                    .ok_or(StackEffectError::UnknownLocal(*index))?;
+                match self {
+                    LocalGet(_) => effect(&[], &[value_type]),
+                    LocalSet(_) => effect(&[value_type], &[]),
+                    _ => effect(&[value_type], &[value_type]),
+                }
+            }
+            GlobalGet(index) | GlobalSet(index) => {
+                let value_type = context
+                    .globals
+                    .get(index.0 as usize)
+                    .ok_or(StackEffectError::UnknownGlobal(*index))?
+                    .value_type;
+                match self {
+                    GlobalGet(_) => effect(&[], &[value_type]),
+                    _ => effect(&[value_type], &[]),
+                }
+            }
+            _ => unreachable!("{} has a fixed signature", self.mnemonic()),
+        })
+    }
+
+    /** Whether the instruction is a control instruction */
+    pub fn is_control(&self) -> bool {
+        use Instruction::*;
+        matches!(
+            self,
+            Unreachable
+                | Nop
+                | Block(..)
+                | Loop(..)
+                | If(..)
+                | IfElse(..)
+                | Branch(_)
+                | BranchIf(_)
+                | BranchTable(..)
+                | Return
+                | Call(_)
+                | CallIndirect(_)
+        )
+    }
+
+    /** Whether the instruction loads from or stores to linear memory */
+    pub fn is_memory_access(&self) -> bool {
+        self.is_load() || self.is_store()
+    }
+
+    /**
+     * Whether the instruction's results depend only on its operands, and it
+     * has no other effect. Pure instructions can be removed when their results
+     * are unused, or reordered with other instructions. `Drop` isn't pure,
+     * since removing it would leave its operand on the stack.
+     */
+    pub fn is_pure(&self) -> bool {
+        use Instruction::*;
+        !self.is_control()
+            && !self.can_trap()
+            && !matches!(
+                self,
+                Drop | LocalGet(_)
+                    | LocalSet(_)
+                    | LocalTee(_)
+                    | GlobalGet(_)
+                    | GlobalSet(_)
+                    | MemorySize
+                    | MemoryGrow
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::MemoryArguments;
+
+    #[test]
+    fn test_stack_effects() {
+        let types = [FunctionType::new(
+            vec![ValueType::I32, ValueType::F64],
+            vec![ValueType::I64],
+        )];
+        let functions = [TypeIndex(0)];
+        let locals = [ValueType::F32];
+        let labels = [vec![ValueType::I64], vec![]];
+        let context = Context {
+            types: &types,
+            functions: &functions,
+            locals: &locals,
+            labels: &labels,
+            ..Context::default()
+        };
+        let i32 = Operand::Value(ValueType::I32);
+        let i64 = Operand::Value(ValueType::I64);
+
+        let add = Instruction::I32Add.stack_effect(&context).unwrap();
+        assert_eq!(add.parameters, vec![i32, i32]);
+        assert_eq!(add.height_change(), -1);
+
+        let load = Instruction::I64Load32U(MemoryArguments::new(0, 2));
+        assert_eq!(load.stack_effect(&context).unwrap().results, vec![i64]);
+
+        let call = Instruction::CallIndirect(TypeIndex(0))
+            .stack_effect(&context)
+            .unwrap();
+        assert_eq!(call.parameters.len(), 3);
+        assert_eq!(
+            Instruction::Call(FunctionIndex(0))
+                .stack_effect(&context)
+                .unwrap()
+                .results,
+            vec![i64]
+        );
+
+        let branch = Instruction::BranchIf(LabelIndex(1))
+            .stack_effect(&context)
+            .unwrap();
+        assert_eq!(branch.parameters, vec![i64, i32]);
+        assert!(!branch.diverges);
+        assert!(
+            Instruction::Branch(LabelIndex(0))
+                .stack_effect(&context)
+                .unwrap()
+                .diverges
+        );
+
+        assert_eq!(
+            Instruction::LocalTee(LocalIndex(0))
+                .stack_effect(&context)
+                .unwrap()
+                .height_change(),
+            0
+        );
+        assert_eq!(
+            Instruction::LocalGet(LocalIndex(1)).stack_effect(&context),
+            Err(StackEffectError::UnknownLocal(LocalIndex(1)))
+        );
+        assert_eq!(
+            Instruction::Branch(LabelIndex(2)).stack_effect(&context),
+            Err(StackEffectError::UnknownLabel(LabelIndex(2)))
+        );
+    }
+
+    #[test]
+    fn test_classification() {
+        let load = Instruction::F32Load(MemoryArguments::new(0, 2));
+        assert!(load.is_memory_access() && load.can_trap() && !load.is_pure());
+        assert!(Instruction::I32Add.is_pure());
+        assert!(!Instruction::I32DivU.is_pure());
+        assert!(!Instruction::LocalGet(LocalIndex(0)).is_pure());
+        assert!(!Instruction::Drop.is_pure());
+        assert!(Instruction::Return.is_control());
+        assert!(!Instruction::Select.is_control() && Instruction::Select.is_pure());
+    }
+}
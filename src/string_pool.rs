@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use crate::{
+    expression::{Expression, Instruction},
+    index::MemoryIndex,
+    memory_layout::MemoryLayout,
+    section::data_section::Data,
+};
+
+/**
+ * The location of an interned string in linear memory. `len` never includes
+ * the null terminator of a null-terminated string.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PooledString {
+    pub ptr: u32,
+    pub len: u32,
+}
+
+impl PooledString {
+    /** Pushes `ptr` followed by `len`, which is the usual calling convention */
+    pub fn instructions(&self) -> Vec<Instruction> {
+        vec![
+            Instruction::I32Const(self.ptr as i32),
+            Instruction::I32Const(self.len as i32),
+        ]
+    }
+}
+
+/**
+ * Interns string literals into one contiguous block of bytes starting at a
+ * fixed address, so that their pointers are known as soon as they are
+ * interned.
+ *
+ * A string shares storage with any previously interned bytes that contain it,
+ * which covers identical strings as well as suffixes of longer ones. Sharing
+ * only looks backwards, so interning longer strings first saves the most
+ * space.
+ */
+pub struct StringPool {
+    base: u32,
+    bytes: Vec<u8>,
+    interned: HashMap<(String, bool), PooledString>,
+}
+
+impl StringPool {
+    pub fn new(base: u32) -> StringPool {
+        StringPool {
+            base,
+            bytes: vec![],
+            interned: HashMap::new(),
+        }
+    }
+
+    pub fn intern(&mut self, string: &str) -> PooledString {
+        self.intern_bytes(string, false)
+    }
+
+    /** Interns a string that is followed by a null byte in memory */
+    pub fn intern_null_terminated(&mut self, string: &str) -> PooledString {
+        self.intern_bytes(string, true)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.bytes.as_slice()
+    }
+
+    /** Returns the first address after the pool */
+    pub fn end(&self) -> u32 {
+        self.base + self.bytes.len() as u32
+    }
+
+    pub fn data(&self, memory_index: MemoryIndex) -> Data {
+        Data::new(
+            memory_index,
+            Expression(vec![Instruction::I32Const(self.base as i32)]),
+            self.bytes.clone(),
+        )
+    }
+
+    /**
+     * Places the pool in `layout`. The pool must have been created with a
+     * base of `layout.size()`, and nothing else may have been placed since.
+     */
+    pub fn place(&self, layout: &mut MemoryLayout) {
+        let address = layout.alloc(self.as_bytes(), 1);
+        assert_eq!(address, self.base, "string pool was not placed at its base");
+    }
+
+    fn intern_bytes(&mut self, string: &str, is_null_terminated: bool) -> PooledString {
+        let key = (string.to_owned(), is_null_terminated);
+        if let Some(pooled) = self.interned.get(&key) {
+            return *pooled;
+        }
+
+        let mut needle = string.as_bytes().to_owned();
+        if is_null_terminated {
+            needle.push(0);
+        }
+        let offset = match self.find(&needle) {
+            Some(offset) => offset,
+            None => {
+                self.bytes.extend_from_slice(&needle);
+                self.bytes.len() - needle.len()
+            }
+        };
+
+        let pooled = PooledString {
+            ptr: self.base + offset as u32,
+            len: string.len() as u32,
+        };
+        self.interned.insert(key, pooled);
+        pooled
+    }
+
+    fn find(&self, needle: &[u8]) -> Option<usize> {
+        if needle.is_empty() {
+            return Some(self.bytes.len());
+        }
+        self.bytes
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::assert_encoding_eq;
+
+    #[test]
+    fn test_deduplication() {
+        let mut pool = StringPool::new(8);
+        let hello = pool.intern("hello world!\n");
+        assert_eq!(hello, PooledString { ptr: 8, len: 13 });
+        assert_eq!(pool.intern("hello world!\n"), hello);
+        assert_eq!(pool.intern("world"), PooledString { ptr: 14, len: 5 });
+        assert_eq!(pool.end(), 21);
+    }
+
+    #[test]
+    fn test_null_terminated_suffix() {
+        let mut pool = StringPool::new(0);
+        assert_eq!(
+            pool.intern_null_terminated("foobar"),
+            PooledString { ptr: 0, len: 6 }
+        );
+        assert_eq!(
+            pool.intern_null_terminated("bar"),
+            PooledString { ptr: 3, len: 3 }
+        );
+        // "foo" is not followed by a null byte, so it can't be shared
+        assert_eq!(
+            pool.intern_null_terminated("foo"),
+            PooledString { ptr: 7, len: 3 }
+        );
+        assert_eq!(pool.as_bytes(), b"foobar\0foo\0");
+    }
+
+    #[test]
+    fn test_place_in_layout() {
+        let mut layout = MemoryLayout::new(MemoryIndex(0));
+        layout.reserve(8, 4);
+        let mut pool = StringPool::new(layout.size());
+        pool.intern("hi");
+        pool.place(&mut layout);
+        assert_eq!(layout.size(), pool.end());
+    }
+
+    #[test]
+    fn test_data_encoding() {
+        let mut pool = StringPool::new(4);
+        pool.intern("ab");
+        assert_encoding_eq(
+            pool.data(MemoryIndex(0)),
+            &[
+                0x00, // memory index
+                0x41, 0x04, 0x0b, // (i32.const 4)
+                0x02, // byte vec length
+                0x61, 0x62, // bytes ("ab")
+            ],
+        );
+    }
+}
@@ -0,0 +1,1336 @@
+/*!
+ * The WebAssembly text format for instructions: `Display` prints an
+ * instruction the way it is written in a `.wat` file, and `FromStr` parses
+ * one back.
+ *
+ * Both are generated from the instruction table, like the binary encoder and
+ * decoder. Blocks are printed flat, with their bodies indented on the
+ * following lines and closed by `end`.
+ */
+use std::{collections::HashMap, convert::TryFrom, error, fmt, str::FromStr};
+
+use crate::{
+    expression::{BlockType, Instruction, MemoryArguments},
+    function_type::{FunctionType, ValueType},
+    index::{
+        FunctionIndex, GlobalIndex, LabelIndex, LocalIndex, MemoryIndex, TableIndex, TypeIndex,
+    },
+    section::name_section::NameSection,
+};
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl error::Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum TokenKind {
+    LeftParen,
+    RightParen,
+    /** A keyword, number, identifier or anything else outside a string */
+    Atom(String),
+    String(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Token {
+    pub kind: TokenKind,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Token {
+    pub fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    pub fn atom(&self) -> Option<&str> {
+        match &self.kind {
+            TokenKind::Atom(atom) => Some(atom),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            TokenKind::LeftParen => write!(f, "`(`"),
+            TokenKind::RightParen => write!(f, "`)`"),
+            TokenKind::Atom(atom) => write!(f, "`{}`", atom),
+            TokenKind::String(_) => write!(f, "a string"),
+        }
+    }
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    /** Skips a block comment, after its opening `(;`, including nested ones */
+    fn skip_block_comment(&mut self, start: ParseError) -> Result<(), ParseError> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some('(') if self.peek() == Some(';') => {
+                    self.next();
+                    depth += 1;
+                }
+                Some(';') if self.peek() == Some(')') => {
+                    self.next();
+                    depth -= 1;
+                }
+                Some(_) => {}
+                None => return Err(start),
+            }
+        }
+        Ok(())
+    }
+
+    fn string(&mut self, start: ParseError) -> Result<Vec<u8>, ParseError> {
+        let mut bytes = vec![];
+        loop {
+            match self.next() {
+                Some('"') => return Ok(bytes),
+                Some('\\') => {
+                    let escape = self.error("invalid escape in string");
+                    match self.next() {
+                        Some('t') => bytes.push(b'\t'),
+                        Some('n') => bytes.push(b'\n'),
+                        Some('r') => bytes.push(b'\r'),
+                        Some('"') => bytes.push(b'"'),
+                        Some('\'') => bytes.push(b'\''),
+                        Some('\\') => bytes.push(b'\\'),
+                        Some('u') => {
+                            if self.next() != Some('{') {
+                                return Err(escape);
+                            }
+                            let mut digits = String::new();
+                            loop {
+                                match self.next() {
+                                    Some('}') => break,
+                                    Some(c) => digits.push(c),
+                                    None => return Err(escape),
+                                }
+                            }
+                            let c = u32::from_str_radix(&digits.replace('_', ""), 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or(escape)?;
+                            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        Some(high) => {
+                            let low = self.next();
+                            match (high.to_digit(16), low.and_then(|c| c.to_digit(16))) {
+                                (Some(high), Some(low)) => bytes.push((high * 16 + low) as u8),
+                                _ => return Err(escape),
+                            }
+                        }
+                        None => return Err(start),
+                    }
+                }
+                Some(c) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                None => return Err(start),
+            }
+        }
+    }
+}
+
+/**
+ * Splits text into tokens, skipping whitespace and comments. Also returns the
+ * position just past the end of the text, for errors about missing tokens.
+ */
+pub(crate) fn tokenize(source: &str) -> Result<(Vec<Token>, (usize, usize)), ParseError> {
+    let mut lexer = Lexer {
+        chars: source.chars().peekable(),
+        line: 1,
+        column: 1,
+    };
+    let mut tokens = vec![];
+    while let Some(c) = lexer.peek() {
+        let (line, column) = (lexer.line, lexer.column);
+        let start = lexer.error("");
+        let kind = match c {
+            _ if c.is_whitespace() => {
+                lexer.next();
+                continue;
+            }
+            ';' => {
+                lexer.next();
+                if lexer.next() != Some(';') {
+                    return Err(ParseError {
+                        message: "unexpected `;`".into(),
+                        ..start
+                    });
+                }
+                while !matches!(lexer.next(), Some('\n') | None) {}
+                continue;
+            }
+            '(' => {
+                lexer.next();
+                if lexer.peek() == Some(';') {
+                    lexer.next();
+                    lexer.skip_block_comment(ParseError {
+                        message: "unterminated block comment".into(),
+                        ..start
+                    })?;
+                    continue;
+                }
+                TokenKind::LeftParen
+            }
+            ')' => {
+                lexer.next();
+                TokenKind::RightParen
+            }
+            '"' => {
+                lexer.next();
+                TokenKind::String(lexer.string(ParseError {
+                    message: "unterminated string".into(),
+                    ..start
+                })?)
+            }
+            _ => {
+                let mut atom = String::new();
+                while let Some(c) = lexer.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '"' | ';') {
+                        break;
+                    }
+                    atom.push(c);
+                    lexer.next();
+                }
+                TokenKind::Atom(atom)
+            }
+        };
+        tokens.push(Token { kind, line, column });
+    }
+    Ok((tokens, (lexer.line, lexer.column)))
+}
+
+/** The index spaces that `$names` can refer to */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Space {
+    Type,
+    Function,
+    Table,
+    Memory,
+    Global,
+    Local,
+}
+
+impl fmt::Display for Space {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Space::Type => "type",
+            Space::Function => "function",
+            Space::Table => "table",
+            Space::Memory => "memory",
+            Space::Global => "global",
+            Space::Local => "local",
+        })
+    }
+}
+
+/** What `$names` and type uses resolve to while parsing */
+#[derive(Default)]
+pub(crate) struct Names {
+    pub indices: HashMap<(Space, String), u32>,
+    /** The label of each enclosing block, innermost last */
+    pub labels: Vec<Option<Token>>,
+    /** The module's function types, including ones added by inline type uses */
+    pub types: Vec<FunctionType>,
+    /** The names defined so far, without their `$`, for the module's `name` section */
+    pub section: NameSection,
+    /** The function whose body is being parsed, which locals and labels belong to */
+    pub function: Option<FunctionIndex>,
+    /** The number of blocks started so far in the function, which numbers labels */
+    pub block_count: u32,
+}
+
+impl Names {
+    pub fn define(&mut self, space: Space, name: &Token, index: u32) -> Result<(), ParseError> {
+        let id = name.atom().unwrap_or_default().to_string();
+        let text = &id[1..];
+        match space {
+            Space::Type => self.section.name_type(TypeIndex(index), text),
+            Space::Function => self.section.name_function(FunctionIndex(index), text),
+            Space::Table => self.section.name_table(TableIndex(index), text),
+            Space::Memory => self.section.name_memory(MemoryIndex(index), text),
+            Space::Global => self.section.name_global(GlobalIndex(index), text),
+            Space::Local => {
+                if let Some(function) = self.function {
+                    self.section.name_local(function, LocalIndex(index), text);
+                }
+            }
+        }
+        match self.indices.insert((space, id), index) {
+            Some(_) => Err(name.error(format!("duplicate {} {}", space, name))),
+            None => Ok(()),
+        }
+    }
+
+    /** Enters a block, naming its label in the `name` section if it has one */
+    pub fn push_label(&mut self, label: Option<Token>) {
+        let name = label.as_ref().and_then(Token::atom);
+        if let (Some(function), Some(name)) = (self.function, name) {
+            self.section
+                .name_label(function, self.block_count, &name[1..]);
+        }
+        self.block_count += 1;
+        self.labels.push(label);
+    }
+}
+
+pub(crate) struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    end: (usize, usize),
+    pub names: Names,
+}
+
+impl Parser {
+    pub fn new(source: &str) -> Result<Parser, ParseError> {
+        let (tokens, end) = tokenize(source)?;
+        Ok(Parser {
+            tokens,
+            position: 0,
+            end,
+            names: Names::default(),
+        })
+    }
+
+    /** The index of the next token, to come back to with `seek` */
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn seek(&mut self, position: usize) {
+        self.position = position;
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    pub fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.position + offset)
+    }
+
+    pub fn peek_atom(&self) -> Option<&str> {
+        self.peek().and_then(Token::atom)
+    }
+
+    /** An error at the next token, or at the end of the text */
+    pub fn error(&self, message: impl Into<String>) -> ParseError {
+        let (line, column) = match self.peek() {
+            Some(token) => (token.line, token.column),
+            None => self.end,
+        };
+        ParseError {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    pub fn next(&mut self) -> Result<Token, ParseError> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| self.error("unexpected end of input"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    pub fn expect(&mut self, kind: TokenKind) -> Result<Token, ParseError> {
+        let expected = Token {
+            kind,
+            line: 0,
+            column: 0,
+        };
+        match self.peek() {
+            Some(token) if token.kind == expected.kind => self.next(),
+            Some(token) => Err(token.error(format!("expected {}, found {}", expected, token))),
+            None => Err(self.error(format!("expected {}", expected))),
+        }
+    }
+
+    pub fn expect_keyword(&mut self, keyword: &str) -> Result<Token, ParseError> {
+        self.expect(TokenKind::Atom(keyword.into()))
+    }
+
+    pub fn expect_end(&self) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(token) => Err(token.error(format!("unexpected {}", token))),
+            None => Ok(()),
+        }
+    }
+
+    /** Takes a `(keyword` pair if it is next */
+    pub fn take_open(&mut self, keyword: &str) -> bool {
+        let is_open = self.peek().map(|token| &token.kind) == Some(&TokenKind::LeftParen)
+            && self.peek_at(1).and_then(Token::atom) == Some(keyword);
+        if is_open {
+            self.position += 2;
+        }
+        is_open
+    }
+
+    fn parse_number<T>(
+        &mut self,
+        description: &str,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<T, ParseError> {
+        let token = self.next()?;
+        token
+            .atom()
+            .and_then(parse)
+            .ok_or_else(|| token.error(format!("expected {}, found {}", description, token)))
+    }
+
+    pub fn parse_u32(&mut self) -> Result<u32, ParseError> {
+        self.parse_number("an index", |text| match parse_integer(text)? {
+            (None, magnitude) => u32::try_from(magnitude).ok(),
+            _ => None,
+        })
+    }
+
+    pub fn parse_i32(&mut self) -> Result<i32, ParseError> {
+        self.parse_number("an i32", |text| {
+            parse_integer_with_width(text, 32).map(|value| value as i32)
+        })
+    }
+
+    pub fn parse_i64(&mut self) -> Result<i64, ParseError> {
+        self.parse_number("an i64", |text| {
+            parse_integer_with_width(text, 64).map(|value| value as i64)
+        })
+    }
+
+    pub fn parse_f32(&mut self) -> Result<f32, ParseError> {
+        self.parse_number("an f32", |text| {
+            parse_float(text, 8, 23, |text| {
+                text.parse::<f32>().ok().map(|f| f.to_bits() as u64)
+            })
+            .map(|bits| f32::from_bits(bits as u32))
+        })
+    }
+
+    pub fn parse_f64(&mut self) -> Result<f64, ParseError> {
+        self.parse_number("an f64", |text| {
+            parse_float(text, 11, 52, |text| {
+                text.parse::<f64>().ok().map(f64::to_bits)
+            })
+            .map(f64::from_bits)
+        })
+    }
+
+    /** Takes a `$name` if it is next */
+    pub fn take_id(&mut self) -> Option<Token> {
+        match self.peek_atom() {
+            Some(atom) if atom.starts_with('$') => self.next().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn is_index_next(&self) -> bool {
+        match self.peek_atom() {
+            Some(atom) => atom.starts_with('$') || matches!(parse_integer(atom), Some((None, _))),
+            None => false,
+        }
+    }
+
+    /** Parses an index, or a `$name` for one in `space` */
+    pub fn parse_index(&mut self, space: Space) -> Result<u32, ParseError> {
+        match self.take_id() {
+            Some(id) => {
+                let name = id.atom().unwrap().to_string();
+                self.names
+                    .indices
+                    .get(&(space, name))
+                    .copied()
+                    .ok_or_else(|| id.error(format!("unknown {} {}", space, id)))
+            }
+            None => self.parse_u32(),
+        }
+    }
+
+    /** Parses a label depth, or the `$name` of an enclosing block */
+    fn parse_label(&mut self) -> Result<LabelIndex, ParseError> {
+        match self.take_id() {
+            Some(id) => {
+                let labels = &self.names.labels;
+                labels
+                    .iter()
+                    .rposition(|label| label.as_ref().and_then(Token::atom) == id.atom())
+                    .map(|position| LabelIndex((labels.len() - 1 - position) as u32))
+                    .ok_or_else(|| id.error(format!("unknown label {}", id)))
+            }
+            None => self.parse_u32().map(LabelIndex),
+        }
+    }
+
+    pub fn parse_value_types(&mut self) -> Result<Vec<ValueType>, ParseError> {
+        let mut value_types = vec![];
+        while self.peek_atom().is_some() {
+            value_types.push(self.parse_value_type()?);
+        }
+        self.expect(TokenKind::RightParen)?;
+        Ok(value_types)
+    }
+
+    /**
+     * Parses a `(type x)`, inline `(param ...)` and `(result ...)`, or both,
+     * returning the type and the parameters' names. A signature that isn't
+     * one of the module's types yet is added to them.
+     */
+    pub fn parse_type_use(&mut self) -> Result<(TypeIndex, Vec<Option<Token>>), ParseError> {
+        let explicit = match self.take_open("type") {
+            true => {
+                let token = self.peek().cloned();
+                let index = self.parse_index(Space::Type)?;
+                self.expect(TokenKind::RightParen)?;
+                Some((index, token.unwrap()))
+            }
+            false => None,
+        };
+        let mut inline = FunctionType::new(vec![], vec![]);
+        let mut parameter_names = vec![];
+        let has_inline = matches!(
+            self.peek_at(1).and_then(Token::atom),
+            Some("param") | Some("result")
+        ) && self.peek().map(|token| &token.kind) == Some(&TokenKind::LeftParen);
+        while self.take_open("param") {
+            match self.take_id() {
+                Some(id) => {
+                    inline.parameters.push(self.parse_value_type()?);
+                    parameter_names.push(Some(id));
+                    self.expect(TokenKind::RightParen)?;
+                }
+                None => {
+                    for value_type in self.parse_value_types()? {
+                        inline.parameters.push(value_type);
+                        parameter_names.push(None);
+                    }
+                }
+            }
+        }
+        while self.take_open("result") {
+            inline.results.extend(self.parse_value_types()?);
+        }
+
+        let types = &mut self.names.types;
+        match explicit {
+            Some((index, token)) => match types.get(index as usize) {
+                Some(function_type) if has_inline && *function_type != inline => {
+                    Err(token.error(format!("inline signature doesn't match type {}", index)))
+                }
+                Some(function_type) if !has_inline => {
+                    Ok((TypeIndex(index), vec![None; function_type.parameters.len()]))
+                }
+                _ => Ok((TypeIndex(index), parameter_names)),
+            },
+            None => {
+                let index = match types.iter().position(|t| *t == inline) {
+                    Some(index) => index,
+                    None => {
+                        types.push(inline);
+                        types.len() - 1
+                    }
+                };
+                Ok((TypeIndex(index as u32), parameter_names))
+            }
+        }
+    }
+
+    pub fn parse_value_type(&mut self) -> Result<ValueType, ParseError> {
+        let token = self.next()?;
+        match token.atom() {
+            Some("i32") => Ok(ValueType::I32),
+            Some("i64") => Ok(ValueType::I64),
+            Some("f32") => Ok(ValueType::F32),
+            Some("f64") => Ok(ValueType::F64),
+            _ => Err(token.error(format!("expected a value type, found {}", token))),
+        }
+    }
+
+    pub fn parse_block_type(&mut self) -> Result<BlockType, ParseError> {
+        if !self.take_open("result") {
+            return Ok(BlockType::Empty);
+        }
+        let value_type = self.parse_value_type()?;
+        self.expect(TokenKind::RightParen)?;
+        Ok(BlockType::Value(value_type))
+    }
+
+    /**
+     * Parses flat and folded instructions up to a `)`, `end`, `else` or the
+     * end of the text, without taking it.
+     */
+    pub fn parse_instructions(&mut self) -> Result<Vec<Instruction>, ParseError> {
+        let mut instructions = vec![];
+        loop {
+            match self.peek().map(|token| &token.kind) {
+                None | Some(TokenKind::RightParen) => return Ok(instructions),
+                Some(TokenKind::Atom(atom)) if atom == "end" || atom == "else" => {
+                    return Ok(instructions)
+                }
+                Some(TokenKind::LeftParen) => self.parse_folded(&mut instructions)?,
+                _ => instructions.push(self.parse_instruction()?),
+            }
+        }
+    }
+
+    /**
+     * Parses a folded instruction, pushing its operands, then the
+     * instruction itself.
+     */
+    pub fn parse_folded(&mut self, instructions: &mut Vec<Instruction>) -> Result<(), ParseError> {
+        self.expect(TokenKind::LeftParen)?;
+        let mnemonic = self.next()?;
+        match mnemonic.atom() {
+            Some("block") | Some("loop") => {
+                let label = self.take_id();
+                let block_type = self.parse_block_type()?;
+                self.names.push_label(label);
+                let body = self.parse_instructions();
+                self.names.labels.pop();
+                instructions.push(match mnemonic.atom() {
+                    Some("block") => Instruction::Block(block_type, body?),
+                    _ => Instruction::Loop(block_type, body?),
+                });
+            }
+            Some("if") => {
+                let label = self.take_id();
+                let block_type = self.parse_block_type()?;
+                while self.peek_at(1).and_then(Token::atom) != Some("then") {
+                    self.parse_folded(instructions)?;
+                }
+                self.names.push_label(label);
+                let bodies = self.parse_folded_if_bodies();
+                self.names.labels.pop();
+                instructions.push(match bodies? {
+                    (body, None) => Instruction::If(block_type, body),
+                    (then_body, Some(else_body)) => {
+                        Instruction::IfElse(block_type, then_body, else_body)
+                    }
+                });
+            }
+            Some(name) => {
+                let instruction = parse_instruction_named(self, name, &mnemonic)?;
+                while self.peek().map(|token| &token.kind) == Some(&TokenKind::LeftParen) {
+                    self.parse_folded(instructions)?;
+                }
+                instructions.push(instruction);
+            }
+            None => {
+                return Err(mnemonic.error(format!("expected an instruction, found {}", mnemonic)))
+            }
+        }
+        self.expect(TokenKind::RightParen)?;
+        Ok(())
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn parse_folded_if_bodies(
+        &mut self,
+    ) -> Result<(Vec<Instruction>, Option<Vec<Instruction>>), ParseError> {
+        self.expect(TokenKind::LeftParen)?;
+        self.expect_keyword("then")?;
+        let then_body = self.parse_instructions()?;
+        self.expect(TokenKind::RightParen)?;
+        if !self.take_open("else") {
+            return Ok((then_body, None));
+        }
+        let else_body = self.parse_instructions()?;
+        self.expect(TokenKind::RightParen)?;
+        Ok((then_body, Some(else_body)))
+    }
+
+    /**
+     * Parses instructions up to and including an `end`, or an `else` if
+     * `allow_else`, returning whether it was an `else`.
+     */
+    pub fn parse_body(&mut self, allow_else: bool) -> Result<(Vec<Instruction>, bool), ParseError> {
+        let instructions = self.parse_instructions()?;
+        let is_else = match self.peek_atom() {
+            Some("end") => false,
+            Some("else") if allow_else => true,
+            _ => return Err(self.error("expected `end`")),
+        };
+        self.next()?;
+        // The block's label can be repeated after `else` and `end`
+        if let Some(id) = self.take_id() {
+            let label = self.names.labels.last().cloned().flatten();
+            if label.as_ref().and_then(Token::atom) != id.atom() {
+                return Err(id.error(format!("mismatched label {}", id)));
+            }
+        }
+        Ok((instructions, is_else))
+    }
+
+    /** Parses a flat block's label, type and bodies, after its mnemonic */
+    #[allow(clippy::type_complexity)]
+    fn parse_block(
+        &mut self,
+        allow_else: bool,
+    ) -> Result<(BlockType, Vec<Instruction>, Option<Vec<Instruction>>), ParseError> {
+        let label = self.take_id();
+        let block_type = self.parse_block_type()?;
+        self.names.push_label(label);
+        let bodies = self
+            .parse_body(allow_else)
+            .and_then(|(body, is_else)| match is_else {
+                true => Ok((body, Some(self.parse_body(false)?.0))),
+                false => Ok((body, None)),
+            });
+        self.names.labels.pop();
+        let (body, else_body) = bodies?;
+        Ok((block_type, body, else_body))
+    }
+
+    /**
+     * Parses `offset=` and `align=`, which are in bytes in the text format,
+     * defaulting to no offset and the access's natural alignment.
+     */
+    fn parse_memory_arguments(&mut self, natural: u32) -> Result<MemoryArguments, ParseError> {
+        let mut arguments = MemoryArguments::new(0, natural);
+        if let Some(offset) = self
+            .peek_atom()
+            .and_then(|atom| atom.strip_prefix("offset="))
+        {
+            let token = self.peek().cloned().unwrap();
+            arguments.offset = match parse_integer(offset) {
+                Some((None, offset)) => u32::try_from(offset).ok(),
+                _ => None,
+            }
+            .ok_or_else(|| token.error(format!("invalid offset {}", token)))?;
+            self.next()?;
+        }
+        if let Some(align) = self
+            .peek_atom()
+            .and_then(|atom| atom.strip_prefix("align="))
+        {
+            let token = self.peek().cloned().unwrap();
+            arguments.align = match parse_integer(align) {
+                Some((None, align)) if align.is_power_of_two() => Some(align.trailing_zeros()),
+                _ => None,
+            }
+            .ok_or_else(|| token.error(format!("invalid alignment {}", token)))?;
+            self.next()?;
+        }
+        Ok(arguments)
+    }
+
+    pub fn parse_instruction(&mut self) -> Result<Instruction, ParseError> {
+        let mnemonic = self.next()?;
+        match mnemonic.atom() {
+            Some(name) => parse_instruction_named(self, name, &mnemonic),
+            None => Err(mnemonic.error(format!("expected an instruction, found {}", mnemonic))),
+        }
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = ParseError;
+
+    fn from_str(source: &str) -> Result<Instruction, ParseError> {
+        let mut parser = Parser::new(source)?;
+        let instruction = parser.parse_instruction()?;
+        parser.expect_end()?;
+        Ok(instruction)
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ValueType::I32 => "i32",
+            ValueType::I64 => "i64",
+            ValueType::F32 => "f32",
+            ValueType::F64 => "f64",
+        })
+    }
+}
+
+fn write_block(
+    f: &mut fmt::Formatter,
+    block_type: &BlockType,
+    bodies: &[&Vec<Instruction>],
+) -> fmt::Result {
+    if let BlockType::Value(value_type) = block_type {
+        write!(f, " (result {})", value_type)?;
+    }
+    for (i, body) in bodies.iter().enumerate() {
+        if i > 0 {
+            f.write_str("\nelse")?;
+        }
+        for instruction in body.iter() {
+            for line in instruction.to_string().lines() {
+                write!(f, "\n  {}", line)?;
+            }
+        }
+    }
+    f.write_str("\nend")
+}
+
+fn write_memory_arguments(
+    f: &mut fmt::Formatter,
+    instruction: &Instruction,
+    arguments: &MemoryArguments,
+) -> fmt::Result {
+    if arguments.offset != 0 {
+        write!(f, " offset={}", arguments.offset)?;
+    }
+    if Some(arguments.align) != instruction.natural_alignment() {
+        write!(f, " align={}", 1u64 << arguments.align)?;
+    }
+    Ok(())
+}
+
+macro_rules! define_text {
+    ($(
+        $name:ident $(($($field:ident: $type:ty),*))?
+            = $constant:ident $opcode:tt $prefix:ident $(. $suffix:tt)? $kind:ident
+                $align:tt $signature:tt $($traps:ident)?;
+    )*) => {
+        impl fmt::Display for Instruction {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                let instruction = self;
+                f.write_str(self.mnemonic())?;
+                match self {
+                    $(Instruction::$name $(($($field),*))? => {
+                        display_immediates!($kind f instruction $($($field)*)?)
+                    })*
+                }
+            }
+        }
+
+        fn parse_instruction_named(
+            parser: &mut Parser,
+            name: &str,
+            mnemonic: &Token,
+        ) -> Result<Instruction, ParseError> {
+            Ok(match name {
+                $(_ if name == concat!(stringify!($prefix) $(, ".", stringify!($suffix))?)
+                    && is_parsed!($kind) =>
+                {
+                    parse_immediates!($kind parser $name)
+                })*
+                _ => return Err(mnemonic.error(format!("unknown instruction {}", mnemonic))),
+            })
+        }
+    };
+}
+
+macro_rules! display_immediates {
+    (None $f:ident $instruction:ident) => {
+        Ok(())
+    };
+    (Memory $f:ident $instruction:ident) => {
+        Ok(())
+    };
+    (Block $f:ident $instruction:ident $block_type:ident $body:ident) => {
+        write_block($f, $block_type, &[$body])
+    };
+    (If $f:ident $instruction:ident $block_type:ident $body:ident) => {
+        write_block($f, $block_type, &[$body])
+    };
+    (IfElse $f:ident $instruction:ident $block_type:ident $then_body:ident $else_body:ident) => {
+        write_block($f, $block_type, &[$then_body, $else_body])
+    };
+    (LabelTable $f:ident $instruction:ident $labels:ident $default:ident) => {{
+        for label in $labels {
+            write!($f, " {}", label.0)?;
+        }
+        write!($f, " {}", $default.0)
+    }};
+    (CallIndirect $f:ident $instruction:ident $type_index:ident) => {
+        write!($f, " (type {})", $type_index.0)
+    };
+    (MemoryArguments $f:ident $instruction:ident $memarg:ident) => {
+        write_memory_arguments($f, $instruction, $memarg)
+    };
+    (I32 $f:ident $instruction:ident $value:ident) => {
+        write!($f, " {}", $value)
+    };
+    (I64 $f:ident $instruction:ident $value:ident) => {
+        write!($f, " {}", $value)
+    };
+    (F32 $f:ident $instruction:ident $value:ident) => {
+        write!($f, " {}", format_float($value.to_bits() as u64, 8, 23))
+    };
+    (F64 $f:ident $instruction:ident $value:ident) => {
+        write!($f, " {}", format_float($value.to_bits(), 11, 52))
+    };
+    // Label, Function, Local and Global
+    ($kind:ident $f:ident $instruction:ident $index:ident) => {
+        write!($f, " {}", $index.0)
+    };
+}
+
+/** `if` parses into `IfElse` too, when it has an `else` */
+macro_rules! is_parsed {
+    (IfElse) => {
+        false
+    };
+    ($kind:ident) => {
+        true
+    };
+}
+
+macro_rules! parse_immediates {
+    (None $parser:ident $name:ident) => {
+        Instruction::$name
+    };
+    (Memory $parser:ident $name:ident) => {
+        Instruction::$name
+    };
+    (Block $parser:ident $name:ident) => {{
+        let (block_type, body, _) = $parser.parse_block(false)?;
+        Instruction::$name(block_type, body)
+    }};
+    (If $parser:ident $name:ident) => {
+        match $parser.parse_block(true)? {
+            (block_type, body, None) => Instruction::If(block_type, body),
+            (block_type, then_body, Some(else_body)) => {
+                Instruction::IfElse(block_type, then_body, else_body)
+            }
+        }
+    };
+    (IfElse $parser:ident $name:ident) => {
+        parse_immediates!(If $parser $name)
+    };
+    (Label $parser:ident $name:ident) => {
+        Instruction::$name($parser.parse_label()?)
+    };
+    (LabelTable $parser:ident $name:ident) => {{
+        let mut labels = vec![$parser.parse_label()?];
+        while $parser.is_index_next() {
+            labels.push($parser.parse_label()?);
+        }
+        let default = labels.pop().unwrap();
+        Instruction::$name(labels, default)
+    }};
+    (Function $parser:ident $name:ident) => {
+        Instruction::$name(FunctionIndex($parser.parse_index(Space::Function)?))
+    };
+    (CallIndirect $parser:ident $name:ident) => {
+        Instruction::$name($parser.parse_type_use()?.0)
+    };
+    (Local $parser:ident $name:ident) => {
+        Instruction::$name(LocalIndex($parser.parse_index(Space::Local)?))
+    };
+    (Global $parser:ident $name:ident) => {
+        Instruction::$name(GlobalIndex($parser.parse_index(Space::Global)?))
+    };
+    (MemoryArguments $parser:ident $name:ident) => {{
+        let natural = Instruction::$name(MemoryArguments::new(0, 0))
+            .natural_alignment()
+            .unwrap();
+        Instruction::$name($parser.parse_memory_arguments(natural)?)
+    }};
+    (I32 $parser:ident $name:ident) => {
+        Instruction::$name($parser.parse_i32()?)
+    };
+    (I64 $parser:ident $name:ident) => {
+        Instruction::$name($parser.parse_i64()?)
+    };
+    (F32 $parser:ident $name:ident) => {
+        Instruction::$name($parser.parse_f32()?)
+    };
+    (F64 $parser:ident $name:ident) => {
+        Instruction::$name($parser.parse_f64()?)
+    };
+}
+
+crate::for_each_instruction!(define_text);
+
+/** Splits off a leading sign, returning whether it was a minus */
+fn split_sign(text: &str) -> (Option<bool>, &str) {
+    match text.as_bytes().first() {
+        Some(b'-') => (Some(true), &text[1..]),
+        Some(b'+') => (Some(false), &text[1..]),
+        _ => (None, text),
+    }
+}
+
+/** Whether the digits are well formed, with underscores only between them */
+fn is_digits(digits: &str, radix: u32) -> bool {
+    !digits.is_empty()
+        && !digits.starts_with('_')
+        && !digits.ends_with('_')
+        && !digits.contains("__")
+        && digits.chars().all(|c| c == '_' || c.is_digit(radix))
+}
+
+/** Digits in `radix`, with single underscores allowed between them */
+fn parse_digits(digits: &str, radix: u32) -> Option<u64> {
+    if !is_digits(digits, radix) {
+        return None;
+    }
+    u64::from_str_radix(&digits.replace('_', ""), radix).ok()
+}
+
+/**
+ * Parses a decimal or `0x` hexadecimal integer, returning its sign, if it has
+ * one, and its magnitude.
+ */
+pub(crate) fn parse_integer(text: &str) -> Option<(Option<bool>, u64)> {
+    let (sign, digits) = split_sign(text);
+    let magnitude = match digits.strip_prefix("0x") {
+        Some(hex) => parse_digits(hex, 16)?,
+        None => parse_digits(digits, 10)?,
+    };
+    Some((sign, magnitude))
+}
+
+/**
+ * Parses an integer of `bits` bits into its two's complement bit pattern.
+ * Unsigned values are in the unsigned range, and signed values in the signed
+ * range.
+ */
+fn parse_integer_with_width(text: &str, bits: u32) -> Option<u64> {
+    let (sign, magnitude) = parse_integer(text)?;
+    let signed_limit = 1u64 << (bits - 1);
+    let value = match sign {
+        None if bits == 64 || magnitude >> bits == 0 => magnitude,
+        Some(false) if magnitude < signed_limit => magnitude,
+        Some(true) if magnitude <= signed_limit => magnitude.wrapping_neg(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/**
+ * Parses a float into the bits of a format with `exponent_bits` and
+ * `mantissa_bits`. Hexadecimal floats are rounded to nearest, ties to even,
+ * and decimal floats are left to `parse_decimal`.
+ */
+fn parse_float(
+    text: &str,
+    exponent_bits: u32,
+    mantissa_bits: u32,
+    parse_decimal: impl Fn(&str) -> Option<u64>,
+) -> Option<u64> {
+    let (sign, unsigned) = split_sign(text);
+    let sign_bit = (sign == Some(true)) as u64;
+    let infinite_exponent = (1u64 << exponent_bits) - 1;
+    let bits = if unsigned == "inf" {
+        infinite_exponent << mantissa_bits
+    } else if unsigned == "nan" {
+        (infinite_exponent << mantissa_bits) | 1 << (mantissa_bits - 1)
+    } else if let Some(payload) = unsigned.strip_prefix("nan:0x") {
+        let payload = parse_digits(payload, 16)?;
+        if payload == 0 || payload >> mantissa_bits != 0 {
+            return None;
+        }
+        (infinite_exponent << mantissa_bits) | payload
+    } else if let Some(hex) = unsigned.strip_prefix("0x") {
+        parse_hex_float(hex, exponent_bits, mantissa_bits)?
+    } else {
+        let first = unsigned.chars().next()?;
+        let valid = first.is_ascii_digit()
+            && !unsigned.contains("__")
+            && unsigned
+                .chars()
+                .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-' | '_'));
+        if !valid {
+            return None;
+        }
+        let bits = parse_decimal(&unsigned.replace('_', ""))?;
+        // Rust parses overflowing decimals to infinity
+        if bits >> mantissa_bits == infinite_exponent {
+            return None;
+        }
+        bits
+    };
+    Some(sign_bit << (exponent_bits + mantissa_bits) | bits)
+}
+
+/** Parses the digits of a hexadecimal float after its `0x` */
+fn parse_hex_float(hex: &str, exponent_bits: u32, mantissa_bits: u32) -> Option<u64> {
+    let (digits, exponent) = match hex.find(['p', 'P']) {
+        Some(p) => {
+            let (sign, exponent) = split_sign(&hex[p + 1..]);
+            if !is_digits(exponent, 10) {
+                return None;
+            }
+            // Exponents too large for a u64 are still just out of range
+            let exponent = parse_digits(exponent, 10).unwrap_or(u64::MAX).min(1 << 20) as i64;
+            (
+                &hex[..p],
+                if sign == Some(true) {
+                    -exponent
+                } else {
+                    exponent
+                },
+            )
+        }
+        None => (hex, 0),
+    };
+    let (integer, fraction) = match digits.find('.') {
+        Some(dot) => (&digits[..dot], &digits[dot + 1..]),
+        None => (digits, ""),
+    };
+    if integer.is_empty() || integer.starts_with('_') || integer.ends_with('_') {
+        return None;
+    }
+    if fraction.starts_with('_') || fraction.ends_with('_') || digits.contains("__") {
+        return None;
+    }
+
+    // The value is mantissa * 2^exponent, with any digits that don't fit in
+    // the mantissa kept as a sticky bit for rounding
+    let mut mantissa: u128 = 0;
+    let mut exponent = exponent;
+    let mut sticky = false;
+    for (i, c) in integer.chars().chain(fraction.chars()).enumerate() {
+        if c == '_' {
+            continue;
+        }
+        let digit = c.to_digit(16)? as u128;
+        let is_fraction = i >= integer.len();
+        if mantissa >> 124 == 0 {
+            mantissa = mantissa << 4 | digit;
+            if is_fraction {
+                exponent -= 4;
+            }
+        } else {
+            sticky |= digit != 0;
+            if !is_fraction {
+                exponent += 4;
+            }
+        }
+    }
+    if mantissa == 0 {
+        return Some(0);
+    }
+
+    let bias = (1i64 << (exponent_bits - 1)) - 1;
+    let minimum_exponent = 1 - bias;
+    let top_exponent = 127 - mantissa.leading_zeros() as i64 + exponent;
+    let mut kept_exponent = top_exponent.max(minimum_exponent);
+    let shift = kept_exponent - mantissa_bits as i64 - exponent;
+    let mut kept = if shift <= 0 {
+        mantissa << -shift
+    } else if shift > 128 {
+        0
+    } else {
+        let kept = if shift == 128 { 0 } else { mantissa >> shift };
+        let remainder = if shift == 128 {
+            mantissa
+        } else {
+            mantissa & ((1 << shift) - 1)
+        };
+        let half = 1u128 << (shift - 1);
+        let round_up = remainder > half || (remainder == half && (sticky || kept & 1 == 1));
+        kept + round_up as u128
+    };
+    if kept >> (mantissa_bits + 1) != 0 {
+        kept >>= 1;
+        kept_exponent += 1;
+    }
+    let mantissa_mask = (1u128 << mantissa_bits) - 1;
+    let biased_exponent = if kept >> mantissa_bits == 0 {
+        0
+    } else {
+        kept_exponent + bias
+    };
+    if biased_exponent >= (1 << exponent_bits) - 1 {
+        return None;
+    }
+    Some((biased_exponent as u64) << mantissa_bits | (kept & mantissa_mask) as u64)
+}
+
+/**
+ * Formats the bits of a float with `exponent_bits` and `mantissa_bits` as a
+ * hexadecimal float, which is exact, unlike decimal.
+ */
+pub(crate) fn format_float(bits: u64, exponent_bits: u32, mantissa_bits: u32) -> String {
+    let sign = if bits >> (exponent_bits + mantissa_bits) & 1 == 1 {
+        "-"
+    } else {
+        ""
+    };
+    let biased_exponent = (bits >> mantissa_bits) & ((1 << exponent_bits) - 1);
+    let mantissa = bits & ((1 << mantissa_bits) - 1);
+    let bias = (1i64 << (exponent_bits - 1)) - 1;
+    let body = if biased_exponent == (1 << exponent_bits) - 1 {
+        if mantissa == 0 {
+            "inf".to_string()
+        } else if mantissa == 1 << (mantissa_bits - 1) {
+            "nan".to_string()
+        } else {
+            format!("nan:{:#x}", mantissa)
+        }
+    } else if biased_exponent == 0 && mantissa == 0 {
+        "0x0p+0".to_string()
+    } else {
+        let (leading, exponent) = match biased_exponent {
+            0 => (0, 1 - bias),
+            _ => (1, biased_exponent as i64 - bias),
+        };
+        let digit_count = (mantissa_bits as usize).div_ceil(4);
+        let padded = mantissa << (digit_count * 4 - mantissa_bits as usize);
+        let digits = format!("{:0width$x}", padded, width = digit_count);
+        let digits = digits.trim_end_matches('0');
+        let dot = if digits.is_empty() { "" } else { "." };
+        format!("0x{}{}{}p{:+}", leading, dot, digits, exponent)
+    };
+    format!("{}{}", sign, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            I32Load(MemoryArguments::new(4, 1)).to_string(),
+            "i32.load offset=4 align=2"
+        );
+        assert_eq!(I64Load(MemoryArguments::new(0, 3)).to_string(), "i64.load");
+        assert_eq!(
+            BranchTable(vec![LabelIndex(0), LabelIndex(1)], LabelIndex(2)).to_string(),
+            "br_table 0 1 2"
+        );
+        assert_eq!(Call(FunctionIndex(3)).to_string(), "call 3");
+        assert_eq!(F64Const(3.0).to_string(), "f64.const 0x1.8p+1");
+        assert_eq!(
+            CallIndirect(TypeIndex(1)).to_string(),
+            "call_indirect (type 1)"
+        );
+        assert_eq!(I32Const(-7).to_string(), "i32.const -7");
+        assert_eq!(F32Const(-0.0).to_string(), "f32.const -0x0p+0");
+    }
+
+    #[test]
+    fn test_nested_blocks_round_trip() {
+        let instruction = Block(
+            BlockType::Value(ValueType::I32),
+            vec![
+                LocalGet(LocalIndex(0)),
+                IfElse(
+                    BlockType::Empty,
+                    vec![Loop(BlockType::Empty, vec![Branch(LabelIndex(0))])],
+                    vec![If(BlockType::Empty, vec![Nop])],
+                ),
+                I32Const(1),
+            ],
+        );
+        let text = instruction.to_string();
+        assert_eq!(
+            text,
+            "block (result i32)\n  local.get 0\n  if\n    loop\n      br 0\n    end\n  else\n    \
+             if\n      nop\n    end\n  end\n  i32.const 1\nend"
+        );
+        assert_eq!(text.parse::<Instruction>(), Ok(instruction));
+    }
+
+    #[test]
+    fn test_parse_numbers() {
+        assert_eq!("i32.const 0xffffffff".parse(), Ok(I32Const(-1)));
+        assert_eq!("i32.const -2147483648".parse(), Ok(I32Const(i32::MIN)));
+        assert_eq!("i64.const 1_000".parse(), Ok(I64Const(1000)));
+        assert!("i32.const 4294967296".parse::<Instruction>().is_err());
+        assert!("i32.const +2147483648".parse::<Instruction>().is_err());
+
+        assert_eq!("f64.const 0x1.8p+1".parse(), Ok(F64Const(3.0)));
+        assert_eq!("f32.const -1.5e3".parse(), Ok(F32Const(-1500.0)));
+        assert_eq!(
+            "f64.const 0x1p-1074".parse(),
+            Ok(F64Const(f64::from_bits(1)))
+        );
+        assert_eq!("f32.const 0x1.000001p0".parse(), Ok(F32Const(1.0)));
+        assert!("f32.const 0x1p128".parse::<Instruction>().is_err());
+        match "f32.const -nan:0x200".parse() {
+            Ok(F32Const(value)) => assert_eq!(value.to_bits(), 0xff80_0200),
+            other => panic!("{:?}", other),
+        }
+
+        for bits in [
+            1,
+            0x000f_ffff_ffff_ffff,
+            0x7fef_ffff_ffff_ffff,
+            0x7ff0_0000_0000_0001,
+        ] {
+            let text = format_float(bits, 11, 52);
+            assert_eq!(parse_float(&text, 11, 52, |_| None), Some(bits), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "i32.const 1 (; comment ;)\n  i32.add".parse::<Instruction>(),
+            Err(ParseError {
+                line: 2,
+                column: 3,
+                message: "unexpected `i32.add`".into(),
+            })
+        );
+        assert_eq!(
+            "block\n  nop".parse::<Instruction>(),
+            Err(ParseError {
+                line: 2,
+                column: 6,
+                message: "expected `end`".into(),
+            })
+        );
+        assert_eq!(
+            "i64.frobnicate".parse::<Instruction>().unwrap_err().message,
+            "unknown instruction `i64.frobnicate`"
+        );
+        assert_eq!(
+            "i32.load align=3"
+                .parse::<Instruction>()
+                .unwrap_err()
+                .to_string(),
+            "1:10: invalid alignment `align=3`"
+        );
+        assert!("f32.const 0x1p-".parse::<Instruction>().is_err());
+        assert!("f32.const 0x1p".parse::<Instruction>().is_err());
+    }
+}
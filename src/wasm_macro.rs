@@ -0,0 +1,314 @@
+/*!
+ * The `wasm!` macro, which writes instruction sequences with the mnemonics of
+ * the WebAssembly text format.
+ */
+use crate::{
+    expression::{Instruction, MemoryArguments},
+    index::LabelIndex,
+};
+
+/**
+ * Builds a `Vec<Instruction>` from WAT-style mnemonics, one instruction per
+ * `;`-terminated line:
+ *
+ * ```
+ * use wasmuter::{index::LocalIndex, wasm};
+ *
+ * let counter = LocalIndex(0);
+ * let instructions = wasm! {
+ *     block;
+ *         loop;
+ *             local.get counter;
+ *             i32.eqz;
+ *             br_if 1;
+ *             local.get counter;
+ *             i32.const 1;
+ *             i32.sub;
+ *             local.set counter;
+ *             br 0;
+ *         end;
+ *     end;
+ *     i32.const 0;
+ *     i32.load offset=4 align=4;
+ * };
+ * assert_eq!(instructions.len(), 3);
+ * ```
+ *
+ * Immediates can be literals or any Rust expression, and indices accept both
+ * `u32`s and the index types. `block`, `loop` and `if` take an optional
+ * `(result <type>)`, and are closed by `end` (with an `else` in between for
+ * `if`). Memory instructions take WAT's `offset=` and `align=`, where the
+ * alignment is in bytes; expressions there have to be parenthesized.
+ * `br_table` takes its labels either as literals (`br_table 0 1 2`), or as a
+ * bracketed list followed by the default label (`br_table [a, b] c`).
+ *
+ * Every line is a step of macro recursion, so very long sequences may need a
+ * higher `#![recursion_limit]`.
+ */
+#[macro_export]
+macro_rules! wasm {
+    // Each step of @parse takes the instructions of the innermost open block,
+    // the stack of enclosing blocks, and the remaining input.
+    (@parse [$($acc:tt)*] []) => {
+        vec![$($acc)*]
+    };
+    (@parse [$($acc:tt)*] [$($stack:tt)*] ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($acc)*] [$($stack)*] $($rest)*)
+    };
+
+    // Structured control instructions
+    (@parse [$($acc:tt)*] [$($stack:tt)*] block (result $value_type:ident) ; $($rest:tt)*) => {
+        $crate::wasm!(@open block ($crate::expression::BlockType::Value($crate::wasm!(@value_type $value_type)))
+            [$($acc)*] [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [$($stack:tt)*] loop (result $value_type:ident) ; $($rest:tt)*) => {
+        $crate::wasm!(@open loop ($crate::expression::BlockType::Value($crate::wasm!(@value_type $value_type)))
+            [$($acc)*] [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [$($stack:tt)*] if (result $value_type:ident) ; $($rest:tt)*) => {
+        $crate::wasm!(@open if ($crate::expression::BlockType::Value($crate::wasm!(@value_type $value_type)))
+            [$($acc)*] [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [$($stack:tt)*] block ; $($rest:tt)*) => {
+        $crate::wasm!(@open block ($crate::expression::BlockType::Empty) [$($acc)*] [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [$($stack:tt)*] loop ; $($rest:tt)*) => {
+        $crate::wasm!(@open loop ($crate::expression::BlockType::Empty) [$($acc)*] [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [$($stack:tt)*] if ; $($rest:tt)*) => {
+        $crate::wasm!(@open if ($crate::expression::BlockType::Empty) [$($acc)*] [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [(if $block_type:tt [$($outer:tt)*]) $($stack:tt)*] else ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [] [(else $block_type [$($outer)*] [$($acc)*]) $($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [(block $block_type:tt [$($outer:tt)*]) $($stack:tt)*] end ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($outer)* $crate::expression::Instruction::Block($block_type, vec![$($acc)*]),]
+            [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [(loop $block_type:tt [$($outer:tt)*]) $($stack:tt)*] end ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($outer)* $crate::expression::Instruction::Loop($block_type, vec![$($acc)*]),]
+            [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [(if $block_type:tt [$($outer:tt)*]) $($stack:tt)*] end ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($outer)* $crate::expression::Instruction::If($block_type, vec![$($acc)*]),]
+            [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [(else $block_type:tt [$($outer:tt)*] [$($then:tt)*]) $($stack:tt)*] end ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($outer)* $crate::expression::Instruction::IfElse(
+            $block_type, vec![$($then)*], vec![$($acc)*]),] [$($stack)*] $($rest)*)
+    };
+    (@open $kind:tt $block_type:tt [$($acc:tt)*] [$($stack:tt)*] $($rest:tt)*) => {
+        $crate::wasm!(@parse [] [($kind $block_type [$($acc)*]) $($stack)*] $($rest)*)
+    };
+
+    // Instructions with a `prefix.name` mnemonic
+    (@parse [$($acc:tt)*] [$($stack:tt)*] $prefix:ident . $name:tt ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($acc)* $crate::wasm!(@instruction op ($prefix . $name) ()),] [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [$($stack:tt)*] $prefix:ident . $name:tt offset = $offset:tt align = $align:tt ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($acc)* $crate::wasm!(@instruction mem ($prefix . $name) ($offset (Some($align)))),] [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [$($stack:tt)*] $prefix:ident . $name:tt offset = $offset:tt ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($acc)* $crate::wasm!(@instruction mem ($prefix . $name) ($offset None)),] [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [$($stack:tt)*] $prefix:ident . $name:tt align = $align:tt ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($acc)* $crate::wasm!(@instruction mem ($prefix . $name) (0 (Some($align)))),] [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [$($stack:tt)*] $prefix:ident . $name:tt $immediate:expr ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($acc)* $crate::wasm!(@instruction immediate ($prefix . $name) ($immediate)),] [$($stack)*] $($rest)*)
+    };
+
+    // Instructions with a single-word mnemonic
+    (@parse [$($acc:tt)*] [$($stack:tt)*] br_table [$($label:expr),* $(,)?] $default:expr ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($acc)* $crate::expression::Instruction::BranchTable(
+            vec![$(::std::convert::Into::into($label)),*], ::std::convert::Into::into($default)),]
+            [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [$($stack:tt)*] br_table $($label:literal)+ ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($acc)* $crate::wasm_macro::branch_table(vec![$($label),+]),] [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [$($stack:tt)*] $name:ident ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($acc)* $crate::wasm!(@instruction op ($name) ()),] [$($stack)*] $($rest)*)
+    };
+    (@parse [$($acc:tt)*] [$($stack:tt)*] $name:ident $immediate:expr ; $($rest:tt)*) => {
+        $crate::wasm!(@parse [$($acc)* $crate::wasm!(@instruction immediate ($name) ($immediate)),] [$($stack)*] $($rest)*)
+    };
+
+    (@value_type i32) => { $crate::function_type::ValueType::I32 };
+    (@value_type i64) => { $crate::function_type::ValueType::I64 };
+    (@value_type f32) => { $crate::function_type::ValueType::F32 };
+    (@value_type f64) => { $crate::function_type::ValueType::F64 };
+
+    // Finds the row of `for_each_instruction!` with the mnemonic, and builds
+    // its instruction from the arguments in the way `$mode` says
+    (@instruction $mode:ident $mnemonic:tt $arguments:tt) => {
+        $crate::for_each_instruction!($crate::wasm, @lookup $mode $mnemonic $arguments)
+    };
+    (@lookup $mode:ident ($($mnemonic:tt)+) $arguments:tt $(
+        $name:ident $(($($field:ident: $type:ty),*))?
+            = $constant:ident $opcode:tt $prefix:ident $(. $suffix:tt)? $kind:ident
+                $align:tt $signature:tt $($traps:ident)?;
+    )*) => {{
+        macro_rules! lookup {
+            $(($prefix $(. $suffix)?) => {
+                $crate::wasm!(@$mode $kind $name $align $arguments)
+            };)*
+        }
+        lookup!($($mnemonic)+)
+    }};
+
+    (@op None $name:ident $align:tt ()) => { $crate::expression::Instruction::$name };
+    (@op Memory $name:ident $align:tt ()) => { $crate::expression::Instruction::$name };
+    (@op MemoryArguments $name:ident $align:tt ()) => {
+        $crate::wasm!(@mem MemoryArguments $name $align (0 None))
+    };
+    (@mem MemoryArguments $name:ident $natural_align:tt ($offset:tt $align:tt)) => {
+        $crate::expression::Instruction::$name(
+            $crate::wasm_macro::memory_arguments($offset, $align, $natural_align))
+    };
+    (@immediate Label $name:ident $align:tt ($index:expr)) => { $crate::wasm!(@index $name $index) };
+    (@immediate Function $name:ident $align:tt ($index:expr)) => { $crate::wasm!(@index $name $index) };
+    (@immediate CallIndirect $name:ident $align:tt ($index:expr)) => { $crate::wasm!(@index $name $index) };
+    (@immediate Local $name:ident $align:tt ($index:expr)) => { $crate::wasm!(@index $name $index) };
+    (@immediate Global $name:ident $align:tt ($index:expr)) => { $crate::wasm!(@index $name $index) };
+    (@immediate I32 $name:ident $align:tt ($value:expr)) => { $crate::expression::Instruction::$name($value) };
+    (@immediate I64 $name:ident $align:tt ($value:expr)) => { $crate::expression::Instruction::$name($value) };
+    (@immediate F32 $name:ident $align:tt ($value:expr)) => { $crate::expression::Instruction::$name($value) };
+    (@immediate F64 $name:ident $align:tt ($value:expr)) => { $crate::expression::Instruction::$name($value) };
+    (@index $name:ident $index:expr) => {
+        $crate::expression::Instruction::$name(::std::convert::Into::into($index))
+    };
+
+    ($($body:tt)*) => {
+        $crate::wasm!(@parse [] [] $($body)* ;)
+    };
+}
+
+/** Converts WAT's memory immediates, where alignment is in bytes */
+#[doc(hidden)]
+pub fn memory_arguments(offset: u32, align: Option<u32>, natural_align: u32) -> MemoryArguments {
+    let align = match align {
+        Some(align) => {
+            assert!(align.is_power_of_two(), "alignment must be a power of two");
+            align.trailing_zeros()
+        }
+        None => natural_align,
+    };
+    MemoryArguments::new(offset, align)
+}
+
+/** Splits WAT's `br_table` labels into the table and its default label */
+#[doc(hidden)]
+pub fn branch_table(mut labels: Vec<u32>) -> Instruction {
+    let default = labels.pop().unwrap();
+    Instruction::BranchTable(
+        labels.into_iter().map(LabelIndex).collect(),
+        LabelIndex(default),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        expression::{BlockType, Instruction::*, MemoryArguments},
+        function_type::ValueType,
+        index::{FunctionIndex, GlobalIndex, LabelIndex, LocalIndex, TypeIndex},
+    };
+
+    #[test]
+    fn test_flat_instructions() {
+        let function_index = FunctionIndex(3);
+        let base = 8;
+        let offset = 8;
+        assert_eq!(
+            wasm! {
+                i32.const 0;
+                i32.const base + 4;
+                i32.store offset=0 align=4;
+                local.get 1;
+                f64.convert_i32_u;
+                f64.const -1.5;
+                f64.mul;
+                i64.load8_u offset=(offset * 2);
+                call function_index;
+                drop;
+            },
+            vec![
+                I32Const(0),
+                I32Const(12),
+                I32Store(MemoryArguments::new(0, 2)),
+                LocalGet(LocalIndex(1)),
+                F64ConvertI32U,
+                F64Const(-1.5),
+                F64Mul,
+                I64Load8U(MemoryArguments::new(16, 0)),
+                Call(FunctionIndex(3)),
+                Drop,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_immediate_kinds() {
+        let global_index = GlobalIndex(1);
+        assert_eq!(
+            wasm! {
+                memory.size;
+                memory.grow;
+                i64.const 1 << 40;
+                global.set global_index;
+                call_indirect 2;
+                i32.trunc_sat_f32_u;
+                f32.store align=1;
+                return;
+            },
+            vec![
+                MemorySize,
+                MemoryGrow,
+                I64Const(1 << 40),
+                GlobalSet(GlobalIndex(1)),
+                CallIndirect(TypeIndex(2)),
+                I32TruncSatF32U,
+                F32Store(MemoryArguments::new(0, 0)),
+                Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_blocks() {
+        assert_eq!(
+            wasm! {
+                block (result i32);
+                    local.get 0;
+                    if;
+                        br 1;
+                    else;
+                        loop;
+                            br_table 0 1 0;
+                        end;
+                    end;
+                    i32.const 1;
+                end;
+            },
+            vec![Block(
+                BlockType::Value(ValueType::I32),
+                vec![
+                    LocalGet(LocalIndex(0)),
+                    IfElse(
+                        BlockType::Empty,
+                        vec![Branch(LabelIndex(1))],
+                        vec![Loop(
+                            BlockType::Empty,
+                            vec![BranchTable(
+                                vec![LabelIndex(0), LabelIndex(1)],
+                                LabelIndex(0)
+                            )],
+                        )],
+                    ),
+                    I32Const(1),
+                ],
+            )]
+        );
+    }
+}
@@ -0,0 +1,260 @@
+/*!
+ * A runner for the `.wast` scripts of the WebAssembly spec test suite.
+ *
+ * Every module in a script is encoded, decoded and encoded again, and has to
+ * come out the same. A binary module is decoded first, and only its
+ * re-encoding has to be stable, since its own bytes may pad their LEBs.
+ * Every malformed module has to be rejected by the text parser or the
+ * decoder. Commands that need a validator or an engine to run code are
+ * skipped.
+ */
+use std::fmt;
+
+use crate::{
+    decoder::{WasmDecode, WasmDecoder},
+    encoder::{WasmEncode, WasmEncoder},
+    module::Module,
+    text::{ParseError, Parser, TokenKind},
+    wat::{parse_fields, parse_module, parse_strings, skip_list},
+};
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    /** The command needs something wasmuter doesn't have */
+    Skipped(&'static str),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CommandResult {
+    pub line: usize,
+    pub column: usize,
+    pub command: String,
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for CommandResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {} ", self.line, self.column, self.command)?;
+        match &self.outcome {
+            Outcome::Passed => write!(f, "passed"),
+            Outcome::Failed(reason) => write!(f, "failed: {}", reason),
+            Outcome::Skipped(reason) => write!(f, "skipped: {}", reason),
+        }
+    }
+}
+
+enum ScriptModule {
+    Text(Result<Module, ParseError>),
+    Binary(Vec<u8>),
+    Quote(String),
+}
+
+impl ScriptModule {
+    fn into_module(self) -> Result<Module, String> {
+        match self {
+            ScriptModule::Text(module) => module.map_err(|error| error.to_string()),
+            ScriptModule::Quote(source) => parse_module(&source).map_err(|e| e.to_string()),
+            ScriptModule::Binary(bytes) => decode(&bytes),
+        }
+    }
+}
+
+fn encode(module: &Module) -> Vec<u8> {
+    let mut encoder = WasmEncoder::new();
+    module.encode(&mut encoder);
+    encoder.as_slice().to_vec()
+}
+
+fn decode(bytes: &[u8]) -> Result<Module, String> {
+    Module::decode(&mut WasmDecoder::new(bytes)).map_err(|error| error.to_string())
+}
+
+/**
+ * Runs every command in a script. Errors in a command are reported as its
+ * failure, and only a script that can't be split into commands is an error.
+ */
+pub fn run_script(source: &str) -> Result<Vec<CommandResult>, ParseError> {
+    let mut parser = Parser::new(source)?;
+    let mut results = vec![];
+    while parser.peek().is_some() {
+        let open = parser.expect(TokenKind::LeftParen)?;
+        let command = parser.next()?;
+        let start = parser.position();
+        let outcome = match run_command(&mut parser, command.atom().unwrap_or_default()) {
+            Ok(outcome) => outcome,
+            Err(error) => {
+                parser.seek(start);
+                skip_list(&mut parser)?;
+                Outcome::Failed(error.to_string())
+            }
+        };
+        results.push(CommandResult {
+            line: open.line,
+            column: open.column,
+            command: command.atom().unwrap_or_default().to_string(),
+            outcome,
+        });
+    }
+    Ok(results)
+}
+
+/** Runs a command after its name, up to and including its `)` */
+fn run_command(parser: &mut Parser, command: &str) -> Result<Outcome, ParseError> {
+    match command {
+        "module" => {
+            let outcome = match parse_script_module(parser)? {
+                ScriptModule::Binary(bytes) => check_round_trip(&bytes),
+                module => match module.into_module() {
+                    Ok(module) => check_round_trip(&encode(&module)),
+                    Err(error) => Outcome::Failed(error),
+                },
+            };
+            Ok(outcome)
+        }
+        "assert_malformed" => {
+            parser.expect(TokenKind::LeftParen)?;
+            parser.expect_keyword("module")?;
+            let module = parse_script_module(parser)?;
+            let message = parse_strings(parser)?;
+            parser.expect(TokenKind::RightParen)?;
+            Ok(match module.into_module() {
+                Ok(_) => Outcome::Failed(format!(
+                    "accepted a module that should be malformed: {}",
+                    String::from_utf8_lossy(&message)
+                )),
+                Err(_) => Outcome::Passed,
+            })
+        }
+        "assert_invalid" => {
+            skip_list(parser)?;
+            Ok(Outcome::Skipped("needs a validator"))
+        }
+        "assert_return"
+        | "assert_trap"
+        | "assert_exhaustion"
+        | "assert_unlinkable"
+        | "assert_uninstantiable"
+        | "invoke"
+        | "get"
+        | "register" => {
+            skip_list(parser)?;
+            Ok(Outcome::Skipped("needs an engine"))
+        }
+        _ => Err(parser.error(format!("unknown command `{}`", command))),
+    }
+}
+
+/** Parses a module after its `(module`, up to and including its `)` */
+fn parse_script_module(parser: &mut Parser) -> Result<ScriptModule, ParseError> {
+    parser.take_id();
+    let module = match parser.peek_atom() {
+        Some("binary") => {
+            parser.next()?;
+            ScriptModule::Binary(parse_strings(parser)?)
+        }
+        Some("quote") => {
+            parser.next()?;
+            let source = parse_strings(parser)?;
+            ScriptModule::Quote(String::from_utf8_lossy(&source).into_owned())
+        }
+        _ => {
+            // A module that doesn't parse is skipped over, so it can be
+            // reported without stopping the script
+            let position = parser.position();
+            let module = parse_fields(parser, None).and_then(|module| {
+                parser.expect(TokenKind::RightParen)?;
+                Ok(module)
+            });
+            if module.is_err() {
+                parser.seek(position);
+                skip_list(parser)?;
+            }
+            return Ok(ScriptModule::Text(module));
+        }
+    };
+    parser.expect(TokenKind::RightParen)?;
+    Ok(module)
+}
+
+/**
+ * Checks that a module's bytes decode, and that re-encoding the decoded module
+ * is stable: its encoding decodes and encodes to the same bytes again. The
+ * first encoding can differ from `bytes`, which may pad their LEBs.
+ */
+fn check_round_trip(bytes: &[u8]) -> Outcome {
+    let encoded = match decode(bytes) {
+        Ok(decoded) => encode(&decoded),
+        Err(error) => return Outcome::Failed(format!("couldn't decode: {}", error)),
+    };
+    match decode(&encoded) {
+        Ok(decoded) if encode(&decoded) == encoded => Outcome::Passed,
+        Ok(_) => Outcome::Failed("decoded module encodes differently".into()),
+        Err(error) => Outcome::Failed(format!("couldn't decode its encoding: {}", error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_script() {
+        let results = run_script(
+            r#"
+            (module $m (func (export "f") (result i32) (i32.const 1)))
+            (assert_return (invoke "f") (i32.const 1))
+            (module binary "\00asm" "\01\00\00\00" "\05\03\01\00\02")
+            (module quote "(memory 1)")
+            (assert_malformed (module quote "(func (i32.const))") "unexpected token")
+            (assert_malformed (module binary "\00asm\02\00\00\00") "unknown binary version")
+            (assert_malformed (module quote "(memory 1)") "wrong on purpose")
+            (assert_invalid (module (func (i32.add))) "type mismatch")
+            (module (func (i32.frobnicate)))
+            (module)
+            "#,
+        )
+        .unwrap();
+        let outcomes: Vec<&Outcome> = results.iter().map(|result| &result.outcome).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                &Outcome::Passed,
+                &Outcome::Skipped("needs an engine"),
+                &Outcome::Passed,
+                &Outcome::Passed,
+                &Outcome::Passed,
+                &Outcome::Passed,
+                &Outcome::Failed(
+                    "accepted a module that should be malformed: wrong on purpose".into()
+                ),
+                &Outcome::Skipped("needs a validator"),
+                &Outcome::Failed("10:28: unknown instruction `i32.frobnicate`".into()),
+                &Outcome::Passed,
+            ]
+        );
+        assert_eq!(
+            results[6].to_string(),
+            "8:13: assert_malformed failed: accepted a module that should be malformed: wrong \
+             on purpose"
+        );
+    }
+
+    #[test]
+    fn test_binary_module_round_trip() {
+        // The section size and the memory's limit are padded LEBs, which the
+        // encoder doesn't reproduce, but its own encoding has to be stable
+        let results = run_script(
+            r#"
+            (module binary "\00asm" "\01\00\00\00" "\05\04\01" "\00\82\00")
+            (module binary "\00asm" "\01\00\00\00" "\05\83\80\80\80\00\01\00\02")
+            (module binary "\00asm" "\01\00\00\00" "\05\03\01\00")
+            "#,
+        )
+        .unwrap();
+        assert_eq!(results[0].outcome, Outcome::Passed);
+        assert_eq!(results[1].outcome, Outcome::Passed);
+        assert!(matches!(results[2].outcome, Outcome::Failed(_)));
+    }
+}
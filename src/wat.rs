@@ -0,0 +1,1375 @@
+/*!
+ * Whole modules in the WebAssembly text format.
+ *
+ * Printing follows every definition with a `(;N;)` comment with its index,
+ * counting imports first, like the binary format does. Function bodies are
+ * printed with the flat instruction syntax, and constant expressions folded.
+ *
+ * Parsing accepts `$names` for every index space and for labels, inline
+ * exports, imports, table elements and memory data, and both the flat and
+ * folded instruction syntax. The `$names` are kept in a `name` section.
+ *
+ * Custom sections are `(@custom "name" (after func) "payload")` annotations,
+ * which say where in the binary the section goes.
+ */
+use std::{
+    convert::TryFrom,
+    fmt::{self, Write},
+    str::FromStr,
+};
+
+use crate::{
+    constants::PAGE_SIZE,
+    expression::{Expression, Instruction},
+    function_type::{FunctionType, ValueType},
+    index::{FunctionIndex, GlobalIndex, MemoryIndex, TableIndex, TypeIndex},
+    limits::Limits,
+    module::Module,
+    section::{
+        code_section::{CodeSection, Function, Local},
+        data_section::{Data, DataSection},
+        element_section::{Element, ElementSection},
+        export_section::{Export, ExportDescriptor, ExportSection},
+        function_section::FunctionSection,
+        global_section::{Global, GlobalSection, GlobalType, Mutability},
+        import_section::{Import, ImportDescriptor, ImportSection},
+        memory_section::{Memory, MemorySection},
+        start_section::StartSection,
+        table_section::{ElementType, Table, TableSection},
+        type_section::TypeSection,
+        Section,
+    },
+    text::{Names, ParseError, Parser, Space, Token, TokenKind},
+};
+
+/** The keywords a custom section annotation places it with, by section id */
+const SECTION_KEYWORDS: [&str; 12] = [
+    "", "type", "import", "func", "table", "memory", "global", "export", "start", "elem", "code",
+    "data",
+];
+
+/** Quotes bytes as a string, escaping anything that isn't printable ASCII */
+pub fn escape_string(bytes: &[u8]) -> String {
+    let mut escaped = String::from("\"");
+    for byte in bytes {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            0x20..=0x7e => escaped.push(*byte as char),
+            _ => write!(escaped, "\\{:02x}", byte).unwrap(),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+struct Printer<'a> {
+    types: Vec<&'a FunctionType>,
+    /** The type of each function defined in the code section */
+    function_types: Vec<TypeIndex>,
+    function_count: u32,
+    table_count: u32,
+    memory_count: u32,
+    global_count: u32,
+    /** The id of the last section that wasn't a custom section */
+    previous_section: Option<u8>,
+}
+
+impl<'a> Printer<'a> {
+    fn new(module: &'a Module) -> Printer<'a> {
+        let mut printer = Printer {
+            types: vec![],
+            function_types: vec![],
+            function_count: 0,
+            table_count: 0,
+            memory_count: 0,
+            global_count: 0,
+            previous_section: None,
+        };
+        for section in module.0.iter() {
+            match section {
+                Section::TypeSection(section) => printer.types.extend(section.0.iter()),
+                Section::FunctionSection(section) => {
+                    printer.function_types.extend(section.0.iter().copied())
+                }
+                _ => {}
+            }
+        }
+        printer
+    }
+
+    fn next_index(count: &mut u32) -> u32 {
+        *count += 1;
+        *count - 1
+    }
+
+    /** `(type N)`, followed by the parameters and results if the type exists */
+    fn type_use(&self, type_index: TypeIndex) -> String {
+        let mut text = format!("(type {})", type_index.0);
+        if let Some(function_type) = self.types.get(type_index.0 as usize) {
+            text.push_str(&signature(function_type));
+        }
+        text
+    }
+
+    fn write_section(&mut self, f: &mut fmt::Formatter, section: &Section) -> fmt::Result {
+        match section {
+            Section::Custom { name, payload } => {
+                let placement = match self.previous_section {
+                    Some(id) => format!("(after {})", SECTION_KEYWORDS[id as usize]),
+                    None => "(before first)".to_string(),
+                };
+                write!(
+                    f,
+                    "\n  (@custom {} {} {})",
+                    escape_string(name.as_bytes()),
+                    placement,
+                    escape_string(payload)
+                )?;
+                return Ok(());
+            }
+            Section::TypeSection(section) => {
+                for (i, function_type) in section.0.iter().enumerate() {
+                    write!(f, "\n  (type (;{};) (func{}))", i, signature(function_type))?;
+                }
+            }
+            Section::ImportSection(section) => {
+                for import in section.0.iter() {
+                    let descriptor = match &import.descriptor {
+                        ImportDescriptor::TypeIndex(type_index) => format!(
+                            "(func (;{};) {})",
+                            Self::next_index(&mut self.function_count),
+                            self.type_use(*type_index)
+                        ),
+                        ImportDescriptor::TableType(table) => format!(
+                            "(table (;{};) {})",
+                            Self::next_index(&mut self.table_count),
+                            table_type(table)
+                        ),
+                        ImportDescriptor::MemoryType(memory) => format!(
+                            "(memory (;{};) {})",
+                            Self::next_index(&mut self.memory_count),
+                            limits(&memory.limits)
+                        ),
+                        ImportDescriptor::GlobalType(global_type) => format!(
+                            "(global (;{};) {})",
+                            Self::next_index(&mut self.global_count),
+                            global_type_text(global_type)
+                        ),
+                    };
+                    write!(
+                        f,
+                        "\n  (import {} {} {})",
+                        escape_string(import.module_name.as_bytes()),
+                        escape_string(import.name.as_bytes()),
+                        descriptor
+                    )?;
+                }
+            }
+            // Printed along with the code section
+            Section::FunctionSection(_) => {}
+            Section::TableSection(section) => {
+                for table in section.0.iter() {
+                    let index = Self::next_index(&mut self.table_count);
+                    write!(f, "\n  (table (;{};) {})", index, table_type(table))?;
+                }
+            }
+            Section::MemorySection(section) => {
+                for memory in section.0.iter() {
+                    let index = Self::next_index(&mut self.memory_count);
+                    write!(f, "\n  (memory (;{};) {})", index, limits(&memory.limits))?;
+                }
+            }
+            Section::GlobalSection(section) => {
+                for global in section.0.iter() {
+                    let index = Self::next_index(&mut self.global_count);
+                    let (Global::Const(_, initializer) | Global::Var(_, initializer)) = global;
+                    write!(
+                        f,
+                        "\n  (global (;{};) {} {})",
+                        index,
+                        global_type_text(&global.global_type()),
+                        constant_expression(initializer)
+                    )?;
+                }
+            }
+            Section::ExportSection(section) => {
+                for export in section.0.iter() {
+                    let descriptor = match &export.descriptor {
+                        ExportDescriptor::FunctionIndex(index) => format!("(func {})", index.0),
+                        ExportDescriptor::TableIndex(index) => format!("(table {})", index.0),
+                        ExportDescriptor::MemoryIndex(index) => format!("(memory {})", index.0),
+                        ExportDescriptor::GlobalIndex(index) => format!("(global {})", index.0),
+                    };
+                    write!(
+                        f,
+                        "\n  (export {} {})",
+                        escape_string(export.name.as_bytes()),
+                        descriptor
+                    )?;
+                }
+            }
+            Section::StartSection(section) => write!(f, "\n  (start {})", section.0)?,
+            Section::ElementSection(section) => {
+                for (i, element) in section.0.iter().enumerate() {
+                    write!(f, "\n  (elem (;{};) ", i)?;
+                    if element.table_index.0 != 0 {
+                        write!(f, "(table {}) ", element.table_index.0)?;
+                    }
+                    f.write_str(&constant_expression(&element.offset))?;
+                    for function_index in element.initializer.iter() {
+                        write!(f, " {}", function_index.0)?;
+                    }
+                    f.write_str(")")?;
+                }
+            }
+            Section::CodeSection(section) => {
+                for (i, function) in section.0.iter().enumerate() {
+                    self.write_function(f, function, self.function_types.get(i).copied())?;
+                }
+            }
+            Section::DataSection(section) => {
+                for (i, data) in section.0.iter().enumerate() {
+                    write!(f, "\n  (data (;{};) ", i)?;
+                    if data.memory_index.0 != 0 {
+                        write!(f, "(memory {}) ", data.memory_index.0)?;
+                    }
+                    write!(
+                        f,
+                        "{} {})",
+                        constant_expression(&data.offset),
+                        escape_string(&data.initializer)
+                    )?;
+                }
+            }
+        }
+        self.previous_section = Some(section.id());
+        Ok(())
+    }
+
+    fn write_function(
+        &mut self,
+        f: &mut fmt::Formatter,
+        function: &Function,
+        type_index: Option<TypeIndex>,
+    ) -> fmt::Result {
+        let index = Self::next_index(&mut self.function_count);
+        write!(f, "\n  (func (;{};)", index)?;
+        if let Some(type_index) = type_index {
+            write!(f, " {}", self.type_use(type_index))?;
+        }
+        let locals: Vec<String> = function
+            .locals
+            .iter()
+            .flat_map(|local| (0..local.count).map(move |_| local.value_type.to_string()))
+            .collect();
+        if !locals.is_empty() {
+            write!(f, "\n    (local {})", locals.join(" "))?;
+        }
+        for instruction in function.expression.0.iter() {
+            for line in instruction.to_string().lines() {
+                write!(f, "\n    {}", line)?;
+            }
+        }
+        f.write_str(")")
+    }
+}
+
+/** The ` (param ...) (result ...)` of a function type, if it has any */
+fn signature(function_type: &FunctionType) -> String {
+    let list = |keyword: &str, types: &[ValueType]| match types {
+        [] => String::new(),
+        _ => {
+            let types: Vec<String> = types.iter().map(ValueType::to_string).collect();
+            format!(" ({} {})", keyword, types.join(" "))
+        }
+    };
+    list("param", &function_type.parameters) + &list("result", &function_type.results)
+}
+
+fn limits(limits: &Limits) -> String {
+    match limits.max {
+        Some(max) => format!("{} {}", limits.min, max),
+        None => limits.min.to_string(),
+    }
+}
+
+fn table_type(table: &Table) -> String {
+    match table.element_type {
+        ElementType::FunctionReference => format!("{} funcref", limits(&table.limits)),
+    }
+}
+
+fn global_type_text(global_type: &GlobalType) -> String {
+    match global_type.mutability {
+        Mutability::Const => global_type.value_type.to_string(),
+        Mutability::Var => format!("(mut {})", global_type.value_type),
+    }
+}
+
+/** Folds each instruction of a constant expression into parentheses */
+fn constant_expression(expression: &Expression) -> String {
+    let instructions: Vec<String> = expression
+        .0
+        .iter()
+        .map(|instruction| format!("({})", instruction))
+        .collect();
+    instructions.join(" ")
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut printer = Printer::new(self);
+        f.write_str("(module")?;
+        for section in self.0.iter() {
+            printer.write_section(f, section)?;
+        }
+        f.write_str(")")
+    }
+}
+
+/** Parses a module, with or without the `(module ...)` around its fields */
+pub fn parse_module(source: &str) -> Result<Module, ParseError> {
+    let mut parser = Parser::new(source)?;
+    let module = match parser.peek_at(1).and_then(Token::atom) {
+        Some("module") => parse_module_form(&mut parser)?,
+        _ => parse_fields(&mut parser, None)?,
+    };
+    parser.expect_end()?;
+    Ok(module)
+}
+
+impl FromStr for Module {
+    type Err = ParseError;
+
+    fn from_str(source: &str) -> Result<Module, ParseError> {
+        parse_module(source)
+    }
+}
+
+/** Parses a `(module $name? field*)` */
+pub(crate) fn parse_module_form(parser: &mut Parser) -> Result<Module, ParseError> {
+    parser.expect(TokenKind::LeftParen)?;
+    parser.expect_keyword("module")?;
+    let name = parser.take_id();
+    let module = parse_fields(parser, name)?;
+    parser.expect(TokenKind::RightParen)?;
+    Ok(module)
+}
+
+/** The definitions of a module, in the order of their index spaces */
+#[derive(Default)]
+struct Fields {
+    imports: Vec<Import>,
+    functions: Vec<TypeIndex>,
+    tables: Vec<Table>,
+    memories: Vec<Memory>,
+    globals: Vec<Global>,
+    exports: Vec<Export>,
+    start: Option<StartSection>,
+    elements: Vec<Element>,
+    code: Vec<Function>,
+    data: Vec<Data>,
+    /** Custom sections, after the position they go in, from `custom_position` */
+    custom: Vec<(usize, Section)>,
+    /** The number of functions, tables, memories and globals so far */
+    counts: [u32; 4],
+}
+
+impl Fields {
+    fn next_index(&mut self, space: Space) -> u32 {
+        let count = &mut self.counts[space_slot(space)];
+        *count += 1;
+        *count - 1
+    }
+
+    fn into_module(self, types: Vec<FunctionType>) -> Module {
+        let mut sections = vec![];
+        if !types.is_empty() {
+            sections.push(Section::TypeSection(TypeSection(types)));
+        }
+        if !self.imports.is_empty() {
+            sections.push(Section::ImportSection(ImportSection(self.imports)));
+        }
+        if !self.functions.is_empty() {
+            sections.push(Section::FunctionSection(FunctionSection(self.functions)));
+        }
+        if !self.tables.is_empty() {
+            sections.push(Section::TableSection(TableSection(self.tables)));
+        }
+        if !self.memories.is_empty() {
+            sections.push(Section::MemorySection(MemorySection(self.memories)));
+        }
+        if !self.globals.is_empty() {
+            sections.push(Section::GlobalSection(GlobalSection(self.globals)));
+        }
+        if !self.exports.is_empty() {
+            sections.push(Section::ExportSection(ExportSection(self.exports)));
+        }
+        if let Some(start) = self.start {
+            sections.push(Section::StartSection(start));
+        }
+        if !self.elements.is_empty() {
+            sections.push(Section::ElementSection(ElementSection(self.elements)));
+        }
+        if !self.code.is_empty() {
+            sections.push(Section::CodeSection(CodeSection(self.code)));
+        }
+        if !self.data.is_empty() {
+            sections.push(Section::DataSection(DataSection(self.data)));
+        }
+        let mut sections: Vec<(usize, Section)> = sections
+            .into_iter()
+            .map(|section| (2 * section.id() as usize + 1, section))
+            .chain(self.custom)
+            .collect();
+        sections.sort_by_key(|(position, _)| *position);
+        Module(sections.into_iter().map(|(_, section)| section).collect())
+    }
+}
+
+fn space_slot(space: Space) -> usize {
+    match space {
+        Space::Function => 0,
+        Space::Table => 1,
+        Space::Memory => 2,
+        Space::Global => 3,
+        _ => unreachable!("{} isn't a module index space", space),
+    }
+}
+
+fn definition_space(kind: &str) -> Option<Space> {
+    match kind {
+        "func" => Some(Space::Function),
+        "table" => Some(Space::Table),
+        "memory" => Some(Space::Memory),
+        "global" => Some(Space::Global),
+        _ => None,
+    }
+}
+
+fn is_open(parser: &Parser) -> bool {
+    parser.peek().map(|token| &token.kind) == Some(&TokenKind::LeftParen)
+}
+
+/** Skips to just past the `)` closing a list whose `(` was already taken */
+pub(crate) fn skip_list(parser: &mut Parser) -> Result<(), ParseError> {
+    let mut depth = 1;
+    while depth > 0 {
+        match parser.next()?.kind {
+            TokenKind::LeftParen => depth += 1,
+            TokenKind::RightParen => depth -= 1,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Parses module fields up to a `)` or the end of the text. Names can be used
+ * before their definitions, so a first pass defines every name and explicit
+ * type, and a second pass parses the fields.
+ *
+ * The module's `name`, and the `$names` of its definitions, locals, labels
+ * and data segments, go in a `name` section at the end of the module, unless
+ * it has one from a custom section annotation.
+ */
+pub(crate) fn parse_fields(parser: &mut Parser, name: Option<Token>) -> Result<Module, ParseError> {
+    parser.names = Names::default();
+    if let Some(name) = name.as_ref().and_then(Token::atom) {
+        parser.names.section.name_module(&name[1..]);
+    }
+    let start = parser.position();
+    declare_fields(parser)?;
+    parser.seek(start);
+
+    let mut fields = Fields::default();
+    while is_open(parser) {
+        parser.next()?;
+        let kind = parser.next()?;
+        match kind.atom() {
+            Some("type") => skip_list(parser)?,
+            Some("import") => parse_import(parser, &mut fields)?,
+            Some("func") => parse_function(parser, &mut fields)?,
+            Some("table") => parse_table(parser, &mut fields)?,
+            Some("memory") => parse_memory(parser, &mut fields)?,
+            Some("global") => parse_global(parser, &mut fields)?,
+            Some("export") => {
+                let name = parse_name(parser)?;
+                parser.expect(TokenKind::LeftParen)?;
+                let space_token = parser.next()?;
+                let descriptor = match space_token.atom().and_then(definition_space) {
+                    Some(Space::Function) => ExportDescriptor::FunctionIndex(FunctionIndex(
+                        parser.parse_index(Space::Function)?,
+                    )),
+                    Some(Space::Table) => {
+                        ExportDescriptor::TableIndex(TableIndex(parser.parse_index(Space::Table)?))
+                    }
+                    Some(Space::Memory) => ExportDescriptor::MemoryIndex(MemoryIndex(
+                        parser.parse_index(Space::Memory)?,
+                    )),
+                    _ => ExportDescriptor::GlobalIndex(GlobalIndex(parse_global_index(
+                        parser,
+                        &space_token,
+                    )?)),
+                };
+                parser.expect(TokenKind::RightParen)?;
+                parser.expect(TokenKind::RightParen)?;
+                fields.exports.push(Export::new(&name, descriptor));
+            }
+            Some("start") => {
+                let token = parser.peek().cloned();
+                let index = parser.parse_index(Space::Function)?;
+                let index = u8::try_from(index).map_err(|_| {
+                    token
+                        .unwrap()
+                        .error("start function index doesn't fit in a byte")
+                })?;
+                parser.expect(TokenKind::RightParen)?;
+                fields.start = Some(StartSection(index));
+            }
+            Some("elem") => {
+                // Segments can be named, though nothing refers to them yet
+                parser.take_id();
+                let mut table_index = 0;
+                if parser.take_open("table") {
+                    table_index = parser.parse_index(Space::Table)?;
+                    parser.expect(TokenKind::RightParen)?;
+                }
+                let offset = parse_offset(parser)?;
+                if parser.peek_atom() == Some("func") {
+                    parser.next()?;
+                }
+                let mut initializer = vec![];
+                while parser.is_index_next() {
+                    initializer.push(FunctionIndex(parser.parse_index(Space::Function)?));
+                }
+                parser.expect(TokenKind::RightParen)?;
+                fields
+                    .elements
+                    .push(Element::new(TableIndex(table_index), offset, initializer));
+            }
+            Some("data") => {
+                if let Some(id) = parser.take_id() {
+                    let index = fields.data.len() as u32;
+                    let name = &id.atom().unwrap()[1..];
+                    parser.names.section.name_data(index, name);
+                }
+                let mut memory_index = 0;
+                if parser.take_open("memory") {
+                    memory_index = parser.parse_index(Space::Memory)?;
+                    parser.expect(TokenKind::RightParen)?;
+                }
+                let offset = parse_offset(parser)?;
+                let initializer = parse_strings(parser)?;
+                parser.expect(TokenKind::RightParen)?;
+                fields
+                    .data
+                    .push(Data::new(MemoryIndex(memory_index), offset, initializer));
+            }
+            Some("@custom") => {
+                let name = parse_name(parser)?;
+                let position = parse_custom_position(parser)?;
+                let payload = parse_strings(parser)?;
+                parser.expect(TokenKind::RightParen)?;
+                fields
+                    .custom
+                    .push((position, Section::Custom { name, payload }));
+            }
+            _ => return Err(kind.error(format!("unknown module field {}", kind))),
+        }
+    }
+    let types = std::mem::take(&mut parser.names.types);
+    let names = std::mem::take(&mut parser.names.section);
+    let mut module = fields.into_module(types);
+    if !names.is_empty() && module.custom_section("name").is_none() {
+        module.set_custom_section(&names);
+    }
+    Ok(module)
+}
+
+/**
+ * Parses the optional `(before section)` or `(after section)` of a custom
+ * section. Sections go at odd positions, twice their id plus one, so custom
+ * sections can go at the even positions around them.
+ */
+fn parse_custom_position(parser: &mut Parser) -> Result<usize, ParseError> {
+    let last = 2 * SECTION_KEYWORDS.len();
+    let is_after = match parser.peek_at(1).and_then(Token::atom) {
+        Some("before") if is_open(parser) => false,
+        Some("after") if is_open(parser) => true,
+        _ => return Ok(last),
+    };
+    parser.next()?;
+    parser.next()?;
+    let token = parser.next()?;
+    let position = match token.atom() {
+        Some("first") => 0,
+        Some("last") => last,
+        Some(keyword) => match SECTION_KEYWORDS[1..].iter().position(|k| *k == keyword) {
+            Some(i) if is_after => 2 * (i + 1) + 2,
+            Some(i) => 2 * (i + 1),
+            None => return Err(token.error(format!("expected a section, found {}", token))),
+        },
+        None => return Err(token.error(format!("expected a section, found {}", token))),
+    };
+    parser.expect(TokenKind::RightParen)?;
+    Ok(position)
+}
+
+/** The `export` descriptor's last case, which has to be `global` */
+fn parse_global_index(parser: &mut Parser, space: &Token) -> Result<u32, ParseError> {
+    match space.atom() {
+        Some("global") => parser.parse_index(Space::Global),
+        _ => Err(space.error(format!("expected an export kind, found {}", space))),
+    }
+}
+
+/** The first pass of `parse_fields` */
+fn declare_fields(parser: &mut Parser) -> Result<(), ParseError> {
+    let mut counts = [0; 4];
+    let mut first_definition = None;
+    while is_open(parser) {
+        parser.next()?;
+        let kind = parser.next()?;
+        let (space, id) = match kind.atom() {
+            Some("type") => {
+                let id = parser.take_id();
+                let index = parser.names.types.len() as u32;
+                if let Some(id) = id {
+                    parser.names.define(Space::Type, &id, index)?;
+                }
+                let function_type = parse_function_type(parser)?;
+                parser.names.types.push(function_type);
+                parser.expect(TokenKind::RightParen)?;
+                continue;
+            }
+            Some("import") => {
+                if let Some(definition) = first_definition {
+                    return Err(kind.error(format!("import after the {} definition", definition)));
+                }
+                parse_name(parser)?;
+                parse_name(parser)?;
+                parser.expect(TokenKind::LeftParen)?;
+                let descriptor = parser.next()?;
+                let space = descriptor
+                    .atom()
+                    .and_then(definition_space)
+                    .ok_or_else(|| {
+                        descriptor.error(format!("expected an import kind, found {}", descriptor))
+                    })?;
+                let id = parser.take_id();
+                skip_list(parser)?;
+                (space, id)
+            }
+            Some(atom) => match definition_space(atom) {
+                Some(space) => {
+                    let id = parser.take_id();
+                    let is_import = inline_import_follows(parser);
+                    if !is_import && first_definition.is_none() {
+                        first_definition = Some(atom.to_string());
+                    } else if is_import && first_definition.is_some() {
+                        return Err(kind.error(format!(
+                            "import after the {} definition",
+                            first_definition.unwrap()
+                        )));
+                    }
+                    (space, id)
+                }
+                None => (Space::Type, None),
+            },
+            None => return Err(kind.error(format!("expected a module field, found {}", kind))),
+        };
+        if space != Space::Type {
+            let index = counts[space_slot(space)];
+            counts[space_slot(space)] += 1;
+            if let Some(id) = id {
+                parser.names.define(space, &id, index)?;
+            }
+        }
+        skip_list(parser)?;
+    }
+    Ok(())
+}
+
+/** Whether an inline `(import ...)` follows a definition's inline exports */
+fn inline_import_follows(parser: &Parser) -> bool {
+    let mut offset = 0;
+    while parser.peek_at(offset).map(|token| &token.kind) == Some(&TokenKind::LeftParen) {
+        match parser.peek_at(offset + 1).and_then(Token::atom) {
+            Some("import") => return true,
+            Some("export") => offset += 4,
+            _ => return false,
+        }
+    }
+    false
+}
+
+/** Parses `(func (param ...) (result ...))` */
+fn parse_function_type(parser: &mut Parser) -> Result<FunctionType, ParseError> {
+    parser.expect(TokenKind::LeftParen)?;
+    parser.expect_keyword("func")?;
+    let mut function_type = FunctionType::new(vec![], vec![]);
+    while parser.take_open("param") {
+        match parser.take_id() {
+            Some(_) => {
+                function_type.parameters.push(parser.parse_value_type()?);
+                parser.expect(TokenKind::RightParen)?;
+            }
+            None => function_type.parameters.extend(parser.parse_value_types()?),
+        }
+    }
+    while parser.take_open("result") {
+        function_type.results.extend(parser.parse_value_types()?);
+    }
+    parser.expect(TokenKind::RightParen)?;
+    Ok(function_type)
+}
+
+pub(crate) fn parse_strings(parser: &mut Parser) -> Result<Vec<u8>, ParseError> {
+    let mut bytes = vec![];
+    while let Some(Token {
+        kind: TokenKind::String(string),
+        ..
+    }) = parser.peek()
+    {
+        bytes.extend_from_slice(string);
+        parser.next()?;
+    }
+    Ok(bytes)
+}
+
+/** Parses a string that has to be valid UTF-8, as import and export names do */
+fn parse_name(parser: &mut Parser) -> Result<String, ParseError> {
+    let token = parser.next()?;
+    match token.kind {
+        TokenKind::String(ref bytes) => {
+            String::from_utf8(bytes.clone()).map_err(|_| token.error("malformed UTF-8 encoding"))
+        }
+        _ => Err(token.error(format!("expected a string, found {}", token))),
+    }
+}
+
+/** Parses any inline `(export "name")` lists, exporting `index` */
+fn parse_inline_exports(
+    parser: &mut Parser,
+    fields: &mut Fields,
+    descriptor: ExportDescriptor,
+) -> Result<(), ParseError> {
+    while parser.take_open("export") {
+        let name = parse_name(parser)?;
+        parser.expect(TokenKind::RightParen)?;
+        fields.exports.push(Export::new(&name, descriptor.clone()));
+    }
+    Ok(())
+}
+
+/** Parses an inline `(import "module" "name")`, if it is next */
+fn parse_inline_import(parser: &mut Parser) -> Result<Option<(String, String)>, ParseError> {
+    if !parser.take_open("import") {
+        return Ok(None);
+    }
+    let module_name = parse_name(parser)?;
+    let name = parse_name(parser)?;
+    parser.expect(TokenKind::RightParen)?;
+    Ok(Some((module_name, name)))
+}
+
+fn parse_import(parser: &mut Parser, fields: &mut Fields) -> Result<(), ParseError> {
+    let module_name = parse_name(parser)?;
+    let name = parse_name(parser)?;
+    parser.expect(TokenKind::LeftParen)?;
+    let kind = parser.next()?;
+    let space = kind.atom().and_then(definition_space).unwrap();
+    fields.next_index(space);
+    parser.take_id();
+    let descriptor = parse_import_descriptor(parser, space)?;
+    parser.expect(TokenKind::RightParen)?;
+    parser.expect(TokenKind::RightParen)?;
+    fields
+        .imports
+        .push(Import::new(&module_name, &name, descriptor));
+    Ok(())
+}
+
+fn parse_import_descriptor(
+    parser: &mut Parser,
+    space: Space,
+) -> Result<ImportDescriptor, ParseError> {
+    Ok(match space {
+        Space::Function => ImportDescriptor::TypeIndex(parser.parse_type_use()?.0),
+        Space::Table => ImportDescriptor::TableType(parse_table_type(parser)?),
+        Space::Memory => ImportDescriptor::MemoryType(Memory::new(parse_limits(parser)?)),
+        _ => ImportDescriptor::GlobalType(parse_global_type(parser)?),
+    })
+}
+
+fn parse_function(parser: &mut Parser, fields: &mut Fields) -> Result<(), ParseError> {
+    parser.take_id();
+    let index = fields.next_index(Space::Function);
+    parse_inline_exports(
+        parser,
+        fields,
+        ExportDescriptor::FunctionIndex(FunctionIndex(index)),
+    )?;
+    if let Some((module_name, name)) = parse_inline_import(parser)? {
+        let descriptor = parse_import_descriptor(parser, Space::Function)?;
+        parser.expect(TokenKind::RightParen)?;
+        fields
+            .imports
+            .push(Import::new(&module_name, &name, descriptor));
+        return Ok(());
+    }
+
+    let (type_index, parameter_names) = parser.parse_type_use()?;
+    parser
+        .names
+        .indices
+        .retain(|(space, _), _| *space != Space::Local);
+    parser.names.function = Some(FunctionIndex(index));
+    parser.names.block_count = 0;
+    for (i, name) in parameter_names.iter().enumerate() {
+        if let Some(name) = name {
+            parser.names.define(Space::Local, name, i as u32)?;
+        }
+    }
+    let mut local_index = parameter_names.len() as u32;
+    let mut locals: Vec<Local> = vec![];
+    while parser.take_open("local") {
+        let value_types = match parser.take_id() {
+            Some(id) => {
+                parser.names.define(Space::Local, &id, local_index)?;
+                let value_type = parser.parse_value_type()?;
+                parser.expect(TokenKind::RightParen)?;
+                vec![value_type]
+            }
+            None => parser.parse_value_types()?,
+        };
+        for value_type in value_types {
+            local_index += 1;
+            match locals.last_mut() {
+                Some(local) if local.value_type == value_type => local.count += 1,
+                _ => locals.push(Local::new(1, value_type)),
+            }
+        }
+    }
+    let instructions = parser.parse_instructions()?;
+    parser.names.function = None;
+    parser.expect(TokenKind::RightParen)?;
+    fields.functions.push(type_index);
+    fields
+        .code
+        .push(Function::new(locals, Expression(instructions)));
+    Ok(())
+}
+
+fn parse_table(parser: &mut Parser, fields: &mut Fields) -> Result<(), ParseError> {
+    parser.take_id();
+    let index = fields.next_index(Space::Table);
+    parse_inline_exports(
+        parser,
+        fields,
+        ExportDescriptor::TableIndex(TableIndex(index)),
+    )?;
+    let table = match parse_inline_import(parser)? {
+        Some((module_name, name)) => {
+            let descriptor = parse_import_descriptor(parser, Space::Table)?;
+            fields
+                .imports
+                .push(Import::new(&module_name, &name, descriptor));
+            None
+        }
+        // A table can be written with its elements, which also set its size
+        None if parser.peek_atom() == Some("funcref") => {
+            parser.next()?;
+            parser.expect(TokenKind::LeftParen)?;
+            parser.expect_keyword("elem")?;
+            let mut initializer = vec![];
+            while parser.is_index_next() {
+                initializer.push(FunctionIndex(parser.parse_index(Space::Function)?));
+            }
+            parser.expect(TokenKind::RightParen)?;
+            let size = initializer.len() as u32;
+            fields.elements.push(Element::new(
+                TableIndex(index),
+                Expression(vec![Instruction::I32Const(0)]),
+                initializer,
+            ));
+            Some(Table::new(
+                ElementType::FunctionReference,
+                Limits::min_max(size, size),
+            ))
+        }
+        None => Some(parse_table_type(parser)?),
+    };
+    parser.expect(TokenKind::RightParen)?;
+    fields.tables.extend(table);
+    Ok(())
+}
+
+fn parse_memory(parser: &mut Parser, fields: &mut Fields) -> Result<(), ParseError> {
+    parser.take_id();
+    let index = fields.next_index(Space::Memory);
+    parse_inline_exports(
+        parser,
+        fields,
+        ExportDescriptor::MemoryIndex(MemoryIndex(index)),
+    )?;
+    let memory = match parse_inline_import(parser)? {
+        Some((module_name, name)) => {
+            let descriptor = parse_import_descriptor(parser, Space::Memory)?;
+            fields
+                .imports
+                .push(Import::new(&module_name, &name, descriptor));
+            None
+        }
+        // A memory can be written with its data, which also sets its size
+        None if parser.take_open("data") => {
+            let initializer = parse_strings(parser)?;
+            parser.expect(TokenKind::RightParen)?;
+            let pages = (initializer.len() as u32).div_ceil(PAGE_SIZE);
+            fields.data.push(Data::new(
+                MemoryIndex(index),
+                Expression(vec![Instruction::I32Const(0)]),
+                initializer,
+            ));
+            Some(Memory::new(Limits::min_max(pages, pages)))
+        }
+        None => Some(Memory::new(parse_limits(parser)?)),
+    };
+    parser.expect(TokenKind::RightParen)?;
+    fields.memories.extend(memory);
+    Ok(())
+}
+
+fn parse_global(parser: &mut Parser, fields: &mut Fields) -> Result<(), ParseError> {
+    parser.take_id();
+    let index = fields.next_index(Space::Global);
+    parse_inline_exports(
+        parser,
+        fields,
+        ExportDescriptor::GlobalIndex(GlobalIndex(index)),
+    )?;
+    if let Some((module_name, name)) = parse_inline_import(parser)? {
+        let descriptor = parse_import_descriptor(parser, Space::Global)?;
+        fields
+            .imports
+            .push(Import::new(&module_name, &name, descriptor));
+    } else {
+        let global_type = parse_global_type(parser)?;
+        let initializer = Expression(parser.parse_instructions()?);
+        fields.globals.push(match global_type.mutability {
+            Mutability::Const => Global::Const(global_type.value_type, initializer),
+            Mutability::Var => Global::Var(global_type.value_type, initializer),
+        });
+    }
+    parser.expect(TokenKind::RightParen)?;
+    Ok(())
+}
+
+fn parse_limits(parser: &mut Parser) -> Result<Limits, ParseError> {
+    let min = parser.parse_u32()?;
+    let has_max = parser
+        .peek_atom()
+        .is_some_and(|atom| atom.starts_with(|c: char| c.is_ascii_digit()));
+    let max = match has_max {
+        true => Some(parser.parse_u32()?),
+        false => None,
+    };
+    Ok(Limits::new(min, max))
+}
+
+fn parse_table_type(parser: &mut Parser) -> Result<Table, ParseError> {
+    let limits = parse_limits(parser)?;
+    parser.expect_keyword("funcref")?;
+    Ok(Table::new(ElementType::FunctionReference, limits))
+}
+
+fn parse_global_type(parser: &mut Parser) -> Result<GlobalType, ParseError> {
+    if parser.take_open("mut") {
+        let value_type = parser.parse_value_type()?;
+        parser.expect(TokenKind::RightParen)?;
+        return Ok(GlobalType::new(value_type, Mutability::Var));
+    }
+    Ok(GlobalType::new(
+        parser.parse_value_type()?,
+        Mutability::Const,
+    ))
+}
+
+/** Parses an `(offset ...)`, or a single folded instruction */
+fn parse_offset(parser: &mut Parser) -> Result<Expression, ParseError> {
+    let mut instructions = vec![];
+    if parser.take_open("offset") {
+        instructions = parser.parse_instructions()?;
+        parser.expect(TokenKind::RightParen)?;
+    } else {
+        parser.parse_folded(&mut instructions)?;
+    }
+    Ok(Expression(instructions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        expression::{BlockType, Instruction::*, MemoryArguments},
+        index::{LabelIndex, LocalIndex},
+        section::{custom_section::CustomSection, name_section::NameSection},
+    };
+
+    #[test]
+    fn test_escape_string() {
+        assert_eq!(
+            escape_string(b"hi \"there\"\\\0\n"),
+            "\"hi \\\"there\\\"\\\\\\00\\0a\""
+        );
+    }
+
+    #[test]
+    fn test_module_printing() {
+        let module = Module(vec![
+            Section::TypeSection(TypeSection(vec![
+                FunctionType::new(vec![ValueType::I32], vec![]),
+                FunctionType::new(vec![ValueType::I32, ValueType::I32], vec![ValueType::I32]),
+            ])),
+            Section::ImportSection(ImportSection(vec![
+                Import::new("env", "log", ImportDescriptor::TypeIndex(TypeIndex(0))),
+                Import::new(
+                    "env",
+                    "counter",
+                    ImportDescriptor::GlobalType(GlobalType::new(ValueType::I64, Mutability::Var)),
+                ),
+            ])),
+            Section::FunctionSection(FunctionSection(vec![TypeIndex(1)])),
+            Section::TableSection(TableSection(vec![Table::new(
+                ElementType::FunctionReference,
+                Limits::min_max(1, 2),
+            )])),
+            Section::MemorySection(MemorySection(vec![Memory::new(Limits::min(1))])),
+            Section::GlobalSection(GlobalSection(vec![Global::Const(
+                ValueType::F64,
+                Expression(vec![F64Const(0.5)]),
+            )])),
+            Section::ExportSection(ExportSection(vec![
+                Export::new("max", ExportDescriptor::FunctionIndex(FunctionIndex(1))),
+                Export::new("memory", ExportDescriptor::MemoryIndex(MemoryIndex(0))),
+            ])),
+            Section::ElementSection(ElementSection(vec![Element::new(
+                TableIndex(0),
+                Expression(vec![I32Const(0)]),
+                vec![FunctionIndex(1)],
+            )])),
+            Section::CodeSection(CodeSection(vec![Function::new(
+                vec![Local::new(1, ValueType::I32)],
+                Expression(vec![
+                    LocalGet(LocalIndex(0)),
+                    LocalGet(LocalIndex(1)),
+                    I32GtS,
+                    IfElse(
+                        BlockType::Value(ValueType::I32),
+                        vec![LocalGet(LocalIndex(0))],
+                        vec![
+                            Block(BlockType::Empty, vec![Branch(LabelIndex(0))]),
+                            LocalGet(LocalIndex(1)),
+                        ],
+                    ),
+                    LocalTee(LocalIndex(2)),
+                    I32Store(MemoryArguments::new(16, 2)),
+                    LocalGet(LocalIndex(2)),
+                ]),
+            )])),
+            Section::DataSection(DataSection(vec![Data::new(
+                MemoryIndex(0),
+                Expression(vec![I32Const(8)]),
+                b"max\0".to_vec(),
+            )])),
+        ]);
+        assert_eq!(
+            module.to_string(),
+            r#"(module
+  (type (;0;) (func (param i32)))
+  (type (;1;) (func (param i32 i32) (result i32)))
+  (import "env" "log" (func (;0;) (type 0) (param i32)))
+  (import "env" "counter" (global (;0;) (mut i64)))
+  (table (;0;) 1 2 funcref)
+  (memory (;0;) 1)
+  (global (;1;) f64 (f64.const 0x1p-1))
+  (export "max" (func 1))
+  (export "memory" (memory 0))
+  (elem (;0;) (i32.const 0) 1)
+  (func (;1;) (type 1) (param i32 i32) (result i32)
+    (local i32)
+    local.get 0
+    local.get 1
+    i32.gt_s
+    if (result i32)
+      local.get 0
+    else
+      block
+        br 0
+      end
+      local.get 1
+    end
+    local.tee 2
+    i32.store offset=16
+    local.get 2)
+  (data (;0;) (i32.const 8) "max\00"))"#
+        );
+    }
+
+    #[test]
+    fn test_empty_module() {
+        assert_eq!(Module(vec![]).to_string(), "(module)");
+    }
+
+    #[test]
+    fn test_parse_names_and_inline_definitions() {
+        let module = parse_module(
+            r#"(module
+              (import "env" "log" (func $log (param i32)))
+              (memory (export "memory") (data "hi"))
+              (global $count (mut i32) (i32.const 0))
+              (func $sum (export "sum") (param $n i32) (result i32) (local $total i32)
+                (block $done
+                  (loop $next
+                    (br_if $done (i32.eqz (local.get $n)))
+                    local.get $total
+                    local.get $n
+                    i32.add
+                    local.set $total
+                    (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                    br $next))
+                (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                (call $log (local.get $total))
+                local.get $total)
+              (start $log))"#,
+        );
+        let mut module = module.unwrap();
+        let mut names = NameSection::new();
+        names.name_function(FunctionIndex(0), "log");
+        names.name_function(FunctionIndex(1), "sum");
+        names.name_global(GlobalIndex(0), "count");
+        names.name_local(FunctionIndex(1), LocalIndex(0), "n");
+        names.name_local(FunctionIndex(1), LocalIndex(1), "total");
+        names.name_label(FunctionIndex(1), 0, "done");
+        names.name_label(FunctionIndex(1), 1, "next");
+        assert_eq!(module.0.pop(), Some(names.to_section()));
+        assert_eq!(
+            module.to_string(),
+            r#"(module
+  (type (;0;) (func (param i32)))
+  (type (;1;) (func (param i32) (result i32)))
+  (import "env" "log" (func (;0;) (type 0) (param i32)))
+  (memory (;0;) 1 1)
+  (global (;0;) (mut i32) (i32.const 0))
+  (export "memory" (memory 0))
+  (export "sum" (func 1))
+  (start 0)
+  (func (;1;) (type 1) (param i32) (result i32)
+    (local i32)
+    block
+      loop
+        local.get 0
+        i32.eqz
+        br_if 1
+        local.get 1
+        local.get 0
+        i32.add
+        local.set 1
+        local.get 0
+        i32.const 1
+        i32.sub
+        local.set 0
+        br 0
+      end
+    end
+    global.get 0
+    i32.const 1
+    i32.add
+    global.set 0
+    local.get 1
+    call 0
+    local.get 1)
+  (data (;0;) (i32.const 0) "hi"))"#
+        );
+    }
+
+    #[test]
+    fn test_name_section() {
+        let module = parse_module(
+            r#"(module $m
+              (type $t (func))
+              (func (type $t)
+                block
+                  block $inner
+                  end
+                end)
+              (memory $heap 1)
+              (data $greeting (i32.const 0) "hi"))"#,
+        )
+        .unwrap();
+        let mut names = NameSection::new();
+        names.name_module("m");
+        names.name_type(TypeIndex(0), "t");
+        names.name_label(FunctionIndex(0), 1, "inner");
+        names.name_memory(MemoryIndex(0), "heap");
+        names.name_data(0, "greeting");
+        assert_eq!(module.0.last(), Some(&names.to_section()));
+
+        // Names an annotation already gives aren't replaced
+        let module = parse_module(r#"(func $f) (@custom "name" "")"#).unwrap();
+        assert_eq!(module.custom_section("name"), Some(&[][..]));
+        assert_eq!(module.0.len(), 4);
+
+        let module = parse_module("(func (block))").unwrap();
+        assert_eq!(module.custom_section("name"), None);
+    }
+
+    #[test]
+    fn test_parse_flat_blocks_and_types() {
+        let module: Module = r#"
+            (type $binary (func (param i64 i64) (result i64)))
+            (table 2 funcref)
+            (func $pick (type $binary)
+              local.get 0
+              if $check (result i64)
+                local.get 1
+              else $check
+                i64.const -1
+              end $check)
+            (func (param i32) (result i64)
+              (call_indirect (type $binary)
+                (i64.const 1) (i64.const 2) (local.get 0)))
+            (elem (i32.const 0) func $pick 1)
+        "#
+        .parse()
+        .unwrap();
+        let Section::CodeSection(code) = &module.0[4] else {
+            panic!("{:?}", module.0[4]);
+        };
+        assert_eq!(
+            code.0[0].expression.0[1],
+            IfElse(
+                BlockType::Value(ValueType::I64),
+                vec![LocalGet(LocalIndex(1))],
+                vec![I64Const(-1)],
+            )
+        );
+        assert_eq!(
+            module.0[3],
+            Section::ElementSection(ElementSection(vec![Element::new(
+                TableIndex(0),
+                Expression(vec![I32Const(0)]),
+                vec![FunctionIndex(0), FunctionIndex(1)],
+            )]))
+        );
+        // The second function's inline signature gets a type of its own
+        assert_eq!(
+            module.0[0],
+            Section::TypeSection(TypeSection(vec![
+                FunctionType::new(vec![ValueType::I64, ValueType::I64], vec![ValueType::I64]),
+                FunctionType::new(vec![ValueType::I32], vec![ValueType::I64]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_printed_module_parses_back() {
+        let module = Module(vec![
+            Section::TypeSection(TypeSection(vec![FunctionType::new(vec![], vec![])])),
+            Section::ImportSection(ImportSection(vec![Import::new(
+                "env",
+                "table",
+                ImportDescriptor::TableType(Table::new(
+                    ElementType::FunctionReference,
+                    Limits::min(1),
+                )),
+            )])),
+            Section::FunctionSection(FunctionSection(vec![TypeIndex(0)])),
+            Section::GlobalSection(GlobalSection(vec![Global::Const(
+                ValueType::F32,
+                Expression(vec![F32Const(-0.1)]),
+            )])),
+            Section::CodeSection(CodeSection(vec![Function::new(
+                vec![Local::new(2, ValueType::F64)],
+                Expression(vec![
+                    Loop(
+                        BlockType::Empty,
+                        vec![BranchTable(vec![LabelIndex(0)], LabelIndex(1))],
+                    ),
+                    I64Load8U(MemoryArguments::new(3, 0)),
+                    Drop,
+                ]),
+            )])),
+        ]);
+        assert_eq!(module.to_string().parse(), Ok(module));
+    }
+
+    #[test]
+    fn test_custom_sections() {
+        let custom = |name: &str, payload: &[u8]| Section::Custom {
+            name: name.to_string(),
+            payload: payload.to_vec(),
+        };
+        let module = Module(vec![
+            custom("first", b""),
+            Section::TypeSection(TypeSection(vec![FunctionType::new(vec![], vec![])])),
+            custom("a", b"\x00\xff"),
+            custom("b", b"text"),
+            Section::FunctionSection(FunctionSection(vec![TypeIndex(0)])),
+            Section::CodeSection(CodeSection(vec![Function::new(vec![], Expression(vec![]))])),
+            custom("last", b""),
+        ]);
+        assert_eq!(
+            module.to_string(),
+            r#"(module
+  (@custom "first" (before first) "")
+  (type (;0;) (func))
+  (@custom "a" (after type) "\00\ff")
+  (@custom "b" (after type) "text")
+  (func (;0;) (type 0))
+  (@custom "last" (after code) ""))"#
+        );
+        assert_eq!(module.to_string().parse(), Ok(module));
+
+        // Without a placement, custom sections go at the end
+        let module = parse_module(r#"(@custom "x" "1") (memory 1) (@custom "y" (before memory))"#);
+        assert_eq!(
+            module,
+            Ok(Module(vec![
+                custom("y", b""),
+                Section::MemorySection(MemorySection(vec![Memory::new(Limits::min(1))])),
+                custom("x", b"1"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse_module("(module\n  (func (local $x i32)\n    local.get $y))"),
+            Err(ParseError {
+                line: 3,
+                column: 15,
+                message: "unknown local `$y`".into(),
+            })
+        );
+        assert_eq!(
+            parse_module("(func $f) (func $f)").unwrap_err().to_string(),
+            "1:17: duplicate function `$f`"
+        );
+        assert_eq!(
+            parse_module("(func) (import \"a\" \"b\" (func))")
+                .unwrap_err()
+                .to_string(),
+            "1:9: import after the func definition"
+        );
+        assert_eq!(
+            parse_module("(type (func)) (func (type 0) (param i32))")
+                .unwrap_err()
+                .message,
+            "inline signature doesn't match type 0"
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use std::{fs, path::Path};
+
+use wasmuter::wast::{run_script, Outcome};
+
+/**
+ * Runs every `.wast` script in `tests/spec`. These are hand-written scripts in
+ * the format of the official spec test suite, covering what wasmuter
+ * supports. The official scripts themselves also use proposals and commands
+ * it doesn't have, so they aren't kept here.
+ */
+#[test]
+fn test_spec_scripts() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/spec");
+    let mut paths: Vec<_> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "wast")
+        })
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let mut failures = vec![];
+    for path in paths {
+        let source = fs::read_to_string(&path).unwrap();
+        let results = match run_script(&source) {
+            Ok(results) => results,
+            Err(error) => {
+                failures.push(format!("{}:{}", path.display(), error));
+                continue;
+            }
+        };
+        for result in results {
+            if let Outcome::Failed(_) = result.outcome {
+                failures.push(format!("{}:{}", path.display(), result));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
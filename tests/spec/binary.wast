@@ -0,0 +1,91 @@
+;; Binary modules, and binaries that have to be rejected by the decoder
+
+(module binary "\00asm" "\01\00\00\00")
+(module binary
+  "\00asm" "\01\00\00\00"
+  "\01\05\01"                         ;; type section
+  "\60\00\01\7f"                      ;; (func (result i32))
+  "\03\02\01\00"                      ;; function section
+  "\07\05\01\01f\00\00"               ;; export section
+  "\0a\06\01"                         ;; code section
+  "\04\00\41\2a\0b"                   ;; i32.const 42
+)
+(module binary
+  "\00asm" "\01\00\00\00"
+  "\00\05\04name"                     ;; an empty custom section
+  "\05\04\01\01\01\02"                ;; memory section, min 1 max 2
+  "\00\06\03abc\01\02"                ;; a custom section between the others
+  "\0b\01\00"                         ;; data section
+)
+
+(assert_malformed (module binary "") "unexpected end")
+(assert_malformed (module binary "\00asm") "unexpected end")
+(assert_malformed (module binary "asm\00" "\01\00\00\00") "magic header not detected")
+(assert_malformed (module binary "\00asm" "\0d\00\00\00") "unknown binary version")
+(assert_malformed (module binary "\00asm" "\01\00\00\00" "\0c\00") "malformed section id")
+(assert_malformed
+  (module binary "\00asm" "\01\00\00\00" "\00\01\05name!")
+  "length out of bounds"
+)
+(assert_malformed
+  (module binary "\00asm" "\01\00\00\00" "\05\04\01\00\01")
+  "unexpected end"
+)
+(assert_malformed
+  (module binary "\00asm" "\01\00\00\00" "\05\03\01\02\01")
+  "integer too large"
+)
+(assert_malformed
+  (module binary "\00asm" "\01\00\00\00" "\01\04\01\60\00\01")
+  "unexpected end"
+)
+(assert_malformed
+  (module binary "\00asm" "\01\00\00\00" "\01\04\01\61\00\00")
+  "integer representation too long"
+)
+(assert_malformed
+  (module binary "\00asm" "\01\00\00\00" "\01\05\01\60\01\6f\00")
+  "malformed value type"
+)
+(assert_malformed
+  (module binary "\00asm" "\01\00\00\00" "\07\05\01\01\ff\00\00")
+  "malformed UTF-8 encoding"
+)
+(assert_malformed
+  (module binary
+    "\00asm" "\01\00\00\00"
+    "\01\04\01\60\00\00" "\03\02\01\00"
+    "\0a\05\01\02\00\0f\0b"           ;; body size one byte too small
+  )
+  "section size mismatch"
+)
+(assert_malformed
+  (module binary
+    "\00asm" "\01\00\00\00"
+    "\01\04\01\60\00\00" "\03\02\01\00"
+    "\0a\07\01\05\00\3f\01\1a\0b"     ;; memory.size with a reserved byte of 1
+  )
+  "zero byte expected"
+)
+(assert_malformed
+  (module binary
+    "\00asm" "\01\00\00\00"
+    "\05\03\01\00\01"                 ;; memory section
+    "\04\04\01\70\00\01"              ;; table section, after the memory section
+  )
+  "unexpected content after last section"
+)
+(assert_malformed
+  (module binary
+    "\00asm" "\01\00\00\00"
+    "\05\03\01\00\01" "\05\03\01\00\01" ;; two memory sections
+  )
+  "unexpected content after last section"
+)
+(assert_malformed
+  (module binary
+    "\00asm" "\01\00\00\00"
+    "\01\04\01\60\00\00" "\03\02\01\00" ;; a function without a code section
+  )
+  "function and code section have inconsistent lengths"
+)
@@ -0,0 +1,93 @@
+;; Flat and folded instructions, labels and constants
+
+(module
+  (func $fac (export "fac") (param i64) (result i64)
+    (if (result i64) (i64.eqz (local.get 0))
+      (then (i64.const 1))
+      (else (i64.mul (local.get 0) (call $fac (i64.sub (local.get 0) (i64.const 1)))))
+    )
+  )
+  (func $fac-flat (param $n i64) (result i64) (local $acc i64)
+    i64.const 1
+    local.set $acc
+    block $done
+      loop $loop
+        local.get $n
+        i64.eqz
+        br_if $done
+        local.get $n
+        local.get $acc
+        i64.mul
+        local.set $acc
+        local.get $n
+        i64.const 1
+        i64.sub
+        local.set $n
+        br $loop
+      end $loop
+    end $done
+    local.get $acc
+  )
+  (func (param i32) (result i32)
+    (block $a (result i32)
+      (block $b
+        (block $c
+          (br_table $a $b $c 1 (i32.const 7) (local.get 0))
+        )
+        (return (i32.const 2))
+      )
+      (i32.const 1)
+    )
+  )
+  (func (param i32)
+    local.get 0
+    if $l
+      nop
+    else $l
+      unreachable
+    end
+  )
+)
+(assert_return (invoke "fac" (i64.const 5)) (i64.const 120))
+
+(module
+  (memory 1)
+  (func (param i32)
+    (i64.store offset=8 align=4 (local.get 0) (i64.load8_u offset=1 (local.get 0)))
+    (f32.store align=1 (i32.const 0) (f32.const -0x1.fffffep+127))
+    (drop (memory.grow (memory.size)))
+    (drop (i32.trunc_sat_f64_u (f64.const nan:0x4)))
+  )
+)
+
+(module
+  (global f32 (f32.const 0x1p-149))
+  (global f32 (f32.const -inf))
+  (global f32 (f32.const nan:0x200000))
+  (global f64 (f64.const 1e308))
+  (global f64 (f64.const 0x1.fffffffffffff8p-1022))
+  (global f64 (f64.const -0_1.2_5e-1_0))
+  (global i32 (i32.const 0xffff_ffff))
+  (global i32 (i32.const -0x8000_0000))
+  (global i64 (i64.const 18446744073709551615))
+  (global i64 (i64.const -9223372036854775808))
+)
+
+(module
+  (type $t (func (param i32) (result i32)))
+  (table 1 funcref)
+  (func (result i32)
+    (call_indirect (type $t) (i32.const 1) (i32.const 0))
+    (call_indirect (param i32) (result i32) (i32.const 1))
+  )
+)
+
+(assert_malformed (module quote "(func i32.const 4294967296)") "constant out of range")
+(assert_malformed (module quote "(func i32.const -2147483649)") "constant out of range")
+(assert_malformed (module quote "(func f32.const 0x1p128)") "constant out of range")
+(assert_malformed (module quote "(func f32.const nan:0x800000)") "constant out of range")
+(assert_malformed (module quote "(func i32.load align=3 (i32.const 0))") "alignment")
+(assert_malformed (module quote "(func block end $l)") "mismatching label")
+(assert_malformed (module quote "(func br $l)") "unknown label")
+(assert_malformed (module quote "(func (if (i32.const 0) (else)))") "unexpected token")
+(assert_malformed (module quote "(func \"string\")") "unexpected token")
@@ -0,0 +1,54 @@
+;; Module fields, names, and inline imports and exports
+
+(module)
+(module $empty)
+(module (type (func)) (type $t (func (param i32 i64) (result f32))))
+
+(module
+  (import "spectest" "print_i32" (func $print (param i32)))
+  (import "spectest" "global_i32" (global $g i32))
+  (import "spectest" "table" (table 10 20 funcref))
+  (import "spectest" "memory" (memory 1 2))
+  (func (export "print-global") (call $print (global.get $g)))
+)
+
+(module
+  (func $f (import "spectest" "print_i32") (param i32))
+  (global (import "spectest" "global_i64") i64)
+  (memory (export "memory") (export "memory-alias") 1)
+  (table (export "table") funcref (elem $f $g $f))
+  (func $g (export "g") (param $x i32) (result i32) (local.get $x))
+  (global $counter (export "counter") (mut i64) (i64.const -1))
+  (start $f)
+)
+
+(module
+  (memory $m 1)
+  (data (i32.const 0) "a" "" "bcd")
+  (data (memory $m) (offset (i32.const 8)) "\00\01\ff" "\u{2603}")
+  (data $segment (i32.const 16))
+  (table $t 4 funcref)
+  (elem $segment (i32.const 0) $f $f)
+  (elem (table $t) (offset (i32.const 2)) func $f)
+  (func $f)
+)
+
+(module
+  (memory (data "\de\ad" "\be\ef"))
+  (func (export "load") (result i32) (i32.load (i32.const 0)))
+)
+(assert_return (invoke "load") (i32.const 0xefbeadde))
+
+(module quote "(func $f (param $p i32) (local.get $p))" "(export \"f\" (func $f))")
+
+(assert_malformed (module quote "(func (local.get $undefined))") "unknown local")
+(assert_malformed (module quote "(func $f) (func $f)") "duplicate func")
+(assert_malformed (module quote "(global $g i32 (i32.const 0)) (global $g i32 (i32.const 0))") "duplicate global")
+(assert_malformed (module quote "(func) (import \"\" \"\" (func))") "import after function")
+(assert_malformed (module quote "(type (func)) (func (type 0) (param i32))") "inline function type")
+(assert_malformed (module quote "(memory)") "unexpected token")
+(assert_malformed (module quote "(table 1 anyfunk)") "unexpected token")
+(assert_malformed (module quote "(export \"\\ff\" (func 0))") "malformed UTF-8 encoding")
+(assert_malformed (module quote "(frobnicate)") "unknown operator")
+
+(assert_invalid (module (func (result i32))) "type mismatch")